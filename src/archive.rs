@@ -1,40 +1,50 @@
 use crate::{
     elf::{ArHdr, AR_HDR_SIZE},
+    error::LinkError,
     file::ElfFile,
     file_type::{get_file_type, FILE_TYPE_ARCHIVE},
     utils::read,
 };
 
 #[allow(dead_code)]
-pub fn read_archive_members(file: ElfFile) -> Vec<ElfFile> {
+pub fn read_archive_members(file: ElfFile) -> Result<Vec<ElfFile>, LinkError> {
     assert!(get_file_type(file.contents) == FILE_TYPE_ARCHIVE);
+    let corrupted = |reason: &str| LinkError::bad_elf(file.name, reason);
 
     let mut pos: usize = 8;
     let mut str_tab: &str = "";
     let mut elf_files: Vec<ElfFile> = vec![];
-    while  file.contents.len() - pos > 1 {
+    while file.contents.len() - pos > 1 {
         if pos % 2 == 1 {
             pos += 1;
         }
+        if file.contents.len() - pos < AR_HDR_SIZE {
+            return Err(corrupted("truncated archive member header"));
+        }
         let hdr: ArHdr = read::<ArHdr>(&file.contents[pos..]);
         let data_start = pos + AR_HDR_SIZE;
-        pos = data_start + hdr.get_size();
+        let size = hdr
+            .get_size()
+            .ok_or_else(|| corrupted("bad archive member size"))?;
+        if file.contents.len() - data_start < size {
+            return Err(corrupted("archive member is out of range"));
+        }
+        pos = data_start + size;
         let data_end = pos;
         let contents = &file.contents[data_start..data_end];
 
         if hdr.is_symtab() {
             continue;
         } else if hdr.is_str_tab() {
-            str_tab = std::str::from_utf8(contents).unwrap();
+            str_tab = std::str::from_utf8(contents)
+                .map_err(|_| corrupted("bad archive string table"))?;
             continue;
         }
 
-        elf_files.push(ElfFile {
-            name: Box::leak(Box::new(hdr.read_name(str_tab))),
-            contents: contents,
-            files: vec![&file as *const ElfFile],
-        });
+        let name = ArHdr::read_name(&file.contents[data_start - AR_HDR_SIZE..], str_tab)
+            .ok_or_else(|| corrupted("bad archive member name"))?;
+        elf_files.push(ElfFile { name, contents });
     }
 
-    elf_files
+    Ok(elf_files)
 }
@@ -0,0 +1,141 @@
+use std::{
+    marker::PhantomData,
+    ops::{Index, IndexMut},
+};
+
+// Input files, sections, symbols, output sections, merged sections and
+// their fragments are owned by arenas in the context and refer to each
+// other by typed indices. An index of one kind can't be used for an arena of
+// another, and nothing points into the arenas, so they may grow while the
+// link runs and are freed with the context.
+pub trait Id: Copy {
+    fn from_index(idx: usize) -> Self;
+    fn index(self) -> usize;
+}
+
+macro_rules! define_id {
+    ($($name:ident),*) => {
+        $(
+            #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+            pub struct $name(u32);
+
+            impl Id for $name {
+                fn from_index(idx: usize) -> Self {
+                    $name(u32::try_from(idx).expect("too many objects in arena"))
+                }
+
+                fn index(self) -> usize {
+                    self.0 as usize
+                }
+            }
+        )*
+    };
+}
+
+define_id!(
+    FileId,
+    DsoId,
+    SectionId,
+    SymbolId,
+    OutputSectionId,
+    MergedSectionId,
+    FragmentId
+);
+
+pub struct Arena<I, T> {
+    items: Vec<T>,
+    _marker: PhantomData<I>,
+}
+
+#[allow(dead_code)]
+impl<I: Id, T> Arena<I, T> {
+    pub fn new() -> Self {
+        Arena {
+            items: vec![],
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn push(&mut self, item: T) -> I {
+        let id = self.next_id();
+        self.items.push(item);
+        id
+    }
+
+    // The index the next pushed item gets, for items that record their
+    // own index.
+    pub fn next_id(&self) -> I {
+        I::from_index(self.items.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = I> {
+        (0..self.items.len()).map(I::from_index)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (I, &T)> {
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| (I::from_index(idx), item))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.items.iter()
+    }
+
+    // The items in ID order, for passes that update them in parallel.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.items
+    }
+}
+
+impl<I: Id, T> Default for Arena<I, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: Id, T> Index<I> for Arena<I, T> {
+    type Output = T;
+
+    fn index(&self, id: I) -> &T {
+        &self.items[id.index()]
+    }
+}
+
+impl<I: Id, T> IndexMut<I> for Arena<I, T> {
+    fn index_mut(&mut self, id: I) -> &mut T {
+        &mut self.items[id.index()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arena() {
+        let mut arena: Arena<SymbolId, &str> = Arena::new();
+        assert_eq!(arena.next_id(), SymbolId::from_index(0));
+        let foo = arena.push("foo");
+        let bar = arena.push("bar");
+        assert_ne!(foo, bar);
+        assert_eq!((arena[foo], arena[bar]), ("foo", "bar"));
+
+        arena[bar] = "baz";
+        assert_eq!(arena.len(), 2);
+        assert_eq!(arena.ids().collect::<Vec<_>>(), [foo, bar]);
+        assert_eq!(
+            arena.iter().collect::<Vec<_>>(),
+            [(foo, &"foo"), (bar, &"baz")]
+        );
+    }
+}
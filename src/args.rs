@@ -0,0 +1,417 @@
+use std::fs;
+
+use crate::{
+    build_id_section::{parse_build_id, BuildId},
+    defsym::parse_defsym,
+    elf::{
+        ELFCOMPRESS_ZLIB, ELFCOMPRESS_ZSTD, STV_DEFAULT, STV_HIDDEN, STV_INTERNAL, STV_PROTECTED,
+    },
+    error::LinkError,
+    linker::{LinkInput, LinkerConfig},
+    machine_type::{MACHINE_TYPE_RISCV32, MACHINE_TYPE_RISCV64, MACHINE_TYPE_X86_64},
+    output_format::parse_output_format,
+    utils::parse_number,
+    version_script::read_version_script,
+};
+
+#[allow(dead_code)]
+struct Args {
+    raw_args: &'static [&'static str],
+    args: &'static [&'static str],
+    idx: usize,
+    arg: &'static str,
+    // Whether a one-letter option may have its argument attached, as in
+    // -e<sym>. See parse_args.
+    short_joined: bool,
+}
+
+#[allow(dead_code)]
+impl Args {
+    fn new(args: Vec<String>) -> Result<Self, LinkError> {
+        let args: Vec<&'static str> = expand_response_files(args)?
+            .into_iter()
+            .map(|arg| &*Box::leak(arg.into_boxed_str()))
+            .collect();
+        let args = Box::leak(Box::new(args));
+
+        Ok(Self {
+            raw_args: args,
+            args: &args[1..],
+            idx: 0,
+            arg: "",
+            short_joined: false,
+        })
+    }
+}
+
+// Replaces every @file argument with the arguments in the file.
+fn expand_response_files(args: Vec<String>) -> Result<Vec<String>, LinkError> {
+    let mut expanded = vec![];
+    for arg in args {
+        match arg.strip_prefix('@') {
+            Some(path) => {
+                let contents = fs::read_to_string(path).map_err(|error| LinkError::Io {
+                    path: path.to_string(),
+                    error,
+                })?;
+                expanded.extend(expand_response_files(split_response_file(&contents))?);
+            }
+            None => expanded.push(arg),
+        }
+    }
+    Ok(expanded)
+}
+
+// Splits the contents of a response file into arguments. Arguments are
+// separated by whitespace. Quotes group characters into one argument and
+// a backslash escapes the next character.
+pub fn split_response_file(s: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut chars = s.chars();
+    let mut arg: Option<String> = None;
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => arg.get_or_insert_with(String::new).extend(chars.next()),
+            _ if Some(c) == quote => quote = None,
+            '"' | '\'' if quote.is_none() => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            }
+            _ if c.is_whitespace() && quote.is_none() => args.extend(arg.take()),
+            _ => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    args
+}
+
+// What a command line asks for. --help and --version only ask for a
+// message to be printed, which is left to the caller.
+#[allow(clippy::large_enum_variant)]
+pub enum ParsedArgs {
+    Link(LinkerConfig, Vec<LinkInput>),
+    Print(String),
+}
+
+// Parses a command line, starting with the program name, into the link
+// options and the inputs in command line order.
+pub fn parse_args(args: Vec<String>) -> Result<ParsedArgs, LinkError> {
+    let mut config = LinkerConfig::default();
+    let mut args = Args::new(args)?;
+
+    let dashes = |name: &str| -> Vec<String> {
+        if name.len() == 1 {
+            return vec!["-".to_string() + name];
+        }
+
+        return vec!["-".to_string() + &name, "--".to_string() + &name];
+    };
+
+    let read_arg = |name: &str, args: &mut Args| -> Result<bool, LinkError> {
+        for opt in dashes(name) {
+            if args.args[0] == &opt {
+                if args.args.len() == 1 {
+                    return Err(LinkError::BadArgument(format!(
+                        "option -{}: argument missing",
+                        name
+                    )));
+                }
+                args.arg = args.args[1];
+                args.args = &args.args[2..];
+                return Ok(true);
+            }
+            let mut prefix = String::from(&opt);
+            if name.len() > 1 {
+                prefix += "=";
+            } else if !args.short_joined {
+                continue;
+            }
+            if args.args[0].starts_with(&prefix) {
+                args.arg = &args.args[0][prefix.len()..];
+                args.args = &args.args[1..];
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    };
+
+    let read_flag = |name: &str, args: &mut Args| -> bool {
+        for opt in dashes(name) {
+            if args.args[0] == &opt {
+                args.args = &args.args[1..];
+                return true;
+            }
+        }
+        return false;
+    };
+
+    let mut inputs: Vec<LinkInput> = vec![];
+    while args.args.len() > 0 {
+        // Options are kept as given for --reproduce, except for those
+        // naming files, which it rewrites itself.
+        let rest = args.args;
+        let mut is_file_arg = false;
+
+        if read_flag("help", &mut args) {
+            let usage = format!("usage: {} [options] file...\n", args.raw_args[0]);
+            return Ok(ParsedArgs::Print(usage));
+        }
+        if read_arg("o", &mut args)? || read_arg("output", &mut args)? {
+            config.output = String::from(args.arg);
+        } else if read_flag("v", &mut args) || read_flag("version", &mut args) {
+            let version = format!("rvld {}\n", env!("CARGO_PKG_VERSION"));
+            return Ok(ParsedArgs::Print(version));
+        } else if read_arg("m", &mut args)? {
+            if args.arg == "elf64lriscv" {
+                config.emulation = MACHINE_TYPE_RISCV64;
+            } else if args.arg == "elf32lriscv" {
+                config.emulation = MACHINE_TYPE_RISCV32;
+            } else if args.arg == "elf_x86_64" {
+                config.emulation = MACHINE_TYPE_X86_64;
+            } else {
+                return Err(LinkError::BadArgument(format!(
+                    "unknown -m argument: {}",
+                    args.arg
+                )));
+            }
+        } else if read_arg("e", &mut args)? || read_arg("entry", &mut args)? {
+            config.entry = String::from(args.arg);
+        } else if read_arg("u", &mut args)? || read_arg("undefined", &mut args)? {
+            config.undefined.push(String::from(args.arg));
+        } else if read_arg("require-defined", &mut args)? {
+            config.require_defined.push(String::from(args.arg));
+        } else if read_arg("defsym", &mut args)? {
+            config.defsyms.push(parse_defsym(args.arg)?);
+        } else if read_arg("wrap", &mut args)? {
+            config.wrap.insert(String::from(args.arg));
+        } else if read_arg("version-script", &mut args)? {
+            let mut script = read_version_script(args.arg)?;
+            config.version_script.nodes.append(&mut script.nodes);
+            config.version_script_files.push(args.arg.to_string());
+            is_file_arg = true;
+        } else if read_flag("default-symver", &mut args) {
+            config.default_symver = true;
+        } else if read_arg("soname", &mut args)? {
+            config.soname = String::from(args.arg);
+        } else if read_flag("E", &mut args) || read_flag("export-dynamic", &mut args) {
+            config.export_dynamic = true;
+        } else if read_flag("static", &mut args) {
+            config.is_static = true;
+        } else if read_flag("s", &mut args) || read_flag("strip-all", &mut args) {
+            config.strip_all = true;
+            config.strip_debug = true;
+        } else if read_flag("S", &mut args) || read_flag("strip-debug", &mut args) {
+            config.strip_debug = true;
+        } else if read_flag("X", &mut args) || read_flag("discard-locals", &mut args) {
+            config.discard_locals = true;
+        } else if read_arg("compress-debug-sections", &mut args)? {
+            config.compress_debug_sections = match args.arg {
+                "none" => 0,
+                "zlib" | "zlib-gabi" => ELFCOMPRESS_ZLIB,
+                "zstd" => ELFCOMPRESS_ZSTD,
+                _ => {
+                    return Err(LinkError::BadArgument(format!(
+                        "invalid --compress-debug-sections argument: {}",
+                        args.arg
+                    )))
+                }
+            };
+        } else if read_flag("build-id", &mut args) {
+            config.build_id = BuildId::Sha1;
+        } else if read_flag("no-build-id", &mut args) {
+            config.build_id = BuildId::None;
+        } else if read_arg("build-id", &mut args)? {
+            config.build_id = parse_build_id(args.arg)?;
+        } else if read_arg("oformat", &mut args)? {
+            config.oformat = parse_output_format(args.arg)?;
+        } else if read_arg("gap-fill", &mut args)? {
+            config.gap_fill = parse_number(args.arg)
+                .and_then(|val| u8::try_from(val).ok())
+                .ok_or_else(|| {
+                    LinkError::BadArgument(format!("invalid --gap-fill argument: {}", args.arg))
+                })?;
+        } else if read_arg("error-limit", &mut args)? {
+            config.error_limit = parse_number(args.arg)
+                .and_then(|val| usize::try_from(val).ok())
+                .ok_or_else(|| {
+                    LinkError::BadArgument(format!("invalid --error-limit argument: {}", args.arg))
+                })?;
+        } else if read_arg("O", &mut args)? {
+            config.optimize = args.arg.parse().map_err(|_| {
+                LinkError::BadArgument(format!("invalid -O argument: {}", args.arg))
+            })?;
+        } else if read_arg("threads", &mut args)? {
+            config.threads = parse_number(args.arg)
+                .and_then(|val| usize::try_from(val).ok())
+                .filter(|val| *val > 0)
+                .ok_or_else(|| {
+                    LinkError::BadArgument(format!("invalid --threads argument: {}", args.arg))
+                })?;
+        } else if read_flag("no-threads", &mut args) {
+            config.threads = 1;
+        } else if read_flag("eh-frame-hdr", &mut args) {
+            config.eh_frame_hdr = true;
+        } else if read_flag("no-eh-frame-hdr", &mut args) {
+            config.eh_frame_hdr = false;
+        } else if read_flag("gc-sections", &mut args) {
+            config.gc_sections = true;
+        } else if read_flag("no-gc-sections", &mut args) {
+            config.gc_sections = false;
+        } else if read_arg("z", &mut args)? {
+            if let Some(vis) = args.arg.strip_prefix("start-stop-visibility=") {
+                config.z_start_stop_visibility = match vis {
+                    "default" => STV_DEFAULT,
+                    "internal" => STV_INTERNAL,
+                    "hidden" => STV_HIDDEN,
+                    "protected" => STV_PROTECTED,
+                    _ => {
+                        return Err(LinkError::BadArgument(format!(
+                            "-z start-stop-visibility: unknown value: {}",
+                            vis
+                        )))
+                    }
+                };
+            }
+        } else if read_arg("b", &mut args)? || read_arg("format", &mut args)? {
+            match args.arg {
+                "binary" | "elf64-littleriscv" | "elf32-littleriscv" | "elf64-x86-64"
+                | "default" => {
+                    inputs.push(LinkInput::Format(args.arg.to_string()));
+                    is_file_arg = true;
+                }
+                _ => {
+                    return Err(LinkError::BadArgument(format!(
+                        "unknown -b argument: {}",
+                        args.arg
+                    )))
+                }
+            }
+        } else if read_arg("L", &mut args)? {
+            config.library_paths.push("".to_string() + args.arg);
+            is_file_arg = true;
+        } else if read_arg("l", &mut args)? {
+            inputs.push(LinkInput::Library(args.arg.to_string()));
+            is_file_arg = true;
+        } else if read_arg("reproduce", &mut args)? {
+            config.reproduce = args.arg.to_string();
+            is_file_arg = true;
+        } else if read_arg("sysroot", &mut args)?
+            || read_arg("plugin", &mut args)?
+            || read_arg("plugin-opt", &mut args)?
+            || read_flag("as-needed", &mut args)
+            || read_flag("start-group", &mut args)
+            || read_flag("end-group", &mut args)
+            || read_arg("hash-style", &mut args)?
+            || read_flag("no-relax", &mut args)
+        {
+            // ignore
+        } else if read_arg("h", &mut args)? {
+            config.soname = String::from(args.arg);
+        } else {
+            if args.args[0].starts_with("-") {
+                // One-letter options with an attached argument are only
+                // tried once no other option matches, so that
+                // -export-dynamic is not -e xport-dynamic.
+                if !args.short_joined {
+                    args.short_joined = true;
+                    continue;
+                }
+                return Err(LinkError::BadArgument(format!(
+                    "unknown command line option: {}",
+                    args.args[0]
+                )));
+            }
+            inputs.push(LinkInput::from(args.args[0]));
+            args.args = &args.args[1..];
+            is_file_arg = true;
+        }
+
+        args.short_joined = false;
+        if !is_file_arg {
+            let consumed = &rest[..rest.len() - args.args.len()];
+            config
+                .options
+                .extend(consumed.iter().map(|arg| arg.to_string()));
+        }
+    }
+    Ok(ParsedArgs::Link(config, inputs))
+}
+
+#[test]
+fn test_parse_args() {
+    let args = [
+        "ld",
+        "-o",
+        "out",
+        "-melf32lriscv",
+        "-O2",
+        "a.o",
+        "-b",
+        "binary",
+        "data.bin",
+        "-lc",
+    ];
+    let args = args.iter().map(|s| s.to_string()).collect();
+    let Ok(ParsedArgs::Link(config, inputs)) = parse_args(args) else {
+        panic!("parse_args failed");
+    };
+    assert_eq!(config.output, "out");
+    assert_eq!(config.emulation, MACHINE_TYPE_RISCV32);
+    assert_eq!(config.optimize, 2);
+    assert_eq!(
+        inputs,
+        vec![
+            LinkInput::Path("a.o".to_string()),
+            LinkInput::Format("binary".to_string()),
+            LinkInput::Path("data.bin".to_string()),
+            LinkInput::Library("c".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_short_options() {
+    let parse = |args: &[&str]| {
+        let args = ["ld"].iter().chain(args).map(|s| s.to_string()).collect();
+        match parse_args(args) {
+            Ok(ParsedArgs::Link(config, _)) => config,
+            _ => panic!("parse_args failed"),
+        }
+    };
+
+    let config = parse(&["-export-dynamic", "-eh-frame-hdr", "-oformat=binary"]);
+    assert!(config.export_dynamic);
+    assert!(config.eh_frame_hdr);
+    assert_eq!(config.entry, "_start");
+    assert_eq!(config.output, "a.out");
+    assert_eq!(config.oformat, crate::output_format::OutputFormat::Binary);
+
+    assert_eq!(parse(&["-efoo"]).entry, "foo");
+    assert_eq!(parse(&["-e", "foo"]).entry, "foo");
+    assert_eq!(parse(&["-entry=foo"]).entry, "foo");
+    assert_eq!(parse(&["-oout", "-L.", "-lc"]).output, "out");
+}
+
+#[test]
+fn test_parse_args_errors() {
+    let parse = |args: &[&str]| {
+        let args = ["ld"].iter().chain(args).map(|s| s.to_string()).collect();
+        parse_args(args)
+    };
+
+    let Err(LinkError::BadArgument(msg)) = parse(&["--no-such-option"]) else {
+        panic!("--no-such-option was accepted");
+    };
+    assert_eq!(msg, "unknown command line option: --no-such-option");
+    assert!(matches!(parse(&["-o"]), Err(LinkError::BadArgument(_))));
+    assert!(matches!(
+        parse(&["--threads=0"]),
+        Err(LinkError::BadArgument(_))
+    ));
+    assert!(matches!(
+        parse(&["@/nonexistent"]),
+        Err(LinkError::Io { .. })
+    ));
+    assert!(matches!(parse(&["--version"]), Ok(ParsedArgs::Print(_))));
+}
@@ -0,0 +1,216 @@
+use crate::{
+    elf::{
+        Ehdr, Shdr, Sym, EI_CLASS, EI_DATA, EI_VERSION, ELFCLASS64, ELFDATA2LSB, ET_REL,
+        EV_CURRENT, SHF_ALLOC, SHF_WRITE, SHN_ABS, SHT_PROGBITS, SHT_STRTAB, SHT_SYMTAB,
+        STB_GLOBAL, STT_NOTYPE,
+    },
+    elf_class::ELFCLASS32,
+    file::ElfFile,
+    magic::write_magic,
+    target::Target,
+    utils::align_to,
+};
+
+// The symbol name prefix GNU ld derives from a file name: every character
+// that can't appear in a C identifier becomes an underscore.
+pub fn get_symbol_prefix(file_name: &str) -> String {
+    let mangled: String = file_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    String::from("_binary_") + &mangled
+}
+
+// -b binary: wraps the contents of a raw file in a relocatable object with
+// a single .data section and the _binary_<name>_start, _end and _size
+// symbols, so that the rest of the linker sees an ordinary object file.
+// The object has the machine and class of the output. Returns the
+// contents of the object.
+#[allow(dead_code)]
+pub fn create_binary_object_file(file: &ElfFile, target: &dyn Target) -> Vec<u8> {
+    let class = target.class();
+    let data = file.contents;
+    let prefix = get_symbol_prefix(file.name);
+
+    let mut strtab = vec![0u8];
+    let mut add_string = |s: &str| -> u32 {
+        let offset = strtab.len() as u32;
+        strtab.extend_from_slice(s.as_bytes());
+        strtab.push(0);
+        offset
+    };
+
+    let mut syms = vec![Sym::new()];
+    for (suffix, shndx, val) in [
+        ("_start", 1, 0),
+        ("_end", 1, data.len() as u64),
+        ("_size", SHN_ABS, data.len() as u64),
+    ] {
+        let mut sym = Sym::new();
+        sym.name = add_string(&(prefix.clone() + suffix));
+        sym.info = STB_GLOBAL << 4 | STT_NOTYPE;
+        sym.shndx = shndx;
+        sym.val = val;
+        syms.push(sym);
+    }
+
+    let shstrtab = b"\0.data\0.symtab\0.strtab\0.shstrtab\0";
+
+    let word_size = class.word_size() as u64;
+    let sym_size = class.sym_size();
+    let shdr_size = class.shdr_size();
+
+    let data_offset = class.ehdr_size() as u64;
+    let symtab_offset = align_to(data_offset + data.len() as u64, word_size);
+    let strtab_offset = symtab_offset + (syms.len() * sym_size) as u64;
+    let shstrtab_offset = strtab_offset + strtab.len() as u64;
+    let shdr_offset = align_to(shstrtab_offset + shstrtab.len() as u64, word_size);
+
+    let section = |name: u32, ty: u32, offset: u64, size: usize| -> Shdr {
+        let mut shdr = Shdr::new();
+        shdr.name = name;
+        shdr.shdr_type = ty;
+        shdr.offset = offset;
+        shdr.size = size as u64;
+        shdr.addr_align = 1;
+        shdr
+    };
+
+    let mut shdrs = vec![Shdr::new()];
+    let mut shdr = section(1, SHT_PROGBITS, data_offset, data.len());
+    shdr.flags = SHF_ALLOC | SHF_WRITE;
+    shdrs.push(shdr);
+    let mut shdr = section(7, SHT_SYMTAB, symtab_offset, syms.len() * sym_size);
+    shdr.link = 3;
+    shdr.info = 1;
+    shdr.addr_align = word_size;
+    shdr.ent_size = sym_size as u64;
+    shdrs.push(shdr);
+    shdrs.push(section(15, SHT_STRTAB, strtab_offset, strtab.len()));
+    shdrs.push(section(23, SHT_STRTAB, shstrtab_offset, shstrtab.len()));
+
+    let mut ehdr = Ehdr::new();
+    write_magic(&mut ehdr.ident);
+    ehdr.ident[EI_CLASS as usize] = if class.is_32() {
+        ELFCLASS32
+    } else {
+        ELFCLASS64
+    };
+    ehdr.ident[EI_DATA as usize] = ELFDATA2LSB;
+    ehdr.ident[EI_VERSION as usize] = EV_CURRENT as u8;
+    ehdr.hdr_type = ET_REL;
+    ehdr.machine = target.e_machine();
+    ehdr.version = EV_CURRENT;
+    ehdr.sh_off = shdr_offset;
+    ehdr.eh_size = class.ehdr_size() as u16;
+    ehdr.sh_ent_size = shdr_size as u16;
+    ehdr.sh_num = shdrs.len() as u16;
+    ehdr.sh_strndx = 4;
+
+    let mut buf = vec![0u8; shdr_offset as usize + shdrs.len() * shdr_size];
+    class.write_ehdr(&mut buf, ehdr);
+    buf[data_offset as usize..data_offset as usize + data.len()].copy_from_slice(data);
+    for (i, sym) in syms.iter().enumerate() {
+        let offset = symtab_offset as usize + i * sym_size;
+        class.write_sym(&mut buf[offset..], *sym);
+    }
+    buf[strtab_offset as usize..strtab_offset as usize + strtab.len()].copy_from_slice(&strtab);
+    buf[shstrtab_offset as usize..shstrtab_offset as usize + shstrtab.len()]
+        .copy_from_slice(shstrtab);
+    for (i, shdr) in shdrs.iter().enumerate() {
+        let offset = shdr_offset as usize + i * shdr_size;
+        class.write_shdr(&mut buf[offset..], *shdr);
+    }
+
+    buf
+}
+
+#[test]
+fn test_create_binary_object_file() {
+    use crate::{
+        arena::{FileId, Id},
+        elf::elf_get_name,
+        file_type::{get_file_type, FILE_TYPE_OBJECT},
+        machine_type::{get_machine_type_from_contents, MACHINE_TYPE_RISCV64},
+        object_file::new_object_file,
+        riscv::RISCV64,
+    };
+
+    let file = ElfFile {
+        name: "fonts/a-b.bin",
+        contents: &[1, 2, 3],
+    };
+    let contents = create_binary_object_file(&file, &RISCV64);
+    let file = ElfFile {
+        name: file.name,
+        contents: &contents,
+    };
+    assert_eq!(get_file_type(file.contents), FILE_TYPE_OBJECT);
+    assert_eq!(
+        get_machine_type_from_contents(file.contents),
+        MACHINE_TYPE_RISCV64
+    );
+
+    let mut obj = new_object_file(FileId::from_index(0), file, true).unwrap();
+    obj.parse().unwrap();
+    let input_file = &obj.input_file;
+    let shdr = input_file.elf_sections[1];
+    assert_eq!(
+        elf_get_name(input_file.sh_strtab.unwrap(), shdr.name).unwrap(),
+        ".data"
+    );
+    assert_eq!(input_file.get_bytes_from_shdr(&shdr).unwrap(), [1, 2, 3]);
+
+    let strtab = input_file.symbol_strtab.unwrap();
+    let names: Vec<(&str, u64)> = input_file.elf_syms[1..]
+        .iter()
+        .map(|sym| (elf_get_name(strtab, sym.name).unwrap(), sym.val))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            ("_binary_fonts_a_b_bin_start", 0),
+            ("_binary_fonts_a_b_bin_end", 3),
+            ("_binary_fonts_a_b_bin_size", 3)
+        ]
+    );
+}
+
+#[test]
+fn test_create_binary_object_file_elf32() {
+    use crate::{
+        arena::{FileId, Id},
+        machine_type::{get_machine_type_from_contents, MACHINE_TYPE_RISCV32},
+        object_file::new_object_file,
+        riscv::RISCV32,
+    };
+
+    let file = ElfFile {
+        name: "a.bin",
+        contents: &[1, 2, 3, 4, 5],
+    };
+    let contents = create_binary_object_file(&file, &RISCV32);
+    let file = ElfFile {
+        name: file.name,
+        contents: &contents,
+    };
+    assert_eq!(
+        get_machine_type_from_contents(file.contents),
+        MACHINE_TYPE_RISCV32
+    );
+
+    let mut obj = new_object_file(FileId::from_index(0), file, true).unwrap();
+    obj.parse().unwrap();
+    let input_file = &obj.input_file;
+    assert_eq!(
+        input_file
+            .get_bytes_from_shdr(&input_file.elf_sections[1])
+            .unwrap(),
+        [1, 2, 3, 4, 5]
+    );
+    let syms: Vec<(u16, u64)> = input_file.elf_syms[1..]
+        .iter()
+        .map(|sym| (sym.shndx, sym.val))
+        .collect();
+    assert_eq!(syms, [(1, 0), (1, 5), (SHN_ABS, 5)]);
+}
@@ -0,0 +1,178 @@
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    chunk::Chunk,
+    context::Context,
+    elf::{Nhdr, NHDR_SIZE, NT_GNU_BUILD_ID, SHF_ALLOC, SHT_NOTE},
+    error::LinkError,
+    parallel::par_map,
+    utils::write,
+};
+
+// Hashes are computed over fixed-size shards so that the result does not
+// depend on how many threads were used.
+const SHARD_SIZE: usize = 1 << 20;
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuildId {
+    None,
+    Fast,
+    Md5,
+    Sha1,
+    Uuid,
+    Hex(Vec<u8>),
+}
+
+#[allow(dead_code)]
+impl BuildId {
+    pub fn size(&self) -> usize {
+        match self {
+            BuildId::None => 0,
+            BuildId::Fast => 8,
+            BuildId::Md5 | BuildId::Uuid => 16,
+            BuildId::Sha1 => 20,
+            BuildId::Hex(bytes) => bytes.len(),
+        }
+    }
+}
+
+// --build-id=fast|md5|sha1|uuid|0x<hex>|none. A bare --build-id is sha1,
+// as with GNU ld.
+#[allow(dead_code)]
+pub fn parse_build_id(arg: &str) -> Result<BuildId, LinkError> {
+    let invalid = || LinkError::BadArgument(format!("invalid --build-id argument: {}", arg));
+    let hex = match arg {
+        "none" => return Ok(BuildId::None),
+        "fast" => return Ok(BuildId::Fast),
+        "md5" => return Ok(BuildId::Md5),
+        "sha1" | "tree" => return Ok(BuildId::Sha1),
+        "uuid" => return Ok(BuildId::Uuid),
+        _ => arg
+            .strip_prefix("0x")
+            .or_else(|| arg.strip_prefix("0X"))
+            .ok_or_else(invalid)?,
+    };
+
+    if hex.is_empty() || hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    Ok(BuildId::Hex(bytes))
+}
+
+// Hashes the shards of `buf` on several threads, then hashes their
+// concatenated digests.
+fn tree_hash(buf: &[u8], num_threads: usize, hash: impl Fn(&[u8]) -> Vec<u8> + Sync) -> Vec<u8> {
+    let shards: Vec<&[u8]> = buf.chunks(SHARD_SIZE).collect();
+    let digests = par_map(num_threads, &shards, |shard| hash(shard));
+    hash(&digests.concat())
+}
+
+fn fast_hash(buf: &[u8]) -> Vec<u8> {
+    xxh3_64(buf).to_le_bytes().to_vec()
+}
+
+fn random_uuid() -> Result<Vec<u8>, LinkError> {
+    let mut bytes = vec![0; 16];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|err| LinkError::Other(format!("--build-id=uuid: {}", err)))?;
+
+    // RFC 4122 version 4, variant 1.
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    Ok(bytes)
+}
+
+// `.note.gnu.build-id` identifies the output file. Its descriptor is left
+// zero by copy_buf and filled in by write_build_id once the rest of the
+// image is in the buffer, so that a hash covers the whole file.
+#[allow(dead_code)]
+pub struct BuildIdSection {
+    pub chunk: Chunk,
+}
+
+#[allow(dead_code)]
+impl BuildIdSection {
+    pub fn new(build_id: &BuildId) -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".note.gnu.build-id".to_string();
+        chunk.shdr.shdr_type = SHT_NOTE;
+        chunk.shdr.flags = SHF_ALLOC;
+        chunk.shdr.addr_align = 4;
+        chunk.shdr.size = (NHDR_SIZE + 4 + build_id.size()) as u64;
+
+        Self { chunk }
+    }
+
+    pub fn copy_buf(&self, ctx: &Context, buf: &mut [u8]) {
+        let shdr = self.chunk.shdr;
+        let buf = &mut buf[shdr.offset as usize..(shdr.offset + shdr.size) as usize];
+        buf.fill(0);
+
+        let nhdr = Nhdr {
+            name_size: 4,
+            desc_size: ctx.args.build_id.size() as u32,
+            ty: NT_GNU_BUILD_ID,
+        };
+        write(&mut buf[..NHDR_SIZE], nhdr);
+        buf[NHDR_SIZE..NHDR_SIZE + 4].copy_from_slice(b"GNU\0");
+    }
+
+    pub fn write_build_id(&self, ctx: &Context, buf: &mut [u8]) -> Result<(), LinkError> {
+        let digest = match &ctx.args.build_id {
+            BuildId::None => return Ok(()),
+            BuildId::Fast => tree_hash(buf, ctx.num_threads(), fast_hash),
+            BuildId::Md5 => tree_hash(buf, ctx.num_threads(), |b| Md5::digest(b).to_vec()),
+            BuildId::Sha1 => tree_hash(buf, ctx.num_threads(), |b| Sha1::digest(b).to_vec()),
+            BuildId::Uuid => random_uuid()?,
+            BuildId::Hex(bytes) => bytes.clone(),
+        };
+
+        let offset = self.chunk.shdr.offset as usize + NHDR_SIZE + 4;
+        buf[offset..offset + digest.len()].copy_from_slice(&digest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_build_id() {
+        assert_eq!(parse_build_id("fast").unwrap(), BuildId::Fast);
+        assert_eq!(parse_build_id("sha1").unwrap(), BuildId::Sha1);
+        assert_eq!(
+            parse_build_id("0xdeadBEEF").unwrap(),
+            BuildId::Hex(vec![0xde, 0xad, 0xbe, 0xef])
+        );
+        assert_eq!(parse_build_id("0xdeadBEEF").unwrap().size(), 4);
+        for arg in ["sha256", "0x", "0xabc", "0xzz", "0xa\u{e9}b"] {
+            assert!(matches!(
+                parse_build_id(arg),
+                Err(LinkError::BadArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_tree_hash() {
+        let buf: Vec<u8> = (0..3 * SHARD_SIZE + 5).map(|i| i as u8).collect();
+        let mut expected = vec![];
+        for shard in buf.chunks(SHARD_SIZE) {
+            expected.extend_from_slice(&xxh3_64(shard).to_le_bytes());
+        }
+        for num_threads in [1, 2, 8] {
+            assert_eq!(
+                tree_hash(&buf, num_threads, fast_hash),
+                xxh3_64(&expected).to_le_bytes()
+            );
+        }
+    }
+}
@@ -1,4 +1,37 @@
-use crate::{context::Context, elf::Shdr};
+use crate::{
+    arena::{MergedSectionId, OutputSectionId},
+    context::Context,
+    elf::Shdr,
+};
+
+// Names a chunk of the output file. Every chunk is owned by the section
+// it belongs to, and the sections are owned by the context; see
+// `Context::chunk`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkId {
+    Ehdr,
+    Phdr,
+    Shdr,
+    Got,
+    Dynamic,
+    Dynsym,
+    Dynstr,
+    Versym,
+    Verneed,
+    Verdef,
+    EhFrame,
+    EhFrameHdr,
+    Symtab,
+    SymtabShndx,
+    Strtab,
+    Shstrtab,
+    BuildId,
+    RiscvAttributes,
+    Output(OutputSectionId),
+    Merged(MergedSectionId),
+    // An index into `Context::compressed_sections`.
+    Compressed(usize),
+}
 
 #[allow(dead_code)]
 #[derive(PartialEq, PartialOrd)]
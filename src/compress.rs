@@ -0,0 +1,75 @@
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{
+    elf::{ELFCOMPRESS_ZLIB, ELFCOMPRESS_ZSTD},
+    error::LinkError,
+};
+
+// The most a stream can expand: 1032:1 for deflate, and about 32768:1 for
+// zstd, whose smallest block repeats one byte up to 128 KiB. A larger size
+// in a header is corrupted, and is rejected before anything is allocated.
+const MAX_ZLIB_RATIO: u64 = 1032;
+const MAX_ZSTD_RATIO: u64 = 32768;
+
+fn check_size(data: &[u8], size: u64, max_ratio: u64) -> Option<usize> {
+    if size > (data.len() as u64).saturating_mul(max_ratio) {
+        return None;
+    }
+    usize::try_from(size).ok()
+}
+
+// Decompresses a zlib stream whose uncompressed size is known from the
+// section's compression header. Returns None if the stream is corrupted.
+#[allow(dead_code)]
+pub fn uncompress_zlib(data: &[u8], size: u64) -> Option<Vec<u8>> {
+    let size = check_size(data, size, MAX_ZLIB_RATIO)?;
+    let mut out = Vec::with_capacity(size);
+    // A stream that is longer than the header says is read no further.
+    let mut dec = ZlibDecoder::new(data).take(size as u64 + 1);
+    if dec.read_to_end(&mut out).is_err() || out.len() != size {
+        return None;
+    }
+    Some(out)
+}
+
+#[allow(dead_code)]
+pub fn uncompress_zstd(data: &[u8], size: u64) -> Option<Vec<u8>> {
+    let size = check_size(data, size, MAX_ZSTD_RATIO)?;
+    match zstd::bulk::decompress(data, size) {
+        Ok(out) if out.len() == size => Some(out),
+        _ => None,
+    }
+}
+
+// Compresses `data` with the given ELFCOMPRESS_* algorithm.
+#[allow(dead_code)]
+pub fn compress(ty: u32, data: &[u8]) -> Result<Vec<u8>, LinkError> {
+    let failed = |what: &str| LinkError::Other(format!("{} compression failed", what));
+    match ty {
+        ELFCOMPRESS_ZLIB => {
+            let mut enc = ZlibEncoder::new(vec![], Compression::default());
+            enc.write_all(data).map_err(|_| failed("zlib"))?;
+            enc.finish().map_err(|_| failed("zlib"))
+        }
+        ELFCOMPRESS_ZSTD => zstd::bulk::compress(data, 0).map_err(|_| failed("zstd")),
+        _ => Err(LinkError::Other(format!("unknown compression type: {}", ty))),
+    }
+}
+
+#[test]
+fn test_compress() {
+    let data = b"hello hello hello hello".to_vec();
+    let size = data.len() as u64;
+
+    let compressed = compress(ELFCOMPRESS_ZLIB, &data).unwrap();
+    assert_eq!(uncompress_zlib(&compressed, size), Some(data.clone()));
+    assert_eq!(uncompress_zlib(&compressed[1..], size), None);
+    assert_eq!(uncompress_zlib(&compressed, size - 1), None);
+    assert_eq!(uncompress_zlib(&compressed, u64::MAX), None);
+
+    let compressed = compress(ELFCOMPRESS_ZSTD, &data).unwrap();
+    assert_eq!(uncompress_zstd(&compressed, size), Some(data));
+    assert_eq!(uncompress_zstd(&compressed, u64::MAX), None);
+}
@@ -0,0 +1,44 @@
+use crate::{
+    chunk::Chunk,
+    elf::{Chdr, SHF_COMPRESSED},
+    elf_class::ElfClass,
+};
+
+// Replaces an output debug section with its compressed contents, preceded
+// by an Elf32_Chdr or Elf64_Chdr describing the uncompressed data.
+#[allow(dead_code)]
+pub struct CompressedSection {
+    pub chunk: Chunk,
+    pub contents: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl CompressedSection {
+    pub fn new(original: &Chunk, class: ElfClass, ch_type: u32, compressed: Vec<u8>) -> Self {
+        let chdr = Chdr {
+            ch_type,
+            reserved: 0,
+            size: original.shdr.size,
+            addr_align: original.shdr.addr_align,
+        };
+
+        let mut contents = vec![0; class.chdr_size()];
+        class.write_chdr(&mut contents[..], chdr);
+        contents.extend_from_slice(&compressed);
+
+        let mut chunk = Chunk::new();
+        chunk.name = original.name.clone();
+        chunk.shdr = original.shdr;
+        chunk.shndx = original.shndx;
+        chunk.shdr.flags |= SHF_COMPRESSED;
+        chunk.shdr.size = contents.len() as u64;
+        chunk.shdr.addr_align = class.word_size() as u64;
+
+        Self { chunk, contents }
+    }
+
+    pub fn copy_buf(&self, buf: &mut [u8]) {
+        let offset = self.chunk.shdr.offset as usize;
+        buf[offset..offset + self.contents.len()].copy_from_slice(&self.contents);
+    }
+}
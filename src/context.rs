@@ -1,108 +1,324 @@
-use std::collections::HashMap;
+use std::sync::Mutex;
 
 use crate::{
-    chunk::Chunk,
-    elf::EF_RISCV_RVC,
+    arena::{
+        Arena, DsoId, FileId, FragmentId, MergedSectionId, OutputSectionId, SectionId, SymbolId,
+    },
+    build_id_section::BuildIdSection,
+    chunk::{Chunk, ChunkId},
+    compressed_section::CompressedSection,
+    dynamic_section::DynamicSection,
+    dynsym_section::{DynstrSection, DynsymSection},
+    eh_frame_hdr_section::EhFrameHdrSection,
+    eh_frame_section::EhFrameSection,
+    elf::VER_NDX_GLOBAL,
+    elf_class::ElfClass,
+    error::LinkError,
     got_section::GotSection,
-    machine_type::{MachineType, MACHINE_TYPE_NONE},
+    input_section::InputSection,
+    linker::LinkerConfig,
     merged_section::MergedSection,
     object_file::ObjectFile,
     output_ehdr::OutputEhdr,
+    output_file::OutputBuffer,
     output_phdr::OutputPhdr,
     output_section::OutputSection,
     output_shdr::OutputShdr,
+    parallel::get_num_threads,
+    riscv::RISCV64,
+    riscv_attributes_section::RiscvAttributesSection,
+    section_fragment::SectionFragment,
+    shared_file::SharedFile,
+    strtab_section::StrtabSection,
     symbol::Symbol,
+    symbol_map::SymbolMap,
+    symtab_section::SymtabSection,
+    symtab_shndx_section::SymtabShndxSection,
+    target::{get_target, Target},
+    utils::warn,
+    verdef_section::VerdefSection,
+    verneed_section::VerneedSection,
+    versym_section::VersymSection,
 };
 
-#[allow(dead_code)]
-pub struct ContextArgs {
-    pub output: String,
-    pub emulation: MachineType,
-    pub library_paths: Vec<String>,
-}
-
 #[allow(dead_code)]
 pub struct Context<'a> {
-    pub args: ContextArgs,
-    pub buf: Vec<u8>,
+    pub args: LinkerConfig,
+    pub buf: OutputBuffer,
 
     pub ehdr: OutputEhdr,
     pub shdr: OutputShdr,
     pub phdr: OutputPhdr,
-    pub got: GotSection<'a>,
+    pub got: GotSection,
+    pub dynamic: Option<DynamicSection>,
+    pub dynsym: Option<DynsymSection>,
+    pub dynstr: Option<DynstrSection>,
+    pub versym: Option<VersymSection>,
+    pub verneed: Option<VerneedSection>,
+    pub verdef: Option<VerdefSection>,
+    pub eh_frame: Option<EhFrameSection>,
+    pub eh_frame_hdr: Option<EhFrameHdrSection>,
+    pub symtab: Option<SymtabSection>,
+    pub symtab_shndx: Option<SymtabShndxSection>,
+    pub strtab: Option<StrtabSection>,
+    pub shstrtab: Option<StrtabSection>,
+    pub compressed_sections: Vec<CompressedSection>,
+    pub build_id: Option<BuildIdSection>,
+    pub riscv_attributes: Option<RiscvAttributesSection>,
 
     pub tp_addr: u64,
-    pub output_sections: *mut Vec<*mut OutputSection<'a>>,
+    pub output_sections: Arena<OutputSectionId, OutputSection>,
 
-    pub objs: Vec<*mut ObjectFile<'a>>,
-    pub chunks: Option<*mut Vec<*mut Chunk>>,
-    pub symbol_map: HashMap<&'static str, *mut Symbol<'a>>,
-    pub merged_sections: Vec<*mut MergedSection>,
+    // Every object file read, and the ones that are part of the link.
+    // Archive members that no one refers to are dropped from `objs` by
+    // resolve_symbols.
+    pub files: Arena<FileId, ObjectFile<'a>>,
+    pub objs: Vec<FileId>,
+    pub dsos: Arena<DsoId, SharedFile<'a>>,
+    pub sections: Arena<SectionId, InputSection<'a>>,
+    pub symbols: Arena<SymbolId, Symbol>,
+    // The chunks of the output file, in file order once they are sorted.
+    pub chunks: Vec<ChunkId>,
+    pub symbol_map: SymbolMap,
+    pub merged_sections: Arena<MergedSectionId, MergedSection>,
+    pub fragments: Arena<FragmentId, SectionFragment>,
+
+    pub version_definitions: Vec<String>,
+    pub default_version: u16,
+    pub e_flags: u32,
+
+    // Errors reported by the passes so far.
+    pub errors: Mutex<Vec<LinkError>>,
 }
 
 #[allow(dead_code)]
 impl<'a> Context<'a> {
     pub fn new() -> Self {
         Context {
-            args: ContextArgs {
-                output: "a.out".to_string(),
-                emulation: MACHINE_TYPE_NONE,
-                library_paths: vec![],
-            },
-            buf: vec![],
+            args: LinkerConfig::default(),
+            buf: OutputBuffer::default(),
 
             ehdr: OutputEhdr::new(),
             shdr: OutputShdr::new(),
             phdr: OutputPhdr::new(),
             got: GotSection::new(),
+            dynamic: None,
+            dynsym: None,
+            dynstr: None,
+            versym: None,
+            verneed: None,
+            verdef: None,
+            eh_frame: None,
+            eh_frame_hdr: None,
+            symtab: None,
+            symtab_shndx: None,
+            strtab: None,
+            shstrtab: None,
+            compressed_sections: vec![],
+            build_id: None,
+            riscv_attributes: None,
 
             tp_addr: 0,
-            output_sections: Box::leak(Box::new(vec![])),
+            output_sections: Arena::new(),
 
+            files: Arena::new(),
             objs: vec![],
-            chunks: None,
-            symbol_map: HashMap::new(),
-            merged_sections: vec![],
+            dsos: Arena::new(),
+            sections: Arena::new(),
+            symbols: Arena::new(),
+            chunks: vec![],
+            symbol_map: SymbolMap::new(),
+            merged_sections: Arena::new(),
+            fragments: Arena::new(),
+
+            version_definitions: vec![],
+            default_version: VER_NDX_GLOBAL,
+            e_flags: 0,
+
+            errors: Mutex::new(vec![]),
+        }
+    }
+
+    // Records an error and lets the caller go on to find more. Once
+    // --error-limit errors have been recorded, returns all of them so that
+    // the caller stops.
+    pub fn error(&self, err: LinkError) -> Result<(), LinkError> {
+        let mut errors = self.errors.lock().unwrap();
+        errors.push(err);
+        if self.args.error_limit != 0 && errors.len() >= self.args.error_limit {
+            return Err(LinkError::Multiple {
+                errors: std::mem::take(&mut errors),
+                truncated: true,
+            });
+        }
+        Ok(())
+    }
+
+    // Fails with the errors recorded so far, if any. Called between passes.
+    pub fn check_errors(&self) -> Result<(), LinkError> {
+        let mut errors = std::mem::take(&mut *self.errors.lock().unwrap());
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(LinkError::Multiple {
+                errors,
+                truncated: false,
+            }),
+        }
+    }
+
+    pub fn get_soname(&self) -> String {
+        if !self.args.soname.is_empty() {
+            return self.args.soname.clone();
+        }
+        let output = &self.args.output;
+        String::from(&output[output.rfind('/').map_or(0, |i| i + 1)..])
+    }
+
+    // Symbols that must be kept regardless of whether any input file
+    // refers to them: the entry point, -u, --require-defined and the
+    // operands of --defsym.
+    pub fn get_root_symbols(&self) -> Vec<SymbolId> {
+        let mut names: Vec<&str> = vec![self.args.entry.as_str()];
+        for name in &self.args.undefined {
+            names.push(name);
+        }
+        for name in &self.args.require_defined {
+            names.push(name);
+        }
+        for defsym in &self.args.defsyms {
+            names.append(&mut defsym.get_symbol_names());
+        }
+
+        let mut syms = vec![];
+        for name in names {
+            if let Some(sym) = self.symbol_map.get(name) {
+                syms.push(*sym);
+            }
         }
+        syms
     }
 
     pub fn get_entry_addr(&self) -> u64 {
-        for osec in unsafe { self.output_sections.as_ref().unwrap() } {
-            if unsafe { &osec.as_ref().unwrap().chunk.as_ref().unwrap().name } == ".text" {
-                return unsafe { osec.as_ref().unwrap().chunk.as_ref().unwrap().shdr.addr };
+        if let Some(sym) = self.symbol_map.get(self.args.entry.as_str()) {
+            let sym = &self.symbols[*sym];
+            if sym.is_defined() {
+                return sym.get_addr(self);
+            }
+        }
+
+        warn(&format!(
+            "cannot find entry symbol {}; defaulting to .text",
+            self.args.entry
+        ));
+        for osec in self.output_sections.values() {
+            if osec.chunk.name == ".text" {
+                return osec.chunk.shdr.addr;
             }
         }
         return 0;
     }
 
-    pub fn get_flags(&self) -> u32 {
-        assert!(self.objs.len() > 0);
-        let mut flags = unsafe {
-            self.objs[0]
-                .as_ref()
-                .unwrap()
-                .input_file
-                .as_ref()
-                .unwrap()
-                .get_ehdr()
-                .flags
-        };
-        for obj in &self.objs[1..] {
-            if unsafe {
-                obj.as_ref()
-                    .unwrap()
-                    .input_file
-                    .as_ref()
-                    .unwrap()
-                    .get_ehdr()
-                    .flags
-            } & EF_RISCV_RVC
-                != 0
-            {
-                flags |= EF_RISCV_RVC;
-                break;
-            }
+    pub fn chunk(&self, id: ChunkId) -> &Chunk {
+        match id {
+            ChunkId::Ehdr => &self.ehdr.chunk,
+            ChunkId::Phdr => &self.phdr.chunk,
+            ChunkId::Shdr => &self.shdr.chunk,
+            ChunkId::Got => &self.got.chunk,
+            ChunkId::Dynamic => &self.dynamic.as_ref().unwrap().chunk,
+            ChunkId::Dynsym => &self.dynsym.as_ref().unwrap().chunk,
+            ChunkId::Dynstr => &self.dynstr.as_ref().unwrap().chunk,
+            ChunkId::Versym => &self.versym.as_ref().unwrap().chunk,
+            ChunkId::Verneed => &self.verneed.as_ref().unwrap().chunk,
+            ChunkId::Verdef => &self.verdef.as_ref().unwrap().chunk,
+            ChunkId::EhFrame => &self.eh_frame.as_ref().unwrap().chunk,
+            ChunkId::EhFrameHdr => &self.eh_frame_hdr.as_ref().unwrap().chunk,
+            ChunkId::Symtab => &self.symtab.as_ref().unwrap().chunk,
+            ChunkId::SymtabShndx => &self.symtab_shndx.as_ref().unwrap().chunk,
+            ChunkId::Strtab => &self.strtab.as_ref().unwrap().chunk,
+            ChunkId::Shstrtab => &self.shstrtab.as_ref().unwrap().chunk,
+            ChunkId::BuildId => &self.build_id.as_ref().unwrap().chunk,
+            ChunkId::RiscvAttributes => &self.riscv_attributes.as_ref().unwrap().chunk,
+            ChunkId::Output(id) => &self.output_sections[id].chunk,
+            ChunkId::Merged(id) => &self.merged_sections[id].chunk,
+            ChunkId::Compressed(i) => &self.compressed_sections[i].chunk,
+        }
+    }
+
+    pub fn chunk_mut(&mut self, id: ChunkId) -> &mut Chunk {
+        match id {
+            ChunkId::Ehdr => &mut self.ehdr.chunk,
+            ChunkId::Phdr => &mut self.phdr.chunk,
+            ChunkId::Shdr => &mut self.shdr.chunk,
+            ChunkId::Got => &mut self.got.chunk,
+            ChunkId::Dynamic => &mut self.dynamic.as_mut().unwrap().chunk,
+            ChunkId::Dynsym => &mut self.dynsym.as_mut().unwrap().chunk,
+            ChunkId::Dynstr => &mut self.dynstr.as_mut().unwrap().chunk,
+            ChunkId::Versym => &mut self.versym.as_mut().unwrap().chunk,
+            ChunkId::Verneed => &mut self.verneed.as_mut().unwrap().chunk,
+            ChunkId::Verdef => &mut self.verdef.as_mut().unwrap().chunk,
+            ChunkId::EhFrame => &mut self.eh_frame.as_mut().unwrap().chunk,
+            ChunkId::EhFrameHdr => &mut self.eh_frame_hdr.as_mut().unwrap().chunk,
+            ChunkId::Symtab => &mut self.symtab.as_mut().unwrap().chunk,
+            ChunkId::SymtabShndx => &mut self.symtab_shndx.as_mut().unwrap().chunk,
+            ChunkId::Strtab => &mut self.strtab.as_mut().unwrap().chunk,
+            ChunkId::Shstrtab => &mut self.shstrtab.as_mut().unwrap().chunk,
+            ChunkId::BuildId => &mut self.build_id.as_mut().unwrap().chunk,
+            ChunkId::RiscvAttributes => &mut self.riscv_attributes.as_mut().unwrap().chunk,
+            ChunkId::Output(id) => &mut self.output_sections[id].chunk,
+            ChunkId::Merged(id) => &mut self.merged_sections[id].chunk,
+            ChunkId::Compressed(i) => &mut self.compressed_sections[i].chunk,
+        }
+    }
+
+    // The target of the emulation. RV64 is assumed until the emulation
+    // is known.
+    pub fn target(&self) -> &'static dyn Target {
+        get_target(self.args.emulation).unwrap_or(&RISCV64)
+    }
+
+    pub fn num_threads(&self) -> usize {
+        get_num_threads(self.args.threads)
+    }
+
+    pub fn elf_class(&self) -> ElfClass {
+        self.target().class()
+    }
+
+    // The ELF header flags of the output, merged by the target. Raw
+    // `-b binary` inputs have no flags of their own.
+    pub fn get_flags(&self) -> Result<u32, LinkError> {
+        let objs: Vec<&ObjectFile<'a>> = self
+            .objs
+            .iter()
+            .map(|id| &self.files[*id])
+            .filter(|file| !file.is_binary)
+            .collect();
+        self.target().merge_flags(&objs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_limit() {
+        let mut ctx = Context::new();
+        ctx.args.error_limit = 2;
+
+        assert!(ctx.error(LinkError::Other("a".into())).is_ok());
+        let err = ctx.error(LinkError::Other("b".into())).unwrap_err();
+        assert!(matches!(err, LinkError::Multiple { truncated: true, .. }));
+        assert_eq!(err.errors().len(), 2);
+        assert!(ctx.check_errors().is_ok());
+
+        ctx.args.error_limit = 0;
+        for _ in 0..3 {
+            ctx.error(LinkError::UnknownEmulation).unwrap();
         }
-        return flags;
+        let err = ctx.check_errors().unwrap_err();
+        assert!(matches!(err, LinkError::Multiple { truncated: false, .. }));
+        assert_eq!(err.errors().len(), 3);
     }
 }
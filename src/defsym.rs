@@ -0,0 +1,130 @@
+use crate::{context::Context, error::LinkError, utils::parse_number};
+
+#[allow(dead_code)]
+#[derive(Debug, PartialEq)]
+pub enum DefsymTerm {
+    Const(u64),
+    Symbol(String),
+}
+
+// --defsym=sym=expr, where expr is a sequence of symbols and constants
+// joined by `+` and `-`.
+#[allow(dead_code)]
+#[derive(Debug, PartialEq)]
+pub struct Defsym {
+    pub name: String,
+    pub terms: Vec<(bool, DefsymTerm)>,
+}
+
+#[allow(dead_code)]
+impl Defsym {
+    pub fn get_symbol_names(&self) -> Vec<&str> {
+        let mut names = vec![];
+        for (_, term) in &self.terms {
+            if let DefsymTerm::Symbol(name) = term {
+                names.push(name.as_str());
+            }
+        }
+        names
+    }
+
+    pub fn get_value(&self, ctx: &Context) -> Result<u64, LinkError> {
+        let mut val = 0u64;
+        for (negate, term) in &self.terms {
+            let v = match term {
+                DefsymTerm::Const(c) => *c,
+                DefsymTerm::Symbol(name) => {
+                    let sym = ctx
+                        .symbol_map
+                        .get(name.as_str())
+                        .map(|sym| &ctx.symbols[*sym]);
+                    if sym.is_none() || !sym.unwrap().is_defined() {
+                        return Err(LinkError::UndefinedSymbol {
+                            name: name.clone(),
+                            referenced_by: format!("--defsym {}", self.name),
+                        });
+                    }
+                    sym.unwrap().get_addr(ctx)
+                }
+            };
+            if *negate {
+                val = val.wrapping_sub(v);
+            } else {
+                val = val.wrapping_add(v);
+            }
+        }
+        Ok(val)
+    }
+}
+
+#[allow(dead_code)]
+pub fn parse_defsym(arg: &str) -> Result<Defsym, LinkError> {
+    let syntax_error = || LinkError::BadArgument(format!("--defsym: syntax error: {}", arg));
+    let pos = arg.find('=');
+    if pos.is_none() || arg[..pos.unwrap()].trim().is_empty() {
+        return Err(syntax_error());
+    }
+    let name = arg[..pos.unwrap()].trim();
+    let mut expr = arg[pos.unwrap() + 1..].trim_start();
+
+    let mut terms = vec![];
+    let mut negate = false;
+    loop {
+        let end = expr
+            .find(|c: char| c == '+' || c == '-' || c.is_whitespace())
+            .unwrap_or(expr.len());
+        let tok = &expr[..end];
+        if tok.is_empty() {
+            return Err(syntax_error());
+        }
+
+        if tok.starts_with(|c: char| c.is_ascii_digit()) {
+            match parse_number(tok) {
+                Some(val) => terms.push((negate, DefsymTerm::Const(val))),
+                None => {
+                    return Err(LinkError::BadArgument(format!(
+                        "--defsym: bad number: {}",
+                        tok
+                    )))
+                }
+            }
+        } else {
+            terms.push((negate, DefsymTerm::Symbol(String::from(tok))));
+        }
+
+        expr = expr[end..].trim_start();
+        if expr.is_empty() {
+            break;
+        }
+        negate = match &expr[..1] {
+            "+" => false,
+            "-" => true,
+            _ => return Err(syntax_error()),
+        };
+        expr = expr[1..].trim_start();
+    }
+
+    Ok(Defsym {
+        name: String::from(name),
+        terms,
+    })
+}
+
+#[test]
+fn test_parse_defsym() {
+    let d = parse_defsym("foo=bar + 0x10-4").unwrap();
+    assert_eq!(d.name, "foo");
+    assert_eq!(
+        d.terms,
+        vec![
+            (false, DefsymTerm::Symbol(String::from("bar"))),
+            (false, DefsymTerm::Const(0x10)),
+            (true, DefsymTerm::Const(4)),
+        ]
+    );
+    assert_eq!(d.get_symbol_names(), vec!["bar"]);
+
+    for arg in ["foo", "=1", "foo=", "foo=1 +", "foo=1 * 2", "foo=0xzz"] {
+        assert!(matches!(parse_defsym(arg), Err(LinkError::BadArgument(_))));
+    }
+}
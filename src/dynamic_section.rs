@@ -0,0 +1,89 @@
+use crate::{
+    chunk::Chunk,
+    context::Context,
+    dynsym_section::DynstrSection,
+    elf::{
+        Dyn, DT_NEEDED, DT_NULL, DT_STRSZ, DT_STRTAB, DT_SYMENT, DT_SYMTAB, DT_VERDEF,
+        DT_VERDEFNUM, DT_VERNEED, DT_VERNEEDNUM, DT_VERSYM, SHF_ALLOC, SHF_WRITE, SHT_DYNAMIC,
+    },
+    elf_class::ElfClass,
+};
+
+// `.dynamic` tells the loader which libraries are needed and where
+// .dynsym, .dynstr and the .gnu.version* sections are. PT_DYNAMIC points
+// at it. Only the entries for these sections are written.
+#[allow(dead_code)]
+pub struct DynamicSection {
+    pub chunk: Chunk,
+    // The .dynstr offsets of the sonames of the shared libraries.
+    pub needed: Vec<u32>,
+}
+
+#[allow(dead_code)]
+impl DynamicSection {
+    pub fn new(class: ElfClass) -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".dynamic".to_string();
+        chunk.shdr.shdr_type = SHT_DYNAMIC;
+        chunk.shdr.flags = SHF_ALLOC | SHF_WRITE;
+        chunk.shdr.addr_align = class.word_size() as u64;
+        chunk.shdr.ent_size = class.dyn_size() as u64;
+
+        Self {
+            chunk,
+            needed: vec![],
+        }
+    }
+
+    // Adds the sonames of the shared libraries to .dynstr. The
+    // libraries are needed in command line order.
+    pub fn add_needed(&mut self, ctx: &Context, dynstr: &mut DynstrSection) {
+        for dso in ctx.dsos.values() {
+            self.needed.push(dynstr.add_string(&dso.soname));
+        }
+    }
+
+    // Called once .dynstr and the other dynamic sections exist.
+    pub fn update_shdr(&mut self, ctx: &Context) {
+        self.chunk.shdr.size = (self.entries(ctx).len() * ctx.elf_class().dyn_size()) as u64;
+    }
+
+    fn entries(&self, ctx: &Context) -> Vec<Dyn> {
+        let mut entries = vec![];
+        let mut define = |tag: i64, val: u64| entries.push(Dyn { tag, val });
+
+        for name in &self.needed {
+            define(DT_NEEDED, *name as u64);
+        }
+
+        let dynsym = &ctx.dynsym.as_ref().unwrap().chunk.shdr;
+        let dynstr = &ctx.dynstr.as_ref().unwrap().chunk.shdr;
+        define(DT_SYMTAB, dynsym.addr);
+        define(DT_SYMENT, dynsym.ent_size);
+        define(DT_STRTAB, dynstr.addr);
+        define(DT_STRSZ, dynstr.size);
+
+        if let Some(versym) = &ctx.versym {
+            define(DT_VERSYM, versym.chunk.shdr.addr);
+        }
+        if let Some(verdef) = &ctx.verdef {
+            define(DT_VERDEF, verdef.chunk.shdr.addr);
+            define(DT_VERDEFNUM, verdef.chunk.shdr.info as u64);
+        }
+        if let Some(verneed) = &ctx.verneed {
+            define(DT_VERNEED, verneed.chunk.shdr.addr);
+            define(DT_VERNEEDNUM, verneed.chunk.shdr.info as u64);
+        }
+
+        define(DT_NULL, 0);
+        entries
+    }
+
+    pub fn copy_buf(&self, ctx: &Context, buf: &mut [u8]) {
+        let class = ctx.elf_class();
+        let base = self.chunk.shdr.offset as usize;
+        for (i, entry) in self.entries(ctx).into_iter().enumerate() {
+            class.write_dyn(&mut buf[base + i * class.dyn_size()..], entry);
+        }
+    }
+}
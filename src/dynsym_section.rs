@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use crate::{
+    arena::SymbolId,
+    chunk::Chunk,
+    context::Context,
+    elf::{Sym, SHF_ALLOC, SHN_UNDEF, SHT_DYNSYM, SHT_STRTAB},
+    elf_class::ElfClass,
+    symbol::Symbol,
+    };
+
+#[allow(dead_code)]
+pub struct DynstrSection {
+    pub chunk: Chunk,
+    pub contents: Vec<u8>,
+    pub offsets: HashMap<String, u32>,
+}
+
+#[allow(dead_code)]
+impl DynstrSection {
+    pub fn new() -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".dynstr".to_string();
+        chunk.shdr.shdr_type = SHT_STRTAB;
+        chunk.shdr.flags = SHF_ALLOC;
+        chunk.shdr.size = 1;
+
+        Self {
+            chunk,
+            contents: vec![0],
+            offsets: HashMap::new(),
+        }
+    }
+
+    pub fn add_string(&mut self, s: &str) -> u32 {
+        if let Some(offset) = self.offsets.get(s) {
+            return *offset;
+        }
+        let offset = self.contents.len() as u32;
+        self.contents.extend_from_slice(s.as_bytes());
+        self.contents.push(0);
+        self.offsets.insert(String::from(s), offset);
+        self.chunk.shdr.size = self.contents.len() as u64;
+        offset
+    }
+
+    pub fn copy_buf(&self, buf: &mut [u8]) {
+        let offset = self.chunk.shdr.offset as usize;
+        buf[offset..offset + self.contents.len()].copy_from_slice(&self.contents);
+    }
+}
+
+#[allow(dead_code)]
+pub struct DynsymSection {
+    pub chunk: Chunk,
+    pub symbols: Vec<SymbolId>,
+    pub name_offsets: Vec<u32>,
+}
+
+#[allow(dead_code)]
+impl DynsymSection {
+    pub fn new(class: ElfClass) -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".dynsym".to_string();
+        chunk.shdr.shdr_type = SHT_DYNSYM;
+        chunk.shdr.flags = SHF_ALLOC;
+        chunk.shdr.addr_align = class.word_size() as u64;
+        chunk.shdr.ent_size = class.sym_size() as u64;
+        chunk.shdr.info = 1;
+        chunk.shdr.size = class.sym_size() as u64;
+
+        Self {
+            chunk,
+            symbols: vec![],
+            name_offsets: vec![],
+        }
+    }
+
+    pub fn add_symbol(&mut self, id: SymbolId, sym: &mut Symbol, dynstr: &mut DynstrSection) {
+        if sym.dynsym_idx != -1 {
+            return;
+        }
+        sym.dynsym_idx = self.symbols.len() as i32 + 1;
+        self.symbols.push(id);
+        self.name_offsets
+            .push(dynstr.add_string(sym.get_base_name()));
+        let chunk = &mut self.chunk;
+        chunk.shdr.size = (self.symbols.len() + 1) as u64 * chunk.shdr.ent_size;
+    }
+
+    pub fn copy_buf(&self, ctx: &Context, buf: &mut [u8]) {
+        let class = ctx.elf_class();
+        let base = self.chunk.shdr.offset as usize;
+        class.write_sym(&mut buf[base..], Sym::new());
+
+        for i in 0..self.symbols.len() {
+            let sym = &ctx.symbols[self.symbols[i]];
+            let esym = sym.elf_sym(ctx);
+
+            let mut out = Sym::new();
+            out.name = self.name_offsets[i];
+            out.info = esym.info;
+            out.other = sym.visibility;
+            if sym.is_imported() {
+                out.shndx = SHN_UNDEF;
+            } else {
+                out.shndx = sym.get_output_shndx(ctx);
+                out.val = sym.get_addr(ctx);
+                out.size = esym.size;
+            }
+
+            let offset = base + (i + 1) * class.sym_size();
+            class.write_sym(&mut buf[offset..], out);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::hash::{Hash, Hasher};
+
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::{
+    arena::{FileId, SectionId},
+    context::Context,
+    elf::Rela,
+    object_file::ObjectFile,
+};
+
+// A `.eh_frame` input section is a sequence of CIE and FDE records. Each
+// FDE describes a single function and points back to a CIE that holds
+// the information shared by many FDEs. Records own the relocations that
+// fall within their byte range, [rel_idx, rel_end) of the section's rels.
+#[allow(dead_code)]
+pub struct CieRecord {
+    pub file: FileId,
+    pub input_section: SectionId,
+    pub offset: u32,
+    pub size: u32,
+    pub rel_idx: usize,
+    pub rel_end: usize,
+    pub output_offset: u32,
+    pub is_leader: bool,
+}
+
+#[allow(dead_code)]
+impl CieRecord {
+    pub fn get_contents<'b>(&self, ctx: &'b Context) -> &'b [u8] {
+        let contents = &ctx.sections[self.input_section].contents;
+        &contents[self.offset as usize..(self.offset + self.size) as usize]
+    }
+
+    pub fn get_rels<'b>(&self, ctx: &'b Context) -> &'b [Rela] {
+        &ctx.sections[self.input_section].rels[self.rel_idx..self.rel_end]
+    }
+
+    // A hash of everything `equals` compares. Equal CIEs have the same
+    // hash.
+    pub fn hash(&self, ctx: &Context) -> u64 {
+        let mut hasher = Xxh3::new();
+        self.get_contents(ctx).hash(&mut hasher);
+        let file = &ctx.files[self.file];
+        for rel in self.get_rels(ctx) {
+            (rel.offset - self.offset as u64).hash(&mut hasher);
+            rel.ty.hash(&mut hasher);
+            rel.addend.hash(&mut hasher);
+            file.get_rel_target(ctx, rel).hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    // Two CIEs are interchangeable if they have the same bytes and their
+    // relocations refer to the same places, e.g. the same personality
+    // routine.
+    pub fn equals(&self, ctx: &Context, other: &CieRecord) -> bool {
+        if self.get_contents(ctx) != other.get_contents(ctx) {
+            return false;
+        }
+
+        let x = self.get_rels(ctx);
+        let y = other.get_rels(ctx);
+        if x.len() != y.len() {
+            return false;
+        }
+
+        let file_x = &ctx.files[self.file];
+        let file_y = &ctx.files[other.file];
+        for i in 0..x.len() {
+            if x[i].offset - self.offset as u64 != y[i].offset - other.offset as u64
+                || x[i].ty != y[i].ty
+                || x[i].addend != y[i].addend
+                || file_x.get_rel_target(ctx, &x[i]) != file_y.get_rel_target(ctx, &y[i])
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[allow(dead_code)]
+pub struct FdeRecord {
+    pub cie_idx: usize,
+    pub offset: u32,
+    pub size: u32,
+    pub rel_idx: usize,
+    pub rel_end: usize,
+    pub output_offset: u32,
+    pub is_alive: bool,
+}
+
+#[allow(dead_code)]
+impl FdeRecord {
+    pub fn get_contents<'b>(&self, ctx: &'b Context, file: &ObjectFile) -> &'b [u8] {
+        let isec = file.cies[self.cie_idx].input_section;
+        let contents = &ctx.sections[isec].contents;
+        &contents[self.offset as usize..(self.offset + self.size) as usize]
+    }
+
+    pub fn get_rels<'b>(&self, ctx: &'b Context, file: &ObjectFile) -> &'b [Rela] {
+        let isec = file.cies[self.cie_idx].input_section;
+        &ctx.sections[isec].rels[self.rel_idx..self.rel_end]
+    }
+
+    // The first relocation of an FDE refers to the beginning of the
+    // function it describes. If that symbol was resolved to another file,
+    // e.g. a duplicate inline function, the FDE describes a discarded copy.
+    pub fn get_target_section(&self, ctx: &Context, file: &ObjectFile) -> Option<SectionId> {
+        let rels = self.get_rels(ctx, file);
+        if rels.is_empty() {
+            return None;
+        }
+        let target = file.get_rel_section(ctx, &rels[0])?;
+        if ctx.sections[target].object_file != file.id {
+            return None;
+        }
+        Some(target)
+    }
+}
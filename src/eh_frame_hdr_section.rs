@@ -0,0 +1,85 @@
+use crate::{
+    chunk::Chunk,
+    context::Context,
+    elf::{SHF_ALLOC, SHT_PROGBITS},
+    utils::write,
+};
+
+const DW_EH_PE_UDATA4: u8 = 0x03;
+const DW_EH_PE_SDATA4: u8 = 0x0b;
+const DW_EH_PE_PCREL: u8 = 0x10;
+const DW_EH_PE_DATAREL: u8 = 0x30;
+
+const HEADER_SIZE: usize = 12;
+
+// `.eh_frame_hdr` holds a pointer to `.eh_frame` followed by a table of
+// (function address, FDE address) pairs sorted by function address, so
+// that the unwinder can find an FDE with a binary search. Addresses in
+// the table are relative to the start of `.eh_frame_hdr`.
+#[allow(dead_code)]
+pub struct EhFrameHdrSection {
+    pub chunk: Chunk,
+}
+
+#[allow(dead_code)]
+impl EhFrameHdrSection {
+    pub fn new() -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".eh_frame_hdr".to_string();
+        chunk.shdr.shdr_type = SHT_PROGBITS;
+        chunk.shdr.flags = SHF_ALLOC;
+        chunk.shdr.addr_align = 4;
+
+        Self { chunk }
+    }
+
+    pub fn construct(&mut self, ctx: &Context) {
+        let num_fdes = ctx.eh_frame.as_ref().unwrap().num_fdes;
+        self.chunk.shdr.size = (HEADER_SIZE + num_fdes * 8) as u64;
+    }
+
+    pub fn copy_buf(&self, ctx: &Context, buf: &mut [u8]) {
+        let shdr = self.chunk.shdr;
+        let eh_frame_addr = ctx.eh_frame.as_ref().unwrap().chunk.shdr.addr;
+
+        let mut entries: Vec<(u64, u64)> = vec![];
+        for file in &ctx.objs {
+            let file = &ctx.files[*file];
+            for fde in &file.fdes {
+                if !fde.is_alive {
+                    continue;
+                }
+                let rel = &fde.get_rels(ctx, file)[0];
+                let func_addr = file
+                    .get_rel_symbol_addr(ctx, rel)
+                    .wrapping_add(rel.addend as u64);
+                entries.push((func_addr, eh_frame_addr + fde.output_offset as u64));
+            }
+        }
+        entries.sort_by_key(|entry| entry.0);
+
+        let base = shdr.offset as usize;
+        let buf = &mut buf[base..base + shdr.size as usize];
+        buf[0] = 1;
+        buf[1] = DW_EH_PE_PCREL | DW_EH_PE_SDATA4;
+        buf[2] = DW_EH_PE_UDATA4;
+        buf[3] = DW_EH_PE_DATAREL | DW_EH_PE_SDATA4;
+        write(
+            &mut buf[4..8],
+            eh_frame_addr.wrapping_sub(shdr.addr + 4) as u32,
+        );
+        write(&mut buf[8..12], entries.len() as u32);
+
+        for (i, (func_addr, fde_addr)) in entries.iter().enumerate() {
+            let off = HEADER_SIZE + i * 8;
+            write(
+                &mut buf[off..off + 4],
+                func_addr.wrapping_sub(shdr.addr) as u32,
+            );
+            write(
+                &mut buf[off + 4..off + 8],
+                fde_addr.wrapping_sub(shdr.addr) as u32,
+            );
+        }
+    }
+}
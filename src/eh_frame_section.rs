@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::{
+    arena::FileId,
+    chunk::Chunk,
+    context::Context,
+    elf::{SHF_ALLOC, SHT_PROGBITS},
+    error::LinkError,
+    utils::write,
+};
+
+// The output `.eh_frame` is built from the CIE and FDE records of all
+// input files. Identical CIEs are emitted once, and FDEs for functions in
+// dead sections are dropped.
+#[allow(dead_code)]
+pub struct EhFrameSection {
+    pub chunk: Chunk,
+    pub num_fdes: usize,
+}
+
+#[allow(dead_code)]
+impl EhFrameSection {
+    pub fn new() -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".eh_frame".to_string();
+        chunk.shdr.shdr_type = SHT_PROGBITS;
+        chunk.shdr.flags = SHF_ALLOC;
+        chunk.shdr.addr_align = 8;
+
+        Self { chunk, num_fdes: 0 }
+    }
+
+    pub fn construct(&mut self, ctx: &mut Context) {
+        // The leaders are keyed by their hash. Those with the same hash
+        // are told apart by comparing them.
+        let mut leaders: HashMap<u64, Vec<(FileId, usize)>> = HashMap::new();
+        let mut offset = 0u32;
+
+        for &id in &ctx.objs {
+            let file = &ctx.files[id];
+            let alive: Vec<bool> = file
+                .fdes
+                .iter()
+                .map(|fde| {
+                    fde.get_target_section(ctx, file)
+                        .is_some_and(|isec| ctx.sections[isec].is_alive)
+                })
+                .collect();
+
+            for i in 0..file.cies.len() {
+                let cie = &ctx.files[id].cies[i];
+                let hash = cie.hash(ctx);
+                let leader = leaders
+                    .get(&hash)
+                    .into_iter()
+                    .flatten()
+                    .map(|(file, idx)| &ctx.files[*file].cies[*idx])
+                    .find(|leader| cie.equals(ctx, leader))
+                    .map(|leader| leader.output_offset);
+
+                let cie = &mut ctx.files[id].cies[i];
+                match leader {
+                    Some(leader_offset) => {
+                        cie.is_leader = false;
+                        cie.output_offset = leader_offset;
+                    }
+                    None => {
+                        cie.is_leader = true;
+                        cie.output_offset = offset;
+                        offset += cie.size;
+                        leaders.entry(hash).or_default().push((id, i));
+                    }
+                }
+            }
+
+            for (fde, is_alive) in ctx.files[id].fdes.iter_mut().zip(alive) {
+                fde.is_alive = is_alive;
+            }
+        }
+
+        self.num_fdes = 0;
+        for &id in &ctx.objs {
+            for fde in &mut ctx.files[id].fdes {
+                if !fde.is_alive {
+                    continue;
+                }
+                fde.output_offset = offset;
+                offset += fde.size;
+                self.num_fdes += 1;
+            }
+        }
+
+        // A zero-length record terminates the section.
+        let chunk = &mut self.chunk;
+        chunk.shdr.size = if offset == 0 { 0 } else { offset as u64 + 4 };
+    }
+
+    // Writes the section to its place in `buf`, the output file.
+    pub fn copy_buf(&self, ctx: &Context, buf: &mut [u8]) -> Result<(), LinkError> {
+        let shdr = self.chunk.shdr;
+        let base = shdr.offset as usize;
+
+        for &id in &ctx.objs {
+            let file = &ctx.files[id];
+
+            for cie in &file.cies {
+                if !cie.is_leader {
+                    continue;
+                }
+                let out = base + cie.output_offset as usize;
+                let contents = cie.get_contents(ctx);
+                buf[out..out + contents.len()].copy_from_slice(contents);
+
+                for rel in cie.get_rels(ctx) {
+                    let loc = (rel.offset - cie.offset as u64) as usize;
+                    let s = file.get_rel_symbol_addr(ctx, rel);
+                    let p = shdr.addr + (cie.output_offset as usize + loc) as u64;
+                    if !ctx
+                        .target()
+                        .apply_eh_reloc(&mut buf[out + loc..], rel, s, p)
+                    {
+                        ctx.error(LinkError::UnsupportedReloc {
+                            section: ".eh_frame".to_string(),
+                            ty: rel.ty,
+                        })?;
+                    }
+                }
+            }
+
+            for fde in &file.fdes {
+                if !fde.is_alive {
+                    continue;
+                }
+                let out = base + fde.output_offset as usize;
+                let contents = fde.get_contents(ctx, file);
+                buf[out..out + contents.len()].copy_from_slice(contents);
+
+                // The CIE pointer is the distance from the field itself
+                // back to the CIE.
+                let cie_offset = file.cies[fde.cie_idx].output_offset;
+                write(
+                    &mut buf[out + 4..out + 8],
+                    fde.output_offset + 4 - cie_offset,
+                );
+
+                for rel in fde.get_rels(ctx, file) {
+                    let loc = (rel.offset - fde.offset as u64) as usize;
+                    let s = file.get_rel_symbol_addr(ctx, rel);
+                    let p = shdr.addr + (fde.output_offset as usize + loc) as u64;
+                    if !ctx
+                        .target()
+                        .apply_eh_reloc(&mut buf[out + loc..], rel, s, p)
+                    {
+                        ctx.error(LinkError::UnsupportedReloc {
+                            section: ".eh_frame".to_string(),
+                            ty: rel.ty,
+                        })?;
+                    }
+                }
+            }
+        }
+
+        let end = base + shdr.size as usize;
+        if shdr.size > 0 {
+            buf[end - 4..end].fill(0);
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
-use std::mem;
+use std::{borrow::Cow, mem};
+
+use crate::utils::Pod;
 
 #[allow(dead_code)]
 pub const EHDR_SIZE: usize = mem::size_of::<Ehdr>();
@@ -9,84 +11,184 @@ pub const SYM_SIZE: usize = mem::size_of::<Sym>();
 #[allow(dead_code)]
 pub const AR_HDR_SIZE: usize = mem::size_of::<ArHdr>();
 #[allow(dead_code)]
-pub const ELF_ABS: u16 = 0;
+pub const ELF_ABS: u16 = SHN_ABS;
 #[allow(dead_code)]
-pub const ELF_UNDEF: u16 = 0;
+pub const ELF_UNDEF: u16 = SHN_UNDEF;
 pub const PHDR_SIZE: usize = mem::size_of::<Phdr>();
 pub const RELA_SIZE: usize = mem::size_of::<Rela>();
+pub const VERDEF_SIZE: usize = mem::size_of::<Verdef>();
+pub const VERDAUX_SIZE: usize = mem::size_of::<Verdaux>();
+pub const VERNEED_SIZE: usize = mem::size_of::<Verneed>();
+pub const VERNAUX_SIZE: usize = mem::size_of::<Vernaux>();
+pub const DYN_SIZE: usize = mem::size_of::<Dyn>();
+pub const CHDR_SIZE: usize = mem::size_of::<Chdr>();
+pub const NHDR_SIZE: usize = mem::size_of::<Nhdr>();
 
 pub const IMAGE_BASE: u64 = 0x200000;
 pub const EF_RISCV_RVC: u32 = 1;
+pub const EF_RISCV_FLOAT_ABI: u32 = 6;
+pub const EF_RISCV_RVE: u32 = 8;
+pub const EF_RISCV_TSO: u32 = 0x10;
 
 pub const R_RISCV_NONE: u32 = 0;
-pub const R_RISCV_RELAX: u32 = 0;
-
-pub const SHF_GROUP: u64 = 0;
-pub const SHF_COMPRESSED: u64 = 0;
-pub const SHF_MERGE: u64 = 0;
-pub const SHF_STRINGS: u64 = 0;
-pub const SHF_COMMON: u64 = 0;
-pub const SHF_LINK_ORDER: u64 = 0;
-pub const SHF_ALLOC: u64 = 0;
-pub const SHF_WRITE: u64 = 0;
-pub const SHF_EXECINSTR: u64 = 0;
-pub const SHF_TLS: u64 = 0;
-
-pub const SHT_GROUP: u32 = 0;
-pub const SHT_SYMTAB: u32 = 1;
-pub const SHT_STRTAB: u32 = 2;
-pub const SHT_REL: u32 = 3;
+pub const R_RISCV_RELAX: u32 = 51;
+
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const SHF_MERGE: u64 = 0x10;
+pub const SHF_STRINGS: u64 = 0x20;
+pub const SHF_LINK_ORDER: u64 = 0x80;
+pub const SHF_GROUP: u64 = 0x200;
+pub const SHF_TLS: u64 = 0x400;
+pub const SHF_COMPRESSED: u64 = 0x800;
+
+pub const SHT_NULL: u32 = 0;
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
 pub const SHT_RELA: u32 = 4;
-pub const SHT_NULL: u32 = 5;
-pub const SHT_SYMTAB_SHNDX: u32 = 6;
-pub const SHT_NOBITS: u32 = 0;
-pub const SHT_PROGBITS: u32 = 0;
-pub const SHT_NOTE: u32 = 0;
-
-pub const SHN_XINDEX: u16 = 7;
-
-pub const EI_CLASS: u8 = 0;
-pub const EI_DATA: u8 = 0;
-pub const EI_VERSION: u8 = 0;
-pub const EI_OSABI: u8 = 0;
-pub const EI_ABIVERSION: u8 = 0;
-
-pub const ELFCLASS64: u8 = 0;
-pub const ELFDATA2LSB: u8 = 0;
-
-pub const EV_CURRENT: u32 = 0;
-
-pub const ET_EXEC: u16 = 0;
-
-pub const EM_RISCV: u16 = 0;
-
-pub const PF_W: u32 = 0;
-pub const PF_X: u32 = 0;
-
-pub const PT_NOTE: u64 = 0;
-pub const PT_LOAD: u64 = 0;
-pub const PT_TLS: u64 = 0;
+pub const SHT_DYNAMIC: u32 = 6;
+pub const SHT_NOTE: u32 = 7;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_REL: u32 = 9;
+pub const SHT_DYNSYM: u32 = 11;
+pub const SHT_INIT_ARRAY: u32 = 14;
+pub const SHT_FINI_ARRAY: u32 = 15;
+pub const SHT_PREINIT_ARRAY: u32 = 16;
+pub const SHT_GROUP: u32 = 17;
+pub const SHT_SYMTAB_SHNDX: u32 = 18;
+pub const SHT_GNU_VERDEF: u32 = 0x6ffffffd;
+pub const SHT_GNU_VERNEED: u32 = 0x6ffffffe;
+pub const SHT_GNU_VERSYM: u32 = 0x6fffffff;
+pub const SHT_RISCV_ATTRIBUTES: u32 = 0x70000003;
+
+pub const SHN_UNDEF: u16 = 0;
+pub const SHN_LORESERVE: u16 = 0xff00;
+pub const SHN_ABS: u16 = 0xfff1;
+pub const SHN_COMMON: u16 = 0xfff2;
+pub const SHN_XINDEX: u16 = 0xffff;
+
+pub const STB_GLOBAL: u8 = 1;
+pub const STB_WEAK: u8 = 2;
+
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_SECTION: u8 = 3;
+
+pub const STV_DEFAULT: u8 = 0;
+pub const STV_INTERNAL: u8 = 1;
+pub const STV_HIDDEN: u8 = 2;
+pub const STV_PROTECTED: u8 = 3;
+
+pub const EI_CLASS: u8 = 4;
+pub const EI_DATA: u8 = 5;
+pub const EI_VERSION: u8 = 6;
+pub const EI_OSABI: u8 = 7;
+pub const EI_ABIVERSION: u8 = 8;
+
+pub const ELFCLASS64: u8 = 2;
+pub const ELFDATA2LSB: u8 = 1;
+
+pub const EV_CURRENT: u32 = 1;
+
+pub const ET_REL: u16 = 1;
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+
+pub const EM_X86_64: u16 = 62;
+pub const EM_RISCV: u16 = 243;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+
+pub const PT_LOAD: u64 = 1;
+pub const PT_DYNAMIC: u64 = 2;
+pub const PT_NOTE: u64 = 4;
+pub const PT_TLS: u64 = 7;
+pub const PT_GNU_EH_FRAME: u64 = 0x6474e550;
+pub const PT_RISCV_ATTRIBUTES: u64 = 0x70000003;
 
 #[allow(dead_code)]
-pub const PF_R: u32 = 0;
+pub const PF_R: u32 = 4;
 
 #[allow(dead_code)]
-pub const PT_PHDR: u64 = 0;
+pub const PT_PHDR: u64 = 6;
+
+pub const DT_NULL: i64 = 0;
+pub const DT_NEEDED: i64 = 1;
+pub const DT_STRTAB: i64 = 5;
+pub const DT_SYMTAB: i64 = 6;
+pub const DT_STRSZ: i64 = 10;
+pub const DT_SYMENT: i64 = 11;
+pub const DT_SONAME: i64 = 14;
+pub const DT_VERSYM: i64 = 0x6ffffff0;
+pub const DT_VERDEF: i64 = 0x6ffffffc;
+pub const DT_VERDEFNUM: i64 = 0x6ffffffd;
+pub const DT_VERNEED: i64 = 0x6ffffffe;
+pub const DT_VERNEEDNUM: i64 = 0x6fffffff;
+
+pub const VER_NDX_LOCAL: u16 = 0;
+pub const VER_NDX_GLOBAL: u16 = 1;
+pub const VER_NDX_LAST_RESERVED: u16 = 1;
+pub const VERSYM_HIDDEN: u16 = 0x8000;
+pub const VER_FLG_BASE: u16 = 1;
+
+pub const ELFCOMPRESS_ZLIB: u32 = 1;
+pub const ELFCOMPRESS_ZSTD: u32 = 2;
+
+pub const NT_GNU_BUILD_ID: u32 = 3;
 
 pub type RRicsv = i64;
-pub const R_RISCV_32: RRicsv = 0;
-pub const R_RISCV_64: RRicsv = 1;
-pub const R_RISCV_BRANCH: RRicsv = 2;
-pub const R_RISCV_JAL: RRicsv = 3;
-pub const R_RISCV_CALL: RRicsv = 4;
-pub const R_RISCV_CALL_PLT: RRicsv = 5;
-pub const R_RISCV_TLS_GOT_HI20: RRicsv = 6;
-pub const R_RISCV_PCREL_HI20: RRicsv = 7;
-pub const R_RISCV_HI20: RRicsv = 8;
-pub const R_RISCV_LO12_I: RRicsv = 9;
-pub const R_RISCV_LO12_S: RRicsv = 10;
-pub const R_RISCV_TPREL_LO12_I: RRicsv = 11;
-pub const R_RISCV_TPREL_LO12_S: RRicsv = 12;
+pub const R_RISCV_32: RRicsv = 1;
+pub const R_RISCV_64: RRicsv = 2;
+pub const R_RISCV_BRANCH: RRicsv = 16;
+pub const R_RISCV_JAL: RRicsv = 17;
+pub const R_RISCV_CALL: RRicsv = 18;
+pub const R_RISCV_CALL_PLT: RRicsv = 19;
+pub const R_RISCV_TLS_GOT_HI20: RRicsv = 21;
+pub const R_RISCV_PCREL_HI20: RRicsv = 23;
+pub const R_RISCV_PCREL_LO12_I: RRicsv = 24;
+pub const R_RISCV_PCREL_LO12_S: RRicsv = 25;
+pub const R_RISCV_HI20: RRicsv = 26;
+pub const R_RISCV_LO12_I: RRicsv = 27;
+pub const R_RISCV_LO12_S: RRicsv = 28;
+pub const R_RISCV_TPREL_LO12_I: RRicsv = 30;
+pub const R_RISCV_TPREL_LO12_S: RRicsv = 31;
+pub const R_RISCV_ADD8: RRicsv = 33;
+pub const R_RISCV_ADD16: RRicsv = 34;
+pub const R_RISCV_ADD32: RRicsv = 35;
+pub const R_RISCV_ADD64: RRicsv = 36;
+pub const R_RISCV_SUB8: RRicsv = 37;
+pub const R_RISCV_SUB16: RRicsv = 38;
+pub const R_RISCV_SUB32: RRicsv = 39;
+pub const R_RISCV_SUB64: RRicsv = 40;
+pub const R_RISCV_SUB6: RRicsv = 52;
+pub const R_RISCV_SET6: RRicsv = 53;
+pub const R_RISCV_SET8: RRicsv = 54;
+pub const R_RISCV_SET16: RRicsv = 55;
+pub const R_RISCV_SET32: RRicsv = 56;
+pub const R_RISCV_32_PCREL: RRicsv = 57;
+pub const R_RISCV_SET_ULEB128: RRicsv = 60;
+pub const R_RISCV_SUB_ULEB128: RRicsv = 61;
+
+pub type RX86_64 = i64;
+pub const R_X86_64_NONE: RX86_64 = 0;
+pub const R_X86_64_64: RX86_64 = 1;
+pub const R_X86_64_PC32: RX86_64 = 2;
+pub const R_X86_64_PLT32: RX86_64 = 4;
+pub const R_X86_64_GOTPCREL: RX86_64 = 9;
+pub const R_X86_64_32: RX86_64 = 10;
+pub const R_X86_64_32S: RX86_64 = 11;
+pub const R_X86_64_16: RX86_64 = 12;
+pub const R_X86_64_PC16: RX86_64 = 13;
+pub const R_X86_64_8: RX86_64 = 14;
+pub const R_X86_64_PC8: RX86_64 = 15;
+pub const R_X86_64_TPOFF64: RX86_64 = 18;
+pub const R_X86_64_GOTTPOFF: RX86_64 = 22;
+pub const R_X86_64_TPOFF32: RX86_64 = 23;
+pub const R_X86_64_PC64: RX86_64 = 24;
+pub const R_X86_64_GOTPCRELX: RX86_64 = 41;
+pub const R_X86_64_REX_GOTPCRELX: RX86_64 = 42;
 
 #[allow(dead_code)]
 #[repr(C)]
@@ -108,6 +210,8 @@ pub struct Ehdr {
     pub sh_strndx: u16,
 }
 
+unsafe impl Pod for Ehdr {}
+
 #[allow(dead_code)]
 impl Ehdr {
     pub fn new() -> Self {
@@ -146,6 +250,8 @@ pub struct Shdr {
     pub ent_size: u64,
 }
 
+unsafe impl Pod for Shdr {}
+
 #[allow(dead_code)]
 impl Shdr {
     pub fn new() -> Self {
@@ -178,6 +284,8 @@ pub struct Phdr {
     pub align: u64,
 }
 
+unsafe impl Pod for Phdr {}
+
 #[allow(dead_code)]
 impl Phdr {
     pub fn new() -> Self {
@@ -206,8 +314,21 @@ pub struct Sym {
     pub size: u64,
 }
 
+unsafe impl Pod for Sym {}
+
 #[allow(dead_code)]
 impl Sym {
+    pub fn new() -> Self {
+        Sym {
+            name: 0,
+            info: 0,
+            other: 0,
+            shndx: 0,
+            val: 0,
+            size: 0,
+        }
+    }
+
     pub fn is_abs(&self) -> bool {
         self.shndx == ELF_ABS
     }
@@ -217,25 +338,56 @@ impl Sym {
     }
 
     pub fn is_common(&self) -> bool {
-        self.shndx == SHF_COMMON as u16
+        self.shndx == SHN_COMMON
+    }
+
+    pub fn bind(&self) -> u8 {
+        self.info >> 4
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.bind() == STB_WEAK
+    }
+
+    pub fn st_type(&self) -> u8 {
+        self.info & 0xf
+    }
+
+    pub fn visibility(&self) -> u8 {
+        self.other & 3
     }
 }
 
+// Reads the null-terminated string at `offset` of a string table. ELF
+// names are bytes and needn't be valid UTF-8.
 #[allow(dead_code)]
-pub fn elf_get_name<'a>(str_tab: &'a [u8], offset: u32) -> &'a str {
-    let offset = offset as usize;
-    let len = binary_search(&str_tab[offset..], 0).unwrap();
-    return std::str::from_utf8(&str_tab[offset..(offset + len)]).unwrap();
+pub fn elf_get_name_bytes(str_tab: &[u8], offset: u32) -> Result<&[u8], &'static str> {
+    let rest = str_tab
+        .get(offset as usize..)
+        .ok_or("string table offset is out of range")?;
+    let len = binary_search(rest, 0).ok_or("string is not null terminated")?;
+    Ok(&rest[..len])
 }
 
+// Like elf_get_name_bytes, as a string. The rest of the linker keys
+// symbols and sections by &str, so a name that isn't valid UTF-8 gets
+// U+FFFD in place of the invalid bytes.
 #[allow(dead_code)]
-fn binary_search(data: &[u8], sep: u8) -> Option<usize> {
-    for i in 0..data.len() {
-        if data[i] == sep {
-            return Some(i);
-        }
+pub fn elf_get_name<'a>(str_tab: &'a [u8], offset: u32) -> Result<&'a str, &'static str> {
+    Ok(name_to_str(elf_get_name_bytes(str_tab, offset)?))
+}
+
+#[allow(dead_code)]
+pub fn name_to_str(name: &[u8]) -> &str {
+    match String::from_utf8_lossy(name) {
+        Cow::Borrowed(name) => name,
+        Cow::Owned(name) => Box::leak(name.into_boxed_str()),
     }
-    None
+}
+
+#[allow(dead_code)]
+fn binary_search(data: &[u8], sep: u8) -> Option<usize> {
+    data.iter().position(|b| *b == sep)
 }
 
 #[allow(dead_code)]
@@ -251,10 +403,12 @@ pub struct ArHdr {
     fmag: [u8; 2],
 }
 
+unsafe impl Pod for ArHdr {}
+
 #[allow(dead_code)]
 impl ArHdr {
     pub fn has_prefix(&self, s: &str) -> bool {
-        return std::str::from_utf8(&self.name).unwrap().starts_with(s);
+        return self.name.starts_with(s.as_bytes());
     }
 
     pub fn is_str_tab(&self) -> bool {
@@ -265,40 +419,24 @@ impl ArHdr {
         return self.has_prefix("/ ") || self.has_prefix("/SYM64");
     }
 
-    pub fn get_size(&self) -> usize {
-        let ss = std::str::from_utf8(&self.size)
-            .unwrap()
-            .trim();
-        let size = str::parse::<usize>(ss).unwrap();
-        size
+    // The size of the member, or None if the field isn't a decimal number.
+    pub fn get_size(&self) -> Option<usize> {
+        let ss = std::str::from_utf8(&self.size).ok()?.trim();
+        str::parse::<usize>(ss).ok()
     }
 
-    pub fn read_name(&self, str_tab: &'static str) -> String {
-        if unsafe {
-            std::str::from_utf8(std::slice::from_raw_parts(
-                (self as *const ArHdr) as *const u8,
-                60,
-            ))
-            .unwrap()
-            .starts_with("// ")
-        } {
-            let start = str::parse::<usize>(
-                std::str::from_utf8(&self.size)
-                    .unwrap()
-                    .strip_suffix(" ")
-                    .unwrap(),
-            )
-            .unwrap();
-            let end = start + str_tab.rfind("/\n").unwrap();
-
-            return (&str_tab[start..end]).to_string();
+    // A name that doesn't fit in the header is stored in the string table
+    // as "/<offset>", and ends with "/\n" there. Other names end with "/".
+    // `hdr` is the header in the archive, so that the name can be borrowed
+    // from the archive rather than copied.
+    pub fn read_name<'a>(hdr: &'a [u8], str_tab: &'a str) -> Option<&'a str> {
+        let name = std::str::from_utf8(hdr.get(..16)?).ok()?;
+        if let Some(offset) = name.strip_prefix('/') {
+            let start = str::parse::<usize>(offset.trim()).ok()?;
+            let rest = str_tab.get(start..)?;
+            return Some(&rest[..rest.find("/\n")?]);
         }
-        let _ss = std::str::from_utf8(&self.name).unwrap();
-        let end: usize = std::str::from_utf8(&self.name)
-            .unwrap()
-            .rfind(r"/")
-            .unwrap();
-        return std::str::from_utf8(&self.name[..end]).unwrap().to_string();
+        Some(&name[..name.find('/')?])
     }
 }
 
@@ -312,9 +450,119 @@ pub struct Rela {
     pub addend: i64,
 }
 
+unsafe impl Pod for Rela {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Dyn {
+    pub tag: i64,
+    pub val: u64,
+}
+
+unsafe impl Pod for Dyn {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Chdr {
+    pub ch_type: u32,
+    pub reserved: u32,
+    pub size: u64,
+    pub addr_align: u64,
+}
+
+unsafe impl Pod for Chdr {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Nhdr {
+    pub name_size: u32,
+    pub desc_size: u32,
+    pub ty: u32,
+}
+
+unsafe impl Pod for Nhdr {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Verdef {
+    pub version: u16,
+    pub flags: u16,
+    pub ndx: u16,
+    pub cnt: u16,
+    pub hash: u32,
+    pub aux: u32,
+    pub next: u32,
+}
+
+unsafe impl Pod for Verdef {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Verdaux {
+    pub name: u32,
+    pub next: u32,
+}
+
+unsafe impl Pod for Verdaux {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Verneed {
+    pub version: u16,
+    pub cnt: u16,
+    pub file: u32,
+    pub aux: u32,
+    pub next: u32,
+}
+
+unsafe impl Pod for Verneed {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Vernaux {
+    pub hash: u32,
+    pub flags: u16,
+    pub other: u16,
+    pub name: u32,
+    pub next: u32,
+}
+
+unsafe impl Pod for Vernaux {}
+
+#[allow(dead_code)]
+pub fn elf_hash(name: &str) -> u32 {
+    let mut h = 0u32;
+    for c in name.bytes() {
+        h = (h << 4).wrapping_add(c as u32);
+        let g = h & 0xf000_0000;
+        if g != 0 {
+            h ^= g >> 24;
+        }
+        h &= !g;
+    }
+    h
+}
+
 #[test]
 fn test_binary_seach() {
     let data = &[1u8, 2, 3, 4, 5, 6];
     let sep = 2u8;
     assert_eq!(binary_search(data, sep), Some(1));
 }
+
+#[test]
+fn test_elf_get_name() {
+    let str_tab = b"\0foo\0b\xffr\0baz";
+    assert_eq!(elf_get_name(str_tab, 1), Ok("foo"));
+    assert_eq!(elf_get_name_bytes(str_tab, 5), Ok(&b"b\xffr"[..]));
+    assert_eq!(elf_get_name(str_tab, 5), Ok("b\u{fffd}r"));
+    assert!(elf_get_name(str_tab, 9).is_err());
+    assert!(elf_get_name(str_tab, 100).is_err());
+}
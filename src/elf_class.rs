@@ -0,0 +1,427 @@
+use std::mem;
+
+use crate::{
+    elf::{
+        Chdr, Dyn, Ehdr, Phdr, Rela, Shdr, Sym, CHDR_SIZE, DYN_SIZE, EHDR_SIZE, EI_CLASS,
+        PHDR_SIZE, RELA_SIZE, SHDR_SIZE, SYM_SIZE,
+    },
+    utils::{read, write, Pod},
+};
+
+pub const ELFCLASS32: u8 = 1;
+
+pub const EHDR32_SIZE: usize = mem::size_of::<Ehdr32>();
+pub const SHDR32_SIZE: usize = mem::size_of::<Shdr32>();
+pub const PHDR32_SIZE: usize = mem::size_of::<Phdr32>();
+pub const SYM32_SIZE: usize = mem::size_of::<Sym32>();
+pub const RELA32_SIZE: usize = mem::size_of::<Rela32>();
+pub const CHDR32_SIZE: usize = mem::size_of::<Chdr32>();
+pub const DYN32_SIZE: usize = mem::size_of::<Dyn32>();
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Ehdr32 {
+    pub ident: [u8; 16],
+    pub hdr_type: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u32,
+    pub ph_off: u32,
+    pub sh_off: u32,
+    pub flags: u32,
+    pub eh_size: u16,
+    pub ph_ent_size: u16,
+    pub ph_num: u16,
+    pub sh_ent_size: u16,
+    pub sh_num: u16,
+    pub sh_strndx: u16,
+}
+
+unsafe impl Pod for Ehdr32 {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Shdr32 {
+    pub name: u32,
+    pub shdr_type: u32,
+    pub flags: u32,
+    pub addr: u32,
+    pub offset: u32,
+    pub size: u32,
+    pub link: u32,
+    pub info: u32,
+    pub addr_align: u32,
+    pub ent_size: u32,
+}
+
+unsafe impl Pod for Shdr32 {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Phdr32 {
+    pub p_type: u32,
+    pub offset: u32,
+    pub v_addr: u32,
+    pub p_addr: u32,
+    pub file_size: u32,
+    pub mem_size: u32,
+    pub flags: u32,
+    pub align: u32,
+}
+
+unsafe impl Pod for Phdr32 {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Sym32 {
+    pub name: u32,
+    pub val: u32,
+    pub size: u32,
+    pub info: u8,
+    pub other: u8,
+    pub shndx: u16,
+}
+
+unsafe impl Pod for Sym32 {}
+
+// The symbol index and type share a single word, 24 and 8 bits wide.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Rela32 {
+    pub offset: u32,
+    pub info: u32,
+    pub addend: i32,
+}
+
+unsafe impl Pod for Rela32 {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Chdr32 {
+    pub ch_type: u32,
+    pub size: u32,
+    pub addr_align: u32,
+}
+
+unsafe impl Pod for Chdr32 {}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Dyn32 {
+    pub tag: i32,
+    pub val: u32,
+}
+
+unsafe impl Pod for Dyn32 {}
+
+// The ELF class of a file. The linker works on the 64-bit structs of
+// `elf`. ELF32 inputs are widened as they are read, and ELF32 outputs are
+// narrowed as they are written, so only the edges depend on the class.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ElfClass {
+    Elf32,
+    Elf64,
+}
+
+#[allow(dead_code)]
+impl ElfClass {
+    pub fn from_contents(contents: &[u8]) -> Self {
+        if contents.len() > EI_CLASS as usize && contents[EI_CLASS as usize] == ELFCLASS32 {
+            ElfClass::Elf32
+        } else {
+            ElfClass::Elf64
+        }
+    }
+
+    pub fn is_32(self) -> bool {
+        self == ElfClass::Elf32
+    }
+
+    pub fn word_size(self) -> usize {
+        if self.is_32() {
+            4
+        } else {
+            8
+        }
+    }
+
+    pub fn ehdr_size(self) -> usize {
+        if self.is_32() {
+            EHDR32_SIZE
+        } else {
+            EHDR_SIZE
+        }
+    }
+
+    pub fn shdr_size(self) -> usize {
+        if self.is_32() {
+            SHDR32_SIZE
+        } else {
+            SHDR_SIZE
+        }
+    }
+
+    pub fn phdr_size(self) -> usize {
+        if self.is_32() {
+            PHDR32_SIZE
+        } else {
+            PHDR_SIZE
+        }
+    }
+
+    pub fn sym_size(self) -> usize {
+        if self.is_32() {
+            SYM32_SIZE
+        } else {
+            SYM_SIZE
+        }
+    }
+
+    pub fn rela_size(self) -> usize {
+        if self.is_32() {
+            RELA32_SIZE
+        } else {
+            RELA_SIZE
+        }
+    }
+
+    pub fn chdr_size(self) -> usize {
+        if self.is_32() {
+            CHDR32_SIZE
+        } else {
+            CHDR_SIZE
+        }
+    }
+
+    pub fn dyn_size(self) -> usize {
+        if self.is_32() {
+            DYN32_SIZE
+        } else {
+            DYN_SIZE
+        }
+    }
+
+    pub fn read_ehdr(self, data: &[u8]) -> Ehdr {
+        if !self.is_32() {
+            return read(data);
+        }
+        let e: Ehdr32 = read(data);
+        Ehdr {
+            ident: e.ident,
+            hdr_type: e.hdr_type,
+            machine: e.machine,
+            version: e.version,
+            entry: e.entry as u64,
+            ph_off: e.ph_off as u64,
+            sh_off: e.sh_off as u64,
+            flags: e.flags,
+            eh_size: e.eh_size,
+            ph_ent_size: e.ph_ent_size,
+            ph_num: e.ph_num,
+            sh_ent_size: e.sh_ent_size,
+            sh_num: e.sh_num,
+            sh_strndx: e.sh_strndx,
+        }
+    }
+
+    pub fn read_shdr(self, data: &[u8]) -> Shdr {
+        if !self.is_32() {
+            return read(data);
+        }
+        let s: Shdr32 = read(data);
+        Shdr {
+            name: s.name,
+            shdr_type: s.shdr_type,
+            flags: s.flags as u64,
+            addr: s.addr as u64,
+            offset: s.offset as u64,
+            size: s.size as u64,
+            link: s.link,
+            info: s.info,
+            addr_align: s.addr_align as u64,
+            ent_size: s.ent_size as u64,
+        }
+    }
+
+    pub fn read_sym(self, data: &[u8]) -> Sym {
+        if !self.is_32() {
+            return read(data);
+        }
+        let s: Sym32 = read(data);
+        Sym {
+            name: s.name,
+            info: s.info,
+            other: s.other,
+            shndx: s.shndx,
+            val: s.val as u64,
+            size: s.size as u64,
+        }
+    }
+
+    pub fn read_rela(self, data: &[u8]) -> Rela {
+        if !self.is_32() {
+            return read(data);
+        }
+        let r: Rela32 = read(data);
+        Rela {
+            offset: r.offset as u64,
+            ty: r.info & 0xff,
+            sym: r.info >> 8,
+            addend: r.addend as i64,
+        }
+    }
+
+    pub fn read_chdr(self, data: &[u8]) -> Chdr {
+        if !self.is_32() {
+            return read(data);
+        }
+        let c: Chdr32 = read(data);
+        Chdr {
+            ch_type: c.ch_type,
+            reserved: 0,
+            size: c.size as u64,
+            addr_align: c.addr_align as u64,
+        }
+    }
+
+    pub fn read_dyn(self, data: &[u8]) -> Dyn {
+        if !self.is_32() {
+            return read(data);
+        }
+        let d: Dyn32 = read(data);
+        Dyn {
+            tag: d.tag as i64,
+            val: d.val as u64,
+        }
+    }
+
+    pub fn write_ehdr(self, buf: &mut [u8], e: Ehdr) {
+        if !self.is_32() {
+            return write(&mut buf[..EHDR_SIZE], e);
+        }
+        let e32 = Ehdr32 {
+            ident: e.ident,
+            hdr_type: e.hdr_type,
+            machine: e.machine,
+            version: e.version,
+            entry: e.entry as u32,
+            ph_off: e.ph_off as u32,
+            sh_off: e.sh_off as u32,
+            flags: e.flags,
+            eh_size: e.eh_size,
+            ph_ent_size: e.ph_ent_size,
+            ph_num: e.ph_num,
+            sh_ent_size: e.sh_ent_size,
+            sh_num: e.sh_num,
+            sh_strndx: e.sh_strndx,
+        };
+        write(&mut buf[..EHDR32_SIZE], e32)
+    }
+
+    pub fn write_shdr(self, buf: &mut [u8], s: Shdr) {
+        if !self.is_32() {
+            return write(&mut buf[..SHDR_SIZE], s);
+        }
+        let s32 = Shdr32 {
+            name: s.name,
+            shdr_type: s.shdr_type,
+            flags: s.flags as u32,
+            addr: s.addr as u32,
+            offset: s.offset as u32,
+            size: s.size as u32,
+            link: s.link,
+            info: s.info,
+            addr_align: s.addr_align as u32,
+            ent_size: s.ent_size as u32,
+        };
+        write(&mut buf[..SHDR32_SIZE], s32)
+    }
+
+    pub fn write_phdr(self, buf: &mut [u8], p: Phdr) {
+        if !self.is_32() {
+            return write(&mut buf[..PHDR_SIZE], p);
+        }
+        let p32 = Phdr32 {
+            p_type: p.p_type,
+            offset: p.offset as u32,
+            v_addr: p.v_addr as u32,
+            p_addr: p.p_addr as u32,
+            file_size: p.file_size as u32,
+            mem_size: p.mem_size as u32,
+            flags: p.flags,
+            align: p.align as u32,
+        };
+        write(&mut buf[..PHDR32_SIZE], p32)
+    }
+
+    pub fn write_sym(self, buf: &mut [u8], s: Sym) {
+        if !self.is_32() {
+            return write(&mut buf[..SYM_SIZE], s);
+        }
+        let s32 = Sym32 {
+            name: s.name,
+            val: s.val as u32,
+            size: s.size as u32,
+            info: s.info,
+            other: s.other,
+            shndx: s.shndx,
+        };
+        write(&mut buf[..SYM32_SIZE], s32)
+    }
+
+    pub fn write_dyn(self, buf: &mut [u8], d: Dyn) {
+        if !self.is_32() {
+            return write(&mut buf[..DYN_SIZE], d);
+        }
+        let d32 = Dyn32 {
+            tag: d.tag as i32,
+            val: d.val as u32,
+        };
+        write(&mut buf[..DYN32_SIZE], d32)
+    }
+
+    pub fn write_chdr(self, buf: &mut [u8], c: Chdr) {
+        if !self.is_32() {
+            return write(&mut buf[..CHDR_SIZE], c);
+        }
+        let c32 = Chdr32 {
+            ch_type: c.ch_type,
+            size: c.size as u32,
+            addr_align: c.addr_align as u32,
+        };
+        write(&mut buf[..CHDR32_SIZE], c32)
+    }
+}
+
+#[test]
+fn test_elf_class_sizes() {
+    assert_eq!(EHDR32_SIZE, 52);
+    assert_eq!(SHDR32_SIZE, 40);
+    assert_eq!(PHDR32_SIZE, 32);
+    assert_eq!(SYM32_SIZE, 16);
+    assert_eq!(RELA32_SIZE, 12);
+    assert_eq!(CHDR32_SIZE, 12);
+}
+
+#[test]
+fn test_read_rela32() {
+    let mut buf = [0u8; RELA32_SIZE];
+    write(
+        &mut buf[..],
+        Rela32 {
+            offset: 0x10,
+            info: 7 << 8 | 18,
+            addend: -4,
+        },
+    );
+    let rel = ElfClass::Elf32.read_rela(&buf);
+    assert_eq!((rel.offset, rel.sym, rel.ty, rel.addend), (0x10, 7, 18, -4));
+}
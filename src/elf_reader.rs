@@ -0,0 +1,249 @@
+use crate::{
+    elf::{
+        Ehdr, Rela, Shdr, Sym, EI_CLASS, EI_DATA, ELFCLASS64, ELFDATA2LSB, SHN_XINDEX, SHT_NOBITS,
+        SHT_NULL,
+    },
+    elf_class::{ElfClass, ELFCLASS32},
+    error::LinkError,
+    magic::check_magic,
+};
+
+// A bounds-checked view of an ELF file. Every offset and size taken from
+// the file is validated before the bytes behind it are decoded, so a
+// malformed input is reported as an error instead of crashing the linker.
+// Nothing is copied: section contents are slices of the file.
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub struct ElfReader<'a> {
+    pub name: &'a str,
+    pub data: &'a [u8],
+    pub class: ElfClass,
+    pub ehdr: Ehdr,
+    shdrs: &'a [u8],
+    num_sections: usize,
+}
+
+// Returns data[offset..offset + size], or None if any of it is out of range.
+#[allow(dead_code)]
+pub fn get_range(data: &[u8], offset: u64, size: u64) -> Option<&[u8]> {
+    let end = offset.checked_add(size)?;
+    data.get(usize::try_from(offset).ok()?..usize::try_from(end).ok()?)
+}
+
+#[allow(dead_code)]
+impl<'a> ElfReader<'a> {
+    pub fn new(name: &'a str, data: &'a [u8]) -> Result<Self, LinkError> {
+        let corrupted = |reason: &str| LinkError::bad_elf(name, reason);
+
+        if !check_magic(data) || data.len() <= EI_DATA as usize {
+            return Err(corrupted("not an ELF file"));
+        }
+        let class = match data[EI_CLASS as usize] {
+            ELFCLASS32 => ElfClass::Elf32,
+            ELFCLASS64 => ElfClass::Elf64,
+            _ => return Err(corrupted("unknown ELF class")),
+        };
+        if data[EI_DATA as usize] != ELFDATA2LSB {
+            return Err(corrupted("big-endian ELF files are not supported"));
+        }
+        if data.len() < class.ehdr_size() {
+            return Err(corrupted("file too small"));
+        }
+        let ehdr = class.read_ehdr(data);
+
+        let mut reader = ElfReader {
+            name,
+            data,
+            class,
+            ehdr,
+            shdrs: &[],
+            num_sections: 0,
+        };
+        if ehdr.sh_off == 0 {
+            return Ok(reader);
+        }
+        if ehdr.sh_ent_size as usize != class.shdr_size() {
+            return Err(corrupted("unexpected section header size"));
+        }
+
+        // With 0xff00 sections or more, e_shnum is zero and the real count
+        // is in the size of the first section header.
+        let out_of_range = || corrupted("section header table is out of range");
+        let first =
+            get_range(data, ehdr.sh_off, class.shdr_size() as u64).ok_or_else(out_of_range)?;
+        let mut num_sections = ehdr.sh_num as u64;
+        if num_sections == 0 {
+            num_sections = class.read_shdr(first).size;
+        }
+        let table_size = num_sections
+            .checked_mul(class.shdr_size() as u64)
+            .ok_or_else(out_of_range)?;
+        reader.shdrs = get_range(data, ehdr.sh_off, table_size).ok_or_else(out_of_range)?;
+        reader.num_sections = num_sections as usize;
+
+        if reader.num_sections > 0 && reader.shstrndx() >= reader.num_sections {
+            return Err(corrupted("invalid section name string table index"));
+        }
+        Ok(reader)
+    }
+
+    pub fn num_sections(&self) -> usize {
+        self.num_sections
+    }
+
+    // The index of the section name string table.
+    pub fn shstrndx(&self) -> usize {
+        if self.ehdr.sh_strndx == SHN_XINDEX && self.num_sections > 0 {
+            return self.section(0).link as usize;
+        }
+        self.ehdr.sh_strndx as usize
+    }
+
+    fn section(&self, idx: usize) -> Shdr {
+        self.class
+            .read_shdr(&self.shdrs[idx * self.class.shdr_size()..])
+    }
+
+    // The section headers. A header whose contents lie outside of the file
+    // is an error.
+    pub fn sections(&self) -> impl Iterator<Item = Result<Shdr, LinkError>> + 'a {
+        let reader = *self;
+        (0..self.num_sections).map(move |idx| {
+            let shdr = reader.section(idx);
+            reader.section_data(&shdr).map_err(|_| {
+                LinkError::bad_elf(reader.name, format!("section {} is out of range", idx))
+            })?;
+            Ok(shdr)
+        })
+    }
+
+    // The contents of a section. SHT_NOBITS and SHT_NULL sections have no
+    // bytes in the file.
+    pub fn section_data(&self, shdr: &Shdr) -> Result<&'a [u8], LinkError> {
+        if shdr.shdr_type == SHT_NOBITS || shdr.shdr_type == SHT_NULL {
+            return Ok(&[]);
+        }
+        get_range(self.data, shdr.offset, shdr.size).ok_or_else(|| {
+            LinkError::bad_elf(
+                self.name,
+                format!("section header is out of range: {:?}", shdr.offset),
+            )
+        })
+    }
+
+    fn entries(&self, shdr: &Shdr, ent_size: usize, what: &str) -> Result<&'a [u8], LinkError> {
+        let data = self.section_data(shdr)?;
+        if data.len() % ent_size != 0 {
+            return Err(LinkError::bad_elf(
+                self.name,
+                format!("{} section size is not a multiple of {}", what, ent_size),
+            ));
+        }
+        Ok(data)
+    }
+
+    // The entries of a SHT_SYMTAB or SHT_DYNSYM section.
+    pub fn symbols(&self, shdr: &Shdr) -> Result<impl Iterator<Item = Sym> + 'a, LinkError> {
+        let class = self.class;
+        let data = self.entries(shdr, class.sym_size(), "symbol table")?;
+        Ok(data
+            .chunks_exact(class.sym_size())
+            .map(move |b| class.read_sym(b)))
+    }
+
+    // The entries of a SHT_RELA section.
+    pub fn relocations(&self, shdr: &Shdr) -> Result<impl Iterator<Item = Rela> + 'a, LinkError> {
+        let class = self.class;
+        let data = self.entries(shdr, class.rela_size(), "relocation")?;
+        Ok(data
+            .chunks_exact(class.rela_size())
+            .map(move |b| class.read_rela(b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        elf::{EHDR_SIZE, SHDR_SIZE, SHT_PROGBITS, SHT_STRTAB, SHT_SYMTAB, SYM_SIZE},
+        utils::write,
+    };
+
+    // An ELF64 header followed by a null section, .shstrtab and .symtab.
+    fn new_object() -> Vec<u8> {
+        let shstrtab = b"\0.shstrtab\0.symtab\0";
+        let mut buf = vec![0; EHDR_SIZE + 3 * SHDR_SIZE];
+        let shstrtab_off = buf.len();
+        buf.extend_from_slice(shstrtab);
+        let symtab_off = buf.len();
+        buf.resize(symtab_off + 2 * SYM_SIZE, 0);
+
+        let mut ehdr = Ehdr::new();
+        ehdr.ident[..4].copy_from_slice(b"\x7fELF");
+        ehdr.ident[EI_CLASS as usize] = ELFCLASS64;
+        ehdr.ident[EI_DATA as usize] = ELFDATA2LSB;
+        ehdr.sh_off = EHDR_SIZE as u64;
+        ehdr.sh_ent_size = SHDR_SIZE as u16;
+        ehdr.sh_num = 3;
+        ehdr.sh_strndx = 1;
+        write(&mut buf[..EHDR_SIZE], ehdr);
+
+        let mut shdr = Shdr::new();
+        shdr.shdr_type = SHT_STRTAB;
+        shdr.name = 1;
+        shdr.offset = shstrtab_off as u64;
+        shdr.size = shstrtab.len() as u64;
+        write(&mut buf[EHDR_SIZE + SHDR_SIZE..][..SHDR_SIZE], shdr);
+        shdr.shdr_type = SHT_SYMTAB;
+        shdr.name = 11;
+        shdr.offset = symtab_off as u64;
+        shdr.size = 2 * SYM_SIZE as u64;
+        write(&mut buf[EHDR_SIZE + 2 * SHDR_SIZE..][..SHDR_SIZE], shdr);
+        buf
+    }
+
+    #[test]
+    fn test_read_object() {
+        let buf = new_object();
+        let reader = ElfReader::new("a.o", &buf).unwrap();
+        let shdrs: Vec<Shdr> = reader.sections().collect::<Result<_, _>>().unwrap();
+        assert_eq!(shdrs.len(), 3);
+        assert_eq!(reader.shstrndx(), 1);
+        assert_eq!(reader.symbols(&shdrs[2]).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_read_truncated() {
+        let buf = new_object();
+        for len in 0..buf.len() {
+            let reader = match ElfReader::new("a.o", &buf[..len]) {
+                Ok(reader) => reader,
+                Err(_) => continue,
+            };
+            let shdrs: Result<Vec<Shdr>, _> = reader.sections().collect();
+            assert!(shdrs.is_err(), "accepted a file truncated to {} bytes", len);
+        }
+
+        let mut buf = new_object();
+        buf[EI_CLASS as usize] = 3;
+        assert!(ElfReader::new("a.o", &buf).is_err());
+    }
+
+    #[test]
+    fn test_bad_section_range() {
+        let mut buf = new_object();
+        let mut shdr = Shdr::new();
+        shdr.shdr_type = SHT_PROGBITS;
+        shdr.offset = u64::MAX;
+        shdr.size = 2;
+        write(&mut buf[EHDR_SIZE + 2 * SHDR_SIZE..][..SHDR_SIZE], shdr);
+
+        let reader = ElfReader::new("a.o", &buf).unwrap();
+        assert!(reader.sections().nth(2).unwrap().is_err());
+        assert!(reader.section_data(&shdr).is_err());
+
+        shdr.offset = 0;
+        shdr.size = 3;
+        assert!(reader.symbols(&shdr).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use std::{fmt, io};
+
+// An error that stops a link. Errors found by the passes, such as
+// undefined symbols, are collected in the context up to --error-limit so
+// that a single run reports as many of them as possible; see
+// `Context::error`.
+#[derive(Debug)]
+pub enum LinkError {
+    Io {
+        path: String,
+        error: io::Error,
+    },
+    // A malformed or unsupported input file.
+    BadElf {
+        file: String,
+        reason: String,
+    },
+    LibraryNotFound(String),
+    UnknownEmulation,
+    // An unknown command line option or an invalid option argument.
+    BadArgument(String),
+    BadVersionScript(String),
+    UndefinedSymbol {
+        name: String,
+        referenced_by: String,
+    },
+    DuplicateSymbol {
+        name: String,
+        file: String,
+        other_file: String,
+    },
+    // The value doesn't fit in the relocated field; [lo, hi) is the range
+    // of values that do.
+    RelocOverflow {
+        section: String,
+        ty: u32,
+        offset: u64,
+        value: i64,
+        lo: i64,
+        hi: i64,
+    },
+    UnsupportedReloc {
+        section: String,
+        ty: u32,
+    },
+    // Anything else that makes the inputs impossible to link, e.g. objects
+    // built for incompatible ABIs.
+    Other(String),
+    // Several of the above, in the order they were found. `truncated` is
+    // set when --error-limit was reached.
+    Multiple {
+        errors: Vec<LinkError>,
+        truncated: bool,
+    },
+}
+
+impl LinkError {
+    pub fn bad_elf(file: &str, reason: impl Into<String>) -> Self {
+        LinkError::BadElf {
+            file: file.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    // The individual errors, for a caller that reports them one by one.
+    pub fn errors(&self) -> &[LinkError] {
+        match self {
+            LinkError::Multiple { errors, .. } => errors,
+            _ => std::slice::from_ref(self),
+        }
+    }
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::Io { path, error } => write!(f, "{}: {}", path, error),
+            LinkError::BadElf { file, reason } => write!(f, "{}: {}", file, reason),
+            LinkError::LibraryNotFound(name) => write!(f, "library not found: -l{}", name),
+            LinkError::UnknownEmulation => write!(f, "unknown emulation type"),
+            LinkError::BadArgument(msg) => write!(f, "{}", msg),
+            LinkError::BadVersionScript(reason) => write!(f, "version script: {}", reason),
+            LinkError::UndefinedSymbol {
+                name,
+                referenced_by,
+            } => write!(f, "undefined symbol: {}\n>>> referenced by {}", name, referenced_by),
+            LinkError::DuplicateSymbol {
+                name,
+                file,
+                other_file,
+            } => write!(
+                f,
+                "duplicate symbol: {}\n>>> defined in {}\n>>> defined in {}",
+                name, other_file, file
+            ),
+            LinkError::RelocOverflow {
+                section,
+                ty,
+                offset,
+                value,
+                lo,
+                hi,
+            } => write!(
+                f,
+                "{}: relocation {} at 0x{:x} out of range: {} is not in [{}, {})",
+                section, ty, offset, value, lo, hi
+            ),
+            LinkError::UnsupportedReloc { section, ty } => {
+                write!(f, "{}: unsupported relocation: {}", section, ty)
+            }
+            LinkError::Other(msg) => write!(f, "{}", msg),
+            LinkError::Multiple { errors, truncated } => {
+                for (i, err) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", err)?;
+                }
+                if *truncated {
+                    write!(
+                        f,
+                        "\ntoo many errors emitted, stopping now (use --error-limit=0 to see all errors)"
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LinkError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
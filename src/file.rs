@@ -1,60 +1,30 @@
-use std::fs::File;
-use std::io::prelude::*;
+use memmap2::Mmap;
 
-use crate::context::Context;
+use crate::linker::LinkerConfig;
+use crate::output_file::map_input_file;
 
 #[allow(dead_code)]
 #[repr(C, align(8))]
 pub struct ElfFile<'a> {
     pub name: &'a str,
-    pub contents: &'static [u8],
-    pub files: Vec<*const ElfFile<'a>>,
+    pub contents: &'a [u8],
 }
 
+// Returns the path and a mapping of lib<name>.so or lib<name>.a in the
+// first library path that has either.
 #[allow(dead_code)]
-pub fn must_new_file(file_name: &str) -> ElfFile {
-    let mut f = File::open(&file_name).unwrap();
-    let mut buffer = Vec::new();
-
-    f.read_to_end(&mut buffer).unwrap();
-    let buf = Box::new(buffer);
-
-    ElfFile {
-        name: file_name,
-        contents: Box::leak(buf),
-        files: vec![],
-    }
-}
-
-#[allow(dead_code)]
-fn open_library(file_path: &str) -> Option<ElfFile<'_>> {
-    let f = File::open(file_path);
-    if f.is_err() {
-        return None;
-    }
-    let mut contents = Vec::new();
-
-    f.unwrap().read_to_end(&mut contents).unwrap();
-    let contents = Box::new(contents);
-
-    Some(ElfFile {
-        name: file_path,
-        contents: Box::leak(contents),
-        files: vec![],
-    })
-}
+pub fn find_library(args: &LinkerConfig, name: &str) -> Option<(String, Mmap)> {
+    for dir in &args.library_paths {
+        if !args.is_static {
+            let stem = String::from(dir) + "/lib" + name + ".so";
+            if let Ok(map) = map_input_file(&stem) {
+                return Some((stem, map));
+            }
+        }
 
-#[allow(dead_code)]
-pub fn find_library<'a>(ctx: &Context, name: &'a str) -> Option<ElfFile<'a>> {
-    for dir in &ctx.args.library_paths {
         let stem = String::from(dir) + "/lib" + name + ".a";
-        let stem = Box::leak(Box::new(stem));
-        let f = open_library(stem);
-        match f {
-            Some(f) => {
-                return Some(f);
-            }
-            _ => {}
+        if let Ok(map) = map_input_file(&stem) {
+            return Some((stem, map));
         }
     }
     None
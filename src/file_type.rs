@@ -1,13 +1,16 @@
+use crate::elf::{ET_DYN, ET_REL};
+use crate::error::LinkError;
 use crate::file::ElfFile;
 use crate::machine_type::get_machine_type_from_contents;
 use crate::magic::check_magic;
-use crate::utils::{fatal, read};
+use crate::utils::try_read;
 
 pub type FileType = u8;
 pub const FILE_TYPE_UNKNOWN: FileType = 0;
 pub const FILE_TYPE_EMPTY: FileType = 1;
 pub const FILE_TYPE_OBJECT: FileType = 2;
 pub const FILE_TYPE_ARCHIVE: FileType = 3;
+pub const FILE_TYPE_DSO: FileType = 4;
 
 #[allow(dead_code)]
 pub fn get_file_type(contents: &[u8]) -> FileType {
@@ -16,9 +19,9 @@ pub fn get_file_type(contents: &[u8]) -> FileType {
     }
 
     if check_magic(contents) {
-        let et = read(&contents[16..]);
-        match et {
-            1u16 => return FILE_TYPE_OBJECT,
+        match contents.get(16..).and_then(try_read) {
+            Some(ET_REL) => return FILE_TYPE_OBJECT,
+            Some(ET_DYN) => return FILE_TYPE_DSO,
             _ => {}
         }
     }
@@ -30,9 +33,10 @@ pub fn get_file_type(contents: &[u8]) -> FileType {
 }
 
 #[allow(dead_code)]
-pub fn check_file_compatibility(emulation: u8, elf_file: &ElfFile) {
+pub fn check_file_compatibility(emulation: u8, elf_file: &ElfFile) -> Result<(), LinkError> {
     let mt = get_machine_type_from_contents(elf_file.contents);
     if mt != emulation {
-        fatal("incompatible file type")
+        return Err(LinkError::bad_elf(elf_file.name, "incompatible file type"));
     }
+    Ok(())
 }
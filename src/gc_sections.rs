@@ -0,0 +1,163 @@
+use std::{collections::HashSet, sync::atomic::Ordering};
+
+use crate::{
+    arena::{SectionId, SymbolId},
+    context::Context,
+    elf::{Rela, SHF_ALLOC, SHT_FINI_ARRAY, SHT_INIT_ARRAY, SHT_NOTE, SHT_PREINIT_ARRAY},
+    input_section::InputSection,
+    object_file::ObjectFile,
+    passes::get_start_stop_section_name,
+};
+
+#[allow(dead_code)]
+pub fn gc_sections(ctx: &mut Context) {
+    let mut roots: Vec<SectionId> = vec![];
+    collect_root_set(ctx, &mut roots);
+    mark_sections(ctx, roots);
+    sweep_sections(ctx);
+}
+
+fn is_gc_root(isec: &InputSection) -> bool {
+    let shdr = isec.shdr();
+    if shdr.flags & SHF_ALLOC == 0 {
+        return true;
+    }
+
+    match shdr.shdr_type {
+        SHT_NOTE | SHT_INIT_ARRAY | SHT_FINI_ARRAY | SHT_PREINIT_ARRAY => return true,
+        _ => {}
+    }
+
+    let name = isec.name();
+    for prefix in [".ctors", ".dtors", ".init_array", ".fini_array", ".preinit_array"] {
+        if name.starts_with(prefix) {
+            return true;
+        }
+    }
+    name == ".init" || name == ".fini" || name == ".jcr"
+}
+
+fn enqueue_section(ctx: &mut Context, roots: &mut Vec<SectionId>, id: SectionId) {
+    let isec = &mut ctx.sections[id];
+    if !isec.is_alive || isec.is_visited {
+        return;
+    }
+    isec.is_visited = true;
+    roots.push(id);
+}
+
+// Adds the section a symbol is defined in to `found`.
+fn find_symbol_section(ctx: &Context, found: &mut Vec<SectionId>, sym: SymbolId) {
+    let sym = &ctx.symbols[sym];
+    if let Some(frag) = sym.section_fragment {
+        ctx.fragments[frag].is_alive.store(true, Ordering::Relaxed);
+    }
+    if let Some(isec) = sym.input_section {
+        found.push(isec);
+    }
+}
+
+fn collect_root_set(ctx: &mut Context, roots: &mut Vec<SectionId>) {
+    let mut found = vec![];
+    for &file in &ctx.objs {
+        for isec in ctx.files[file].input_sections.iter().flatten() {
+            let isec_ref = &ctx.sections[*isec];
+            if isec_ref.is_alive && is_gc_root(isec_ref) {
+                found.push(*isec);
+            }
+        }
+    }
+
+    for sym in ctx.get_root_symbols() {
+        find_symbol_section(ctx, &mut found, sym);
+    }
+    for isec in found {
+        enqueue_section(ctx, roots, isec);
+    }
+}
+
+// A reference to an undefined `__start_foo` or `__stop_foo` keeps every
+// input section that ends up in the output section `foo` alive.
+fn find_start_stop_sections(ctx: &Context, found: &mut Vec<SectionId>, osec_name: &str) {
+    for &file in &ctx.objs {
+        for isec in ctx.files[file].input_sections.iter().flatten() {
+            let osec = match ctx.sections[*isec].output_section {
+                Some(osec) => osec,
+                None => continue,
+            };
+            if ctx.output_sections[osec].chunk.name == osec_name {
+                found.push(*isec);
+            }
+        }
+    }
+}
+
+// Adds the sections a relocation keeps alive to `found`.
+fn visit_rel(
+    ctx: &Context,
+    found: &mut Vec<SectionId>,
+    start_stop_done: &mut HashSet<String>,
+    file: &ObjectFile,
+    rel: &Rela,
+) {
+    let input_file = &file.input_file;
+    let idx = rel.sym as usize;
+
+    if (idx as i64) < input_file.first_global.unwrap_or(0) {
+        if let Some(target) = file.get_rel_section(ctx, rel) {
+            found.push(target);
+        }
+        return;
+    }
+
+    let sym = input_file.symbols[idx];
+    if ctx.symbols[sym].is_defined() {
+        find_symbol_section(ctx, found, sym);
+        return;
+    }
+
+    let name = &ctx.symbols[sym].name;
+    if let Some(osec_name) = get_start_stop_section_name(name) {
+        if start_stop_done.insert(osec_name.to_string()) {
+            find_start_stop_sections(ctx, found, osec_name);
+        }
+    }
+}
+
+fn mark_sections(ctx: &mut Context, mut roots: Vec<SectionId>) {
+    let mut start_stop_done: HashSet<String> = HashSet::new();
+
+    while let Some(isec) = roots.pop() {
+        let mut found = vec![];
+        let isec = &ctx.sections[isec];
+        let file = &ctx.files[isec.object_file];
+
+        for rel in &isec.rels {
+            visit_rel(ctx, &mut found, &mut start_stop_done, file, rel);
+        }
+
+        // A live function keeps what its FDEs refer to alive, such as an
+        // LSDA or a personality routine. The first relocation of an FDE
+        // refers to the function itself and is skipped.
+        for fde in &file.fdes[isec.fde_begin..isec.fde_end] {
+            for rel in &fde.get_rels(ctx, file)[1..] {
+                visit_rel(ctx, &mut found, &mut start_stop_done, file, rel);
+            }
+        }
+
+        for target in found {
+            enqueue_section(ctx, &mut roots, target);
+        }
+    }
+}
+
+fn sweep_sections(ctx: &mut Context) {
+    for &file in &ctx.objs {
+        for isec in ctx.files[file].input_sections.iter().flatten() {
+            let isec = &mut ctx.sections[*isec];
+            if isec.is_alive && !isec.is_visited {
+                isec.is_alive = false;
+            }
+        }
+    }
+}
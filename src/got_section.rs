@@ -1,4 +1,5 @@
 use crate::{
+    arena::SymbolId,
     chunk::Chunk,
     context::Context,
     elf::{SHF_ALLOC, SHF_WRITE, SHT_PROGBITS},
@@ -7,49 +8,70 @@ use crate::{
 };
 
 #[allow(dead_code)]
-pub struct GotSection<'a> {
+pub struct GotSection {
     pub chunk: Chunk,
-    pub got_tp_syms: Vec<*mut Symbol<'a>>,
+    pub got_syms: Vec<SymbolId>,
+    pub got_tp_syms: Vec<SymbolId>,
+    // The size of an entry, set by the target.
+    pub word_size: u64,
 }
 
 #[allow(dead_code)]
-impl<'a> GotSection<'a> {
+impl GotSection {
     pub fn new() -> Self {
         let mut chunk = Chunk::new();
         chunk.name = ".got".to_string();
         chunk.shdr.shdr_type = SHT_PROGBITS;
         chunk.shdr.flags = SHF_ALLOC | SHF_WRITE;
-        chunk.shdr.addr_align = 0;
+        chunk.shdr.addr_align = 8;
 
         Self {
             chunk: chunk,
+            got_syms: vec![],
             got_tp_syms: vec![],
+            word_size: 8,
         }
     }
 
-    pub fn add_got_tp_symbol(&mut self, sym: *mut Symbol<'a>) {
-        unsafe { sym.as_mut().unwrap().got_tp_id = (self.chunk.shdr.size / 8).try_into().unwrap() };
-        self.chunk.shdr.size += 8;
-        self.got_tp_syms.push(sym);
+    // An entry holding the address of the symbol.
+    pub fn add_got_symbol(&mut self, id: SymbolId, sym: &mut Symbol) {
+        sym.got_id = (self.chunk.shdr.size / self.word_size).try_into().unwrap();
+        self.chunk.shdr.size += self.word_size;
+        self.got_syms.push(id);
     }
 
-    pub fn get_entries(&self, ctx: *mut Context) -> Vec<GotEntry> {
+    // An entry holding the offset of a TLS symbol from the thread pointer.
+    pub fn add_got_tp_symbol(&mut self, id: SymbolId, sym: &mut Symbol) {
+        sym.got_tp_id = (self.chunk.shdr.size / self.word_size).try_into().unwrap();
+        self.chunk.shdr.size += self.word_size;
+        self.got_tp_syms.push(id);
+    }
+
+    pub fn get_entries(&self, ctx: &Context) -> Vec<GotEntry> {
         let mut entries: Vec<GotEntry> = vec![];
+        for sym in &self.got_syms {
+            let sym = &ctx.symbols[*sym];
+            entries.push(GotEntry::new(sym.got_id as i64, sym.get_addr(ctx)))
+        }
         for sym in &self.got_tp_syms {
-            let idx = unsafe { sym.as_ref().unwrap().got_tp_id };
-            let val = unsafe { sym.as_ref().unwrap().get_addr() }
-                - unsafe { ctx.as_ref().unwrap().tp_addr };
-            let entry = GotEntry::new(idx as i64, val);
+            let sym = &ctx.symbols[*sym];
+            let val = sym.get_addr(ctx) - ctx.tp_addr;
+            let entry = GotEntry::new(sym.got_tp_id as i64, val);
             entries.push(entry)
         }
         entries
     }
 
-    pub fn copy_buf(&self, ctx: *mut Context) {
-        let base =
-            &mut unsafe { &mut ctx.as_mut().unwrap().buf }[self.chunk.shdr.offset as usize..];
-        for ent in self.get_entries(ctx) {
-            write(base, ent.val);
+    pub fn copy_buf(&self, ctx: &Context, buf: &mut [u8]) {
+        let entries = self.get_entries(ctx);
+        let base = &mut buf[self.chunk.shdr.offset as usize..];
+        for ent in entries {
+            let offset = ent.idx as usize * self.word_size as usize;
+            if self.word_size == 4 {
+                write(&mut base[offset..offset + 4], ent.val as u32);
+            } else {
+                write(&mut base[offset..offset + 8], ent.val);
+            }
         }
     }
 }
@@ -1,52 +1,191 @@
+use std::borrow::Cow;
+use std::ops::Deref;
+
+use memmap2::Mmap;
+
 use crate::archive::read_archive_members;
+use crate::arena::FileId;
+use crate::binary_file::create_binary_object_file;
 use crate::context::Context;
-use crate::file::{find_library, must_new_file, ElfFile};
+use crate::error::LinkError;
+use crate::file::{find_library, ElfFile};
 use crate::file_type::{
-    check_file_compatibility, get_file_type, FILE_TYPE_ARCHIVE, FILE_TYPE_OBJECT,
+    check_file_compatibility, get_file_type, FILE_TYPE_ARCHIVE, FILE_TYPE_DSO, FILE_TYPE_OBJECT,
 };
+use crate::linker::{LinkInput, LinkerConfig};
 use crate::object_file::{new_object_file, ObjectFile};
-use crate::utils::{fatal, remove_prefix};
+use crate::output_file::map_input_file;
+use crate::shared_file::{new_shared_file, SharedFile};
+use crate::target::Target;
+
+// The contents of an input: a mapping of the file it was read from, the
+// bytes it was given as, or the object made from it by -b binary.
+pub enum InputContents<'a> {
+    Map(Mmap),
+    Bytes(&'a [u8]),
+    Owned(Vec<u8>),
+}
+
+impl Deref for InputContents<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            InputContents::Map(map) => map,
+            InputContents::Bytes(data) => data,
+            InputContents::Owned(data) => data,
+        }
+    }
+}
+
+// An input of the link, opened once. The files of the link borrow their
+// names and contents from these, so they are kept until the link ends.
+pub struct OpenInput<'a> {
+    pub input: &'a LinkInput,
+    // The input's path or name, or the path a -l library was found at.
+    pub name: Cow<'a, str>,
+    // None for -b, and for a -l library that was not found.
+    pub contents: Option<InputContents<'a>>,
+    // Whether -b binary applies to the input.
+    pub is_binary: bool,
+}
+
+// Opens the inputs in command line order. Files are mapped rather than
+// read, so that their pages are only loaded as the link touches them.
+#[allow(dead_code)]
+pub fn open_input_files<'a>(
+    args: &LinkerConfig,
+    inputs: &'a [LinkInput],
+) -> Result<Vec<OpenInput<'a>>, LinkError> {
+    // -b binary applies to the files that follow it, up to the next -b.
+    let mut is_binary = false;
+    let mut files = vec![];
+    for input in inputs {
+        let (name, contents) = match input {
+            LinkInput::Path(path) => match map_input_file(path) {
+                Ok(map) => (Cow::Borrowed(path.as_str()), Some(InputContents::Map(map))),
+                Err(error) => {
+                    return Err(LinkError::Io {
+                        path: path.clone(),
+                        error,
+                    })
+                }
+            },
+            LinkInput::Bytes { name, data } => (
+                Cow::Borrowed(name.as_str()),
+                Some(InputContents::Bytes(data)),
+            ),
+            LinkInput::Library(name) => match find_library(args, name) {
+                Some((path, map)) => (Cow::Owned(path), Some(InputContents::Map(map))),
+                None => (Cow::Borrowed(name.as_str()), None),
+            },
+            LinkInput::Format(format) => {
+                is_binary = format == "binary";
+                (Cow::Borrowed(format.as_str()), None)
+            }
+        };
+        files.push(OpenInput {
+            input,
+            name,
+            contents,
+            is_binary: is_binary && matches!(input, LinkInput::Path(_) | LinkInput::Bytes { .. }),
+        });
+    }
+    Ok(files)
+}
+
+// Replaces the contents of -b binary inputs with objects that wrap them.
+#[allow(dead_code)]
+pub fn create_binary_object_files(files: &mut [OpenInput], target: &dyn Target) {
+    for file in files.iter_mut().filter(|file| file.is_binary) {
+        let elf_file = ElfFile {
+            name: &file.name,
+            contents: file.contents.as_deref().unwrap(),
+        };
+        let contents = create_binary_object_file(&elf_file, target);
+        file.contents = Some(InputContents::Owned(contents));
+    }
+}
 
 #[allow(dead_code)]
-pub fn read_input_files(mut ctx: &mut Context, remaining: &Vec<String>) {
-    for arg in remaining {
-        let (arg, ok) = remove_prefix(&arg, "-l");
-        let arg = Box::leak(Box::new(String::from(&arg)));
-        if ok {
-            let lib = find_library(&mut ctx, arg).unwrap();
-            read_file(&mut ctx, lib)
-        } else {
-            read_file(&mut ctx, must_new_file(arg))
+pub fn read_input_files<'a>(
+    ctx: &mut Context<'a>,
+    files: &'a [OpenInput],
+) -> Result<(), LinkError> {
+    for file in files {
+        let contents = match (&file.contents, file.input) {
+            (Some(contents), _) => contents,
+            (None, LinkInput::Library(name)) => {
+                return Err(LinkError::LibraryNotFound(name.to_string()))
+            }
+            (None, _) => continue,
+        };
+        read_file(
+            ctx,
+            ElfFile {
+                name: &file.name,
+                contents,
+            },
+        )?;
+        if file.is_binary {
+            let id = *ctx.objs.last().unwrap();
+            ctx.files[id].is_binary = true;
         }
     }
+    Ok(())
 }
 
 #[allow(dead_code)]
-pub fn read_file<'a>(ctx: &mut Context<'a>, elf_file: ElfFile<'a>) {
+pub fn read_file<'a>(ctx: &mut Context<'a>, elf_file: ElfFile<'a>) -> Result<(), LinkError> {
     let ft = get_file_type(elf_file.contents);
     let emulation: u8 = ctx.args.emulation;
     match ft {
         FILE_TYPE_OBJECT => {
-            ctx.objs.push(Box::leak(Box::new(create_object_file(
-                emulation, elf_file, false,
-            ))));
+            let file = create_object_file(ctx.files.next_id(), emulation, elf_file, false)?;
+            ctx.objs.push(ctx.files.push(file));
         }
         FILE_TYPE_ARCHIVE => {
-            for child in read_archive_members(elf_file) {
-                assert_eq!(get_file_type(child.contents), FILE_TYPE_OBJECT);
-                ctx.objs.push(Box::leak(Box::new(create_object_file(
-                    emulation, child, true,
-                ))))
+            for child in read_archive_members(elf_file)? {
+                if get_file_type(child.contents) != FILE_TYPE_OBJECT {
+                    return Err(LinkError::bad_elf(child.name, "archive member is not an object file"));
+                }
+                let file = create_object_file(ctx.files.next_id(), emulation, child, true)?;
+                ctx.objs.push(ctx.files.push(file));
+            }
+        }
+        FILE_TYPE_DSO => {
+            if ctx.args.is_static {
+                return Err(LinkError::bad_elf(
+                    elf_file.name,
+                    "attempted static link of dynamic object",
+                ));
             }
+            let file = create_shared_file(ctx, elf_file)?;
+            ctx.dsos.push(file);
         }
-        _ => fatal("unkown file type!"),
+        _ => return Err(LinkError::bad_elf(elf_file.name, "unknown file type")),
     }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn create_object_file<'a>(
+    id: FileId,
+    emulation: u8,
+    elf_file: ElfFile<'a>,
+    in_lib: bool,
+) -> Result<ObjectFile<'a>, LinkError> {
+    check_file_compatibility(emulation, &elf_file)?;
+    new_object_file(id, elf_file, !in_lib)
 }
 
 #[allow(dead_code)]
-fn create_object_file<'a>(emulation: u8, elf_file: ElfFile<'a>, in_lib: bool) -> ObjectFile<'a> {
-    check_file_compatibility(emulation, &elf_file);
-    let mut obj = new_object_file(elf_file, !in_lib);
-    obj.parse();
-    obj
+fn create_shared_file<'a>(
+    ctx: &mut Context<'a>,
+    elf_file: ElfFile<'a>,
+) -> Result<SharedFile<'a>, LinkError> {
+    check_file_compatibility(ctx.args.emulation, &elf_file)?;
+    let mut file = new_shared_file(ctx.dsos.next_id(), elf_file)?;
+    file.parse(ctx)?;
+    Ok(file)
 }
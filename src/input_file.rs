@@ -1,30 +1,33 @@
+use crate::arena::SymbolId;
 use crate::elf::{Ehdr, Shdr, Sym};
-use crate::elf::{EHDR_SIZE, SHDR_SIZE, SYM_SIZE};
+use crate::elf_class::ElfClass;
+use crate::elf_reader::ElfReader;
+use crate::error::LinkError;
 use crate::file::ElfFile;
-use crate::magic::check_magic;
-use crate::symbol::Symbol;
-use crate::utils::{fatal, read};
-
-const SHN_XINDEX: u16 = 0xffff;
 
 #[allow(dead_code)]
 pub struct InputFile<'a> {
     pub file: ElfFile<'a>,
+    pub reader: ElfReader<'a>,
+    pub class: ElfClass,
     pub elf_sections: Vec<Shdr>,
     pub elf_syms: Vec<Sym>,
     pub first_global: Option<i64>,
     pub sh_strtab: Option<&'a [u8]>,
     pub symbol_strtab: Option<&'a [u8]>,
     pub is_alive: bool,
-    pub symbols: Vec<*mut Symbol<'a>>,
-    pub local_symbols: Vec<*mut Symbol<'a>>,
+    pub symbols: Vec<SymbolId>,
+    pub local_symbols: Vec<SymbolId>,
 }
 
 #[allow(dead_code)]
-pub fn new_input_file(file: ElfFile) -> *mut InputFile {
+pub fn new_input_file(file: ElfFile) -> Result<InputFile, LinkError> {
+    let reader = ElfReader::new(file.name, file.contents)?;
     let mut f = InputFile {
+        class: reader.class,
         file: file,
-        elf_sections: Vec::new(),
+        reader,
+        elf_sections: reader.sections().collect::<Result<_, _>>()?,
         elf_syms: Vec::new(),
         first_global: None,
         sh_strtab: None,
@@ -34,63 +37,38 @@ pub fn new_input_file(file: ElfFile) -> *mut InputFile {
         local_symbols: vec![],
     };
 
-    if f.file.contents.len() < EHDR_SIZE {
-        fatal("file too small");
-    }
-    if !check_magic(f.file.contents) {
-        fatal("not an ELF file")
-    }
-    let ehdr: Ehdr = read::<Ehdr>(f.file.contents);
-    let contents = &f.file.contents[ehdr.sh_off as usize..];
-    let shdr: Shdr = read(contents);
-
-    let mut num_sections = ehdr.sh_num as i64;
-    if num_sections == 0 {
-        num_sections = shdr.size as i64;
-    }
-
-    f.elf_sections.push(shdr);
-    for i in 0..(num_sections - 1) {
-        let idx_shdr = (i + 1) as usize * SHDR_SIZE;
-        let contents = &contents[idx_shdr..];
-        let shdr: Shdr = read(contents);
-        f.elf_sections.push(shdr);
-        num_sections -= 1;
-    }
-
-    let mut sh_strtab = ehdr.sh_strndx as i64;
-    if ehdr.sh_strndx == SHN_XINDEX {
-        sh_strtab = shdr.link as i64;
-    }
-    f.sh_strtab = Some(f.get_bytes_from_idx(sh_strtab));
-    return Box::leak(Box::new(f));
+    f.sh_strtab = Some(f.get_bytes_from_idx(reader.shstrndx() as i64)?);
+    return Ok(f);
 }
 
 #[allow(dead_code)]
 impl<'a> InputFile<'a> {
-    pub fn get_bytes_from_shdr(&self, shdr: &Shdr) -> &'a [u8] {
-        let end = (shdr.offset + shdr.size) as usize;
-        if self.file.contents.len() < end {
-            fatal(&format!(
-                "section header is out of range: {:?}",
-                shdr.offset
-            ));
-        }
+    pub fn get_bytes_from_shdr(&self, shdr: &Shdr) -> Result<&'a [u8], LinkError> {
+        self.reader.section_data(shdr)
+    }
 
-        &self.file.contents[shdr.offset as usize..end]
+    pub fn get_bytes_from_idx(&self, idx: i64) -> Result<&'a [u8], LinkError> {
+        match self.elf_sections.get(idx as usize) {
+            Some(shdr) => self.get_bytes_from_shdr(shdr),
+            None => Err(LinkError::bad_elf(
+                self.file.name,
+                format!("invalid section index: {}", idx),
+            )),
+        }
     }
 
-    pub fn get_bytes_from_idx(&self, idx: i64) -> &'a [u8] {
-        &self.get_bytes_from_shdr(&self.elf_sections[idx as usize])
+    // An error for a malformed part of this file.
+    pub fn corrupted(&self, reason: impl Into<String>) -> LinkError {
+        LinkError::bad_elf(self.file.name, reason)
     }
 
-    pub fn fillup_elf_syms(&mut self, shdr: Shdr) {
-        let mut bs = self.get_bytes_from_shdr(&shdr);
-        let nums = bs.len() / SYM_SIZE;
-        for _ in 0..nums {
-            self.elf_syms.push(read(&bs[..SYM_SIZE]));
-            bs = &bs[SYM_SIZE..]
+    pub fn fillup_elf_syms(&mut self, shdr: Shdr) -> Result<(), LinkError> {
+        self.elf_syms.extend(self.reader.symbols(&shdr)?);
+        // sh_info is the index of the first global symbol.
+        if shdr.info as usize > self.elf_syms.len() {
+            return Err(self.corrupted(format!("invalid first global symbol index: {}", shdr.info)));
         }
+        Ok(())
     }
 
     pub fn find_section(&self, ty: u32) -> Option<Shdr> {
@@ -104,6 +82,6 @@ impl<'a> InputFile<'a> {
     }
 
     pub fn get_ehdr(&self) -> Ehdr {
-        return read::<Ehdr>(self.file.contents);
+        self.reader.ehdr
     }
 }
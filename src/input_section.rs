@@ -1,352 +1,242 @@
+use std::borrow::Cow;
+
 use crate::{
+    arena::{FileId, FragmentId, OutputSectionId},
+    compress::{uncompress_zlib, uncompress_zstd},
     context::Context,
     elf::{
-        self, elf_get_name, RRicsv, Rela, Shdr, RELA_SIZE, R_RISCV_32, R_RISCV_64, R_RISCV_BRANCH,
-        R_RISCV_CALL, R_RISCV_CALL_PLT, R_RISCV_HI20, R_RISCV_JAL, R_RISCV_LO12_I, R_RISCV_LO12_S,
-        R_RISCV_NONE, R_RISCV_PCREL_HI20, R_RISCV_RELAX, R_RISCV_TLS_GOT_HI20,
-        R_RISCV_TPREL_LO12_I, R_RISCV_TPREL_LO12_S, SHDR_SIZE, SHF_ALLOC, SHF_COMPRESSED,
-        SHT_NOBITS,
+        Chdr, Rela, Shdr, ELFCOMPRESS_ZLIB, ELFCOMPRESS_ZSTD, SHF_ALLOC, SHF_COMPRESSED, SHT_NOBITS,
     },
-    object_file::ObjectFile,
+    error::LinkError,
     output_section::OutputSection,
-    symbol::NEEDS_GOT_TP,
-    utils::{bit, bits, read, read_slice, sign_extend, write},
+    symbol::Symbol,
 };
 #[allow(dead_code)]
 pub struct InputSection<'a> {
-    pub object_file: *mut ObjectFile<'a>,
-    pub contents: &'static [u8],
+    pub object_file: FileId,
+    name: String,
+    shdr: Shdr,
+    // The bytes of the section in the input file, or its uncompressed
+    // contents.
+    pub contents: Cow<'a, [u8]>,
     pub shndx: usize,
-    pub sh_size: u32,
+    pub sh_size: u64,
     pub is_alive: bool,
+    pub is_visited: bool,
     pub p2_align: u8,
 
     pub offset: u32,
-    pub output_section: Option<*mut OutputSection<'a>>,
-
-    pub relsec_idx: u32,
-    pub rels: *mut Vec<Rela>,
+    pub output_section: Option<OutputSectionId>,
+
+    // Set by ObjectFile::initialize_sections from the SHT_RELA section
+    // that applies to this one.
+    pub rels: Vec<Rela>,
+    // For each relocation that refers to a mergeable section through its
+    // section symbol, the fragment and the offset in it that S + A points
+    // to. Empty if there are none; see
+    // ObjectFile::register_section_pieces.
+    pub rel_fragments: Vec<Option<(FragmentId, u64)>>,
+
+    // FDEs describing this section, as a range of the file's fdes.
+    pub fde_begin: usize,
+    pub fde_end: usize,
 }
 
 #[allow(dead_code)]
 impl<'a> InputSection<'a> {
     pub fn new(
-        ctx: &Context<'a>,
-        name: String,
-        object_file: *mut ObjectFile<'a>,
+        ctx: &mut Context<'a>,
+        mut name: String,
+        object_file: FileId,
         shndx: usize,
-    ) -> Self {
-        let shdr = unsafe { (object_file.as_ref()).unwrap().input_file.as_ref().unwrap() }
-            .elf_sections[shndx];
-        let contents = &unsafe { (object_file.as_ref()).unwrap().input_file.as_ref().unwrap() }
-            .file
-            .contents[shdr.offset as usize..(shdr.offset + SHDR_SIZE as u64) as usize];
-        assert!(shdr.flags & SHF_COMPRESSED == 0);
-        let sh_size = shdr.size;
+    ) -> Result<Self, LinkError> {
+        let input_file = &ctx.files[object_file].input_file;
+        let shdr = input_file.elf_sections[shndx];
+        let mut contents = Cow::Borrowed(input_file.get_bytes_from_shdr(&shdr)?);
+        let mut sh_size = shdr.size;
+        let mut addr_align = shdr.addr_align;
+
+        let file_name = input_file.file.name;
+        let class = input_file.class;
+        let corrupted = |name: &str| {
+            LinkError::bad_elf(file_name, format!("{}: corrupted compressed section", name))
+        };
+        if shdr.flags & SHF_COMPRESSED != 0 {
+            if contents.len() < class.chdr_size() {
+                return Err(corrupted(&name));
+            }
+            let chdr: Chdr = class.read_chdr(&contents);
+            let data = &contents[class.chdr_size()..];
+            let uncompressed = match chdr.ch_type {
+                ELFCOMPRESS_ZLIB => uncompress_zlib(data, chdr.size),
+                ELFCOMPRESS_ZSTD => uncompress_zstd(data, chdr.size),
+                ty => {
+                    return Err(LinkError::bad_elf(
+                        file_name,
+                        format!("{}: unsupported compression type: 0x{:x}", name, ty),
+                    ))
+                }
+            };
+            contents = Cow::Owned(uncompressed.ok_or_else(|| corrupted(&name))?);
+            sh_size = chdr.size;
+            addr_align = chdr.addr_align;
+        } else if name.starts_with(".zdebug") && contents.starts_with(b"ZLIB") {
+            // The legacy GNU format: "ZLIB", the uncompressed size as a
+            // 64-bit big-endian integer, then a zlib stream. The section is
+            // renamed from .zdebug_* to .debug_*.
+            if contents.len() < 12 {
+                return Err(corrupted(&name));
+            }
+            let size = u64::from_be_bytes(contents[4..12].try_into().unwrap());
+            let uncompressed =
+                uncompress_zlib(&contents[12..], size).ok_or_else(|| corrupted(&name))?;
+            contents = Cow::Owned(uncompressed);
+            sh_size = size;
+            name = String::from(".") + &name[2..];
+        }
+
         let to_p2_align = |align: u64| -> u8 {
             if align == 0 {
                 return 0;
             }
             return u64::trailing_zeros(align) as u8;
         };
-        let p2_align = to_p2_align(shdr.addr_align);
+        let p2_align = to_p2_align(addr_align);
         let output_section =
-            OutputSection::get_output_section(&ctx, name, shdr.shdr_type as u64, shdr.flags);
-        InputSection {
+            OutputSection::get_output_section(ctx, name.clone(), shdr.shdr_type as u64, shdr.flags);
+        Ok(InputSection {
             object_file,
+            name,
+            shdr,
             contents,
             shndx,
-            sh_size: sh_size as u32,
+            sh_size,
             is_alive: true,
+            is_visited: false,
             p2_align,
 
             offset: 0,
             output_section: Some(output_section),
 
-            relsec_idx: 0,
-            rels: Box::leak(Box::new(vec![])),
-        }
+            rels: vec![],
+            rel_fragments: vec![],
+
+            fde_begin: 0,
+            fde_end: 0,
+        })
     }
 
     pub fn shdr(&self) -> Shdr {
-        assert!(
-            self.shndx
-                < unsafe {
-                    (self.object_file.as_ref().unwrap())
-                        .input_file
-                        .as_ref()
-                        .unwrap()
-                }
-                .elf_sections
-                .len()
-        );
-        unsafe {
-            (self.object_file.as_ref().unwrap())
-                .input_file
-                .as_ref()
-                .unwrap()
-        }
-        .elf_sections[self.shndx]
+        self.shdr
     }
 
     pub fn name(&self) -> &str {
-        elf_get_name(
-            unsafe {
-                (self.object_file.as_ref().unwrap())
-                    .input_file
-                    .as_ref()
-                    .unwrap()
-            }
-            .sh_strtab
-            .unwrap(),
-            self.shdr().name,
-        )
+        &self.name
     }
 
-    pub fn write_to(&mut self, ctx: &Context, buf: &mut [u8]) {
+    pub fn write_to(&self, ctx: &Context<'a>, buf: &mut [u8]) -> Result<(), LinkError> {
         if self.shdr().shdr_type == SHT_NOBITS || self.sh_size == 0 {
-            return;
+            return Ok(());
         }
-        let mut buf: Vec<u8> = buf.into_iter().map(|n| -> u8 { *n }).collect();
-        let buf = buf.as_mut_slice();
         self.copy_contents(buf);
 
         if self.shdr().flags & SHF_ALLOC != 0 {
-            self.apply_reloc_alloc(ctx, buf)
+            ctx.target().apply_reloc_alloc(ctx, self, buf)?;
+        } else {
+            ctx.target().apply_reloc_nonalloc(ctx, self, buf)?;
         }
+
+        // .ctors and .dtors are run from the last entry to the first, and
+        // .init_array and .fini_array from the first to the last.
+        let is_ctors = |prefix: &str| {
+            self.name
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        };
+        if is_ctors(".ctors") || is_ctors(".dtors") {
+            let buf = &mut buf[..self.sh_size as usize];
+            let words: Vec<u8> = buf
+                .chunks(ctx.elf_class().word_size())
+                .rev()
+                .flatten()
+                .copied()
+                .collect();
+            buf.copy_from_slice(&words);
+        }
+        Ok(())
     }
 
-    pub fn copy_contents(&mut self, buf: &mut [u8]) {
-        buf.copy_from_slice(self.contents)
+    pub fn copy_contents(&self, buf: &mut [u8]) {
+        buf[..self.contents.len()].copy_from_slice(&self.contents)
     }
 
-    pub fn apply_reloc_alloc(&mut self, ctx: &Context, base: &mut [u8]) {
-        let symbols = unsafe {
-            &self
-                .object_file
-                .as_ref()
-                .unwrap()
-                .input_file
-                .as_ref()
-                .unwrap()
-                .symbols
-        };
-        let rels = self.get_rels();
+    // S and A of the i-th relocation, whose symbol is `sym`. A reference
+    // to a merged piece through a section symbol is S = the address of
+    // the piece and A = the offset in it.
+    pub fn get_rel_target(&self, ctx: &Context<'a>, i: usize, sym: &Symbol) -> (u64, u64) {
+        match self.rel_fragments.get(i) {
+            Some(Some((frag, offset))) => (ctx.fragments[*frag].get_addr(ctx), *offset),
+            _ => (sym.get_addr(ctx), self.rels[i].addend as u64),
+        }
+    }
 
-        for i in 0..unsafe { rels.unwrap().as_ref().unwrap().len() } {
-            let rel = unsafe { rels.unwrap().as_ref().unwrap() }[i];
-            if rel.ty == R_RISCV_NONE || rel.ty == R_RISCV_RELAX {
-                continue;
+    // The value a relocation in a non-alloc section refers to, S + A, or
+    // None if the symbol is in a section that was discarded.
+    pub fn get_nonalloc_value(&self, ctx: &Context<'a>, rel: &Rela) -> Option<u64> {
+        let file = &ctx.files[self.object_file];
+        let input_file = &file.input_file;
+        let idx = rel.sym as usize;
+        let sym = &ctx.symbols[input_file.symbols[idx]];
+        let a = rel.addend as u64;
+
+        if (idx as i64) < input_file.first_global.unwrap_or(0) {
+            let esym = input_file.elf_syms[idx];
+            if esym.is_abs() {
+                return Some(esym.val.wrapping_add(a));
             }
 
-            let sym = symbols[rel.sym as usize];
-            let loc = &mut base[rel.offset as usize..];
-
-            if unsafe { sym.as_ref().unwrap().object_file.is_none() } {
-                continue;
+            // References to strings in .debug_str and the like are made
+            // with a section symbol and an offset into the section.
+            let shndx = file.get_shndx(esym, idx as i32);
+            if let Some(Some(m)) = file.mergeable_sections.get(shndx) {
+                let (frag, offset) = m.get_fragment(esym.val.wrapping_add(a) as usize);
+                return frag.map(|frag| ctx.fragments[frag].get_addr(ctx) + offset as u64);
             }
-
-            let s: u64 = unsafe { sym.as_ref().unwrap().get_addr() };
-            let a = rel.addend as u64;
-            let p = self.get_addr() + rel.offset;
-
-            match rel.ty as RRicsv {
-                R_RISCV_32 => write(loc, (s + a) as u32),
-                R_RISCV_64 => write(loc, s + a),
-                R_RISCV_BRANCH => write_b_type(loc, (s + a - p) as u32),
-                R_RISCV_JAL => write_j_type(loc, (s + a - p) as u32),
-                R_RISCV_CALL_PLT | R_RISCV_CALL => {
-                    let val = s + a - p;
-                    write_u_type(loc, val as u32);
-                    write_i_type(&mut loc[4..], val as u32);
-                }
-                R_RISCV_TLS_GOT_HI20 => write(loc, unsafe {
-                    sym.as_ref().unwrap().get_got_tp_addr(ctx) + a - p
-                }),
-                R_RISCV_PCREL_HI20 => write(loc, s + a - p),
-                R_RISCV_HI20 => write(loc, s + a),
-                R_RISCV_LO12_S | R_RISCV_LO12_I => {
-                    let val = s + a;
-                    if rel.ty == R_RISCV_LO12_I as u32 {
-                        write_i_type(loc, val as u32)
-                    } else {
-                        write_s_type(loc, val as u32)
-                    }
-                    if sign_extend(val, 11) == val {
-                        set_rs1(loc, 0)
-                    }
-                }
-                R_RISCV_TPREL_LO12_I | R_RISCV_TPREL_LO12_S => {
-                    let val = s + a - ctx.tp_addr;
-                    if rel.ty == R_RISCV_TPREL_LO12_I as u32 {
-                        write_i_type(loc, val as u32);
-                    } else {
-                        write_s_type(loc, val as u32);
-                    }
-
-                    if sign_extend(val, 11) == val {
-                        set_rs1(loc, 4);
-                    }
-                }
-                _ => {}
+            if sym.section_fragment.is_some() {
+                return Some(sym.get_addr(ctx).wrapping_add(a));
             }
 
-            for i in 0..unsafe { rels.unwrap().as_ref().unwrap().len() } {
-                match unsafe { rels.unwrap().as_ref().unwrap()[i].ty } as RRicsv {
-                    R_RISCV_PCREL_HI20 | R_RISCV_TLS_GOT_HI20 => {
-                        let loc = &mut base
-                            [unsafe { rels.unwrap().as_ref().unwrap() }[i].offset as usize..];
-                        let val = read::<u32>(&loc);
-                        write(
-                            loc,
-                            read::<u32>(
-                                &self.contents[unsafe { rels.unwrap().as_ref().unwrap() }[i].offset
-                                    as usize..],
-                            ),
-                        );
-                        write_u_type(loc, val)
-                    }
-                    _ => {}
-                }
+            let isec = &ctx.sections[file.get_symbol_section(ctx, idx)?];
+            if !isec.is_alive {
+                return None;
             }
+            return Some(isec.get_addr(ctx) + esym.val.wrapping_add(a));
         }
-    }
 
-    pub fn get_rels(&mut self) -> Option<*mut Vec<Rela>> {
-        if self.relsec_idx == u32::MAX || !unsafe { self.rels.as_ref().unwrap().is_empty() } {
-            return Some(self.rels);
+        if !sym.is_defined() {
+            return None;
         }
-        let bs = unsafe {
-            self.object_file
-                .as_ref()
-                .unwrap()
-                .input_file
-                .as_ref()
-                .unwrap()
-                .get_bytes_from_shdr(
-                    &self
-                        .object_file
-                        .as_ref()
-                        .unwrap()
-                        .input_file
-                        .as_mut()
-                        .unwrap()
-                        .elf_sections[self.relsec_idx as usize],
-                )
-        };
-        let mut bs: Vec<u8> = bs.into_iter().map(|n| -> u8 { *n }).collect();
-        let mut _rels = unsafe { self.rels.as_mut().unwrap() };
-        _rels = &mut read_slice::<Rela>(bs.as_mut_slice(), RELA_SIZE);
-        None
-    }
-
-    pub fn get_addr(&self) -> u64 {
-        unsafe {
-            self.output_section
-                .unwrap()
-                .as_ref()
-                .unwrap()
-                .chunk
-                .as_ref()
-                .unwrap()
-                .shdr
-                .addr
-                + self.offset as u64
+        if let Some(isec) = sym.input_section {
+            if !ctx.sections[isec].is_alive {
+                return None;
+            }
         }
+        Some(sym.get_addr(ctx).wrapping_add(a))
     }
 
-    pub fn scan_relocations(&mut self) {
-        for rel in unsafe { self.get_rels().unwrap().as_ref().unwrap() } {
-            let sym = unsafe {
-                &self
-                    .object_file
-                    .as_ref()
-                    .unwrap()
-                    .input_file
-                    .as_ref()
-                    .unwrap()
-                    .symbols[rel.sym as usize]
-            };
-            if unsafe { sym.as_ref().unwrap().object_file.is_none() } {
-                continue;
-            }
-            if rel.ty == elf::R_RISCV_TLS_GOT_HI20 as u32 {
-                unsafe { sym.as_mut().unwrap().flags |= NEEDS_GOT_TP }
-            }
+    // The value written for a reference to a discarded section. 0 would
+    // terminate a .debug_ranges or .debug_loc list, and -1 starts a base
+    // address selection entry there, so 1 is used for those sections.
+    pub fn get_tombstone(&self) -> u64 {
+        let name = self.name();
+        if name == ".debug_ranges" || name == ".debug_loc" {
+            return 1;
         }
+        0
     }
-}
 
-#[allow(dead_code)]
-fn i_type(val: u32) -> u32 {
-    return val << 20;
-}
-
-#[allow(dead_code)]
-fn s_type(val: u32) -> u32 {
-    (bits(val, 11, 5) << 25 | bits(val, 4, 0) << 7) as u32
-}
-
-#[allow(dead_code)]
-pub fn b_type(val: u32) -> u32 {
-    bit(val, 12) << 31 | bits(val, 10, 5) << 25 | bits(val, 4, 1) << 8 | bit(val, 11) << 7
-}
-
-#[allow(dead_code)]
-fn u_type(val: u32) -> u32 {
-    (val + 0x800) & 0xffff_f000
-}
-
-#[allow(dead_code)]
-fn j_type(val: u32) -> u32 {
-    bit(val, 20) << 31 | bits(val, 10, 1) << 21 | bit(val, 11) << 20 | bits(val, 19, 12) << 12
-}
-
-#[allow(dead_code)]
-fn cb_type(_val: u16) -> u16 {
-    0
-}
-
-#[allow(dead_code)]
-fn cj_type(_al: u16) -> u16 {
-    0
-}
-
-#[allow(dead_code)]
-fn write_b_type(loc: &mut [u8], val: u32) {
-    let mask = 0b000000_11111_11111_111_00000_1111111 as u32;
-    write(loc, read::<u32>(&loc) & mask | val)
-}
-
-#[allow(dead_code)]
-fn write_j_type(loc: &mut [u8], val: u32) {
-    let mask = 0b000000_00000_00000_000_11111_1111111 as u32;
-    write(loc, read::<u32>(&loc) & mask | val)
-}
-
-#[allow(dead_code)]
-fn write_i_type(loc: &mut [u8], val: u32) {
-    let mask = 0b000000_11111_11111_111_00000_1111111 as u32;
-    write(loc, read::<u32>(&loc) & mask | val)
-}
-
-#[allow(dead_code)]
-fn write_s_type(loc: &mut [u8], val: u32) {
-    let mask = 0b000000_11111_11111_111_00000_1111111 as u32;
-    write(loc, read::<u32>(&loc) & mask | val)
-}
-
-#[allow(dead_code)]
-fn write_u_type(loc: &mut [u8], val: u32) {
-    let mask = 0b000000_11111_11111_111_00000_1111111 as u32;
-    write(loc, read::<u32>(&loc) & mask | val)
+    pub fn get_addr(&self, ctx: &Context) -> u64 {
+        let chunk = &ctx.output_sections[self.output_section.unwrap()].chunk;
+        chunk.shdr.addr + self.offset as u64
+    }
 }
-
-#[allow(dead_code)]
-fn set_rs1(loc: &mut [u8], rs1: u32) {
-    write(
-        loc,
-        read::<u32>(&loc) & 0b111111_11111_00000_111_11111_1111111,
-    );
-    write(loc, read::<u32>(&loc) | rs1 << 15);
-}
\ No newline at end of file
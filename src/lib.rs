@@ -0,0 +1,70 @@
+mod archive;
+mod arena;
+mod args;
+mod binary_file;
+mod build_id_section;
+mod chunk;
+mod compress;
+mod compressed_section;
+mod context;
+mod defsym;
+mod dynamic_section;
+mod dynsym_section;
+mod eh_frame;
+mod eh_frame_hdr_section;
+mod eh_frame_section;
+mod elf;
+mod elf_class;
+mod elf_reader;
+mod error;
+mod file;
+mod file_type;
+mod gc_sections;
+mod got_section;
+mod input;
+mod input_file;
+mod input_section;
+mod linker;
+mod machine_type;
+mod magic;
+mod mergeablesection;
+mod merged_section;
+mod object_file;
+mod output;
+mod output_ehdr;
+mod output_file;
+mod output_format;
+mod output_phdr;
+mod output_section;
+mod output_shdr;
+mod parallel;
+mod passes;
+mod reproduce;
+mod riscv;
+mod riscv_attributes;
+mod riscv_attributes_section;
+mod section_fragment;
+mod shared_file;
+mod strtab_section;
+mod symbol;
+mod symbol_map;
+mod symtab_section;
+mod symtab_shndx_section;
+mod target;
+mod utils;
+mod verdef_section;
+mod verneed_section;
+mod version_script;
+mod versym_section;
+mod x86_64;
+
+pub use args::{parse_args, ParsedArgs};
+pub use build_id_section::BuildId;
+pub use defsym::{parse_defsym, Defsym, DefsymTerm};
+pub use error::LinkError;
+pub use linker::{LinkInput, LinkOutput, Linker, LinkerConfig};
+pub use machine_type::{
+    MachineType, MACHINE_TYPE_NONE, MACHINE_TYPE_RISCV32, MACHINE_TYPE_RISCV64, MACHINE_TYPE_X86_64,
+};
+pub use output_format::OutputFormat;
+pub use version_script::{parse_version_script, VersionNode, VersionScript};
@@ -0,0 +1,961 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use crate::{
+    build_id_section::BuildId,
+    context::Context,
+    defsym::Defsym,
+    elf::STV_PROTECTED,
+    error::LinkError,
+    gc_sections::gc_sections,
+    input::{create_binary_object_files, open_input_files, read_input_files},
+    machine_type::{get_machine_type_from_contents, MachineType, MACHINE_TYPE_NONE},
+    output_file::{OutputBuffer, OutputFile},
+    output_format::OutputFormat,
+    output_phdr::OutputPhdr,
+    passes::{
+        add_defsym_symbols, add_output_sections, add_start_stop_symbols, apply_version_script,
+        bin_sections, check_duplicate_symbols, check_flags, check_require_defined,
+        compress_debug_sections, compute_merged_sections_size, compute_section_headers,
+        compute_section_sizes, convert_output_format, copy_chunks, create_dynamic_sections,
+        create_synthetic_sections, fix_defsym_symbols, fix_synthetic_symbols,
+        initialize_ehframe_sections, initialize_mergeable_sections, initialize_sections,
+        initialize_symbols, parse_object_files, register_section_pieces, resolve_symbols,
+        scan_relocations, set_output_section_offsets, sort_output_sections, strip_debug_sections,
+        write_build_id,
+    },
+    reproduce::write_reproduce,
+    target::get_target,
+    version_script::VersionScript,
+};
+
+// The options of a link. The command line options map onto these fields
+// one to one; see `parse_args`.
+pub struct LinkerConfig {
+    pub output: String,
+    // MACHINE_TYPE_NONE takes the emulation from the first input file.
+    pub emulation: MachineType,
+    pub library_paths: Vec<String>,
+    pub gc_sections: bool,
+    pub z_start_stop_visibility: u8,
+    pub entry: String,
+    pub undefined: Vec<String>,
+    pub require_defined: Vec<String>,
+    pub defsyms: Vec<Defsym>,
+    pub wrap: HashSet<String>,
+    pub version_script: VersionScript,
+    // The files the version script was read from, for --reproduce.
+    pub version_script_files: Vec<String>,
+    pub default_symver: bool,
+    pub soname: String,
+    pub export_dynamic: bool,
+    pub is_static: bool,
+    pub eh_frame_hdr: bool,
+    pub strip_all: bool,
+    pub strip_debug: bool,
+    pub discard_locals: bool,
+    pub compress_debug_sections: u32,
+    pub build_id: BuildId,
+    pub oformat: OutputFormat,
+    pub gap_fill: u8,
+    // The number of errors after which a link stops. 0 means no limit.
+    pub error_limit: usize,
+    // -O level. At 2 and above, strings in mergeable sections that are
+    // the tail of another string share its bytes.
+    pub optimize: u8,
+    // The number of threads the passes may use. 0 means one per CPU.
+    pub threads: usize,
+    // --reproduce=<tar>. Empty if not given.
+    pub reproduce: String,
+    // The command line options other than inputs, -l, -b, -L,
+    // --version-script and --reproduce, as given. --reproduce replays
+    // them from its response file.
+    pub options: Vec<String>,
+}
+
+impl Default for LinkerConfig {
+    fn default() -> Self {
+        LinkerConfig {
+            output: "a.out".to_string(),
+            emulation: MACHINE_TYPE_NONE,
+            library_paths: vec![],
+            gc_sections: false,
+            z_start_stop_visibility: STV_PROTECTED,
+            entry: "_start".to_string(),
+            undefined: vec![],
+            require_defined: vec![],
+            defsyms: vec![],
+            wrap: HashSet::new(),
+            version_script: VersionScript::default(),
+            version_script_files: vec![],
+            default_symver: false,
+            soname: String::new(),
+            export_dynamic: false,
+            is_static: false,
+            eh_frame_hdr: false,
+            strip_all: false,
+            strip_debug: false,
+            discard_locals: false,
+            compress_debug_sections: 0,
+            build_id: BuildId::None,
+            oformat: OutputFormat::Elf,
+            gap_fill: 0,
+            error_limit: 20,
+            optimize: 0,
+            threads: 0,
+            reproduce: String::new(),
+            options: vec![],
+        }
+    }
+}
+
+// An input of the link, in command line order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LinkInput {
+    // An object file, archive or shared library on disk.
+    Path(String),
+    // The contents of an object file, archive or shared library. The name
+    // is used in messages and for -b binary symbol names.
+    Bytes { name: String, data: Vec<u8> },
+    // -l<name>, searched for in the library paths.
+    Library(String),
+    // -b <format>, which applies to the inputs that follow.
+    Format(String),
+}
+
+impl From<&str> for LinkInput {
+    fn from(path: &str) -> Self {
+        LinkInput::Path(path.to_string())
+    }
+}
+
+impl From<String> for LinkInput {
+    fn from(path: String) -> Self {
+        LinkInput::Path(path)
+    }
+}
+
+impl From<PathBuf> for LinkInput {
+    fn from(path: PathBuf) -> Self {
+        LinkInput::Path(path.to_string_lossy().into_owned())
+    }
+}
+
+impl From<(&str, Vec<u8>)> for LinkInput {
+    fn from((name, data): (&str, Vec<u8>)) -> Self {
+        LinkInput::Bytes {
+            name: name.to_string(),
+            data,
+        }
+    }
+}
+
+// The output file image.
+#[derive(Debug)]
+pub struct LinkOutput {
+    pub data: Vec<u8>,
+}
+
+impl LinkOutput {
+    // Writes the image to `path`. An ELF output is made executable. See
+    // `OutputFile` for how an existing file is replaced.
+    pub fn write(&self, path: &str) -> Result<(), LinkError> {
+        let mut file = OutputFile::create(path, self.data.len())?;
+        file.copy_from_slice(&self.data);
+        file.commit()
+    }
+}
+
+// Links in-process:
+//
+//     let output = Linker::new(config)
+//         .add_input("a.o")
+//         .add_input(LinkInput::Library("c".into()))
+//         .link()?;
+//     output.write("a.out")?;
+pub struct Linker {
+    config: LinkerConfig,
+    inputs: Vec<LinkInput>,
+}
+
+impl Linker {
+    pub fn new(config: LinkerConfig) -> Self {
+        Self {
+            config,
+            inputs: vec![],
+        }
+    }
+
+    pub fn add_input(mut self, input: impl Into<LinkInput>) -> Self {
+        self.inputs.push(input.into());
+        self
+    }
+
+    pub fn link(self) -> Result<LinkOutput, LinkError> {
+        let buf = self.run(|size| Ok(OutputBuffer::Memory(vec![0; size])))?;
+        let data = match buf {
+            OutputBuffer::Memory(data) => data,
+            OutputBuffer::File(file) => file.to_vec(),
+        };
+        Ok(LinkOutput { data })
+    }
+
+    // Links into the file at `path`. An ELF image is written in place
+    // through a mapping of the file rather than built in memory first.
+    pub fn link_to(self, path: &str) -> Result<(), LinkError> {
+        if self.config.oformat != OutputFormat::Elf {
+            return self.link()?.write(path);
+        }
+        let buf = self.run(|size| OutputFile::create(path, size).map(OutputBuffer::File))?;
+        match buf {
+            OutputBuffer::File(file) => file.commit(),
+            OutputBuffer::Memory(data) => LinkOutput { data }.write(path),
+        }
+    }
+
+    // Runs the passes and returns the output image. `create_buf` makes
+    // the buffer for an image of the given size. Everything else the link
+    // creates is owned by the context and freed with it.
+    fn run(
+        self,
+        create_buf: impl FnOnce(usize) -> Result<OutputBuffer, LinkError>,
+    ) -> Result<OutputBuffer, LinkError> {
+        let Linker { config, inputs } = self;
+        // Each input is opened once, here. The context borrows the names and
+        // contents of its files from `files`, which outlives it.
+        let mut files = open_input_files(&config, &inputs)?;
+        let mut ctx = Context::new();
+        ctx.args = config;
+
+        if ctx.args.emulation == MACHINE_TYPE_NONE {
+            for file in files.iter().filter(|file| !file.is_binary) {
+                if let Some(contents) = &file.contents {
+                    ctx.args.emulation = get_machine_type_from_contents(contents);
+                    if ctx.args.emulation != MACHINE_TYPE_NONE {
+                        break;
+                    }
+                }
+            }
+        }
+        let Some(target) = get_target(ctx.args.emulation) else {
+            return Err(LinkError::UnknownEmulation);
+        };
+        if !ctx.args.reproduce.is_empty() {
+            write_reproduce(&ctx, &files)?;
+        }
+
+        create_binary_object_files(&mut files, target);
+        read_input_files(&mut ctx, &files)?;
+        parse_object_files(&mut ctx)?;
+        initialize_sections(&mut ctx)?;
+        initialize_symbols(&mut ctx)?;
+        resolve_symbols(&mut ctx);
+        check_duplicate_symbols(&ctx)?;
+        check_flags(&mut ctx)?;
+        if ctx.args.strip_debug {
+            strip_debug_sections(&mut ctx);
+        }
+        add_defsym_symbols(&mut ctx);
+        check_require_defined(&ctx)?;
+        apply_version_script(&mut ctx)?;
+        ctx.check_errors()?;
+        initialize_mergeable_sections(&mut ctx)?;
+        register_section_pieces(&mut ctx)?;
+        initialize_ehframe_sections(&mut ctx)?;
+        if ctx.args.gc_sections {
+            gc_sections(&mut ctx);
+        }
+        compute_merged_sections_size(&mut ctx);
+        bin_sections(&mut ctx);
+        // __start_ and __stop_ symbols are defined before the symbol tables
+        // are built, so that they are in .symtab.
+        add_start_stop_symbols(&mut ctx);
+        create_synthetic_sections(&mut ctx)?;
+        create_dynamic_sections(&mut ctx);
+        add_output_sections(&mut ctx);
+        scan_relocations(&mut ctx)?;
+        ctx.check_errors()?;
+        compute_section_sizes(&mut ctx);
+        sort_output_sections(&mut ctx);
+        compute_section_headers(&mut ctx);
+        OutputPhdr::update_shdr(&mut ctx);
+        fix_synthetic_symbols(&mut ctx)?;
+        ctx.check_errors()?;
+
+        let mut file_size = set_output_section_offsets(&mut ctx);
+        fix_defsym_symbols(&mut ctx)?;
+        ctx.check_errors()?;
+        // Debug sections are relocated against the final addresses, so they
+        // are compressed once those are known. They are not allocated, so
+        // laying out the file again only moves what follows them.
+        if ctx.args.compress_debug_sections != 0 {
+            compress_debug_sections(&mut ctx)?;
+            file_size = set_output_section_offsets(&mut ctx);
+        }
+        ctx.buf = create_buf(file_size as usize)?;
+        copy_chunks(&mut ctx)?;
+        ctx.check_errors()?;
+        write_build_id(&mut ctx)?;
+        convert_output_format(&mut ctx)?;
+        Ok(std::mem::take(&mut ctx.buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process::Command};
+
+    use super::*;
+    use crate::{
+        args::{parse_args, split_response_file, ParsedArgs},
+        compress::uncompress_zlib,
+        defsym::parse_defsym,
+        elf::{
+            elf_get_name, Shdr, ELFCOMPRESS_ZLIB, EM_X86_64, ET_EXEC, SHF_COMPRESSED, SHT_RELA,
+            SHT_SYMTAB, VERSYM_HIDDEN,
+        },
+        elf_class::ElfClass,
+        utils::sign_extend,
+        version_script::parse_version_script,
+    };
+
+    // Compiles C sources into objects in a fresh directory under the
+    // system temporary directory and returns the directory.
+    fn compile(test: &str, flags: &[&str], sources: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rvld-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, src) in sources {
+            fs::write(dir.join(name), src).unwrap();
+            let status = Command::new("cc")
+                .args([
+                    "-c",
+                    "-O2",
+                    "-fno-pic",
+                    "-fno-stack-protector",
+                    "-ffreestanding",
+                ])
+                .args(flags)
+                .arg(name)
+                .current_dir(&dir)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+        dir
+    }
+
+    // Assembles `source` for `triple` into a.o in a fresh directory and
+    // returns the directory.
+    fn assemble(test: &str, triple: &str, source: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rvld-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.s"), source).unwrap();
+        let status = Command::new("llvm-mc")
+            .args([
+                "-filetype=obj",
+                &format!("-triple={}", triple),
+                "a.s",
+                "-o",
+                "a.o",
+            ])
+            .current_dir(&dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        dir
+    }
+
+    fn read_shdrs(data: &[u8]) -> Vec<Shdr> {
+        let class = ElfClass::from_contents(data);
+        let ehdr = class.read_ehdr(data);
+        (0..ehdr.sh_num as usize)
+            .map(|i| class.read_shdr(&data[ehdr.sh_off as usize + i * class.shdr_size()..]))
+            .collect()
+    }
+
+    fn section_contents<'a>(data: &'a [u8], shdr: &Shdr) -> &'a [u8] {
+        &data[shdr.offset as usize..(shdr.offset + shdr.size) as usize]
+    }
+
+    // Returns the value of the symbol `name` in the .symtab of `data`.
+    fn find_symbol(data: &[u8], name: &str) -> Option<u64> {
+        let class = ElfClass::from_contents(data);
+        let shdrs = read_shdrs(data);
+        let symtab = shdrs.iter().find(|shdr| shdr.shdr_type == SHT_SYMTAB)?;
+        let strtab = section_contents(data, &shdrs[symtab.link as usize]);
+        (0..symtab.size as usize / class.sym_size())
+            .map(|i| class.read_sym(&data[symtab.offset as usize + i * class.sym_size()..]))
+            .find(|sym| elf_get_name(strtab, sym.name) == Ok(name))
+            .map(|sym| sym.val)
+    }
+
+    // Returns the header and contents of the section `name` of `data`.
+    fn find_section<'a>(data: &'a [u8], name: &str) -> Option<(Shdr, &'a [u8])> {
+        let ehdr = ElfClass::from_contents(data).read_ehdr(data);
+        let shdrs = read_shdrs(data);
+        let shstrtab = section_contents(data, &shdrs[ehdr.sh_strndx as usize]);
+        let shdr = shdrs
+            .into_iter()
+            .find(|shdr| elf_get_name(shstrtab, shdr.name) == Ok(name))?;
+        Some((shdr, section_contents(data, &shdr)))
+    }
+
+    const HELLO_C: &str = r#"
+static const char msg[] = "hello, world\n";
+int status = 42;
+int zero;
+extern int helper(int);
+
+static long sys(long n, long a, long b, long c) {
+    long ret;
+    __asm__ volatile("syscall" : "=a"(ret) : "a"(n), "D"(a), "S"(b), "d"(c) : "rcx", "r11", "memory");
+    return ret;
+}
+
+void _start(void) {
+    sys(1, 1, (long)msg, sizeof(msg) - 1);
+    sys(60, helper(status) + zero, 0, 0);
+}
+"#;
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_link_executable() {
+        let dir = compile(
+            "link-executable",
+            &[],
+            &[
+                ("a.c", HELLO_C),
+                ("b.c", "int helper(int x) { return x + 1; }\n"),
+            ],
+        );
+        let output = Linker::new(LinkerConfig::default())
+            .add_input(dir.join("a.o"))
+            .add_input(dir.join("b.o"))
+            .link()
+            .unwrap();
+
+        let data = &output.data;
+        let ehdr = ElfClass::from_contents(data).read_ehdr(data);
+        assert_eq!(&ehdr.ident[..4], b"\x7fELF");
+        assert_eq!(ehdr.hdr_type, ET_EXEC);
+        assert_eq!(ehdr.machine, EM_X86_64);
+        assert_eq!(Some(ehdr.entry), find_symbol(data, "_start"));
+        assert!(find_symbol(data, "helper").is_some_and(|val| val != 0));
+
+        let exe = dir.join("a.out");
+        output.write(exe.to_str().unwrap()).unwrap();
+        let out = Command::new(&exe).output().unwrap();
+        assert_eq!(out.stdout, b"hello, world\n");
+        assert_eq!(out.status.code(), Some(43));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_link_compress_debug_sections() {
+        let dir = compile(
+            "link-compress-debug-sections",
+            &["-g"],
+            &[
+                ("a.c", HELLO_C),
+                ("b.c", "int helper(int x) { return x + 1; }\n"),
+            ],
+        );
+        let link = |compress_debug_sections| {
+            let config = LinkerConfig {
+                compress_debug_sections,
+                ..LinkerConfig::default()
+            };
+            Linker::new(config)
+                .add_input(dir.join("a.o"))
+                .add_input(dir.join("b.o"))
+                .link()
+                .unwrap()
+        };
+        let plain = link(0);
+        let compressed = link(ELFCOMPRESS_ZLIB);
+
+        // The compressed .debug_info is relocated like the plain one.
+        let (_, expected) = find_section(&plain.data, ".debug_info").unwrap();
+        let (shdr, contents) = find_section(&compressed.data, ".debug_info").unwrap();
+        assert_ne!(shdr.flags & SHF_COMPRESSED, 0);
+        let class = ElfClass::from_contents(&compressed.data);
+        let chdr = class.read_chdr(contents);
+        let data = uncompress_zlib(&contents[class.chdr_size()..], chdr.size);
+        assert_eq!(data.as_deref(), Some(expected));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    const RISCV_S: &str = r#"
+    .text
+    .globl _start
+func:
+    ret
+_start:
+    lui a0, %hi(var)
+    addi a0, a0, %lo(var)
+.Lpcrel:
+    auipc a1, %pcrel_hi(var)
+    addi a1, a1, %pcrel_lo(.Lpcrel)
+    jal ra, func
+    beq a0, a1, func
+    call func
+    sw a0, %lo(var)(a0)
+
+    .data
+    .zero 16
+var:
+    .word 0
+"#;
+
+    #[test]
+    fn test_link_riscv_relocations() {
+        for triple in ["riscv64", "riscv32"] {
+            let dir = assemble(&format!("link-{}-relocations", triple), triple, RISCV_S);
+            let output = Linker::new(LinkerConfig::default())
+                .add_input(dir.join("a.o"))
+                .link()
+                .unwrap();
+
+            let data = &output.data;
+            let (text, contents) = find_section(data, ".text").unwrap();
+            let func = find_symbol(data, "func").unwrap();
+            let start = find_symbol(data, "_start").unwrap();
+            let var = find_symbol(data, "var").unwrap();
+            let insn = |i: u64| {
+                let offset = (start - text.addr + i * 4) as usize;
+                u32::from_le_bytes(contents[offset..offset + 4].try_into().unwrap())
+            };
+            let pc = |i: u64| start + i * 4;
+            let i_imm = |insn: u32| (insn as i32 >> 20) as u64;
+            let u_imm = |insn: u32| (insn & 0xffff_f000) as i32 as u64;
+            let s_imm =
+                |insn: u32| ((insn as i32 >> 25 << 5) as u32 | insn >> 7 & 0x1f) as i32 as u64;
+            let b_imm = |insn: u32| {
+                let imm = (insn >> 31) << 12
+                    | (insn >> 7 & 1) << 11
+                    | (insn >> 25 & 0x3f) << 5
+                    | (insn >> 8 & 0xf) << 1;
+                ((imm << 19) as i32 >> 19) as u64
+            };
+            let j_imm = |insn: u32| {
+                let imm = (insn >> 31) << 20
+                    | (insn >> 12 & 0xff) << 12
+                    | (insn >> 20 & 1) << 11
+                    | (insn >> 21 & 0x3ff) << 1;
+                ((imm << 11) as i32 >> 11) as u64
+            };
+            // RV32 addresses wrap around at 4 GiB.
+            let addr = |val: u64| {
+                if triple == "riscv32" {
+                    val as u32 as u64
+                } else {
+                    val
+                }
+            };
+
+            // The opcodes and registers are kept.
+            assert_eq!(insn(0) & 0xfff, 0x537);
+            assert_eq!(insn(2) & 0xfff, 0x597);
+            assert_eq!(insn(4) & 0xfff, 0x0ef);
+            assert_eq!(insn(5) & 0x1fff07f, 0xb50063);
+
+            assert_eq!(addr(u_imm(insn(0)).wrapping_add(i_imm(insn(1)))), var);
+            let pcrel = u_imm(insn(2)).wrapping_add(i_imm(insn(3)));
+            assert_eq!(addr(pc(2).wrapping_add(pcrel)), var);
+            assert_eq!(addr(pc(4).wrapping_add(j_imm(insn(4)))), func);
+            assert_eq!(addr(pc(5).wrapping_add(b_imm(insn(5)))), func);
+            let call = u_imm(insn(6)).wrapping_add(i_imm(insn(7)));
+            assert_eq!(addr(pc(6).wrapping_add(call)), func);
+            assert_eq!(s_imm(insn(8)), sign_extend(var & 0xfff, 11));
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_link_eh_frame() {
+        let dir = compile(
+            "link-eh-frame",
+            &["-fasynchronous-unwind-tables"],
+            &[
+                ("a.c", HELLO_C),
+                ("b.c", "int helper(int x) { return x + 1; }\n"),
+            ],
+        );
+        let output = Linker::new(LinkerConfig::default())
+            .add_input(dir.join("a.o"))
+            .add_input(dir.join("b.o"))
+            .link()
+            .unwrap();
+
+        // GCC encodes pc_begin as a 4-byte PC-relative value and
+        // pc_range as a 4-byte length.
+        let data = &output.data;
+        let (text, _) = find_section(data, ".text").unwrap();
+        let (shdr, contents) = find_section(data, ".eh_frame").unwrap();
+        let read32 =
+            |offset: usize| u32::from_le_bytes(contents[offset..offset + 4].try_into().unwrap());
+        let mut ranges = vec![];
+        let mut offset = 0;
+        while read32(offset) != 0 {
+            if read32(offset + 4) != 0 {
+                let field = shdr.addr + offset as u64 + 8;
+                let begin = field.wrapping_add(read32(offset + 8) as i32 as u64);
+                ranges.push((begin, begin + read32(offset + 12) as u64));
+            }
+            offset += 4 + read32(offset) as usize;
+        }
+
+        ranges.sort();
+        let mut funcs = [
+            find_symbol(data, "_start").unwrap(),
+            find_symbol(data, "helper").unwrap(),
+        ];
+        funcs.sort();
+        assert_eq!(ranges.iter().map(|r| r.0).collect::<Vec<_>>(), funcs);
+        for (begin, end) in ranges {
+            assert!(text.addr <= begin && begin < end && end <= text.addr + text.size);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_link_oformat_binary() {
+        let dir = compile(
+            "link-oformat-binary",
+            &[],
+            &[
+                ("a.c", HELLO_C),
+                ("b.c", "int helper(int x) { return x + 1; }\n"),
+            ],
+        );
+        let link = |oformat| {
+            let config = LinkerConfig {
+                oformat,
+                gap_fill: 0xff,
+                ..LinkerConfig::default()
+            };
+            Linker::new(config)
+                .add_input(dir.join("a.o"))
+                .add_input(dir.join("b.o"))
+                .link()
+                .unwrap()
+                .data
+        };
+        let elf = link(OutputFormat::Elf);
+        let bin = link(OutputFormat::Binary);
+
+        // The image starts with .text and ends with .data; the headers
+        // and .bss are not in it.
+        let (text, text_contents) = find_section(&elf, ".text").unwrap();
+        let (data, data_contents) = find_section(&elf, ".data").unwrap();
+        assert_eq!(bin.len() as u64, data.addr + data.size - text.addr);
+        assert_eq!(&bin[..text.size as usize], text_contents);
+        assert_eq!(bin[text.size as usize], 0xff);
+        assert_eq!(&bin[(data.addr - text.addr) as usize..], data_contents);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_link_reproduce_binary() {
+        let dir = compile(
+            "link-reproduce-binary",
+            &[],
+            &[(
+                "a.c",
+                r#"
+extern char _binary_blob_bin_start[], _binary_blob_bin_end[];
+long _start(void) { return _binary_blob_bin_end - _binary_blob_bin_start; }
+"#,
+            )],
+        );
+        let config = LinkerConfig {
+            reproduce: dir.join("repro.tar").to_string_lossy().into_owned(),
+            ..LinkerConfig::default()
+        };
+        let output = Linker::new(config)
+            .add_input(dir.join("a.o"))
+            .add_input(LinkInput::Format("binary".to_string()))
+            .add_input(("blob.bin", b"blob".to_vec()))
+            .link()
+            .unwrap();
+
+        // Replay the link from the extracted archive. The inputs are
+        // passed by the paths in the response file, as from its directory.
+        let status = Command::new("tar")
+            .args(["xf", "repro.tar"])
+            .current_dir(&dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let repro = dir.join("repro");
+        let response = fs::read_to_string(repro.join("response.txt")).unwrap();
+        let args = ["ld".to_string()]
+            .into_iter()
+            .chain(split_response_file(&response))
+            .collect();
+        let Ok(ParsedArgs::Link(config, inputs)) = parse_args(args) else {
+            panic!("cannot parse {}", response);
+        };
+        let mut linker = Linker::new(config);
+        for input in inputs {
+            linker = linker.add_input(match input {
+                LinkInput::Path(path) => {
+                    let data = fs::read(repro.join(&path)).unwrap();
+                    LinkInput::Bytes { name: path, data }
+                }
+                input => input,
+            });
+        }
+        let replayed = linker.link().unwrap();
+
+        for name in [
+            "_binary_blob_bin_start",
+            "_binary_blob_bin_end",
+            "_binary_blob_bin_size",
+        ] {
+            let val = find_symbol(&output.data, name);
+            assert!(val.is_some());
+            assert_eq!(find_symbol(&replayed.data, name), val);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_link_start_stop_symbols() {
+        let dir = compile(
+            "link-start-stop-symbols",
+            &[],
+            &[(
+                "a.c",
+                r#"
+__attribute__((section("my_data"))) int item = 1;
+extern int __start_my_data[], __stop_my_data[];
+long _start(void) { return __stop_my_data - __start_my_data; }
+"#,
+            )],
+        );
+        let output = Linker::new(LinkerConfig::default())
+            .add_input(dir.join("a.o"))
+            .link()
+            .unwrap();
+
+        let data = &output.data;
+        let start = find_symbol(data, "__start_my_data").unwrap();
+        assert_eq!(Some(start), find_symbol(data, "item"));
+        assert_eq!(find_symbol(data, "__stop_my_data"), Some(start + 4));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_link_defsym() {
+        let dir = compile(
+            "link-defsym",
+            &[],
+            &[("a.c", "void _start(void) {}\nvoid start2(void) {}\n")],
+        );
+        let config = LinkerConfig {
+            defsyms: vec![
+                parse_defsym("mystart=start2 + 4").unwrap(),
+                parse_defsym("other=mystart-1").unwrap(),
+            ],
+            ..LinkerConfig::default()
+        };
+        let output = Linker::new(config)
+            .add_input(dir.join("a.o"))
+            .link()
+            .unwrap();
+
+        let data = &output.data;
+        let start2 = find_symbol(data, "start2").unwrap();
+        assert_ne!(start2, 0);
+        assert_eq!(find_symbol(data, "mystart"), Some(start2 + 4));
+        assert_eq!(find_symbol(data, "other"), Some(start2 + 3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_link_wrap() {
+        let dir = compile(
+            "link-wrap",
+            &[],
+            &[
+                (
+                    "a.c",
+                    r#"
+extern int foo, __real_foo;
+int *wrapped = &foo;
+int *real = &__real_foo;
+void _start(void) {}
+"#,
+                ),
+                ("b.c", "int foo = 1;\nint __wrap_foo = 2;\n"),
+            ],
+        );
+        let config = LinkerConfig {
+            wrap: HashSet::from(["foo".to_string()]),
+            ..LinkerConfig::default()
+        };
+        let output = Linker::new(config)
+            .add_input(dir.join("a.o"))
+            .add_input(dir.join("b.o"))
+            .link()
+            .unwrap();
+
+        // foo is bound to __wrap_foo, and __real_foo to foo.
+        let data = &output.data;
+        let (shdr, contents) = find_section(data, ".data").unwrap();
+        let read_ptr = |name: &str| {
+            let offset = (find_symbol(data, name).unwrap() - shdr.addr) as usize;
+            u64::from_le_bytes(contents[offset..offset + 8].try_into().unwrap())
+        };
+        assert_eq!(Some(read_ptr("wrapped")), find_symbol(data, "__wrap_foo"));
+        assert_eq!(Some(read_ptr("real")), find_symbol(data, "foo"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn test_link_version_script() {
+        let dir = compile(
+            "link-version-script",
+            &[],
+            &[(
+                "a.c",
+                r#"
+int foo_v1 = 1;
+int foo_v2 = 2;
+int baz = 3;
+int hidden = 4;
+__asm__(".symver foo_v1, foo@V1");
+__asm__(".symver foo_v2, foo@@V2");
+void _start(void) {}
+"#,
+            )],
+        );
+        let config = LinkerConfig {
+            export_dynamic: true,
+            version_script: parse_version_script("V1 { global: baz; local: *; };\nV2 {} V1;\n")
+                .unwrap(),
+            ..LinkerConfig::default()
+        };
+        let output = Linker::new(config)
+            .add_input(dir.join("a.o"))
+            .link()
+            .unwrap();
+
+        // foo@@V2 is exported as the default version of foo, foo@V1 as a
+        // hidden version and baz gets V1 from the script. Versions are
+        // numbered from 2.
+        let data = &output.data;
+        let class = ElfClass::from_contents(data);
+        let shdrs = read_shdrs(data);
+        let (dynsym, _) = find_section(data, ".dynsym").unwrap();
+        let dynstr = section_contents(data, &shdrs[dynsym.link as usize]);
+        let (_, versym) = find_section(data, ".gnu.version").unwrap();
+        let mut syms: Vec<(&str, u16)> = (1..dynsym.size as usize / class.sym_size())
+            .map(|i| {
+                let sym = class.read_sym(&data[dynsym.offset as usize + i * class.sym_size()..]);
+                let name = elf_get_name(dynstr, sym.name).unwrap();
+                (name, u16::from_le_bytes([versym[i * 2], versym[i * 2 + 1]]))
+            })
+            .collect();
+        syms.sort();
+        assert_eq!(syms, [("baz", 2), ("foo", 3), ("foo", 2 | VERSYM_HIDDEN)]);
+        assert!(find_section(data, ".dynamic").is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_link_input_from() {
+        assert_eq!(LinkInput::from("a.o"), LinkInput::Path("a.o".to_string()));
+        assert_eq!(
+            LinkInput::from(PathBuf::from("dir/b.o")),
+            LinkInput::Path("dir/b.o".to_string())
+        );
+        assert_eq!(
+            LinkInput::from(("c.o", vec![1])),
+            LinkInput::Bytes {
+                name: "c.o".to_string(),
+                data: vec![1]
+            }
+        );
+    }
+
+    #[test]
+    fn test_link_missing_input() {
+        let err = Linker::new(LinkerConfig::default())
+            .add_input("does/not/exist.o")
+            .link()
+            .unwrap_err();
+        assert!(matches!(err, LinkError::Io { ref path, .. } if path == "does/not/exist.o"));
+    }
+
+    #[test]
+    fn test_link_unknown_emulation() {
+        let err = Linker::new(LinkerConfig::default())
+            .add_input(("a.txt", b"not an object".to_vec()))
+            .link()
+            .unwrap_err();
+        assert!(matches!(err, LinkError::UnknownEmulation));
+    }
+
+    #[test]
+    fn test_link_bad_symbol_index() {
+        let dir = assemble("link-bad-symbol-index", "riscv64", RISCV_S);
+        let obj = fs::read(dir.join("a.o")).unwrap();
+        let ehdr = ElfClass::from_contents(&obj).read_ehdr(&obj);
+        let shdrs = read_shdrs(&obj);
+        let link = |data: Vec<u8>| {
+            Linker::new(LinkerConfig::default())
+                .add_input(("a.o", data))
+                .link()
+                .unwrap_err()
+        };
+
+        // The sh_info of .symtab is the index of the first global symbol.
+        let mut data = obj.clone();
+        let symtab = shdrs
+            .iter()
+            .position(|shdr| shdr.shdr_type == SHT_SYMTAB)
+            .unwrap();
+        let offset = ehdr.sh_off as usize + symtab * 64 + 44;
+        data[offset..offset + 4].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(matches!(link(data), LinkError::BadElf { ref reason, .. }
+            if reason == "invalid first global symbol index: 1000"));
+
+        // The symbol index is the upper half of r_info.
+        let mut data = obj;
+        let rela = shdrs
+            .iter()
+            .find(|shdr| shdr.shdr_type == SHT_RELA)
+            .unwrap();
+        let offset = rela.offset as usize + 12;
+        data[offset..offset + 4].copy_from_slice(&5000u32.to_le_bytes());
+        assert!(matches!(link(data), LinkError::BadElf { ref reason, .. }
+            if reason == "invalid symbol index: 5000"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_link_truncated_object() {
+        // An ELF header cut off right after e_machine (EM_RISCV).
+        let mut data = b"\x7fELF\x02\x01\x01".to_vec();
+        data.resize(16, 0);
+        data.extend_from_slice(&[1, 0, 243, 0]);
+
+        let err = Linker::new(LinkerConfig::default())
+            .add_input(("a.o", data))
+            .link()
+            .unwrap_err();
+        assert!(matches!(err, LinkError::BadElf { ref file, .. } if file == "a.o"));
+    }
+}
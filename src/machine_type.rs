@@ -1,28 +1,36 @@
 use crate::{
-    file_type::{get_file_type, FileType, FILE_TYPE_OBJECT},
-    utils::read,
+    file_type::{get_file_type, FileType, FILE_TYPE_DSO, FILE_TYPE_OBJECT},
+    utils::try_read,
 };
 pub type MachineType = u8;
 
 pub const MACHINE_TYPE_NONE: MachineType = 0;
 pub const MACHINE_TYPE_RISCV64: MachineType = 1;
+pub const MACHINE_TYPE_RISCV32: MachineType = 2;
+pub const MACHINE_TYPE_X86_64: MachineType = 3;
 
 #[allow(dead_code)]
 pub fn get_machine_type_from_contents(contents: &[u8]) -> MachineType {
     let ft: FileType = get_file_type(contents);
 
     match ft {
-        FILE_TYPE_OBJECT => {
-            let machine: u16 = read(&contents[18..]);
+        FILE_TYPE_OBJECT | FILE_TYPE_DSO => {
+            let machine: u16 = contents.get(18..).and_then(try_read).unwrap_or(0);
             if machine == 243u16 {
                 let class = &contents[4];
                 match class {
                     &2u8 => {
                         return MACHINE_TYPE_RISCV64;
                     }
+                    &1u8 => {
+                        return MACHINE_TYPE_RISCV32;
+                    }
                     _ => {}
                 }
             }
+            if machine == 62u16 && contents[4] == 2u8 {
+                return MACHINE_TYPE_X86_64;
+            }
         }
         _ => {}
     }
@@ -38,6 +46,8 @@ impl MachineTypeStringer {
     fn string(&self) -> &str {
         match self.machine_type {
             MACHINE_TYPE_RISCV64 => "risc64",
+            MACHINE_TYPE_RISCV32 => "riscv32",
+            MACHINE_TYPE_X86_64 => "x86_64",
             _ => "None",
         }
     }
@@ -1,14 +1,24 @@
 #[allow(dead_code)]
 pub fn check_magic(contents: &[u8]) -> bool {
-    let prefix = std::str::from_utf8(&contents[..4]).unwrap();
-    if prefix == "\u{7f}ELF" {
-        true
-    } else {
-        false
-    }
+    contents.starts_with(b"\x7fELF")
 }
 
+// Writes the ELF magic number to the first four bytes of `contents`,
+// usually e_ident.
 #[allow(dead_code)]
-pub fn write_magic(contents: &mut [u8]){
-    contents.copy_from_slice("\u{7f}ELF".as_bytes())
-}
\ No newline at end of file
+pub fn write_magic(contents: &mut [u8]) {
+    contents[..4].copy_from_slice(b"\x7fELF")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_magic() {
+        let mut ident = [0u8; 16];
+        write_magic(&mut ident);
+        assert!(check_magic(&ident));
+        assert!(ident[4..].iter().all(|b| *b == 0));
+    }
+}
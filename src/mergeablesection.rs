@@ -1,12 +1,12 @@
-use crate::{merged_section::MergedSection, section_fragment::SectionFragment};
+use crate::arena::{FragmentId, MergedSectionId};
 
 #[allow(dead_code)]
 pub struct MergeableSection {
-    pub parent: Option<*mut MergedSection>,
+    pub parent: Option<MergedSectionId>,
     pub p2_align: u8,
-    pub strs: Vec<String>,
+    pub strs: Vec<Vec<u8>>,
     pub frag_offsets: Vec<usize>,
-    pub fragments: Vec<*mut SectionFragment>,
+    pub fragments: Vec<FragmentId>,
 }
 
 #[allow(dead_code)]
@@ -21,16 +21,37 @@ impl MergeableSection {
         }
     }
 
-    pub fn get_fragment(&self, offset: usize) -> (Option<*mut SectionFragment>, usize) {
-        let pos = self
-            .frag_offsets
-            .binary_search_by(|fo| fo.cmp(&offset))
-            .unwrap();
-
+    // Returns the fragment that `offset` in the input section falls in and
+    // the offset within the fragment.
+    pub fn get_fragment(&self, offset: usize) -> (Option<FragmentId>, usize) {
+        let pos = self.frag_offsets.partition_point(|fo| *fo <= offset);
         if pos == 0 {
             return (None, 0);
         }
         let idx = pos - 1;
-        return (Some(self.fragments[idx]), offset - self.frag_offsets[idx]);
+        (Some(self.fragments[idx]), offset - self.frag_offsets[idx])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::Id;
+
+    #[test]
+    fn test_get_fragment() {
+        let frags: Vec<FragmentId> = (0..3).map(FragmentId::from_index).collect();
+        let mut m = MergeableSection::new();
+        m.frag_offsets = vec![0, 4, 10];
+        m.fragments = frags.clone();
+
+        assert_eq!(m.get_fragment(0), (Some(frags[0]), 0));
+        assert_eq!(m.get_fragment(3), (Some(frags[0]), 3));
+        assert_eq!(m.get_fragment(4), (Some(frags[1]), 0));
+        assert_eq!(m.get_fragment(12), (Some(frags[2]), 2));
+
+        m.frag_offsets = vec![2];
+        m.fragments = vec![frags[0]];
+        assert_eq!(m.get_fragment(1), (None, 0));
     }
 }
@@ -1,123 +1,236 @@
-use std::{collections::HashMap, cmp::Ordering};
+use std::collections::HashMap;
 
 use crate::{
+    arena::{Arena, FragmentId, Id, MergedSectionId},
     chunk::Chunk,
     context::Context,
-    elf::{SHF_COMPRESSED, SHF_GROUP, SHF_MERGE, SHF_STRINGS},
+    elf::{SHF_COMPRESSED, SHF_GROUP, SHF_STRINGS},
     output::get_output_name,
-    section_fragment::SectionFragment, utils::align_to,
+    section_fragment::SectionFragment,
+    utils::align_to,
 };
 
+// An output section that SHF_MERGE input sections with the same name,
+// flags and entry size are merged into. Identical pieces share one
+// fragment. The map is only used for lookups; fragments are laid out and
+// written in the order they were first inserted, so the output doesn't
+// depend on the hash map's iteration order.
 #[allow(dead_code)]
 pub struct MergedSection {
-    pub chunk: *mut Chunk,
-    pub map: HashMap<String, *mut SectionFragment>,
+    pub id: MergedSectionId,
+    pub chunk: Chunk,
+    pub map: HashMap<Vec<u8>, FragmentId>,
 }
 
 #[allow(dead_code)]
 impl MergedSection {
-    pub fn new(name: String, flags: u64, ty: u32) -> Self {
+    pub fn new(id: MergedSectionId, name: String, flags: u64, ty: u32, ent_size: u64) -> Self {
         let mut chunk = Chunk::new();
         chunk.name = name;
         chunk.shdr.flags = flags;
         chunk.shdr.shdr_type = ty;
+        chunk.shdr.ent_size = ent_size;
 
         MergedSection {
-            chunk: Box::leak(Box::new(chunk)),
+            id,
+            chunk,
             map: HashMap::new(),
         }
     }
 
-    pub fn insert(&mut self, key: &str, p2_align: u32) -> Option<*mut SectionFragment>{
-        let sf = self.map.remove(key);
-        match sf {
-            Some(v) => {
-                if (unsafe { &(*v) }).p2_align < p2_align {
-                    let frag = Box::new(SectionFragment::new(self as *mut MergedSection));
-                    let frag = Box::leak(frag);
-                    frag.p2_align = p2_align;
-                    self.map.insert(String::from(key), frag);
-                }
-            }
-            None => {
-                let frag = Box::new(SectionFragment::new(self as *mut MergedSection));
-                let frag = Box::leak(frag);
-                self.map.insert(String::from(key), frag);
-            }
-        }
-        sf
+    // Returns the fragment for `key`, creating it if it is new. A fragment
+    // is aligned as strictly as the most aligned section it comes from.
+    pub fn insert(
+        &mut self,
+        fragments: &mut Arena<FragmentId, SectionFragment>,
+        key: &[u8],
+        p2_align: u32,
+    ) -> FragmentId {
+        let idx = self.map.len() as u32;
+        let frag = *self
+            .map
+            .entry(key.to_vec())
+            .or_insert_with(|| fragments.push(SectionFragment::new(self.id, idx)));
+        let frag_ref = &mut fragments[frag];
+        frag_ref.p2_align = frag_ref.p2_align.max(p2_align);
+        frag
     }
 
-    pub fn assign_offsets(&mut self){
-        struct Fragment{
-            key: String,
-            val: *mut SectionFragment
+    // The fragments and their contents in first insertion order.
+    fn get_fragments(
+        &self,
+        fragments: &Arena<FragmentId, SectionFragment>,
+    ) -> Vec<(&[u8], FragmentId)> {
+        let mut ret = vec![(&[][..], FragmentId::from_index(0)); self.map.len()];
+        for (key, frag) in &self.map {
+            ret[fragments[*frag].idx as usize] = (&key[..], *frag);
         }
+        ret
+    }
 
-        let mut fragments: Vec<Fragment> = vec![];
-        for (key,sec_f)  in &self.map{
-            fragments.push(Fragment { key: String::from(key), val: *sec_f })
-        }
+    // Lays out the fragments and sets the section size. Fragments are
+    // grouped by alignment and otherwise kept in insertion order. With
+    // `tail_merge`, a string that is the tail of another one, e.g. "bar\0"
+    // of "foobar\0", is not copied but points into the longer string.
+    pub fn assign_offsets(
+        &mut self,
+        fragments: &mut Arena<FragmentId, SectionFragment>,
+        tail_merge: bool,
+    ) {
+        let mut order = self.get_fragments(fragments);
 
-        fragments.sort_by(|a: &Fragment, b:&Fragment| -> Ordering{
-            if unsafe{a.val.as_ref().unwrap().p2_align != b.val.as_ref().unwrap().p2_align}{
-                return Ordering::Less
-            }
-            if a.key != b.key {
-                return Ordering::Less
-            }
-            Ordering::Less
-        });
+        let tail_merge = tail_merge && self.chunk.shdr.flags & SHF_STRINGS != 0;
+        if tail_merge {
+            // Sorting the strings backwards by their reversed bytes puts
+            // every string right after one that ends with it, if any.
+            order.sort_by(|a, b| b.0.iter().rev().cmp(a.0.iter().rev()));
+        } else {
+            order.sort_by_key(|(_, frag)| fragments[*frag].p2_align);
+        }
 
         let mut offset = 0u64;
-        let mut p2_align = 0u64;
-
-        for frag in fragments{
-            offset = align_to(offset, 1<<unsafe { frag.val.as_ref().unwrap().p2_align });
-            unsafe { frag.val.as_mut().unwrap().offset = offset as u32 };
-            offset += frag.key.len() as u64;
-            if p2_align < unsafe { frag.val.as_ref().unwrap().p2_align as u64} {
-                p2_align = unsafe { frag.val.as_ref().unwrap().p2_align  as u64};
-            }
+        let mut max_align = 0u32;
+        let mut prev: Option<(&[u8], u64)> = None;
+        for (key, frag) in order {
+            let align = fragments[frag].p2_align;
+            max_align = max_align.max(align);
+
+            let tail = prev
+                .filter(|(prev_key, _)| tail_merge && prev_key.ends_with(key))
+                .map(|(prev_key, prev_offset)| prev_offset + (prev_key.len() - key.len()) as u64)
+                .filter(|tail_offset| tail_offset % (1 << align) == 0);
+            let frag_offset = tail.unwrap_or_else(|| {
+                let frag_offset = align_to(offset, 1 << align);
+                offset = frag_offset + key.len() as u64;
+                frag_offset
+            });
+            fragments[frag].offset = frag_offset as u32;
+            prev = Some((key, frag_offset));
         }
 
-        unsafe { self.chunk.as_mut().unwrap().shdr.size = align_to(offset, 1<<p2_align) };
-        unsafe { self.chunk.as_mut().unwrap().shdr.addr_align = 1 << p2_align };
+        let chunk = &mut self.chunk;
+        chunk.shdr.size = align_to(offset, 1 << max_align);
+        chunk.shdr.addr_align = 1 << max_align;
+    }
+
+    pub fn copy_buf(&self, ctx: &Context, buf: &mut [u8]) {
+        let shdr = self.chunk.shdr;
+        let base = shdr.offset as usize;
+        self.write_to(&ctx.fragments, &mut buf[base..base + shdr.size as usize]);
     }
 
-    pub fn copy_buf(&self, ctx: &mut Context){
-        let buf = &mut ctx.buf[unsafe { self.chunk.as_ref().unwrap().shdr.offset as usize }..];
-        for (key, _sec_f) in &self.map{
-            buf.copy_from_slice(key.as_bytes());
+    // Writes every fragment at its offset. Tail merged strings are
+    // written over the end of the string they share, with the same bytes.
+    pub fn write_to(&self, fragments: &Arena<FragmentId, SectionFragment>, buf: &mut [u8]) {
+        for (key, frag) in self.get_fragments(fragments) {
+            let offset = fragments[frag].offset as usize;
+            buf[offset..offset + key.len()].copy_from_slice(key);
         }
     }
 }
 
+// Returns the merged section for SHF_MERGE input sections with the given
+// name, type, flags and entry size, creating it on first use.
 #[allow(dead_code)]
-pub fn get_merged_section_instance<'a>(
-    ctx: &'a mut Context,
+pub fn get_merged_section_instance(
+    ctx: &mut Context,
     name: &str,
     ty: u32,
     flags: u64,
-) -> Option<*mut MergedSection> {
-    let name = get_output_name(&name, flags);
-    let flags = flags & SHF_GROUP & SHF_MERGE & SHF_STRINGS & SHF_COMPRESSED;
-
-    let find = || -> Option<*mut MergedSection> {
-        for osec in &ctx.merged_sections {
-            if &name == unsafe { &osec.as_ref().unwrap().chunk.as_ref().unwrap().name }
-                && flags == unsafe { osec.as_ref().unwrap().chunk.as_ref().unwrap().shdr.flags}
-                && ty == unsafe { osec.as_ref().unwrap().chunk.as_ref().unwrap().shdr.shdr_type}
-            {
-                return Some(osec.clone());
-            }
+    ent_size: u64,
+) -> MergedSectionId {
+    let name = get_output_name(name, flags);
+    let flags = flags & !SHF_GROUP & !SHF_COMPRESSED;
+
+    for (id, m) in ctx.merged_sections.iter() {
+        let shdr = m.chunk.shdr;
+        if m.chunk.name == name
+            && shdr.flags == flags
+            && shdr.shdr_type == ty
+            && shdr.ent_size == ent_size
+        {
+            return id;
+        }
+    }
+
+    let id = ctx.merged_sections.next_id();
+    ctx.merged_sections
+        .push(MergedSection::new(id, name, flags, ty, ent_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::{SHF_ALLOC, SHF_MERGE, SHT_PROGBITS};
+
+    type Fragments = Arena<FragmentId, SectionFragment>;
+
+    fn layout(strs: &[&[u8]], tail_merge: bool) -> (MergedSection, Fragments, Vec<u32>) {
+        let mut fragments = Arena::new();
+        let mut m = MergedSection::new(
+            MergedSectionId::from_index(0),
+            ".rodata.str".to_string(),
+            SHF_ALLOC | SHF_MERGE | SHF_STRINGS,
+            SHT_PROGBITS,
+            1,
+        );
+        let frags: Vec<_> = strs
+            .iter()
+            .map(|s| m.insert(&mut fragments, s, 0))
+            .collect();
+        m.assign_offsets(&mut fragments, tail_merge);
+        let offsets = frags.iter().map(|f| fragments[*f].offset).collect();
+        (m, fragments, offsets)
+    }
+
+    fn contents(m: &MergedSection, fragments: &Fragments) -> Vec<u8> {
+        let mut buf = vec![0xff; m.chunk.shdr.size as usize];
+        m.write_to(fragments, &mut buf);
+        buf
+    }
+
+    #[test]
+    fn test_merge_strings() {
+        let (m, fragments, offsets) = layout(&[b"foo\0", b"bar\0", b"foo\0", b"obar\0"], false);
+        assert_eq!(m.map.len(), 3);
+        assert_eq!(offsets[0], offsets[2]);
+        assert_eq!(contents(&m, &fragments), b"foo\0bar\0obar\0");
+        assert_eq!(offsets, [0, 4, 0, 8]);
+    }
+
+    #[test]
+    fn test_tail_merge() {
+        let (m, fragments, offsets) = layout(&[b"bar\0", b"foobar\0", b"ar\0", b"baz\0"], true);
+        let buf = contents(&m, &fragments);
+        assert_eq!(buf.len(), 11);
+        for (s, offset) in [&b"bar\0"[..], b"foobar\0", b"ar\0", b"baz\0"]
+            .iter()
+            .zip(offsets)
+        {
+            assert_eq!(&buf[offset as usize..offset as usize + s.len()], *s);
         }
-        return None;
-    };
+    }
+
+    #[test]
+    fn test_insert_keeps_max_alignment() {
+        let mut fragments = Arena::new();
+        let mut m = MergedSection::new(
+            MergedSectionId::from_index(0),
+            ".rodata.cst".to_string(),
+            SHF_MERGE,
+            SHT_PROGBITS,
+            8,
+        );
+        let a = m.insert(&mut fragments, &[1; 8], 3);
+        let b = m.insert(&mut fragments, &[1; 8], 0);
+        let c = m.insert(&mut fragments, &[2; 8], 0);
+        assert_eq!(a, b);
+        assert_eq!(fragments[a].p2_align, 3);
 
-    let osce = find();
-    match osce {
-        Some(ms) => return Some(ms as *mut MergedSection),
-        None => return None,
+        // Tail merging only applies to strings.
+        m.assign_offsets(&mut fragments, true);
+        assert_eq!(fragments[a].offset, 8);
+        assert_eq!(fragments[c].offset, 0);
+        assert_eq!(m.chunk.shdr.addr_align, 8);
     }
-}
\ No newline at end of file
+}
@@ -1,373 +1,697 @@
-use std::vec;
+use std::{collections::HashSet, vec};
 
 use crate::{
+    arena::{FileId, SectionId, SymbolId},
     context::Context,
+    eh_frame::{CieRecord, FdeRecord},
     elf::{
-        elf_get_name, Shdr, Sym, SHF_ALLOC, SHF_MERGE, SHF_STRINGS, SHN_XINDEX, SHT_GROUP,
-        SHT_NULL, SHT_REL, SHT_RELA, SHT_STRTAB, SHT_SYMTAB, SHT_SYMTAB_SHNDX,
+        elf_get_name, Rela, Shdr, Sym, SHF_ALLOC, SHF_STRINGS, SHN_XINDEX, SHT_GROUP, SHT_NULL,
+        SHT_REL, SHT_RELA, SHT_RISCV_ATTRIBUTES, SHT_STRTAB, SHT_SYMTAB, SHT_SYMTAB_SHNDX,
+        STT_SECTION,
     },
+    error::LinkError,
     file::ElfFile,
     input_file::{new_input_file, InputFile},
     input_section::InputSection,
     mergeablesection::MergeableSection,
-    merged_section::get_merged_section_instance,
+    riscv_attributes::RiscvAttributes,
     symbol::Symbol,
-    utils::{all_zeros, fatal},
+    utils::{all_zeros, read, try_read},
 };
 
+// Methods that only read the file take the context as `&Context`. Those
+// that also update sections or symbols owned by the context are
+// associated functions taking the context and the file's ID.
 #[allow(dead_code)]
 pub struct ObjectFile<'a> {
-    pub input_file: *mut InputFile<'a>,
+    pub id: FileId,
+    pub input_file: InputFile<'a>,
     pub symtab_sec: Option<Shdr>,
     pub symbol_shndx_sec: Vec<u32>,
-    pub input_sections: Vec<Option<*mut InputSection<'a>>>,
-    pub mergeable_sections: Vec<Option<*mut MergeableSection>>,
+    pub input_sections: Vec<Option<SectionId>>,
+    pub mergeable_sections: Vec<Option<MergeableSection>>,
+    pub symvers: Vec<Option<String>>,
+    pub cies: Vec<CieRecord>,
+    pub fdes: Vec<FdeRecord>,
+    pub riscv_attributes: Option<RiscvAttributes>,
+
+    // Created by -b binary from a raw file. It has no ABI of its own.
+    pub is_binary: bool,
 }
 
 #[allow(dead_code)]
-pub fn new_object_file(elf_file: ElfFile, is_alive: bool) -> ObjectFile {
-    let input_file = new_input_file(elf_file);
-    unsafe { input_file.as_mut().unwrap().is_alive = is_alive };
+pub fn new_object_file(
+    id: FileId,
+    elf_file: ElfFile,
+    is_alive: bool,
+) -> Result<ObjectFile, LinkError> {
+    let mut input_file = new_input_file(elf_file)?;
+    input_file.is_alive = is_alive;
     let object_file = ObjectFile {
+        id,
         input_file: input_file,
         symtab_sec: None,
         symbol_shndx_sec: vec![],
         input_sections: vec![],
         mergeable_sections: vec![],
+        symvers: vec![],
+        cies: vec![],
+        fdes: vec![],
+        riscv_attributes: None,
+        is_binary: false,
     };
-    object_file
+    Ok(object_file)
+}
+
+// --wrap=foo: an undefined reference to `foo` becomes a reference to
+// `__wrap_foo`, and an undefined reference to `__real_foo` becomes a
+// reference to `foo`. As with GNU ld, symbols defined in the object
+// itself are never renamed.
+fn get_wrapped_name(wrap: &HashSet<String>, esym: Sym, name: &str) -> Option<String> {
+    if !esym.is_undef() {
+        return None;
+    }
+    if wrap.contains(name) {
+        return Some(String::from("__wrap_") + name);
+    }
+    match name.strip_prefix("__real_") {
+        Some(real) if wrap.contains(real) => Some(String::from(real)),
+        _ => None,
+    }
 }
 
 #[allow(dead_code)]
 impl<'a> ObjectFile<'a> {
-    pub fn parse(&mut self) {
-        self.symtab_sec =
-            unsafe { self.input_file.as_mut().unwrap() }.find_section(SHT_SYMTAB as u32);
+    pub fn parse(&mut self) -> Result<(), LinkError> {
+        self.symtab_sec = self.input_file.find_section(SHT_SYMTAB as u32);
         match self.symtab_sec {
             None => {}
             Some(shdr) => {
-                unsafe { self.input_file.as_mut().unwrap() }.first_global = Some(shdr.info as i64);
-                unsafe { self.input_file.as_mut().unwrap() }.fillup_elf_syms(shdr);
-                unsafe { self.input_file.as_mut().unwrap() }.symbol_strtab = Some(
-                    unsafe { self.input_file.as_ref().unwrap() }
-                        .get_bytes_from_idx(shdr.link as i64),
-                );
+                self.input_file.first_global = Some(shdr.info as i64);
+                self.input_file.fillup_elf_syms(shdr)?;
+                self.input_file.symbol_strtab =
+                    Some(self.input_file.get_bytes_from_idx(shdr.link as i64)?);
             }
         }
+        Ok(())
     }
 
-    pub fn initialize_sections(&'a mut self, ctx: Context<'a>) {
-        for i in 0..unsafe { self.input_file.as_ref().unwrap() }
-            .elf_sections
-            .len()
-        {
-            let shdr = unsafe { self.input_file.as_ref().unwrap() }.elf_sections[i];
+    pub fn initialize_sections(ctx: &mut Context<'a>, id: FileId) -> Result<(), LinkError> {
+        let num_sections = ctx.files[id].input_file.elf_sections.len();
+        ctx.files[id].input_sections = vec![None; num_sections];
+
+        for i in 0..num_sections {
+            let file = &mut ctx.files[id];
+            let shdr = file.input_file.elf_sections[i];
             match shdr.shdr_type {
-                SHT_GROUP | SHT_SYMTAB | SHT_STRTAB | SHT_RELA | SHT_NULL | SHT_REL => {
-                    break;
-                }
+                SHT_GROUP | SHT_SYMTAB | SHT_STRTAB | SHT_RELA | SHT_NULL | SHT_REL => {}
                 SHT_SYMTAB_SHNDX => {
-                    self.fillup_symtab_shndx_sec(shdr);
+                    file.fillup_symtab_shndx_sec(shdr)?;
+                }
+                SHT_RISCV_ATTRIBUTES => {
+                    file.riscv_attributes = Some(RiscvAttributes::parse(
+                        file.input_file.get_bytes_from_shdr(&shdr)?,
+                        file.input_file.file.name,
+                    )?);
                 }
                 _ => {
-                    let name = elf_get_name(
-                        unsafe { self.input_file.as_ref().unwrap().sh_strtab.unwrap() },
-                        shdr.name,
-                    );
-                    self.input_sections[i] = Some(Box::leak(Box::new(InputSection::new(
-                        &ctx,
-                        name.to_owned(),
-                        self,
-                        i,
-                    ))));
+                    let input_file = &file.input_file;
+                    let name = elf_get_name(input_file.sh_strtab.unwrap(), shdr.name)
+                        .map_err(|reason| input_file.corrupted(reason))?;
+                    let isec = InputSection::new(ctx, name.to_owned(), id, i)?;
+                    ctx.files[id].input_sections[i] = Some(ctx.sections.push(isec));
+                }
+            }
+        }
+
+        let file = &ctx.files[id];
+        let input_file = &file.input_file;
+        for shdr in &input_file.elf_sections {
+            if shdr.shdr_type != SHT_RELA {
+                continue;
+            }
+
+            if shdr.info as usize >= file.input_sections.len() {
+                return Err(
+                    input_file.corrupted(format!("invalid relocated section index: {}", shdr.info))
+                );
+            }
+            if let Some(target) = file.input_sections[shdr.info as usize] {
+                let target = &mut ctx.sections[target];
+                // The offsets are into the uncompressed contents.
+                let size = target.sh_size;
+                for rel in input_file.reader.relocations(shdr)? {
+                    if rel.offset >= size {
+                        return Err(input_file
+                            .corrupted(format!("relocation offset is out of range: 0x{:x}", rel.offset)));
+                    }
+                    if rel.sym as usize >= input_file.elf_syms.len() {
+                        return Err(
+                            input_file.corrupted(format!("invalid symbol index: {}", rel.sym))
+                        );
+                    }
+                    target.rels.push(rel);
                 }
             }
         }
+        Ok(())
     }
 
-    pub fn initialize_symbols(&'a mut self, ctx: Context<'a>) {
+    // The names of the global symbols, with --wrap applied and `@@VER`
+    // suffixes removed, and the versions they define. This only reads the
+    // file, so it runs for several files at once.
+    pub fn read_global_names(
+        &self,
+        wrap: &HashSet<String>,
+    ) -> Result<(Vec<String>, Vec<Option<String>>), LinkError> {
+        let mut names = vec![];
+        let mut symvers = vec![];
         if self.symtab_sec.is_none() {
-            return ();
+            return Ok((names, symvers));
         }
 
-        for _i in 0..unsafe { self.input_file.as_ref().unwrap().local_symbols.len() } {
-            unsafe {
-                self.input_file
-                    .as_mut()
-                    .unwrap()
-                    .local_symbols
-                    .push(Box::leak(Box::new(Symbol::new(""))))
+        let input_file = &self.input_file;
+        let first_global = input_file.first_global.unwrap() as usize;
+        let str_tab = input_file.symbol_strtab.unwrap();
+        for esym in &input_file.elf_syms[first_global..] {
+            let mut name =
+                elf_get_name(str_tab, esym.name).map_err(|reason| input_file.corrupted(reason))?;
+            let wrapped = get_wrapped_name(wrap, *esym, name);
+            if let Some(wrapped) = &wrapped {
+                name = wrapped;
+            }
+
+            // `foo@@VER` defines the default version of `foo` and is
+            // registered as plain `foo`. `foo@VER` is a non-default version
+            // and keeps its versioned name. The recorded version keeps the
+            // second `@` of a default version.
+            let mut symver = None;
+            if let Some(pos) = name.find('@') {
+                let ver = &name[pos + 1..];
+                if !esym.is_undef() {
+                    symver = Some(String::from(ver));
+                }
+                if ver.starts_with('@') {
+                    name = &name[..pos];
+                }
             }
+            names.push(name.to_string());
+            symvers.push(symver);
         }
+        Ok((names, symvers))
+    }
 
-        for i in 0..unsafe { self.input_file.as_ref().unwrap().local_symbols.len() } {
-            let esym = unsafe { &self.input_file.as_ref().unwrap().elf_syms }[i];
-            let sym = unsafe { &self.input_file.as_mut().unwrap().local_symbols }[i];
-            let str_tab = unsafe { self.input_file.as_ref().unwrap().symbol_strtab.unwrap() };
-            unsafe { sym.as_mut().unwrap() }.name = elf_get_name(str_tab, esym.name);
-            unsafe { sym.as_mut().unwrap() }.object_file = Some(self as *mut ObjectFile);
-            unsafe { sym.as_mut().unwrap() }.value = esym.val;
-            unsafe { sym.as_mut().unwrap() }.symidx = i as i32;
+    // Creates the local symbols and records the global ones, which the
+    // caller has looked up by the names from read_global_names.
+    pub fn initialize_symbols(
+        ctx: &mut Context<'a>,
+        id: FileId,
+        globals: Vec<SymbolId>,
+        symvers: Vec<Option<String>>,
+    ) -> Result<(), LinkError> {
+        if ctx.files[id].symtab_sec.is_none() {
+            return Ok(());
         }
 
-        for i in 0..unsafe { self.input_file.as_ref().unwrap().local_symbols.len() } {
-            unsafe {
-                self.input_file
-                    .as_mut()
-                    .unwrap()
-                    .symbols
-                    .push(self.input_file.as_ref().unwrap().local_symbols[i])
-            };
+        let first_global = ctx.files[id].input_file.first_global.unwrap() as usize;
+        for _i in 0..first_global {
+            let sym = ctx.symbols.push(Symbol::new(""));
+            ctx.files[id].input_file.local_symbols.push(sym);
         }
 
-        let len_ls = unsafe { self.input_file.as_ref().unwrap().local_symbols.len() };
-        let len_es = unsafe { self.input_file.as_ref().unwrap().elf_syms.len() };
-        for i in len_ls..len_es {
-            let esym = unsafe { &self.input_file.as_ref().unwrap().elf_syms }[i];
-            let str_tab = unsafe { self.input_file.as_ref().unwrap().symbol_strtab.unwrap() };
-            let name = elf_get_name(str_tab, esym.name);
-            unsafe { self.input_file.as_mut().unwrap() }.symbols[i] =
-                Symbol::get_symbol_by_name(&ctx, name)
+        let file = &mut ctx.files[id];
+        let input_file = &file.input_file;
+        let get_name = |str_tab, offset| {
+            elf_get_name(str_tab, offset).map_err(|reason| input_file.corrupted(reason))
+        };
+        for i in 0..input_file.local_symbols.len() {
+            let esym = input_file.elf_syms[i];
+            let sym = &mut ctx.symbols[input_file.local_symbols[i]];
+            sym.name = get_name(input_file.symbol_strtab.unwrap(), esym.name)?.to_string();
+            sym.object_file = Some(id);
+            sym.value = esym.val;
+            sym.symidx = i as i32;
+            if !esym.is_abs() && !esym.is_undef() && !esym.is_common() {
+                if let Some(isec) = file.get_section(esym, i) {
+                    sym.set_input_section(isec);
+                }
+            }
         }
+
+        let local_symbols = file.input_file.local_symbols.clone();
+        file.input_file.symbols.extend(local_symbols);
+        file.input_file.symbols.extend(globals);
+
+        file.symvers = vec![None; first_global];
+        file.symvers.extend(symvers);
+        Ok(())
     }
 
-    pub fn fillup_symtab_shndx_sec(&mut self, shdr: Shdr) {
-        let bs = unsafe { self.input_file.as_ref().unwrap() }.get_bytes_from_shdr(&shdr);
-        self.symbol_shndx_sec = bs.into_iter().map(|n| *n as u32).collect();
+    pub fn fillup_symtab_shndx_sec(&mut self, shdr: Shdr) -> Result<(), LinkError> {
+        let bs = self.input_file.get_bytes_from_shdr(&shdr)?;
+        self.symbol_shndx_sec = bs.chunks_exact(4).map(read::<u32>).collect();
+        Ok(())
     }
 
     pub fn get_shndx(&self, esym: Sym, idx: i32) -> usize {
-        assert!(
-            idx >= 0
-                && idx
-                    < (unsafe { self.input_file.as_ref() }.unwrap().elf_syms.len() as usize)
-                        .try_into()
-                        .unwrap()
-        );
+        assert!(idx >= 0 && (idx as usize) < self.input_file.elf_syms.len());
         if esym.shndx == SHN_XINDEX {
-            return self.symbol_shndx_sec[idx as usize] as usize;
+            return self
+                .symbol_shndx_sec
+                .get(idx as usize)
+                .copied()
+                .unwrap_or(0) as usize;
         }
-        return esym.shndx as usize;
+        esym.shndx as usize
     }
 
-    pub fn resolve_symbols(&'a mut self) {
-        for i in 0..unsafe { self.input_file.as_ref().unwrap().first_global.unwrap() } {
-            let sym = unsafe { &self.input_file.as_ref().unwrap().symbols }[i as usize];
-            let esym = unsafe { self.input_file.as_ref().unwrap() }.elf_syms[i as usize];
-
-            if esym.is_undef() {
+    // Claims the global symbols this file defines. A symbol defined by
+    // several files goes to the first one, unless that definition is
+    // weak and this one is not.
+    pub fn resolve_symbols(ctx: &mut Context<'a>, id: FileId) {
+        let file = &ctx.files[id];
+        let input_file = &file.input_file;
+        let Some(first_global) = input_file.first_global else {
+            return;
+        };
+
+        for i in first_global as usize..input_file.elf_syms.len() {
+            let esym = input_file.elf_syms[i];
+            if esym.is_undef() || esym.is_common() {
                 continue;
             }
 
-            let mut isec: Option<*mut InputSection> = None;
+            let mut isec = None;
             if !esym.is_abs() {
-                isec = Some(self.get_section(esym, i.try_into().unwrap()))
+                isec = file.get_section(esym, i);
+                if isec.is_none() {
+                    continue;
+                }
+            }
+
+            let sym = &ctx.symbols[input_file.symbols[i]];
+            let claim = match sym.object_file {
+                None => true,
+                Some(owner) => owner != id && !esym.is_weak() && sym.elf_sym(ctx).is_weak(),
+            };
+            if !claim {
+                continue;
             }
 
-            if unsafe { sym.as_ref().unwrap().object_file.is_none() } {
-                let sym = unsafe { sym.as_mut().unwrap() };
-                sym.object_file = Some((self as *const ObjectFile).cast_mut());
-                sym.value = esym.val;
-                sym.symidx = i as i32;
-                sym.set_input_section(isec.unwrap());
+            let sym = &mut ctx.symbols[input_file.symbols[i]];
+            sym.object_file = Some(id);
+            sym.value = esym.val;
+            sym.symidx = i as i32;
+            match isec {
+                Some(isec) => sym.set_input_section(isec),
+                None => {
+                    sym.input_section = None;
+                    sym.section_fragment = None;
+                }
             }
         }
     }
 
-    pub fn get_section(&'a self, esym: Sym, idx: usize) -> *mut InputSection {
-        self.input_sections[self.get_shndx(esym, idx.try_into().unwrap())].unwrap()
+    // The input section a symbol is defined in, if the file has one
+    // there.
+    pub fn get_section(&self, esym: Sym, idx: usize) -> Option<SectionId> {
+        self.input_sections
+            .get(self.get_shndx(esym, idx as i32))
+            .copied()
+            .flatten()
     }
 
-    pub fn mark_live_objects(&self, _ctx: &Context, mut feeder: impl FnMut()) {
-        assert!(unsafe { self.input_file.as_ref().unwrap().is_alive });
-
-        let fg = unsafe { self.input_file.as_ref().unwrap().first_global.unwrap() };
-        let len_es = unsafe { self.input_file.as_ref().unwrap().elf_syms.len() };
-
-        for i in fg..(len_es as u64) as i64 {
-            let sym = unsafe { &self.input_file.as_ref().unwrap().symbols }[i as usize];
-            let esym = unsafe { self.input_file.as_ref().unwrap().elf_syms[i as usize] };
+    // Calls `feeder` for every file not yet in the link that defines a
+    // symbol this file refers to, after marking it live.
+    pub fn mark_live_objects(ctx: &mut Context<'a>, id: FileId, mut feeder: impl FnMut(FileId)) {
+        assert!(ctx.files[id].input_file.is_alive);
+        let first_global = ctx.files[id].input_file.first_global.unwrap_or(0) as usize;
 
-            if unsafe { sym.as_ref().unwrap().object_file.is_none() } {
+        for i in first_global..ctx.files[id].input_file.elf_syms.len() {
+            let input_file = &ctx.files[id].input_file;
+            let esym = input_file.elf_syms[i];
+            let Some(owner) = ctx.symbols[input_file.symbols[i]].object_file else {
                 continue;
-            }
-
-            if esym.is_undef()
-                && !unsafe {
-                    sym.as_ref()
-                        .unwrap()
-                        .input_section
-                        .unwrap()
-                        .as_ref()
-                        .unwrap()
-                        .is_alive
-                }
-            {
-                unsafe {
-                    sym.as_ref()
-                        .unwrap()
-                        .input_section
-                        .unwrap()
-                        .as_mut()
-                        .unwrap()
-                }
-                .is_alive = true;
-                let _ = &feeder();
-            }
-        }
-    }
+            };
 
-    pub fn clear_symbols(&self) {
-        for sym in unsafe { &self.input_file.as_ref().unwrap().symbols } {
-            if unsafe { sym.as_ref().unwrap().object_file.is_none() } {
-                unsafe { sym.as_ref().unwrap().clear() }
+            if esym.is_undef() && !ctx.files[owner].input_file.is_alive {
+                ctx.files[owner].input_file.is_alive = true;
+                feeder(owner);
             }
         }
     }
 
-    pub fn initilize_mergeable_sections(&mut self, ctx: &mut Context) {
-        for i in 0..unsafe { self.input_file.as_ref().unwrap().symbols.len() } {
-            let isec = self.input_sections[i];
-            if isec.is_none()
-                && unsafe { isec.unwrap().as_ref().unwrap().is_alive }
-                && unsafe { isec.unwrap().as_ref().unwrap().shdr().flags } & SHF_MERGE != 0
-            {
-                self.mergeable_sections[i] = Some(self.split_section(ctx, isec.unwrap()));
-                unsafe { isec.unwrap().as_mut().unwrap().is_alive = false };
+    // Gives up the symbols of a file that is not part of the link.
+    pub fn clear_symbols(ctx: &mut Context<'a>, id: FileId) {
+        let input_file = &ctx.files[id].input_file;
+        let first_global = input_file.first_global.unwrap_or(0) as usize;
+        for sym in &input_file.symbols[first_global..] {
+            let sym = &mut ctx.symbols[*sym];
+            if sym.object_file == Some(id) {
+                sym.clear()
             }
         }
     }
 
+    // Returns the offset of the first null character, which is `ent_size`
+    // bytes wide, or -1 if there is none.
     pub fn find_null(data: &[u8], ent_size: usize) -> isize {
         if ent_size == 1 {
-            return data.binary_search(&0u8).unwrap() as isize;
+            return data.iter().position(|b| *b == 0).map_or(-1, |i| i as isize);
         }
 
-        for i in 0..((data.len() - ent_size) / ent_size) {
-            let bs = &data[i..(i + ent_size)];
-            if all_zeros(bs) {
-                return i.try_into().unwrap();
+        for i in (0..data.len() / ent_size).map(|i| i * ent_size) {
+            if all_zeros(&data[i..i + ent_size]) {
+                return i as isize;
             }
         }
-
-        return -1;
+        -1
     }
 
+    // Splits the contents of a SHF_MERGE section into pieces: null
+    // terminated strings for SHF_STRINGS and sh_entsize-sized constants
+    // otherwise. Returns the pieces and their offsets in the section.
+    // Sections are split on several threads; see
+    // passes::initialize_mergeable_sections.
     pub fn split_section(
-        &self,
-        ctx: &mut Context,
-        isec: *mut InputSection,
-    ) -> *mut MergeableSection {
+        ctx: &Context,
+        isec: SectionId,
+    ) -> Result<(Vec<Vec<u8>>, Vec<usize>), LinkError> {
         let mut m = MergeableSection::new();
-        let shdr = unsafe { isec.as_ref().unwrap() }.shdr();
-
-        m.parent = get_merged_section_instance(
-            ctx,
-            unsafe { isec.as_ref().unwrap() }.name(),
-            shdr.shdr_type,
-            shdr.flags,
-        );
-        m.p2_align = unsafe { isec.as_ref().unwrap() }.p2_align;
-
-        let mut data = unsafe { isec.as_ref().unwrap() }.contents;
-        let mut offset: usize = 0usize;
-
+        let isec = &ctx.sections[isec];
+        let shdr = isec.shdr();
+        let corrupted = |reason: &str| {
+            ctx.files[isec.object_file]
+                .input_file
+                .corrupted(format!("{}: {}", isec.name(), reason))
+        };
+
+        let mut data = &isec.contents[..];
+        let mut offset = 0;
         if shdr.flags & SHF_STRINGS != 0 {
-            for _i in 0..data.len() {
-                let end = ObjectFile::find_null(data, shdr.ent_size.try_into().unwrap());
+            let ent_size = shdr.ent_size.max(1) as usize;
+            while !data.is_empty() {
+                let end = ObjectFile::find_null(data, ent_size);
                 if end == -1 {
-                    fatal("string is not null terminated")
+                    return Err(corrupted("string is not null terminated"));
                 }
 
-                let sz = end + shdr.ent_size as isize;
-                let sub_str = &data[..(sz as usize)];
-                m.strs.push(String::from_utf8(sub_str.to_vec()).unwrap());
+                let sz = end as usize + ent_size;
+                m.strs.push(data[..sz].to_vec());
                 m.frag_offsets.push(offset);
-                offset += sz as usize;
+                data = &data[sz..];
+                offset += sz;
             }
         } else {
-            if data.len() % shdr.ent_size as usize != 0 {
-                let sub_str = &data[..shdr.ent_size as usize];
-                data = &data[shdr.ent_size as usize..];
-                m.strs.push(String::from_utf8(sub_str.to_vec()).unwrap());
+            let ent_size = shdr.ent_size as usize;
+            if !data.len().is_multiple_of(ent_size) {
+                return Err(corrupted("section size is not a multiple of sh_entsize"));
+            }
+            for piece in data.chunks(ent_size) {
+                m.strs.push(piece.to_vec());
                 m.frag_offsets.push(offset);
-                offset += shdr.ent_size as usize;
-                println!("{:?}{:?}", offset, data);
+                offset += ent_size;
             }
         }
-        return Box::leak(Box::new(m));
+        Ok((m.strs, m.frag_offsets))
     }
 
-    pub fn register_section_pieces(&self) {
-        for m in &self.mergeable_sections {
-            if m.is_none() {
-                continue;
-            }
+    // Replaces the pieces of the file's mergeable sections with fragments
+    // of the merged sections. Symbols and relocations that refer to a
+    // mergeable section are redirected to the fragment they point into.
+    pub fn register_section_pieces(ctx: &mut Context<'a>, id: FileId) -> Result<(), LinkError> {
+        for m in ctx.files[id].mergeable_sections.iter_mut().flatten() {
+            let parent = &mut ctx.merged_sections[m.parent.unwrap()];
+            m.fragments = m
+                .strs
+                .iter()
+                .map(|key| parent.insert(&mut ctx.fragments, key, m.p2_align.into()))
+                .collect();
+        }
 
-            for i in 0..unsafe { m.unwrap().as_ref().unwrap().strs.len() } {
-                let fs = unsafe {
-                    m.unwrap()
-                        .as_ref()
-                        .unwrap()
-                        .parent
-                        .unwrap()
-                        .as_mut()
-                        .unwrap()
-                        .insert(
-                            &m.unwrap().as_ref().unwrap().strs[i],
-                            m.unwrap().as_ref().unwrap().p2_align.into(),
-                        )
-                };
-                unsafe { m.unwrap().as_mut().unwrap().fragments.push(fs.unwrap()) };
+        let file = &ctx.files[id];
+        let get_fragment = |shndx: usize, offset: u64| {
+            let m = file.mergeable_sections.get(shndx)?.as_ref()?;
+            let (frag, frag_offset) = m.get_fragment(offset as usize);
+            Some(frag.map(|frag| (frag, frag_offset as u64)))
+        };
+
+        for i in 0..file.input_file.elf_syms.len() {
+            let esym = file.input_file.elf_syms[i];
+            let sym = &mut ctx.symbols[file.input_file.symbols[i]];
+            if esym.is_abs() || esym.is_undef() || esym.is_common() || sym.object_file != Some(id) {
+                continue;
             }
 
-            for i in 0..unsafe { self.input_file.as_ref().unwrap().elf_syms.len() } {
-                let sym = unsafe { self.input_file.as_ref().unwrap().symbols[i] };
-                let esym = unsafe { &self.input_file.as_ref().unwrap().elf_syms }[i];
+            let Some(frag) = get_fragment(file.get_shndx(esym, i as i32), esym.val) else {
+                continue;
+            };
+            let Some((frag, frag_offset)) = frag else {
+                return Err(file.input_file.corrupted("bad symbol value"));
+            };
+            sym.set_section_fragment(frag);
+            sym.value = frag_offset;
+        }
 
-                if esym.is_abs() || esym.is_undef() || esym.is_common() {
+        // A reference through a section symbol is an offset into the
+        // section, S + A, so the addend decides the fragment.
+        let first_global = file.input_file.first_global.unwrap_or(0) as usize;
+        for isec in file.input_sections.iter().flatten() {
+            let isec = &mut ctx.sections[*isec];
+            if !isec.is_alive {
+                continue;
+            }
+            for (i, rel) in isec.rels.iter().enumerate() {
+                let idx = rel.sym as usize;
+                if idx >= first_global || file.input_file.elf_syms[idx].st_type() != STT_SECTION {
                     continue;
                 }
-
-                let m = self.mergeable_sections[self.get_shndx(esym, i.try_into().unwrap())];
-                if m.is_none() {
+                let esym = file.input_file.elf_syms[idx];
+                let offset = esym.val.wrapping_add(rel.addend as u64);
+                let Some(frag) = get_fragment(file.get_shndx(esym, idx as i32), offset) else {
                     continue;
-                }
-
-                let (frag, frag_offset) = unsafe {
-                    m.unwrap()
-                        .as_ref()
-                        .unwrap()
-                        .get_fragment(esym.val.try_into().unwrap())
                 };
-                if frag.is_none() {
-                    fatal("bad symbol value")
+                let Some((frag, frag_offset)) = frag else {
+                    return Err(file.input_file.corrupted("bad relocation addend"));
+                };
+                if isec.rel_fragments.is_empty() {
+                    isec.rel_fragments = vec![None; isec.rels.len()];
                 }
-                unsafe { sym.as_mut().unwrap().set_section_fragment(frag.unwrap()) };
-                unsafe { sym.as_mut().unwrap().value = frag_offset as u64 };
+                isec.rel_fragments[i] = Some((frag, frag_offset));
             }
         }
+        Ok(())
+    }
+
+    // Returns the input section a relocation refers to, if any.
+    pub fn get_rel_section(&self, ctx: &Context, rel: &Rela) -> Option<SectionId> {
+        self.get_symbol_section(ctx, rel.sym as usize)
     }
 
-    pub fn skip_eh_fragment_sections(&self) {
-        for isec in &self.input_sections {
-            if !isec.unwrap().is_null()
-                && unsafe { isec.unwrap().as_ref().unwrap().is_alive }
-                && unsafe { isec.unwrap().as_ref().unwrap().name() } == ".eh_frame"
-            {
-                unsafe { isec.unwrap().as_mut().unwrap().is_alive = false }
+    pub fn get_symbol_section(&self, ctx: &Context, idx: usize) -> Option<SectionId> {
+        let input_file = &self.input_file;
+        if (idx as i64) < input_file.first_global.unwrap_or(0) {
+            let esym = input_file.elf_syms[idx];
+            if esym.is_abs() || esym.is_undef() {
+                return None;
             }
+            return self.input_sections[self.get_shndx(esym, idx as i32)];
         }
+        ctx.symbols[input_file.symbols[idx]].input_section
+    }
+
+    // Identifies what a relocation refers to, so that relocations in
+    // different files can be compared: a global symbol, or a local symbol
+    // given as its section and value.
+    pub fn get_rel_target(
+        &self,
+        ctx: &Context,
+        rel: &Rela,
+    ) -> (Option<SymbolId>, Option<SectionId>, u64) {
+        let input_file = &self.input_file;
+        let idx = rel.sym as usize;
+        if (idx as i64) < input_file.first_global.unwrap_or(0) {
+            let esym = input_file.elf_syms[idx];
+            return (None, self.get_rel_section(ctx, rel), esym.val);
+        }
+        (Some(input_file.symbols[idx]), None, 0)
+    }
+
+    // Returns S, the address of the symbol a relocation refers to.
+    pub fn get_rel_symbol_addr(&self, ctx: &Context, rel: &Rela) -> u64 {
+        self.get_symbol_addr(ctx, rel.sym as usize)
     }
 
-    pub fn scan_relocations(&self) {
-        for isec in &self.input_sections {
-            if !isec.unwrap().is_null()
-                && unsafe { isec.unwrap().as_ref().unwrap().is_alive }
-                && unsafe { isec.unwrap().as_ref().unwrap().shdr().flags } & SHF_ALLOC != 0
-            {
-                unsafe { isec.unwrap().as_mut().unwrap().scan_relocations() }
+    pub fn get_symbol_addr(&self, ctx: &Context, idx: usize) -> u64 {
+        let input_file = &self.input_file;
+        let sym = &ctx.symbols[input_file.symbols[idx]];
+        if (idx as i64) < input_file.first_global.unwrap_or(0) {
+            let esym = input_file.elf_syms[idx];
+            if sym.section_fragment.is_some() {
+                return sym.get_addr(ctx);
+            }
+            if esym.is_abs() {
+                return esym.val;
+            }
+            return match self.get_symbol_section(ctx, idx) {
+                Some(isec) => ctx.sections[isec].get_addr(ctx) + esym.val,
+                None => 0,
+            };
+        }
+
+        if !sym.is_defined() {
+            return 0;
+        }
+        sym.get_addr(ctx)
+    }
+
+    // `.eh_frame` sections are not copied as-is. They are split into CIE
+    // and FDE records which are written out by the synthetic `.eh_frame`
+    // section.
+    pub fn initialize_ehframe_sections(ctx: &mut Context<'a>, id: FileId) -> Result<(), LinkError> {
+        for i in 0..ctx.files[id].input_sections.len() {
+            let isec = match ctx.files[id].input_sections[i] {
+                Some(isec) => isec,
+                None => continue,
+            };
+            if !ctx.sections[isec].is_alive || ctx.sections[isec].name() != ".eh_frame" {
+                continue;
+            }
+            Self::read_ehframe(ctx, id, isec)?;
+            ctx.sections[isec].is_alive = false;
+        }
+        Ok(())
+    }
+
+    fn read_ehframe(ctx: &mut Context<'a>, id: FileId, isec: SectionId) -> Result<(), LinkError> {
+        ctx.sections[isec].rels.sort_by_key(|rel| rel.offset);
+
+        let file = &mut ctx.files[id];
+        let data = &ctx.sections[isec].contents;
+        let rels = &ctx.sections[isec].rels;
+        let cies_begin = file.cies.len();
+        let mut fdes: Vec<FdeRecord> = vec![];
+        let mut offset = 0usize;
+        let mut rel_idx = 0usize;
+
+        let input_file = &file.input_file;
+        let corrupted = |reason: &str| input_file.corrupted(reason);
+        while offset < data.len() {
+            let len = try_read::<u32>(&data[offset..])
+                .ok_or_else(|| corrupted("truncated .eh_frame record"))?
+                as usize;
+            if len == 0 {
+                break;
+            }
+            if len == 0xffff_ffff {
+                return Err(corrupted("64-bit .eh_frame records are not supported"));
+            }
+
+            let size = len + 4;
+            if len < 4 || size > data.len() - offset {
+                return Err(corrupted(".eh_frame record is out of range"));
+            }
+            let id = read::<u32>(&data[offset + 4..]);
+            let begin = rel_idx;
+            while rel_idx < rels.len() && (rels[rel_idx].offset as usize) < offset + size {
+                rel_idx += 1;
+            }
+
+            if id == 0 {
+                file.cies.push(CieRecord {
+                    file: file.id,
+                    input_section: isec,
+                    offset: offset as u32,
+                    size: size as u32,
+                    rel_idx: begin,
+                    rel_end: rel_idx,
+                    output_offset: u32::MAX,
+                    is_leader: false,
+                });
+            } else {
+                // The CIE pointer is relative to the field itself.
+                let cie_offset = (offset + 4).wrapping_sub(id as usize);
+                let cie_idx = file.cies[cies_begin..]
+                    .iter()
+                    .position(|cie| cie.offset as usize == cie_offset);
+                if cie_idx.is_none() {
+                    return Err(corrupted("bad FDE pointer in .eh_frame"));
+                }
+                fdes.push(FdeRecord {
+                    cie_idx: cies_begin + cie_idx.unwrap(),
+                    offset: offset as u32,
+                    size: size as u32,
+                    rel_idx: begin,
+                    rel_end: rel_idx,
+                    output_offset: u32::MAX,
+                    is_alive: true,
+                });
+            }
+            offset += size;
+        }
+
+        // Group FDEs by the section they describe, so that every input
+        // section can refer to its own FDEs as a range.
+        let file = &ctx.files[id];
+        let mut fdes: Vec<(Option<SectionId>, FdeRecord)> = fdes
+            .into_iter()
+            .map(|fde| (fde.get_target_section(ctx, file), fde))
+            .collect();
+        fdes.sort_by_key(|(target, _)| match target {
+            Some(target) => ctx.sections[*target].shndx,
+            None => usize::MAX,
+        });
+
+        let fdes_begin = file.fdes.len();
+        let mut i = 0;
+        while i < fdes.len() {
+            let target = fdes[i].0;
+            let begin = i;
+            while i < fdes.len() && fdes[i].0 == target {
+                i += 1;
+            }
+            if let Some(target) = target {
+                let target = &mut ctx.sections[target];
+                target.fde_begin = fdes_begin + begin;
+                target.fde_end = fdes_begin + i;
+            }
+        }
+        ctx.files[id]
+            .fdes
+            .extend(fdes.into_iter().map(|(_, fde)| fde));
+        Ok(())
+    }
+
+    // Finds references to undefined symbols, which GNU ld also reports for
+    // static executables, and lets the target mark the symbols that need
+    // GOT entries. Weak references may stay undefined and resolve to 0.
+    // Files are scanned in parallel, so the errors are returned for the
+    // caller to report in file order.
+    pub fn scan_relocations(&self, ctx: &Context<'a>) -> Vec<LinkError> {
+        let input_file = &self.input_file;
+        let mut errors = vec![];
+        for isec in self.input_sections.iter().flatten() {
+            let isec = &ctx.sections[*isec];
+            if !isec.is_alive || isec.shdr().flags & SHF_ALLOC == 0 {
+                continue;
+            }
+
+            for rel in &isec.rels {
+                let sym = &ctx.symbols[input_file.symbols[rel.sym as usize]];
+                if rel.sym == 0
+                    || sym.is_defined()
+                    || sym.is_imported()
+                    || input_file.elf_syms[rel.sym as usize].is_weak()
+                {
+                    continue;
+                }
+                errors.push(LinkError::UndefinedSymbol {
+                    name: sym.name.clone(),
+                    referenced_by: format!("{}:({})", input_file.file.name, isec.name()),
+                });
             }
+            ctx.target().scan_relocations(ctx, isec);
         }
+        errors
     }
 }
@@ -1,9 +1,7 @@
+use crate::elf::{SHF_MERGE, SHF_STRINGS};
+
 #[allow(dead_code)]
-const ELF_MERGE: u64 = 0;
-#[allow(dead_code)]
-const ELF_STRING: u64 = 0;
-#[allow(dead_code)]
-const PREFIXES: [&str; 13] = [
+const PREFIXES: [&str; 11] = [
     ".text.",
     ".data.rel.ro.",
     ".data.",
@@ -15,20 +13,27 @@ const PREFIXES: [&str; 13] = [
     ".tbss.",
     ".tdata.",
     ".gcc_except_table.",
-    ".ctors.",
-    ".dtors.",
 ];
 
 #[allow(dead_code)]
 pub fn get_output_name(name: &str, flags: u64) -> String {
-    if name == ".rodata" || name.starts_with(".rodata") && flags & ELF_MERGE != 0 {
-        if flags & ELF_STRING != 0 {
-            return ".rodata".to_string();
+    if (name == ".rodata" || name.starts_with(".rodata.")) && flags & SHF_MERGE != 0 {
+        if flags & SHF_STRINGS != 0 {
+            return ".rodata.str".to_string();
         } else {
             return ".rodata.cst".to_string();
         }
     }
 
+    // Constructors and destructors of old toolchains run with those in
+    // .init_array and .fini_array; see get_init_fini_priority.
+    if name == ".ctors" || name.starts_with(".ctors.") {
+        return ".init_array".to_string();
+    }
+    if name == ".dtors" || name.starts_with(".dtors.") {
+        return ".fini_array".to_string();
+    }
+
     for prefix in PREFIXES {
         let stem = &prefix[..(prefix.len() - 1)];
         if name == stem || name.starts_with(prefix) {
@@ -38,3 +43,57 @@ pub fn get_output_name(name: &str, flags: u64) -> String {
 
     return String::from(name);
 }
+
+// The key .init_array and .fini_array members are sorted by, or None for
+// other sections. .init_array.N runs before .init_array.M if N < M, and
+// sections without a priority run last. .ctors.N and .dtors.N use the
+// opposite order, so they get 65535 - N, as in GNU ld.
+#[allow(dead_code)]
+pub fn get_init_fini_priority(name: &str) -> Option<u32> {
+    for (prefix, is_ctors) in [
+        (".init_array", false),
+        (".fini_array", false),
+        (".ctors", true),
+        (".dtors", true),
+    ] {
+        let priority = match name.strip_prefix(prefix) {
+            Some("") => None,
+            Some(suffix) => match suffix.strip_prefix('.') {
+                Some(n) => n.parse::<u32>().ok(),
+                None => continue,
+            },
+            None => continue,
+        };
+        return Some(match priority {
+            Some(n) if is_ctors => 65535u32.saturating_sub(n),
+            Some(n) => n,
+            None => 65536,
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_output_name() {
+        assert_eq!(get_output_name(".init_array.00100", 0), ".init_array");
+        assert_eq!(get_output_name(".ctors", 0), ".init_array");
+        assert_eq!(get_output_name(".ctors.65435", 0), ".init_array");
+        assert_eq!(get_output_name(".dtors.00001", 0), ".fini_array");
+        assert_eq!(get_output_name(".ctorsx", 0), ".ctorsx");
+    }
+
+    #[test]
+    fn test_get_init_fini_priority() {
+        assert_eq!(get_init_fini_priority(".init_array.00100"), Some(100));
+        assert_eq!(get_init_fini_priority(".fini_array.5"), Some(5));
+        assert_eq!(get_init_fini_priority(".init_array"), Some(65536));
+        assert_eq!(get_init_fini_priority(".ctors.65435"), Some(100));
+        assert_eq!(get_init_fini_priority(".dtors"), Some(65536));
+        assert_eq!(get_init_fini_priority(".init_arrayx"), None);
+        assert_eq!(get_init_fini_priority(".text"), None);
+    }
+}
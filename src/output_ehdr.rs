@@ -2,52 +2,62 @@ use crate::{
     chunk::Chunk,
     context::Context,
     elf::{
-        Ehdr, EHDR_SIZE, EI_ABIVERSION, EI_CLASS, EI_DATA, EI_OSABI, EI_VERSION, ELFCLASS64,
-        ELFDATA2LSB, EM_RISCV, ET_EXEC, EV_CURRENT, PHDR_SIZE, SHDR_SIZE,
+        Ehdr, EI_ABIVERSION, EI_CLASS, EI_DATA, EI_OSABI, EI_VERSION, ELFCLASS64, ELFDATA2LSB,
+        ET_EXEC, EV_CURRENT, SHF_ALLOC, SHN_LORESERVE, SHN_XINDEX,
     },
+    elf_class::ELFCLASS32,
     magic::write_magic,
 };
 
 #[allow(dead_code)]
 pub struct OutputEhdr {
-    pub chunk: *mut Chunk,
+    pub chunk: Chunk,
 }
 
 #[allow(dead_code)]
 impl OutputEhdr {
     pub fn new() -> Self {
-        OutputEhdr {
-            chunk: &mut Chunk::new(),
-        }
+        let mut chunk = Chunk::new();
+        chunk.shdr.flags = SHF_ALLOC;
+        OutputEhdr { chunk }
     }
 
-    pub fn copy_buf(&self, ctx: &mut Context) {
+    pub fn copy_buf(&self, ctx: &Context, buf: &mut [u8]) {
+        let class = ctx.elf_class();
         let mut ehdr = Ehdr::new();
-        write_magic(&mut ehdr.ident[..]);
+        write_magic(&mut ehdr.ident);
 
-        ehdr.ident[EI_CLASS as usize] = ELFCLASS64;
+        ehdr.ident[EI_CLASS as usize] = if class.is_32() { ELFCLASS32 } else { ELFCLASS64 };
         ehdr.ident[EI_DATA as usize] = ELFDATA2LSB;
-        ehdr.ident[EI_VERSION as usize] = EI_VERSION;
+        ehdr.ident[EI_VERSION as usize] = EV_CURRENT as u8;
         ehdr.ident[EI_OSABI as usize] = 0;
         ehdr.ident[EI_ABIVERSION as usize] = 0;
 
         ehdr.hdr_type = ET_EXEC;
-        ehdr.machine = EM_RISCV;
+        ehdr.machine = ctx.target().e_machine();
         ehdr.version = EV_CURRENT;
         ehdr.entry = ctx.get_entry_addr();
-        ehdr.ph_off = unsafe { ctx.phdr.chunk.as_ref().unwrap().shdr.offset };
+        ehdr.ph_off = ctx.phdr.chunk.shdr.offset;
         ehdr.sh_off = ctx.shdr.chunk.shdr.offset;
-        ehdr.flags = ctx.get_flags();
-        ehdr.eh_size = EHDR_SIZE as u16;
-        ehdr.ph_ent_size = PHDR_SIZE as u16;
-        ehdr.ph_num = (unsafe { ctx.phdr.chunk.as_ref().unwrap().shdr.size } / PHDR_SIZE as u64) as u16;
-        ehdr.sh_ent_size = SHDR_SIZE as u16;
-        ehdr.sh_num = (ctx.shdr.chunk.shdr.size / SHDR_SIZE as u64) as u16;
-
-        let buf =
-            unsafe { std::slice::from_raw_parts((&ehdr as *const Ehdr) as *const u8, EHDR_SIZE) };
-        
-        let _ = (ctx.buf[(unsafe { self.chunk.as_mut().unwrap().shdr.offset } as usize)..]).copy_from_slice(buf);
+        ehdr.flags = ctx.e_flags;
+        ehdr.eh_size = class.ehdr_size() as u16;
+        ehdr.ph_ent_size = class.phdr_size() as u16;
+        ehdr.ph_num = (ctx.phdr.chunk.shdr.size / class.phdr_size() as u64) as u16;
+        ehdr.sh_ent_size = class.shdr_size() as u16;
+        ehdr.sh_num = ctx.shdr.get_shnum() as u16;
+        if ctx.shdr.get_shnum() >= SHN_LORESERVE as u64 {
+            ehdr.sh_num = 0;
+        }
+        if let Some(shstrtab) = &ctx.shstrtab {
+            let shndx = shstrtab.chunk.shndx;
+            ehdr.sh_strndx = if shndx >= SHN_LORESERVE as i64 {
+                SHN_XINDEX
+            } else {
+                shndx as u16
+            };
+        }
 
+        let offset = self.chunk.shdr.offset as usize;
+        class.write_ehdr(&mut buf[offset..], ehdr);
     }
 }
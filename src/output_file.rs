@@ -0,0 +1,169 @@
+use std::{
+    fs::{self, File},
+    io,
+    ops::{Deref, DerefMut},
+    process,
+};
+
+use memmap2::{Mmap, MmapMut};
+
+use crate::error::LinkError;
+
+// Maps a file read-only. Input sections borrow their contents from the
+// mapping, which is kept until the end of the link; see `OpenInput`.
+pub fn map_input_file(path: &str) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    unsafe { Mmap::map(&file) }
+}
+
+// The image the chunks copy themselves to. A link to a file writes to a
+// mapping of the output file so the image is never held in memory twice.
+pub enum OutputBuffer {
+    Memory(Vec<u8>),
+    File(OutputFile),
+}
+
+impl Default for OutputBuffer {
+    fn default() -> Self {
+        OutputBuffer::Memory(vec![])
+    }
+}
+
+impl Deref for OutputBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            OutputBuffer::Memory(buf) => buf,
+            OutputBuffer::File(file) => file,
+        }
+    }
+}
+
+impl DerefMut for OutputBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            OutputBuffer::Memory(buf) => buf,
+            OutputBuffer::File(file) => file,
+        }
+    }
+}
+
+// An output file being written. It is created next to `path` under a
+// temporary name at its final size and only renamed to `path` by
+// `commit`, so a failed link leaves an existing output alone.
+pub struct OutputFile {
+    path: String,
+    tmp_path: String,
+    file: File,
+    map: MmapMut,
+    committed: bool,
+}
+
+impl OutputFile {
+    pub fn create(path: &str, size: usize) -> Result<Self, LinkError> {
+        let tmp_path = format!("{}.rvld-{}.tmp", path, process::id());
+        let io_error = |error| LinkError::Io {
+            path: path.to_string(),
+            error,
+        };
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .map_err(io_error)?;
+        let map = file
+            .set_len(size as u64)
+            .and_then(|_| unsafe { MmapMut::map_mut(&file) })
+            .map_err(|error| {
+                let _ = fs::remove_file(&tmp_path);
+                io_error(error)
+            })?;
+        Ok(OutputFile {
+            path: path.to_string(),
+            tmp_path,
+            file,
+            map,
+            committed: false,
+        })
+    }
+
+    // Moves the file to its final path. rename() replaces the previous
+    // output atomically: no reader sees a partly written file, and a
+    // program that is running from the old one keeps its inode.
+    pub fn commit(mut self) -> Result<(), LinkError> {
+        let io_error = |error| LinkError::Io {
+            path: self.path.clone(),
+            error,
+        };
+
+        #[cfg(unix)]
+        if self.map.starts_with(b"\x7fELF") {
+            use std::os::unix::fs::PermissionsExt;
+            self.file
+                .set_permissions(fs::Permissions::from_mode(0o755))
+                .map_err(io_error)?;
+        }
+
+        fs::rename(&self.tmp_path, &self.path).map_err(io_error)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Deref for OutputFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+impl DerefMut for OutputFile {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.map
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_file() {
+        let dir = std::env::temp_dir().join(format!("rvld-output-file-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.out").to_string_lossy().into_owned();
+        fs::write(&path, b"old").unwrap();
+
+        // A dropped file leaves the previous output in place.
+        let mut out = OutputFile::create(&path, 4).unwrap();
+        out.copy_from_slice(b"junk");
+        drop(out);
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let mut out = OutputFile::create(&path, 5).unwrap();
+        out.copy_from_slice(b"\x7fELF!");
+        out.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"\x7fELF!");
+        assert_eq!(&map_input_file(&path).unwrap()[..], b"\x7fELF!");
+
+        let out = OutputFile::create(&path, 0).unwrap();
+        out.commit().unwrap();
+        assert!(map_input_file(&path).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+use std::fmt::Write;
+
+use crate::{
+    context::Context,
+    elf::{PT_LOAD, SHF_ALLOC, SHT_NOBITS, SHT_NULL},
+    elf_class::ElfClass,
+    error::LinkError,
+};
+
+const RECORD_SIZE: usize = 16;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Elf,
+    Binary,
+    Ihex,
+    Srec,
+}
+
+#[allow(dead_code)]
+pub fn parse_output_format(arg: &str) -> Result<OutputFormat, LinkError> {
+    match arg {
+        "elf64-littleriscv" | "elf32-littleriscv" | "elf64-x86-64" | "elf" => Ok(OutputFormat::Elf),
+        "binary" => Ok(OutputFormat::Binary),
+        "ihex" => Ok(OutputFormat::Ihex),
+        "srec" => Ok(OutputFormat::Srec),
+        _ => Err(LinkError::BadArgument(format!(
+            "unknown --oformat argument: {}",
+            arg
+        ))),
+    }
+}
+
+// The contents of the allocated sections keyed by their load (physical)
+// address, in address order, as `objcopy -O binary` takes them. The ELF
+// and program headers are not sections, and .bss and the like take no
+// space in a flash image, so they are left out.
+fn get_load_sections<'b>(ctx: &'b Context) -> Vec<(u64, &'b [u8])> {
+    let mut sections = vec![];
+    for &id in &ctx.chunks {
+        let shdr = ctx.chunk(id).shdr;
+        if shdr.flags & SHF_ALLOC == 0
+            || shdr.shdr_type == SHT_NULL
+            || shdr.shdr_type == SHT_NOBITS
+            || shdr.size == 0
+        {
+            continue;
+        }
+
+        // A section is loaded at its offset from the start of its
+        // segment, relative to the segment's physical address.
+        let addr = ctx
+            .phdr
+            .phdrs
+            .iter()
+            .find(|phdr| {
+                phdr.p_type == PT_LOAD as u32
+                    && phdr.v_addr <= shdr.addr
+                    && shdr.addr < phdr.v_addr + phdr.mem_size
+            })
+            .map_or(shdr.addr, |phdr| phdr.p_addr + (shdr.addr - phdr.v_addr));
+        let offset = shdr.offset as usize;
+        sections.push((addr, &ctx.buf[offset..offset + shdr.size as usize]));
+    }
+    sections.sort_by_key(|(addr, _)| *addr);
+    sections
+}
+
+// Converts the ELF image in ctx.buf to the format selected by --oformat.
+#[allow(dead_code)]
+pub fn convert_output(ctx: &Context) -> Result<Vec<u8>, LinkError> {
+    let sections = get_load_sections(ctx);
+    let entry = ElfClass::from_contents(&ctx.buf).read_ehdr(&ctx.buf).entry;
+
+    Ok(match ctx.args.oformat {
+        OutputFormat::Elf => ctx.buf.to_vec(),
+        OutputFormat::Binary => to_binary(&sections, ctx.args.gap_fill),
+        OutputFormat::Ihex => to_ihex(&sections, entry)?.into_bytes(),
+        OutputFormat::Srec => {
+            let name = ctx.args.output.rsplit('/').next().unwrap_or("");
+            to_srec(&sections, entry, name)?.into_bytes()
+        }
+    })
+}
+
+// A raw memory image starting at the lowest load address. Gaps between
+// sections are filled with `gap_fill`.
+fn to_binary(sections: &[(u64, &[u8])], gap_fill: u8) -> Vec<u8> {
+    if sections.is_empty() {
+        return vec![];
+    }
+    let base = sections[0].0;
+    let end = sections
+        .iter()
+        .map(|(addr, data)| addr + data.len() as u64)
+        .max()
+        .unwrap();
+
+    let mut buf = vec![gap_fill; (end - base) as usize];
+    for (addr, data) in sections {
+        let offset = (addr - base) as usize;
+        buf[offset..offset + data.len()].copy_from_slice(data);
+    }
+    buf
+}
+
+fn write_ihex_record(out: &mut String, ty: u8, addr: u16, data: &[u8]) {
+    let mut sum = data.len() as u8;
+    sum = sum.wrapping_add((addr >> 8) as u8).wrapping_add(addr as u8);
+    sum = sum.wrapping_add(ty);
+
+    write!(out, ":{:02X}{:04X}{:02X}", data.len(), addr, ty).unwrap();
+    for b in data {
+        write!(out, "{:02X}", b).unwrap();
+        sum = sum.wrapping_add(*b);
+    }
+    write!(out, "{:02X}\r\n", sum.wrapping_neg()).unwrap();
+}
+
+// Intel HEX. Data records carry a 16-bit address, so an extended linear
+// address record sets the upper 16 bits whenever they change. A record
+// never crosses a 64 KiB boundary.
+fn to_ihex(sections: &[(u64, &[u8])], entry: u64) -> Result<String, LinkError> {
+    let mut out = String::new();
+    let mut upper = 0;
+
+    for (addr, data) in sections {
+        if addr + data.len() as u64 > 1 << 32 {
+            return Err(LinkError::Other(format!(
+                "--oformat=ihex: address out of range: 0x{:x}",
+                addr
+            )));
+        }
+
+        let mut pos = 0;
+        while pos < data.len() {
+            let addr = addr + pos as u64;
+            if addr >> 16 != upper {
+                upper = addr >> 16;
+                write_ihex_record(&mut out, 4, 0, &(upper as u16).to_be_bytes());
+            }
+
+            let to_boundary = (0x10000 - (addr & 0xffff)) as usize;
+            let len = RECORD_SIZE.min(data.len() - pos).min(to_boundary);
+            write_ihex_record(&mut out, 0, addr as u16, &data[pos..pos + len]);
+            pos += len;
+        }
+    }
+
+    if entry != 0 {
+        write_ihex_record(&mut out, 5, 0, &(entry as u32).to_be_bytes());
+    }
+    write_ihex_record(&mut out, 1, 0, &[]);
+    Ok(out)
+}
+
+fn write_srec_record(out: &mut String, ty: u8, addr_size: usize, addr: u64, data: &[u8]) {
+    let addr_bytes = &addr.to_be_bytes()[8 - addr_size..];
+    let count = (addr_size + data.len() + 1) as u8;
+
+    write!(out, "S{}{:02X}", ty, count).unwrap();
+    let mut sum = count;
+    for b in addr_bytes.iter().chain(data) {
+        write!(out, "{:02X}", b).unwrap();
+        sum = sum.wrapping_add(*b);
+    }
+    write!(out, "{:02X}\r\n", !sum).unwrap();
+}
+
+// Motorola S-records. The narrowest address width that covers every
+// address is used: S1/S9 for 16 bits, S2/S8 for 24 bits and S3/S7 for 32.
+fn to_srec(sections: &[(u64, &[u8])], entry: u64, header: &str) -> Result<String, LinkError> {
+    let max_addr = sections
+        .iter()
+        .map(|(addr, data)| addr + data.len() as u64)
+        .chain([entry])
+        .max()
+        .unwrap();
+
+    let (addr_size, data_ty, end_ty) = if max_addr <= 1 << 16 {
+        (2, 1, 9)
+    } else if max_addr <= 1 << 24 {
+        (3, 2, 8)
+    } else if max_addr <= 1 << 32 {
+        (4, 3, 7)
+    } else {
+        return Err(LinkError::Other(format!(
+            "--oformat=srec: address out of range: 0x{:x}",
+            max_addr
+        )));
+    };
+
+    let mut out = String::new();
+    write_srec_record(&mut out, 0, 2, 0, header.as_bytes());
+    for (addr, data) in sections {
+        for (i, record) in data.chunks(RECORD_SIZE).enumerate() {
+            let addr = addr + (i * RECORD_SIZE) as u64;
+            write_srec_record(&mut out, data_ty, addr_size, addr, record);
+        }
+    }
+    write_srec_record(&mut out, end_ty, addr_size, entry, &[]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_binary() {
+        let sections: [(u64, &[u8]); 2] = [(0x100, &[1, 2]), (0x104, &[3])];
+        assert_eq!(to_binary(&sections, 0xff), [1, 2, 0xff, 0xff, 3]);
+    }
+
+    #[test]
+    fn test_to_ihex() {
+        let sections: [(u64, &[u8]); 1] = [(0x100, &[1, 2])];
+        assert_eq!(
+            to_ihex(&sections, 0x100).unwrap(),
+            ":020100000102FA\r\n:0400000500000100F6\r\n:00000001FF\r\n"
+        );
+
+        let sections: [(u64, &[u8]); 1] = [(0x1fffe, &[0xaa, 0xbb, 0xcc])];
+        assert_eq!(
+            to_ihex(&sections, 0).unwrap(),
+            ":020000040001F9\r\n:02FFFE00AABB9C\r\n:020000040002F8\r\n:01000000CC33\r\n:00000001FF\r\n"
+        );
+    }
+
+    #[test]
+    fn test_to_srec() {
+        let sections: [(u64, &[u8]); 1] = [(0x100, &[1, 2])];
+        assert_eq!(
+            to_srec(&sections, 0x100, "a").unwrap(),
+            "S0040000619A\r\nS10501000102F6\r\nS9030100FB\r\n"
+        );
+
+        let sections: [(u64, &[u8]); 1] = [(1 << 32, &[1])];
+        assert!(to_srec(&sections, 0, "a").is_err());
+    }
+}
@@ -1,22 +1,74 @@
-use std::cmp;
-
 use crate::{
-    chunk::{Chunk, Chunker},
+    chunk::Chunk,
     context::Context,
     elf::{
-        Phdr, Shdr, PF_W, PF_X, PT_LOAD, PT_NOTE, PT_PHDR, PT_TLS, SHF_ALLOC, SHF_EXECINSTR,
-        SHF_TLS, SHF_WRITE, SHT_NOBITS, SHT_NOTE, PHDR_SIZE,
+        Phdr, Shdr, PF_R, PF_W, PF_X, PT_DYNAMIC, PT_GNU_EH_FRAME, PT_LOAD, PT_NOTE, PT_PHDR,
+        PT_RISCV_ATTRIBUTES, PT_TLS, SHF_ALLOC, SHF_EXECINSTR, SHF_TLS, SHF_WRITE, SHT_NOBITS,
+        SHT_NOTE,
     },
     passes::is_tbss,
-    utils::{remove_if, write},
 };
 
-const PAGE_SIZE: u64 = 4096;
-
 #[allow(dead_code)]
 pub struct OutputPhdr {
-    pub chunk: *mut Chunk,
-    pub phdrs: *mut Vec<Phdr>,
+    pub chunk: Chunk,
+    pub phdrs: Vec<Phdr>,
+}
+
+// The segment permissions for a chunk.
+#[allow(dead_code)]
+pub fn to_phdr_flags(chunk: &Chunk) -> u32 {
+    let mut ret = PF_R;
+    if chunk.shdr.flags & SHF_WRITE != 0 {
+        ret |= PF_W;
+    }
+    if chunk.shdr.flags & SHF_EXECINSTR != 0 {
+        ret |= PF_X;
+    }
+    ret
+}
+
+fn is_tls(chunk: &Chunk) -> bool {
+    chunk.shdr.flags & SHF_TLS != 0
+}
+
+fn is_bss(chunk: &Chunk) -> bool {
+    chunk.shdr.shdr_type == SHT_NOBITS && !is_tls(chunk)
+}
+
+fn is_note(chunk: &Chunk) -> bool {
+    chunk.shdr.shdr_type == SHT_NOTE && chunk.shdr.flags & SHF_ALLOC != 0
+}
+
+// Program headers are built from runs of consecutive chunks. `define`
+// starts a segment at a chunk and `push` extends the last one to cover
+// another chunk.
+fn define(phdrs: &mut Vec<Phdr>, ty: u64, flags: u32, min_align: u64, chunk: &Chunk) {
+    let shdr = &chunk.shdr;
+    let mut phdr = Phdr::new();
+    phdr.p_type = ty as u32;
+    phdr.flags = flags;
+    phdr.align = min_align.max(shdr.addr_align);
+    phdr.offset = shdr.offset;
+    phdr.file_size = if shdr.shdr_type == SHT_NOBITS {
+        0
+    } else {
+        shdr.size
+    };
+    phdr.v_addr = shdr.addr;
+    phdr.p_addr = shdr.addr;
+    phdr.mem_size = shdr.size;
+    phdrs.push(phdr);
+}
+
+fn push(phdrs: &mut [Phdr], chunk: &Chunk) {
+    let shdr = &chunk.shdr;
+    let phdr = phdrs.last_mut().unwrap();
+    phdr.align = phdr.align.max(shdr.addr_align);
+    if shdr.shdr_type != SHT_NOBITS {
+        phdr.file_size = shdr.addr + shdr.size - phdr.v_addr;
+    }
+    phdr.mem_size = shdr.addr + shdr.size - phdr.v_addr;
 }
 
 #[allow(dead_code)]
@@ -29,158 +81,109 @@ impl OutputPhdr {
         let mut chunk = Chunk::new();
         chunk.shdr = shdr;
         Self {
-            chunk: Box::leak(Box::new(chunk)),
-            phdrs: Box::leak(Box::new(vec![])),
+            chunk,
+            phdrs: vec![],
         }
     }
 
-    pub fn to_phdr_flags(&self, chunk: *mut Chunk) -> u32 {
-        let mut ret = PF_W;
-        let write = unsafe { chunk.as_ref().unwrap().get_shdr().flags } & SHF_WRITE != 0;
-        if write {
-            ret |= PF_W;
-        }
-        if unsafe { chunk.as_ref().unwrap().get_shdr().flags } & SHF_EXECINSTR != 0 {
-            ret |= PF_X;
-        }
-        ret
-    }
+    // Builds the program headers from the chunks, which are sorted and
+    // laid out so that every segment is a run of consecutive chunks.
+    pub fn create_phdr(ctx: &Context) -> Vec<Phdr> {
+        let mut phdrs = vec![];
+        let chunks: Vec<&Chunk> = ctx.chunks.iter().map(|chunk| ctx.chunk(*chunk)).collect();
 
-    pub fn create_phdr(&self, ctx: &mut Context) -> *mut Vec<Phdr> {
-        let vec: *mut Vec<Phdr> = Box::leak(Box::new(vec![]));
-        let define = |ty: u64, flags: u64, min_align: i64, chunk: *mut Chunk| {
-            let mut phdr = Phdr::new();
-            phdr.p_type = ty as u32;
-            phdr.flags = flags as u32;
-            phdr.align = cmp::max(min_align as u64, unsafe {
-                chunk.as_ref().unwrap().get_shdr().addr_align
-            });
-            phdr.offset = unsafe { self.chunk.as_ref().unwrap().get_shdr().offset };
-
-            if unsafe { chunk.as_ref().unwrap().get_shdr().shdr_type } == SHT_NOBITS {
-                phdr.file_size = 0;
-            } else {
-                phdr.file_size = unsafe { chunk.as_ref().unwrap().get_shdr().size }
-            }
+        define(&mut phdrs, PT_PHDR, PF_R, 8, &ctx.phdr.chunk);
 
-            phdr.v_addr = unsafe { chunk.as_ref().unwrap().get_shdr().addr };
-            phdr.p_addr = unsafe { chunk.as_ref().unwrap().get_shdr().addr };
-            phdr.mem_size = unsafe { chunk.as_ref().unwrap().get_shdr().size };
-            unsafe { vec.as_mut().unwrap().push(phdr) }
-        };
-
-        let push = |chunk: *mut Chunk| {
-            let mut phdr = Phdr::new();
-            phdr.align = cmp::max(phdr.align, unsafe {
-                chunk.as_ref().unwrap().get_shdr().addr_align
-            });
-            if unsafe { chunk.as_ref().unwrap().get_shdr().shdr_type } != SHT_NOBITS {
-                phdr.file_size = unsafe { chunk.as_ref().unwrap().get_shdr().addr }
-                    + unsafe { chunk.as_ref().unwrap().get_shdr().size }
-                    + phdr.v_addr;
-            }
-            phdr.mem_size = unsafe { chunk.as_ref().unwrap().get_shdr().addr }
-                + unsafe { chunk.as_ref().unwrap().get_shdr().size }
-                - phdr.v_addr;
-            unsafe { vec.as_mut().unwrap().push(phdr) }
-        };
-
-        define(PT_PHDR, PF_W.into(), 8, ctx.phdr.chunk);
-
-        let is_tls = |chunk: *mut Chunk| -> bool {
-            unsafe { chunk.as_ref().unwrap().get_shdr().flags & SHF_TLS != 0 }
-        };
-
-        let is_bss = |chunk: *mut Chunk| -> bool {
-            unsafe { chunk.as_ref().unwrap().get_shdr().shdr_type == SHT_NOBITS && is_tls(chunk) }
-        };
-
-        let is_note = |chunk: *mut Chunk| -> bool {
-            let shdr = unsafe { chunk.as_ref().unwrap().get_shdr() };
-            shdr.shdr_type == SHT_NOTE && shdr.flags & SHF_ALLOC != 0
-        };
-
-        let chunks = ctx.chunks.unwrap();
-        let end = unsafe { chunks.as_ref().unwrap().len() };
-        for i in 0..end {
-            let first = unsafe { chunks.as_ref().unwrap() }[i];
+        let mut i = 0;
+        while i < chunks.len() {
+            let first = chunks[i];
+            i += 1;
             if !is_note(first) {
                 continue;
             }
-            let flags = self.to_phdr_flags(first);
-            let alignment = unsafe { first.as_ref().unwrap().get_shdr().addr_align };
-            define(PT_NOTE, flags as u64, alignment.try_into().unwrap(), first);
-
-            for j in 0..end {
-                if !is_note(unsafe { chunks.as_ref().unwrap() }[j]) {
-                    continue;
-                }
-                if !self.to_phdr_flags(unsafe { chunks.as_ref().unwrap() }[j]) == flags {
-                    continue;
-                }
-                push(unsafe { chunks.as_ref().unwrap() }[j]);
+            let flags = to_phdr_flags(first);
+            define(&mut phdrs, PT_NOTE, flags, first.shdr.addr_align, first);
+            while i < chunks.len() && is_note(chunks[i]) && to_phdr_flags(chunks[i]) == flags {
+                push(&mut phdrs, chunks[i]);
+                i += 1;
             }
         }
 
-        {
-            let mut chunks_c: Vec<*mut Chunk> = vec![];
-            for i in 0..unsafe { chunks.as_ref().unwrap().len() } {
-                chunks_c.push(unsafe { chunks.as_ref().unwrap() }[i])
+        // .tbss takes no space in the memory image; it is only covered
+        // by PT_TLS.
+        let loads: Vec<&Chunk> = chunks
+            .iter()
+            .copied()
+            .filter(|chunk| chunk.shdr.flags & SHF_ALLOC != 0 && !is_tbss(chunk))
+            .collect();
+        let page_size = ctx.target().page_size();
+        let mut i = 0;
+        while i < loads.len() {
+            let first = loads[i];
+            i += 1;
+            let flags = to_phdr_flags(first);
+            define(&mut phdrs, PT_LOAD, flags, page_size, first);
+            if !is_bss(first) {
+                while i < loads.len() && !is_bss(loads[i]) && to_phdr_flags(loads[i]) == flags {
+                    push(&mut phdrs, loads[i]);
+                    i += 1;
+                }
             }
+            while i < loads.len() && is_bss(loads[i]) && to_phdr_flags(loads[i]) == flags {
+                push(&mut phdrs, loads[i]);
+                i += 1;
+            }
+        }
 
-            chunks_c = remove_if(chunks_c, |chunk: &*mut Chunk| -> bool { is_tbss(*chunk) });
-
-            let end = chunks_c.len();
-            for i in 0..end {
-                let first = unsafe { chunks.as_ref().unwrap() }[i];
+        if let Some(dynamic) = &ctx.dynamic {
+            define(&mut phdrs, PT_DYNAMIC, PF_R | PF_W, 1, &dynamic.chunk);
+        }
 
-                if unsafe { first.as_ref().unwrap().get_shdr().flags } & SHF_ALLOC == 0 {
-                    break;
-                }
-                let flags = self.to_phdr_flags(first) as u64;
-                define(PT_LOAD, flags, PAGE_SIZE as i64, first);
-                if !is_bss(first) {
-                    for i in 0..end {
-                        if self.to_phdr_flags(chunks_c[i]) == flags as u32 {
-                            continue;
-                        }
-                        push(chunks_c[i])
-                    }
-                }
+        if let Some(eh_frame_hdr) = &ctx.eh_frame_hdr {
+            define(&mut phdrs, PT_GNU_EH_FRAME, PF_R, 4, &eh_frame_hdr.chunk);
+        }
 
-                for i in 0..end {
-                    if self.to_phdr_flags(chunks_c[i]) == flags as u32 {
-                        continue;
-                    }
-                    push(chunks_c[i])
-                }
-            }
+        if let Some(riscv_attributes) = &ctx.riscv_attributes {
+            define(
+                &mut phdrs,
+                PT_RISCV_ATTRIBUTES,
+                PF_R,
+                1,
+                &riscv_attributes.chunk,
+            );
         }
 
-        for i in 0..unsafe { chunks.as_ref().unwrap().len() } {
-            if !is_tls(unsafe { chunks.as_ref().unwrap() }[i]) {
+        let mut i = 0;
+        while i < chunks.len() {
+            if !is_tls(chunks[i]) {
+                i += 1;
                 continue;
             }
-            define(PT_TLS, self.to_phdr_flags(unsafe { chunks.as_ref().unwrap() }[i]) as u64, 1, unsafe { chunks.as_ref().unwrap() }[i]);
-
-            for i in 0..unsafe { chunks.as_ref().unwrap() }.len() {
-                if is_tls(unsafe { chunks.as_ref().unwrap() }[i]) {
-                    continue;
-                }
-                push(unsafe { chunks.as_ref().unwrap() }[i])
+            define(&mut phdrs, PT_TLS, to_phdr_flags(chunks[i]), 1, chunks[i]);
+            i += 1;
+            while i < chunks.len() && is_tls(chunks[i]) {
+                push(&mut phdrs, chunks[i]);
+                i += 1;
             }
-            let phdr = unsafe { vec.as_ref().unwrap()[vec.as_ref().unwrap().len()] };
-            ctx.tp_addr = phdr.v_addr;
         }
-        vec
+        phdrs
     }
 
-    pub fn update_shdr(&mut self, ctx: &mut Context){
-        self.phdrs = self.create_phdr(ctx);
-        unsafe { self.chunk.as_mut().unwrap().shdr.size = (self.phdrs.as_ref().unwrap().len() * PHDR_SIZE) as u64};
+    // Also sets the thread pointer, which is derived from PT_TLS.
+    pub fn update_shdr(ctx: &mut Context) {
+        let phdrs = Self::create_phdr(ctx);
+        if let Some(tls) = phdrs.iter().find(|phdr| phdr.p_type == PT_TLS as u32) {
+            ctx.tp_addr = ctx.target().get_tp_addr(tls);
+        }
+        ctx.phdr.chunk.shdr.size = (phdrs.len() * ctx.elf_class().phdr_size()) as u64;
+        ctx.phdr.phdrs = phdrs;
     }
 
-    pub fn copy_buf(&self, ctx:&mut Context){
-        write(&mut ctx.buf[unsafe { self.chunk.as_ref().unwrap().shdr.offset } as usize..], self.phdrs)
+    pub fn copy_buf(&self, ctx: &Context, buf: &mut [u8]) {
+        let class = ctx.elf_class();
+        let base = self.chunk.shdr.offset as usize;
+        for (i, phdr) in self.phdrs.iter().enumerate() {
+            class.write_phdr(&mut buf[base + i * class.phdr_size()..], *phdr);
+        }
     }
 }
@@ -1,21 +1,24 @@
 use crate::{
+    arena::{OutputSectionId, SectionId},
     chunk::Chunk,
     context::Context,
-    elf::{Shdr, SHF_COMPRESSED, SHF_GROUP, SHF_LINK_ORDER, SHT_NOBITS},
-    input_section::InputSection,
+    elf::{
+        Shdr, SHF_COMPRESSED, SHF_GROUP, SHF_LINK_ORDER, SHT_FINI_ARRAY, SHT_INIT_ARRAY,
+        SHT_PREINIT_ARRAY,
+    },
+    error::LinkError,
     output::get_output_name,
 };
 
 #[allow(dead_code)]
-pub struct OutputSection<'a> {
-    pub chunk: *mut Chunk,
-    pub members: *mut Vec<*mut InputSection<'a>>,
-    pub idx: u32,
+pub struct OutputSection {
+    pub chunk: Chunk,
+    pub members: Vec<SectionId>,
 }
 
 #[allow(dead_code)]
-impl<'a> OutputSection<'a> {
-    pub fn new(name: String, ty: u32, flags: u64, idx: u32) -> Self {
+impl OutputSection {
+    pub fn new(name: String, ty: u32, flags: u64) -> Self {
         let mut shdr = Shdr::new();
         shdr.shdr_type = ty;
         shdr.flags = flags;
@@ -25,71 +28,56 @@ impl<'a> OutputSection<'a> {
         chunk.name = name;
 
         Self {
-            chunk: Box::leak(Box::new(chunk)),
-            members: Box::leak(Box::new(vec![])),
-            idx: idx,
+            chunk,
+            members: vec![],
         }
     }
 
-    pub fn copy_buf(&self, ctx: Context<'a>) {
-        if unsafe { self.chunk.as_ref().unwrap().shdr.shdr_type } == SHT_NOBITS {
-            return;
-        }
-
-        let base = &ctx.buf[unsafe { self.chunk.as_ref().unwrap().shdr.offset } as usize..];
-        let base = Box::leak(base.into_iter().map(|n| -> u8 { *n }).collect());
-        for isec in unsafe { self.members.as_ref().unwrap() } {
-            unsafe {
-                isec.as_mut()
-                    .unwrap()
-                    .write_to(&ctx, &mut base[isec.as_ref().unwrap().offset as usize..])
-            };
+    // Writes the section to its place in `buf`, the output file.
+    // Writes the contents of all members to `base`, which is as large as
+    // the section.
+    pub fn write_to(&self, ctx: &Context, base: &mut [u8]) -> Result<(), LinkError> {
+        for id in &self.members {
+            let isec = &ctx.sections[*id];
+            isec.write_to(ctx, &mut base[isec.offset as usize..])?;
         }
+        Ok(())
     }
 
     pub fn get_output_section(
-        ctx: &Context<'a>,
+        ctx: &mut Context,
         mut name: String,
         ty: u64,
         mut flags: u64,
-    ) -> *mut OutputSection<'a> {
+    ) -> OutputSectionId {
         name = get_output_name(&name, flags);
         flags = flags & !SHF_GROUP & !SHF_COMPRESSED & !SHF_LINK_ORDER;
 
-        let find = || -> Option<*mut OutputSection<'_>> {
-            for osec in unsafe { ctx.output_sections.as_ref().unwrap() } {
-                if &name == unsafe { &osec.as_ref().unwrap().chunk.as_ref().unwrap().name }
-                    && ty
-                        == unsafe {
-                            osec.as_ref()
-                                .unwrap()
-                                .chunk
-                                .as_ref()
-                                .unwrap()
-                                .shdr
-                                .shdr_type
-                                .into()
-                        }
-                    && flags == unsafe { osec.as_ref().unwrap().chunk.as_ref().unwrap().shdr.flags }
+        // .ctors and .dtors are SHT_PROGBITS, but they are put in the same
+        // section as the .init_array and .fini_array inputs.
+        let ty = match name.as_str() {
+            ".init_array" => SHT_INIT_ARRAY as u64,
+            ".fini_array" => SHT_FINI_ARRAY as u64,
+            ".preinit_array" => SHT_PREINIT_ARRAY as u64,
+            _ => ty,
+        };
+
+        let find = || -> Option<OutputSectionId> {
+            for (id, osec) in ctx.output_sections.iter() {
+                let chunk = &osec.chunk;
+                if name == chunk.name
+                    && ty == chunk.shdr.shdr_type.into()
+                    && flags == chunk.shdr.flags
                 {
-                    return Some(*osec);
+                    return Some(id);
                 }
             }
             None
         };
 
-        let osec = find();
-        if !osec.is_none() {
-            return osec.unwrap();
+        if let Some(id) = find() {
+            return id;
         }
-        let os_len = unsafe { ctx.output_sections.as_ref().unwrap().len() } as u32;
-        let osec: *mut OutputSection = Box::leak(Box::new(Self::new(
-            String::from(&name),
-            ty as u32,
-            flags,
-            os_len,
-        )));
-        unsafe { ctx.output_sections.as_mut().unwrap().push(osec) };
-        return osec;
+        ctx.output_sections.push(Self::new(name, ty as u32, flags))
     }
 }
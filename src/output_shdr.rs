@@ -1,13 +1,14 @@
 use crate::{
     chunk::Chunk,
     context::Context,
-    elf::{Shdr, SHDR_SIZE},
-    utils::write,
+    elf::{Shdr, SHN_LORESERVE},
+    elf_class::ElfClass,
 };
 
 #[allow(dead_code)]
 pub struct OutputShdr {
     pub chunk: Chunk,
+    pub class: ElfClass,
 }
 
 #[allow(dead_code)]
@@ -18,15 +19,52 @@ impl OutputShdr {
 
         let mut chunk = Chunk::new();
         chunk.shdr = shdr;
-        Self { chunk: chunk }
+        Self {
+            chunk: chunk,
+            class: ElfClass::Elf64,
+        }
     }
 
-    pub fn update_shdr(&mut self, _ctx: Context) {
-        self.chunk.shdr.size = 1 * SHDR_SIZE as u64;
+    // Number of entries, including the null section header at index 0.
+    pub fn get_shnum(&self) -> u64 {
+        self.chunk.shdr.size / self.class.shdr_size() as u64
     }
 
-    pub fn copy_buf(&self, mut ctx: Context) {
-        let base = &mut ctx.buf[*(&self.chunk.shdr.offset) as usize..];
-        write(base, Shdr::new());
+    pub fn update_shdr(ctx: &mut Context) {
+        let mut n = 0;
+        for chunk in &ctx.chunks {
+            n = n.max(ctx.chunk(*chunk).shndx);
+        }
+        let shdr = &mut ctx.shdr;
+        shdr.chunk.shdr.size = (n + 1) as u64 * shdr.class.shdr_size() as u64;
+    }
+
+    // e_shnum and e_shstrndx are only 16 bits wide. If they don't fit,
+    // the real values are stored in sh_size and sh_link of the null
+    // section header, and the ELF header refers to them.
+    pub fn copy_buf(&self, ctx: &Context, buf: &mut [u8]) {
+        let base = self.chunk.shdr.offset as usize;
+        let shdr_size = self.class.shdr_size();
+
+        let mut null = Shdr::new();
+        if self.get_shnum() >= SHN_LORESERVE as u64 {
+            null.size = self.get_shnum();
+        }
+        if let Some(shstrtab) = &ctx.shstrtab {
+            let shndx = shstrtab.chunk.shndx;
+            if shndx >= SHN_LORESERVE as i64 {
+                null.link = shndx as u32;
+            }
+        }
+        self.class.write_shdr(&mut buf[base..], null);
+
+        for chunk in &ctx.chunks {
+            let chunk = ctx.chunk(*chunk);
+            if chunk.shndx == 0 {
+                continue;
+            }
+            let offset = base + chunk.shndx as usize * shdr_size;
+            self.class.write_shdr(&mut buf[offset..], chunk.shdr);
+        }
     }
 }
@@ -0,0 +1,87 @@
+use std::thread;
+
+// The passes that run on several threads split their work into contiguous
+// runs of items, one per thread, and put the results back in item order.
+// Nothing they produce depends on which thread handled an item, so the
+// output is the same for any --threads value.
+
+// The number of threads to use for --threads=N. 0 means one per CPU.
+pub fn get_num_threads(threads: usize) -> usize {
+    if threads > 0 {
+        return threads;
+    }
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+fn get_run_size(num_threads: usize, num_items: usize) -> usize {
+    num_items.div_ceil(num_threads.max(1)).max(1)
+}
+
+// Returns f(item) for every item, in the order of `items`.
+pub fn par_map<T, R, F>(num_threads: usize, items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if num_threads <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let f = &f;
+    thread::scope(|s| {
+        let handles: Vec<_> = items
+            .chunks(get_run_size(num_threads, items.len()))
+            .map(|run| s.spawn(move || run.iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    })
+}
+
+// Calls f on every item. Each item is only seen by one thread, so f may
+// update it in place.
+pub fn par_for_each_mut<T, F>(num_threads: usize, items: &mut [T], f: F)
+where
+    T: Send,
+    F: Fn(&mut T) + Sync,
+{
+    if num_threads <= 1 || items.len() <= 1 {
+        items.iter_mut().for_each(f);
+        return;
+    }
+
+    let f = &f;
+    let run_size = get_run_size(num_threads, items.len());
+    thread::scope(|s| {
+        for run in items.chunks_mut(run_size) {
+            s.spawn(move || run.iter_mut().for_each(f));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_par_map() {
+        let items: Vec<u64> = (0..1000).collect();
+        let expected: Vec<u64> = items.iter().map(|x| x * x).collect();
+        for num_threads in [1, 2, 3, 8, 2000] {
+            assert_eq!(par_map(num_threads, &items, |x| x * x), expected);
+        }
+        assert!(par_map(4, &[] as &[u64], |x| *x).is_empty());
+    }
+
+    #[test]
+    fn test_par_for_each_mut() {
+        for num_threads in [1, 3, 16] {
+            let mut items: Vec<u64> = (0..100).collect();
+            par_for_each_mut(num_threads, &mut items, |x| *x += 1);
+            assert_eq!(items, (1..101).collect::<Vec<u64>>());
+        }
+    }
+}
@@ -1,232 +1,711 @@
-use std::{cmp, vec};
+use std::{cmp, collections::VecDeque, sync::atomic::Ordering, vec};
 
 use crate::{
-    chunk::{Chunk, Chunker},
+    arena::{FileId, Id, SectionId, SymbolId},
+    build_id_section::{BuildId, BuildIdSection},
+    chunk::{Chunk, ChunkId, Chunker},
+    compress::compress,
+    compressed_section::CompressedSection,
     context::Context,
-    elf::{self, IMAGE_BASE, SHF_ALLOC, SHF_TLS, SHT_NOBITS},
-    input_section::InputSection,
+    dynamic_section::DynamicSection,
+    dynsym_section::{DynstrSection, DynsymSection},
+    eh_frame_hdr_section::EhFrameHdrSection,
+    eh_frame_section::EhFrameSection,
+    elf::{
+        self, SHF_ALLOC, SHF_MERGE, SHF_STRINGS, SHF_TLS, SHN_LORESERVE, SHT_NOBITS, SHT_NULL,
+        STV_DEFAULT, STV_PROTECTED, VERSYM_HIDDEN, VER_NDX_GLOBAL, VER_NDX_LAST_RESERVED,
+        VER_NDX_LOCAL,
+    },
+    error::LinkError,
+    mergeablesection::MergeableSection,
+    merged_section::get_merged_section_instance,
     object_file::ObjectFile,
+    output::get_init_fini_priority,
     output_ehdr::OutputEhdr,
-    symbol::{Symbol, NEEDS_GOT_TP},
-    utils::{align_to, remove_if},
+    output_file::OutputBuffer,
+    output_format::{convert_output, OutputFormat},
+    output_phdr::{to_phdr_flags, OutputPhdr},
+    output_shdr::OutputShdr,
+    parallel::{par_for_each_mut, par_map},
+    riscv_attributes_section::RiscvAttributesSection,
+    shared_file::SharedFile,
+    strtab_section::StrtabSection,
+    symbol::{Symbol, NEEDS_GOT, NEEDS_GOT_TP},
+    symtab_section::SymtabSection,
+    symtab_shndx_section::SymtabShndxSection,
+    utils::{align_to, is_c_identifier},
+    verdef_section::VerdefSection,
+    verneed_section::VerneedSection,
+    versym_section::VersymSection,
 };
 
+// Reads the symbol tables of all object files, several files at a time.
+// If some are malformed, the first one in command line order is reported.
+#[allow(dead_code)]
+pub fn parse_object_files(ctx: &mut Context) -> Result<(), LinkError> {
+    let num_threads = ctx.num_threads();
+    let mut jobs: Vec<(&mut ObjectFile, Result<(), LinkError>)> = ctx
+        .files
+        .as_mut_slice()
+        .iter_mut()
+        .map(|file| (file, Ok(())))
+        .collect();
+    par_for_each_mut(num_threads, &mut jobs, |(file, result)| {
+        *result = file.parse()
+    });
+    jobs.into_iter().try_for_each(|(_, result)| result)
+}
+
+// Creates the symbols of all object files. The global symbol names are
+// read and looked up in the symbol table on several threads.
+#[allow(dead_code)]
+pub fn initialize_symbols(ctx: &mut Context) -> Result<(), LinkError> {
+    let num_threads = ctx.num_threads();
+    let ids: Vec<FileId> = ctx.files.ids().collect();
+    let names: Vec<(Vec<String>, Vec<Option<String>>)> = {
+        let ctx: &Context = ctx;
+        par_map(num_threads, &ids, |id| {
+            ctx.files[*id].read_global_names(&ctx.args.wrap)
+        })
+        .into_iter()
+        .collect::<Result<_, _>>()?
+    };
+    let (names, symvers): (Vec<_>, Vec<_>) = names.into_iter().unzip();
+
+    let globals = ctx.symbol_map.intern(&mut ctx.symbols, &names, num_threads);
+    for ((id, globals), symvers) in ids.into_iter().zip(globals).zip(symvers) {
+        ObjectFile::initialize_symbols(ctx, id, globals, symvers)?;
+    }
+    Ok(())
+}
+
+// Creates the input sections of every file, including archive members
+// that may not end up in the link.
+#[allow(dead_code)]
+pub fn initialize_sections(ctx: &mut Context) -> Result<(), LinkError> {
+    for id in ctx.files.ids().collect::<Vec<_>>() {
+        ObjectFile::initialize_sections(ctx, id)?;
+    }
+    Ok(())
+}
+
+// Decides which file defines each global symbol, and which archive
+// members are part of the link: those that define a symbol a file in the
+// link refers to.
 #[allow(dead_code)]
 pub fn resolve_symbols(ctx: &mut Context) {
-    for file in &ctx.objs {
-        unsafe { file.as_mut().unwrap().resolve_symbols() }
+    for id in ctx.objs.clone() {
+        ObjectFile::resolve_symbols(ctx, id)
     }
 
     mark_live_objects(ctx);
+
+    // Definitions in archive members that were left out may have won over
+    // those in later files, so the symbols are resolved again.
+    for id in ctx.objs.clone() {
+        if !ctx.files[id].input_file.is_alive {
+            ObjectFile::clear_symbols(ctx, id);
+        }
+    }
+    ctx.objs.retain(|id| ctx.files[*id].input_file.is_alive);
+    for id in ctx.objs.clone() {
+        ObjectFile::resolve_symbols(ctx, id)
+    }
+
+    for id in ctx.dsos.ids().collect::<Vec<_>>() {
+        SharedFile::resolve_symbols(ctx, id)
+    }
+}
+
+#[allow(dead_code)]
+pub fn mark_live_objects(ctx: &mut Context) {
+    let mut roots: VecDeque<FileId> = VecDeque::new();
+    for id in &ctx.objs {
+        if ctx.files[*id].input_file.is_alive {
+            roots.push_back(*id)
+        }
+    }
+
+    for sym in ctx.get_root_symbols() {
+        if let Some(file) = ctx.symbols[sym].object_file {
+            let input_file = &mut ctx.files[file].input_file;
+            if !input_file.is_alive {
+                input_file.is_alive = true;
+                roots.push_back(file);
+            }
+        }
+    }
+
+    while let Some(file) = roots.pop_front() {
+        ObjectFile::mark_live_objects(ctx, file, |file| roots.push_back(file));
+    }
+}
+
+// Reports global symbols defined by more than one object file. Weak and
+// common definitions may be overridden, so only strong ones conflict.
+#[allow(dead_code)]
+pub fn check_duplicate_symbols(ctx: &Context) -> Result<(), LinkError> {
     for file in &ctx.objs {
-        if unsafe { file.as_ref().unwrap().input_file.as_ref().unwrap().is_alive } {
-            unsafe { file.as_ref().unwrap().clear_symbols() };
+        let input_file = &ctx.files[*file].input_file;
+        let first_global = input_file.first_global.unwrap_or(0) as usize;
+
+        for i in first_global..input_file.elf_syms.len() {
+            let esym = input_file.elf_syms[i];
+            if esym.is_undef() || esym.is_common() || esym.is_weak() {
+                continue;
+            }
+
+            let sym = &ctx.symbols[input_file.symbols[i]];
+            let owner = match sym.object_file {
+                Some(owner) if owner != *file => owner,
+                _ => continue,
+            };
+            let owner_esym = sym.elf_sym(ctx);
+            if owner_esym.is_common() || owner_esym.is_weak() {
+                continue;
+            }
+            ctx.error(LinkError::DuplicateSymbol {
+                name: sym.name.to_string(),
+                file: input_file.file.name.to_string(),
+                other_file: ctx.files[owner].input_file.file.name.to_string(),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+// Reports objects with incompatible ELF header flags before any work is
+// done on them.
+#[allow(dead_code)]
+pub fn check_flags(ctx: &mut Context) -> Result<(), LinkError> {
+    ctx.e_flags = ctx.get_flags()?;
+    Ok(())
+}
+
+// Replaces SHF_MERGE sections with their pieces, which are merged with
+// those of other files later. The sections are split on several threads.
+#[allow(dead_code)]
+pub fn initialize_mergeable_sections(ctx: &mut Context) -> Result<(), LinkError> {
+    let mut isecs: Vec<(FileId, usize, SectionId)> = vec![];
+    for id in &ctx.objs {
+        let file = &mut ctx.files[*id];
+        file.mergeable_sections = file.input_sections.iter().map(|_| None).collect();
+        for (shndx, isec) in file.input_sections.iter().enumerate() {
+            let Some(isec) = *isec else { continue };
+            let isec_ref = &ctx.sections[isec];
+            let shdr = isec_ref.shdr();
+            // Constants of size 0 can't be merged; the section is kept as is.
+            if isec_ref.is_alive
+                && shdr.flags & SHF_MERGE != 0
+                && (shdr.ent_size != 0 || shdr.flags & SHF_STRINGS != 0)
+            {
+                isecs.push((*id, shndx, isec));
+            }
         }
     }
 
-    let func = |file: &*mut ObjectFile| -> bool {
-        unsafe { file.as_ref().unwrap().input_file.as_ref().unwrap().is_alive }
+    let pieces = {
+        let ctx: &Context = ctx;
+        par_map(ctx.num_threads(), &isecs, |(_, _, isec)| {
+            ObjectFile::split_section(ctx, *isec)
+        })
     };
-    ctx.objs = remove_if(ctx.objs.clone(), func);
+
+    for ((file, shndx, isec), pieces) in isecs.into_iter().zip(pieces) {
+        let (strs, frag_offsets) = pieces?;
+        let shdr = ctx.sections[isec].shdr();
+        let name = ctx.sections[isec].name().to_string();
+
+        let mut m = MergeableSection::new();
+        m.parent = Some(get_merged_section_instance(
+            ctx,
+            &name,
+            shdr.shdr_type,
+            shdr.flags,
+            shdr.ent_size,
+        ));
+        m.p2_align = ctx.sections[isec].p2_align;
+        m.strs = strs;
+        m.frag_offsets = frag_offsets;
+        ctx.files[file].mergeable_sections[shndx] = Some(m);
+        ctx.sections[isec].is_alive = false;
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+pub fn register_section_pieces(ctx: &mut Context) -> Result<(), LinkError> {
+    for id in ctx.objs.clone() {
+        ObjectFile::register_section_pieces(ctx, id)?
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+pub fn initialize_ehframe_sections(ctx: &mut Context) -> Result<(), LinkError> {
+    for id in ctx.objs.clone() {
+        ObjectFile::initialize_ehframe_sections(ctx, id)?
+    }
+    Ok(())
 }
 
 #[allow(dead_code)]
-pub fn mark_live_objects(ctx: &Context) {
-    let mut roots = vec![];
+pub fn create_synthetic_sections(ctx: &mut Context) -> Result<(), LinkError> {
+    let class = ctx.elf_class();
+    ctx.ehdr = OutputEhdr::new();
+    ctx.ehdr.chunk.shdr.size = class.ehdr_size() as u64;
+    ctx.ehdr.chunk.shdr.addr_align = class.word_size() as u64;
+    ctx.shdr.class = class;
+    ctx.shdr.chunk.shdr.addr_align = class.word_size() as u64;
+    ctx.phdr.chunk.shdr.addr_align = class.word_size() as u64;
+    ctx.chunks.push(ChunkId::Ehdr);
+    ctx.chunks.push(ChunkId::Phdr);
+    ctx.chunks.push(ChunkId::Shdr);
+
+    if ctx.args.build_id != BuildId::None {
+        ctx.build_id = Some(BuildIdSection::new(&ctx.args.build_id));
+        ctx.chunks.push(ChunkId::BuildId);
+    }
+
+    let mut eh_frame = EhFrameSection::new();
+    eh_frame.construct(ctx);
+    let has_eh_frame = eh_frame.chunk.shdr.size > 0;
+    ctx.eh_frame = Some(eh_frame);
+    if has_eh_frame {
+        ctx.chunks.push(ChunkId::EhFrame);
+    }
+
+    if ctx.args.eh_frame_hdr {
+        let mut eh_frame_hdr = EhFrameHdrSection::new();
+        eh_frame_hdr.construct(ctx);
+        ctx.eh_frame_hdr = Some(eh_frame_hdr);
+        ctx.chunks.push(ChunkId::EhFrameHdr);
+    }
+
+    if !ctx.args.strip_all {
+        let mut strtab = StrtabSection::new(".strtab");
+        let mut symtab = SymtabSection::new(ctx.elf_class());
+        symtab.construct(ctx, &mut strtab);
+        ctx.strtab = Some(strtab);
+        ctx.symtab = Some(symtab);
+        ctx.chunks.push(ChunkId::Symtab);
+        ctx.chunks.push(ChunkId::Strtab);
+    }
+
+    ctx.got.word_size = ctx.target().got_entry_size();
+    ctx.got.chunk.shdr.addr_align = ctx.got.word_size;
+    ctx.chunks.push(ChunkId::Got);
+
+    let mut riscv_attributes = RiscvAttributesSection::new();
+    riscv_attributes.construct(ctx)?;
+    if riscv_attributes.chunk.shdr.size > 0 {
+        ctx.riscv_attributes = Some(riscv_attributes);
+        ctx.chunks.push(ChunkId::RiscvAttributes);
+    }
+
+    ctx.shstrtab = Some(StrtabSection::new(".shstrtab"));
+    ctx.chunks.push(ChunkId::Shstrtab);
+    Ok(())
+}
+
+// -S drops debug info. Only non-allocated sections are affected, so that
+// a stray SHF_ALLOC `.debug_` section does not change the memory image.
+#[allow(dead_code)]
+pub fn strip_debug_sections(ctx: &mut Context) {
     for file in &ctx.objs {
-        if unsafe { file.as_ref().unwrap().input_file.as_ref().unwrap().is_alive } {
-            roots.push(file.cast())
+        for isec in &ctx.files[*file].input_sections {
+            if isec.is_none() {
+                continue;
+            }
+            let isec = &mut ctx.sections[isec.unwrap()];
+            let name = isec.name();
+            if isec.shdr().flags & SHF_ALLOC == 0
+                && (name.starts_with(".debug") || name.starts_with(".zdebug"))
+            {
+                isec.is_alive = false;
+            }
         }
     }
+}
 
-    assert!(roots.len() > 0);
+// Assigns a section index to every chunk that has a section header and
+// records its name in .shstrtab. The ELF, program and section headers are
+// chunks too, but have no section header of their own. Empty synthetic
+// sections are dropped here rather than given an empty header.
+#[allow(dead_code)]
+pub fn compute_section_headers(ctx: &mut Context) {
+    let mut chunks = std::mem::take(&mut ctx.chunks);
+    chunks.retain(|chunk| {
+        let shdr = ctx.chunk(*chunk).shdr;
+        shdr.shdr_type == SHT_NULL || shdr.size > 0
+    });
+    ctx.chunks = chunks;
+
+    // Section indexes from SHN_LORESERVE up do not fit st_shndx. Symbols
+    // in those sections get theirs from .symtab_shndx.
+    let num_sections = ctx
+        .chunks
+        .iter()
+        .filter(|chunk| ctx.chunk(**chunk).shdr.shdr_type != SHT_NULL)
+        .count();
+    if let Some(symtab) = &ctx.symtab {
+        if num_sections + 1 >= SHN_LORESERVE as usize {
+            ctx.symtab_shndx = Some(SymtabShndxSection::new(symtab));
+            let pos = ctx
+                .chunks
+                .iter()
+                .position(|c| *c == ChunkId::Symtab)
+                .unwrap();
+            ctx.chunks.insert(pos + 1, ChunkId::SymtabShndx);
+        }
+    }
 
-    for _i in 0..roots.len() {
-        let file: *mut ObjectFile = roots[0];
-        if unsafe { file.as_ref().unwrap().input_file.as_ref().unwrap().is_alive } {
+    let mut shndx = 1;
+    for id in ctx.chunks.clone() {
+        if ctx.chunk(id).shdr.shdr_type == SHT_NULL {
             continue;
         }
+        let name = ctx.chunk(id).name.clone();
+        let name = ctx.shstrtab.as_mut().unwrap().add_string(&name);
+        let chunk = ctx.chunk_mut(id);
+        chunk.shndx = shndx;
+        chunk.shdr.name = name;
+        shndx += 1;
+    }
 
-        let func = || roots.push(file);
+    let set_link = |ctx: &mut Context, chunk: ChunkId, link: ChunkId| {
+        ctx.chunk_mut(chunk).shdr.link = ctx.chunk(link).shndx as u32;
+    };
 
-        unsafe { file.as_ref().unwrap().mark_live_objects(ctx, func) }
+    if ctx.symtab.is_some() {
+        set_link(ctx, ChunkId::Symtab, ChunkId::Strtab);
+        if ctx.symtab_shndx.is_some() {
+            set_link(ctx, ChunkId::SymtabShndx, ChunkId::Symtab);
+        }
+    }
 
-        roots.remove(0);
+    if ctx.dynsym.is_some() {
+        set_link(ctx, ChunkId::Dynamic, ChunkId::Dynstr);
+        set_link(ctx, ChunkId::Dynsym, ChunkId::Dynstr);
+        if ctx.verdef.is_some() {
+            set_link(ctx, ChunkId::Verdef, ChunkId::Dynstr);
+        }
+        if ctx.verneed.is_some() {
+            set_link(ctx, ChunkId::Verneed, ChunkId::Dynstr);
+        }
+        if ctx.versym.is_some() {
+            set_link(ctx, ChunkId::Versym, ChunkId::Dynsym);
+        }
     }
+
+    OutputShdr::update_shdr(ctx);
 }
 
+// Assigns a version index to every global symbol defined by an object
+// file, either from an explicit `foo@VER`/`foo@@VER` name or from the
+// version script. Named version script nodes are numbered from
+// VER_NDX_LAST_RESERVED + 1 in the order they appear.
 #[allow(dead_code)]
-pub fn register_section_pieces(ctx: &mut Context) {
+pub fn apply_version_script(ctx: &mut Context) -> Result<(), LinkError> {
+    for node in &ctx.args.version_script.nodes {
+        if !node.name.is_empty() {
+            ctx.version_definitions.push(node.name.clone());
+        }
+    }
+    if ctx.args.default_symver {
+        ctx.version_definitions.push(ctx.get_soname());
+        ctx.default_version = ctx.version_definitions.len() as u16 + VER_NDX_LAST_RESERVED;
+    }
+
+    let get_ver_idx = |name: &str| -> Option<u16> {
+        let pos = ctx.version_definitions.iter().position(|v| v == name)?;
+        Some(pos as u16 + VER_NDX_LAST_RESERVED + 1)
+    };
+
+    // The context is read while the versions are looked up, so they are
+    // assigned once all of them are known.
+    let mut ver_idxs = vec![];
+
     for file in &ctx.objs {
-        unsafe { file.as_mut().unwrap().register_section_pieces() }
+        let obj = &ctx.files[*file];
+        let input_file = &obj.input_file;
+        let first_global = input_file.first_global.unwrap_or(0) as usize;
+
+        for i in first_global..input_file.elf_syms.len() {
+            let sym = &ctx.symbols[input_file.symbols[i]];
+            if sym.object_file != Some(*file) {
+                continue;
+            }
+
+            let ver_idx = if let Some(ver) = &obj.symvers[i] {
+                let (ver, is_default) = match ver.strip_prefix('@') {
+                    Some(ver) => (ver, true),
+                    None => (ver.as_str(), false),
+                };
+                match get_ver_idx(ver) {
+                    Some(idx) if is_default => idx,
+                    Some(idx) => idx | VERSYM_HIDDEN,
+                    None => {
+                        ctx.error(LinkError::Other(format!(
+                            "{}: symbol {} has undefined version {}",
+                            input_file.file.name, sym.name, ver
+                        )))?;
+                        continue;
+                    }
+                }
+            } else {
+                match ctx.args.version_script.find_symbol(&sym.name) {
+                    Some((node, true)) => {
+                        let name = &ctx.args.version_script.nodes[node].name;
+                        get_ver_idx(name).unwrap_or(ctx.default_version)
+                    }
+                    Some((_, false)) => VER_NDX_LOCAL,
+                    None => ctx.default_version,
+                }
+            };
+            ver_idxs.push((input_file.symbols[i], ver_idx));
+        }
+    }
+
+    for (sym, ver_idx) in ver_idxs {
+        ctx.symbols[sym].ver_idx = ver_idx;
     }
+    Ok(())
 }
 
+// Collects symbols imported from shared objects and, with
+// --export-dynamic, the symbols we export, and creates .dynsym, .dynstr,
+// the symbol versioning sections and the .dynamic that points at them.
 #[allow(dead_code)]
-pub fn create_synthetic_sections(ctx: &mut Context) {
-    ctx.ehdr = OutputEhdr::new();
-    unsafe { ctx.chunks.unwrap().as_mut().unwrap().push(ctx.ehdr.chunk) };
+pub fn create_dynamic_sections(ctx: &mut Context) {
+    let mut syms: Vec<SymbolId> = vec![];
+    for file in &ctx.objs {
+        let input_file = &ctx.files[*file].input_file;
+        let first_global = input_file.first_global.unwrap_or(0) as usize;
+
+        for i in first_global..input_file.elf_syms.len() {
+            let sym = input_file.symbols[i];
+            let sym_ref = &ctx.symbols[sym];
+            let esym = input_file.elf_syms[i];
+
+            if esym.is_undef() {
+                if sym_ref.is_imported() {
+                    syms.push(sym);
+                }
+                continue;
+            }
+
+            let vis = esym.visibility();
+            if ctx.args.export_dynamic
+                && sym_ref.object_file == Some(*file)
+                && sym_ref.ver_idx != VER_NDX_LOCAL
+                && (vis == STV_DEFAULT || vis == STV_PROTECTED)
+            {
+                syms.push(sym);
+            }
+        }
+    }
+
+    if syms.is_empty() {
+        return;
+    }
+
+    let mut dynstr = DynstrSection::new();
+    let mut dynsym = DynsymSection::new(ctx.elf_class());
+    for sym in syms {
+        dynsym.add_symbol(sym, &mut ctx.symbols[sym], &mut dynstr);
+    }
+    ctx.dynsym = Some(dynsym);
+    ctx.chunks.push(ChunkId::Dynsym);
+    ctx.chunks.push(ChunkId::Dynstr);
+
+    let needs_versions = ctx
+        .dynsym
+        .as_ref()
+        .unwrap()
+        .symbols
+        .iter()
+        .any(|sym| ctx.symbols[*sym].ver_idx > VER_NDX_GLOBAL);
+    if needs_versions || !ctx.version_definitions.is_empty() {
+        if !ctx.version_definitions.is_empty() {
+            let mut verdef = VerdefSection::new();
+            verdef.construct(ctx, &mut dynstr);
+            ctx.verdef = Some(verdef);
+            ctx.chunks.push(ChunkId::Verdef);
+        }
+
+        let mut verneed = VerneedSection::new();
+        verneed.construct(ctx, &mut dynstr);
+        if verneed.chunk.shdr.size > 0 {
+            ctx.verneed = Some(verneed);
+            ctx.chunks.push(ChunkId::Verneed);
+        }
+
+        let mut versym = VersymSection::new();
+        versym.update_shdr(ctx);
+        ctx.versym = Some(versym);
+        ctx.chunks.push(ChunkId::Versym);
+    }
+
+    let mut dynamic = DynamicSection::new(ctx.elf_class());
+    dynamic.add_needed(ctx, &mut dynstr);
+    ctx.dynstr = Some(dynstr);
+    dynamic.update_shdr(ctx);
+    ctx.dynamic = Some(dynamic);
+    ctx.chunks.push(ChunkId::Dynamic);
 }
 
 #[allow(dead_code)]
 fn get_file_size(ctx: Context) -> u64 {
     let mut file_off = 0u64;
 
-    for c in unsafe { ctx.chunks.unwrap().as_ref().unwrap() } {
-        file_off = align_to(file_off, unsafe { c.as_ref().unwrap().shdr.addr_align });
-        file_off += unsafe { c.as_ref().unwrap().shdr.size };
+    for c in &ctx.chunks {
+        let chunk = ctx.chunk(*c);
+        file_off = align_to(file_off, chunk.shdr.addr_align);
+        file_off += chunk.shdr.size;
     }
 
     file_off
 }
 
 #[allow(dead_code)]
-pub fn is_tbss(chunk: *mut Chunk) -> bool {
-    let shdr = unsafe { chunk.as_ref().unwrap().get_shdr() };
-    shdr.shdr_type == SHT_NOBITS && shdr.flags & SHF_TLS != 0
+pub fn is_tbss(chunk: &Chunk) -> bool {
+    chunk.shdr.shdr_type == SHT_NOBITS && chunk.shdr.flags & SHF_TLS != 0
 }
 
+// Assigns addresses and file offsets to the chunks and returns the size
+// of the output file. Allocated chunks are at the same offset from the
+// start of the file as from the image base, and each segment starts on a
+// new page so that segments with different permissions don't share one.
 #[allow(dead_code)]
-fn set_output_section_offsets(ctx: *mut Context) -> u64 {
-    let mut addr = IMAGE_BASE;
-    let chunks = unsafe { ctx.as_ref().unwrap().chunks.unwrap() };
-    for chunk in unsafe { chunks.as_ref().unwrap() } {
-        if unsafe { chunk.as_ref().unwrap().get_shdr().flags } & SHF_ALLOC == 0 {
+pub fn set_output_section_offsets(ctx: &mut Context) -> u64 {
+    let chunks = ctx.chunks.clone();
+    let base = ctx.target().image_base();
+    let page_size = ctx.target().page_size();
+
+    let mut addr = base;
+    let mut prev_flags = None;
+    for id in &chunks {
+        let chunk = ctx.chunk_mut(*id);
+        if chunk.shdr.flags & SHF_ALLOC == 0 {
             continue;
         }
-        addr = align_to(addr, unsafe {
-            chunk.as_ref().unwrap().get_shdr().addr_align
-        });
-        unsafe { chunk.as_mut().unwrap().get_shdr().addr = addr };
-
-        if !is_tbss(*chunk) {
-            addr += unsafe { chunk.as_ref().unwrap().get_shdr().size };
+        let flags = to_phdr_flags(chunk);
+        if prev_flags.is_some_and(|prev| prev != flags) {
+            addr = align_to(addr, page_size);
         }
-    }
+        prev_flags = Some(flags);
 
-    let mut i = 0;
-    let first = unsafe { chunks.as_ref().unwrap() }[0];
-
-    loop {
-        let shdr = unsafe { &mut chunks.as_mut().unwrap()[i].as_ref().unwrap().get_shdr() };
-        shdr.offset = shdr.addr - unsafe { first.as_ref().unwrap().shdr.addr };
-        i += 1;
-        if i >= unsafe { chunks.as_ref().unwrap().len() }
-            || unsafe {
-                &chunks.as_mut().unwrap()[i]
-                    .as_ref()
-                    .unwrap()
-                    .get_shdr()
-                    .flags
-            } & SHF_ALLOC
-                == 0
-        {
-            break;
+        addr = align_to(addr, chunk.shdr.addr_align);
+        chunk.shdr.addr = addr;
+        chunk.shdr.offset = addr - base;
+        if !is_tbss(chunk) {
+            addr += chunk.shdr.size;
         }
     }
 
-    let last_shdr = unsafe { chunks.as_ref().unwrap()[i - 1].as_ref().unwrap().get_shdr() };
-    let mut file_off = last_shdr.offset + last_shdr.size;
-
-    for _j in i..unsafe { chunks.as_ref().unwrap().len() } {
-        let mut shdr = unsafe { chunks.as_ref().unwrap()[i].as_ref().unwrap().get_shdr() };
-        file_off = align_to(file_off, shdr.addr_align);
-        shdr.offset = file_off;
-        file_off += shdr.size;
+    let mut file_off = addr - base;
+    for id in &chunks {
+        let chunk = ctx.chunk_mut(*id);
+        if chunk.shdr.flags & SHF_ALLOC != 0 {
+            continue;
+        }
+        file_off = align_to(file_off, chunk.shdr.addr_align);
+        chunk.shdr.offset = file_off;
+        file_off += chunk.shdr.size;
     }
 
-    unsafe {
-        ctx.as_mut()
-            .unwrap()
-            .phdr
-            .update_shdr(ctx.as_mut().unwrap())
-    };
+    OutputPhdr::update_shdr(ctx);
     file_off
 }
 
 #[allow(dead_code)]
-pub fn bin_sections(ctx: &Context) {
-    let mut group: Vec<*mut Vec<*mut InputSection>> = vec![];
-    for _i in 0..unsafe { ctx.output_sections.as_ref().unwrap() }.len() {
-        group.push(Box::leak(Box::new(vec![])))
-    }
+pub fn bin_sections(ctx: &mut Context) {
+    let mut group: Vec<Vec<SectionId>> = vec![vec![]; ctx.output_sections.len()];
     for file in &ctx.objs {
-        for isec in unsafe { &file.as_ref().unwrap().input_sections } {
-            let isec_op = isec.is_none();
-            let isec_ref = unsafe { isec.unwrap().as_ref().unwrap() };
-            if isec_op || isec_ref.is_alive {
+        for isec in ctx.files[*file].input_sections.iter().flatten() {
+            let isec_ref = &ctx.sections[*isec];
+            if !isec_ref.is_alive {
                 continue;
             }
-            let idx = unsafe { isec_ref.output_section.unwrap().as_ref().unwrap().idx as usize };
-            unsafe { group[idx].as_mut().unwrap().push(isec.unwrap()) };
+            let idx = isec_ref.output_section.unwrap().index();
+            group[idx].push(*isec);
         }
     }
-    let idx = 0;
-    for osec in unsafe { ctx.output_sections.as_ref().unwrap() } {
-        unsafe { osec.as_mut().unwrap().members = group[idx] };
+
+    // Members of .init_array and .fini_array are sorted by priority.
+    // Those with the same priority stay in command line order.
+    for members in &mut group {
+        members.sort_by_key(|isec| get_init_fini_priority(ctx.sections[*isec].name()));
+    }
+    for (osec, members) in ctx.output_sections.ids().zip(group) {
+        ctx.output_sections[osec].members = members;
     }
 }
 
 #[allow(dead_code)]
-pub fn collect_output_sections(ctx: Context) -> Vec<*mut Chunk> {
-    let mut osecs: Vec<*mut Chunk> = vec![];
-    for osec in unsafe { ctx.output_sections.as_ref().unwrap() } {
-        if unsafe { osec.as_ref().unwrap().members.as_ref().unwrap().len() } > 0 {
-            osecs.push(unsafe { osec.as_ref().unwrap().chunk })
+pub fn collect_output_sections(ctx: &Context) -> Vec<ChunkId> {
+    let mut osecs: Vec<ChunkId> = vec![];
+    for (id, osec) in ctx.output_sections.iter() {
+        if !osec.members.is_empty() {
+            osecs.push(ChunkId::Output(id))
         }
     }
 
-    for osec in ctx.merged_sections {
-        if unsafe { osec.as_ref().unwrap().chunk.as_ref().unwrap().shdr.size } > 0 {
-            osecs.push(unsafe { osec.as_ref().unwrap().chunk })
+    for (id, osec) in ctx.merged_sections.iter() {
+        if osec.chunk.shdr.size > 0 {
+            osecs.push(ChunkId::Merged(id))
         }
     }
 
     osecs
 }
 
+// Adds the output sections and merged sections that have contents to
+// the chunks.
+#[allow(dead_code)]
+pub fn add_output_sections(ctx: &mut Context) {
+    let osecs = collect_output_sections(ctx);
+    ctx.chunks.extend(osecs);
+}
+
 #[allow(dead_code)]
-pub fn compute_section_sizes(ctx: &Context) {
-    for osec in unsafe { ctx.output_sections.as_ref().unwrap() } {
+pub fn compute_section_sizes(ctx: &mut Context) {
+    for osec in ctx.output_sections.ids() {
+        let osec = &mut ctx.output_sections[osec];
         let mut offset = 0u64;
         let mut p2_align = 0u64;
 
-        for isec in unsafe { osec.as_ref().unwrap().members.as_ref().unwrap() } {
+        for isec in &osec.members {
+            let isec = &mut ctx.sections[*isec];
             offset = align_to(offset, 1 << p2_align);
-            unsafe { isec.as_mut().unwrap().offset = offset as u32 };
-            offset += unsafe { isec.as_ref().unwrap().sh_size as u64 };
-            p2_align = cmp::max(p2_align, unsafe { isec.as_ref().unwrap().p2_align as u64 })
-        }
-
-        unsafe { osec.as_mut().unwrap().chunk.as_mut().unwrap().shdr.size = offset };
-        unsafe {
-            osec.as_mut()
-                .unwrap()
-                .chunk
-                .as_mut()
-                .unwrap()
-                .shdr
-                .addr_align = 1 << p2_align
-        };
+            isec.offset = offset as u32;
+            offset += isec.sh_size;
+            p2_align = cmp::max(p2_align, isec.p2_align as u64)
+        }
+
+        osec.chunk.shdr.size = offset;
+        osec.chunk.shdr.addr_align = 1 << p2_align;
     }
 }
 
 #[allow(dead_code)]
-pub fn sort_output_sections(ctx: &Context) {
-    let rank = |chunk: &Chunk| -> i32 {
+pub fn sort_output_sections(ctx: &mut Context) {
+    let rank = |id: ChunkId| -> i32 {
+        let chunk = ctx.chunk(id);
         let ty = chunk.get_shdr().shdr_type;
         let flags = chunk.get_shdr().flags;
 
-        if flags & SHF_ALLOC == 0 {
-            return (u32::MAX - 1) as i32;
-        }
-        if chunk == &ctx.shdr.chunk {
+        if id == ChunkId::Shdr {
             return i32::MAX;
         }
-        if chunk == &*unsafe { ctx.ehdr.chunk.as_ref().unwrap() } {
+        if flags & SHF_ALLOC == 0 {
+            return i32::MAX - 1;
+        }
+        if id == ChunkId::Ehdr {
             return 0;
         }
-        if chunk == &*unsafe { ctx.phdr.chunk.as_ref().unwrap() } {
+        if id == ChunkId::Phdr {
             return 1;
         }
         if ty == elf::SHT_NOTE {
@@ -248,39 +727,283 @@ pub fn sort_output_sections(ctx: &Context) {
         return writeable << 7 | not_exec << 6 | not_tls << 5 | is_bss << 4 as i32;
     };
 
-    unsafe {
-        ctx.chunks.unwrap().as_mut().unwrap().sort_by(|a, b| {
-            rank(a.as_ref().unwrap())
-                .partial_cmp(&rank(b.as_ref().unwrap()))
-                .unwrap()
-        })
-    };
+    let mut chunks = ctx.chunks.clone();
+    chunks.sort_by(|a, b| rank(*a).partial_cmp(&rank(*b)).unwrap());
+    ctx.chunks = chunks;
 }
 
 #[allow(dead_code)]
-pub fn compute_merged_sections_size(ctx: &Context) {
-    for osec in ctx.merged_sections.clone() {
-        unsafe { osec.as_mut().unwrap().assign_offsets() }
+pub fn compute_merged_sections_size(ctx: &mut Context) {
+    let tail_merge = ctx.args.optimize >= 2;
+    for osec in ctx.merged_sections.as_mut_slice() {
+        osec.assign_offsets(&mut ctx.fragments, tail_merge);
     }
 }
 
 #[allow(dead_code)]
-pub fn scan_relocations(ctx: &mut Context) {
-    let mut syms: Vec<*mut Symbol> = vec![];
+pub fn scan_relocations(ctx: &mut Context) -> Result<(), LinkError> {
+    let errors = par_map(ctx.num_threads(), &ctx.objs, |id| {
+        ctx.files[*id].scan_relocations(ctx)
+    });
+    for err in errors.into_iter().flatten() {
+        ctx.error(err)?;
+    }
+
+    let mut syms: Vec<SymbolId> = vec![];
     for file in &ctx.objs {
-        for sym in unsafe { &file.as_ref().unwrap().input_file.as_ref().unwrap().symbols } {
-            if unsafe { sym.as_ref().unwrap().object_file == Some(file.clone()) }
-                && unsafe { sym.as_ref().unwrap().flags } != 0
-            {
+        for sym in &ctx.files[*file].input_file.symbols {
+            let sym_ref = &ctx.symbols[*sym];
+            if sym_ref.object_file == Some(*file) && sym_ref.flags.load(Ordering::Relaxed) != 0 {
                 syms.push(*sym)
             }
         }
     }
 
-    for sym in syms {
-        if unsafe { sym.as_ref().unwrap().flags } & NEEDS_GOT_TP != 0 {
-            ctx.got.add_got_tp_symbol(sym)
+    for id in syms {
+        let sym = &mut ctx.symbols[id];
+        let flags = *sym.flags.get_mut();
+        if flags & NEEDS_GOT != 0 {
+            ctx.got.add_got_symbol(id, sym)
+        }
+        if flags & NEEDS_GOT_TP != 0 {
+            ctx.got.add_got_tp_symbol(id, sym)
+        }
+        *sym.flags.get_mut() = 0
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+pub fn get_start_stop_section_name(name: &str) -> Option<&str> {
+    let stem = name
+        .strip_prefix("__start_")
+        .or_else(|| name.strip_prefix("__stop_"))?;
+    if is_c_identifier(stem) {
+        return Some(stem);
+    }
+    None
+}
+
+#[allow(dead_code)]
+pub fn add_start_stop_symbols(ctx: &mut Context) {
+    for (id, osec) in ctx.output_sections.iter() {
+        if osec.members.is_empty() {
+            continue;
+        }
+
+        let name = &osec.chunk.name;
+        if !is_c_identifier(name) {
+            continue;
+        }
+
+        for prefix in ["__start_", "__stop_"] {
+            let sym = ctx.symbol_map.get((String::from(prefix) + name).as_str());
+            if sym.is_none() || ctx.symbols[*sym.unwrap()].is_defined() {
+                continue;
+            }
+
+            let sym = &mut ctx.symbols[*sym.unwrap()];
+            sym.set_chunk(ChunkId::Output(id), 0);
+            sym.visibility = ctx.args.z_start_stop_visibility;
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub fn add_defsym_symbols(ctx: &mut Context) {
+    for i in 0..ctx.args.defsyms.len() {
+        let name = ctx.args.defsyms[i].name.clone();
+        let sym = Symbol::get_symbol_by_name(ctx, &name);
+        ctx.symbols[sym].set_absolute(0);
+    }
+}
+
+#[allow(dead_code)]
+pub fn check_require_defined(ctx: &Context) -> Result<(), LinkError> {
+    for name in &ctx.args.require_defined {
+        let sym = ctx.symbol_map.get(name.as_str());
+        if sym.is_none() || !ctx.symbols[*sym.unwrap()].is_defined() {
+            ctx.error(LinkError::UndefinedSymbol {
+                name: name.clone(),
+                referenced_by: "--require-defined".to_string(),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+pub fn fix_synthetic_symbols(ctx: &mut Context) -> Result<(), LinkError> {
+    for (id, osec) in ctx.output_sections.iter() {
+        let chunk = ChunkId::Output(id);
+        let sym = ctx
+            .symbol_map
+            .get((String::from("__stop_") + &osec.chunk.name).as_str());
+        if sym.is_none() || ctx.symbols[*sym.unwrap()].chunk != Some(chunk) {
+            continue;
+        }
+        ctx.symbols[*sym.unwrap()].value = osec.chunk.shdr.size;
+    }
+    Ok(())
+}
+
+// --defsym symbols are absolute, and their values may refer to the
+// addresses of other symbols, so they are computed once the sections
+// have been laid out. A --defsym can refer to an earlier one.
+#[allow(dead_code)]
+pub fn fix_defsym_symbols(ctx: &mut Context) -> Result<(), LinkError> {
+    for i in 0..ctx.args.defsyms.len() {
+        let defsym = &ctx.args.defsyms[i];
+        let sym = ctx.symbol_map[defsym.name.as_str()];
+        match defsym.get_value(ctx) {
+            Ok(val) => ctx.symbols[sym].value = val,
+            Err(err) => ctx.error(err)?,
+        }
+    }
+    Ok(())
+}
+
+// --compress-debug-sections. The contents of each output debug section
+// are produced with relocations applied, then compressed in parallel on
+// up to --threads threads. The compressed chunks replace the originals,
+// so the file layout computed afterwards uses the compressed sizes.
+#[allow(dead_code)]
+pub fn compress_debug_sections(ctx: &mut Context) -> Result<(), LinkError> {
+    let ch_type = ctx.args.compress_debug_sections;
+    let class = ctx.elf_class();
+    let is_debug = |id: ChunkId| -> bool {
+        let chunk = ctx.chunk(id);
+        chunk.shdr.flags & SHF_ALLOC == 0 && chunk.shdr.size > 0 && chunk.name.starts_with(".debug")
+    };
+
+    let mut targets: Vec<(ChunkId, Vec<u8>)> = vec![];
+    for &id in &ctx.chunks {
+        if !is_debug(id) {
+            continue;
         }
-        unsafe { sym.as_mut().unwrap().flags = 0 }
+        let mut buf = vec![0; ctx.chunk(id).shdr.size as usize];
+        match id {
+            ChunkId::Output(osec) => ctx.output_sections[osec].write_to(ctx, &mut buf)?,
+            ChunkId::Merged(m) => ctx.merged_sections[m].write_to(&ctx.fragments, &mut buf),
+            _ => continue,
+        }
+        targets.push((id, buf));
+    }
+
+    let (targets, bufs): (Vec<_>, Vec<_>) = targets.into_iter().unzip();
+    let compressed: Vec<Vec<u8>> = par_map(ctx.num_threads(), &bufs, |buf| compress(ch_type, buf))
+        .into_iter()
+        .collect::<Result<_, _>>()?;
+
+    for (id, data) in targets.into_iter().zip(compressed) {
+        let sec = CompressedSection::new(ctx.chunk(id), class, ch_type, data);
+        let i = ctx.chunks.iter().position(|c| *c == id).unwrap();
+        ctx.chunks[i] = ChunkId::Compressed(ctx.compressed_sections.len());
+        ctx.compressed_sections.push(sec);
+    }
+    Ok(())
+}
+
+// Copies and relocates the members of the output sections. Input
+// sections never overlap in the file, so each thread gets its own
+// slices of the buffer. Errors are reported in file offset order.
+fn copy_input_sections(ctx: &Context, buf: &mut [u8]) -> Result<(), LinkError> {
+    let mut members: Vec<(usize, SectionId)> = vec![];
+    for &id in &ctx.chunks {
+        let ChunkId::Output(osec) = id else { continue };
+        let osec = &ctx.output_sections[osec];
+        let shdr = osec.chunk.shdr;
+        if shdr.shdr_type == SHT_NOBITS {
+            continue;
+        }
+        for id in &osec.members {
+            let isec = &ctx.sections[*id];
+            if isec.sh_size > 0 {
+                members.push((shdr.offset as usize + isec.offset as usize, *id));
+            }
+        }
+    }
+    members.sort_by_key(|(offset, _)| *offset);
+
+    let mut jobs: Vec<(SectionId, &mut [u8], Result<(), LinkError>)> = vec![];
+    let mut rest = buf;
+    let mut pos = 0;
+    for (offset, id) in members {
+        let size = ctx.sections[id].sh_size as usize;
+        let (_, tail) = rest.split_at_mut(offset - pos);
+        let (slice, tail) = tail.split_at_mut(size);
+        jobs.push((id, slice, Ok(())));
+        rest = tail;
+        pos = offset + size;
+    }
+
+    par_for_each_mut(ctx.num_threads(), &mut jobs, |(id, slice, res)| {
+        *res = ctx.sections[*id].write_to(ctx, slice);
+    });
+    jobs.into_iter().try_for_each(|(_, _, res)| res)
+}
+
+// Copies the contents of every chunk to ctx.buf. Sections whose chunk
+// was dropped or replaced, e.g. by a compressed section, are not in the
+// chunk list and are skipped. The chunks read the context while they
+// write to the buffer, so it is taken out of the context meanwhile.
+#[allow(dead_code)]
+pub fn copy_chunks(ctx: &mut Context) -> Result<(), LinkError> {
+    let mut buf = std::mem::take(&mut ctx.buf);
+    let res = copy_chunks_to(ctx, &mut buf);
+    ctx.buf = buf;
+    res
+}
+
+fn copy_chunks_to(ctx: &Context, buf: &mut [u8]) -> Result<(), LinkError> {
+    for &id in &ctx.chunks {
+        match id {
+            ChunkId::Ehdr => ctx.ehdr.copy_buf(ctx, buf),
+            ChunkId::Phdr => ctx.phdr.copy_buf(ctx, buf),
+            ChunkId::Shdr => ctx.shdr.copy_buf(ctx, buf),
+            ChunkId::Got => ctx.got.copy_buf(ctx, buf),
+            ChunkId::Dynamic => ctx.dynamic.as_ref().unwrap().copy_buf(ctx, buf),
+            ChunkId::Dynsym => ctx.dynsym.as_ref().unwrap().copy_buf(ctx, buf),
+            ChunkId::Dynstr => ctx.dynstr.as_ref().unwrap().copy_buf(buf),
+            ChunkId::Versym => ctx.versym.as_ref().unwrap().copy_buf(buf),
+            ChunkId::Verneed => ctx.verneed.as_ref().unwrap().copy_buf(buf),
+            ChunkId::Verdef => ctx.verdef.as_ref().unwrap().copy_buf(buf),
+            ChunkId::EhFrame => ctx.eh_frame.as_ref().unwrap().copy_buf(ctx, buf)?,
+            ChunkId::EhFrameHdr => ctx.eh_frame_hdr.as_ref().unwrap().copy_buf(ctx, buf),
+            ChunkId::Symtab => ctx.symtab.as_ref().unwrap().copy_buf(ctx, buf),
+            // Written with .symtab.
+            ChunkId::SymtabShndx => {}
+            ChunkId::Strtab => ctx.strtab.as_ref().unwrap().copy_buf(buf),
+            ChunkId::Shstrtab => ctx.shstrtab.as_ref().unwrap().copy_buf(buf),
+            ChunkId::BuildId => ctx.build_id.as_ref().unwrap().copy_buf(ctx, buf),
+            ChunkId::RiscvAttributes => ctx.riscv_attributes.as_ref().unwrap().copy_buf(buf),
+            ChunkId::Output(_) => {}
+            ChunkId::Merged(m) => ctx.merged_sections[m].copy_buf(ctx, buf),
+            ChunkId::Compressed(i) => ctx.compressed_sections[i].copy_buf(buf),
+        }
+    }
+    copy_input_sections(ctx, buf)
+}
+
+// The build ID may be a hash of the output file, so this must run after
+// every other chunk has been copied to the buffer.
+#[allow(dead_code)]
+pub fn write_build_id(ctx: &mut Context) -> Result<(), LinkError> {
+    let mut buf = std::mem::take(&mut ctx.buf);
+    let res = match &ctx.build_id {
+        Some(build_id) => build_id.write_build_id(ctx, &mut buf),
+        None => Ok(()),
+    };
+    ctx.buf = buf;
+    res
+}
+
+// --oformat=binary|ihex|srec replaces the ELF image with the contents of
+// its allocated sections. This runs last, after the build ID is written.
+#[allow(dead_code)]
+pub fn convert_output_format(ctx: &mut Context) -> Result<(), LinkError> {
+    if ctx.args.oformat != OutputFormat::Elf {
+        ctx.buf = OutputBuffer::Memory(convert_output(ctx)?);
     }
+    Ok(())
 }
@@ -0,0 +1,305 @@
+use std::{
+    env, fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    binary_file::get_symbol_prefix, context::Context, error::LinkError, input::OpenInput,
+    linker::LinkInput, output_file::map_input_file,
+};
+
+const BLOCK_SIZE: usize = 512;
+
+// A tar file in the ustar format. Paths that don't fit in a ustar header
+// get a pax extended header. Timestamps and owners are zero so that the
+// archive only depends on the files in it.
+struct TarWriter {
+    buf: Vec<u8>,
+}
+
+impl TarWriter {
+    fn new() -> Self {
+        TarWriter { buf: vec![] }
+    }
+
+    fn write_header(&mut self, name: &str, size: usize, ty: u8) {
+        let mut hdr = [0u8; BLOCK_SIZE];
+        let name = &name.as_bytes()[..name.len().min(100)];
+        hdr[..name.len()].copy_from_slice(name);
+        hdr[100..107].copy_from_slice(b"0000644");
+        hdr[108..115].copy_from_slice(b"0000000");
+        hdr[116..123].copy_from_slice(b"0000000");
+        hdr[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        hdr[136..147].copy_from_slice(b"00000000000");
+        hdr[156] = ty;
+        hdr[257..263].copy_from_slice(b"ustar\0");
+        hdr[263..265].copy_from_slice(b"00");
+
+        // The checksum is computed with its own field set to spaces.
+        hdr[148..156].copy_from_slice(b"        ");
+        let sum: u32 = hdr.iter().map(|b| *b as u32).sum();
+        hdr[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        self.buf.extend_from_slice(&hdr);
+    }
+
+    fn write_data(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        self.buf
+            .resize(self.buf.len().next_multiple_of(BLOCK_SIZE), 0);
+    }
+
+    fn append(&mut self, path: &str, data: &[u8]) {
+        if path.len() > 100 {
+            // A pax record is "<length> path=<path>\n", where the length
+            // counts its own digits.
+            let rest = " path=".len() + path.len() + 1;
+            let mut len = rest + 1;
+            while len != rest + len.to_string().len() {
+                len = rest + len.to_string().len();
+            }
+            let record = format!("{} path={}\n", len, path);
+            self.write_header("././@PaxHeader", record.len(), b'x');
+            self.write_data(record.as_bytes());
+        }
+        self.write_header(path, data.len(), b'0');
+        self.write_data(data);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.buf.resize(self.buf.len() + 2 * BLOCK_SIZE, 0);
+        self.buf
+    }
+}
+
+// The path a file is stored at in the archive: its absolute path without
+// the leading slash, so that files from different directories don't
+// collide.
+fn rewrite_path(path: &str) -> String {
+    let path = Path::new(path);
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir().unwrap_or_default().join(path)
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => normalized.push(c),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => {}
+        }
+    }
+    normalized.to_string_lossy().into_owned()
+}
+
+// Quotes an argument for a response file if it would otherwise be split
+// or unescaped; see args::split_response_file.
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "\"'\\".contains(c)) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Writes the archive for --reproduce=<file>.tar. It holds the inputs and
+// version scripts, and a response file that links them again with the
+// same options. The files are under a directory named after the archive:
+//
+//     tar xf repro.tar; cd repro; rvld @response.txt
+//
+// Libraries given with -l are stored at the path they were found at and
+// passed by that path. The symbols of a -b binary input are named after
+// its path, so the names the link saw are defined with --defsym.
+#[allow(dead_code)]
+pub fn write_reproduce(ctx: &Context, files: &[OpenInput]) -> Result<(), LinkError> {
+    let tar_path = &ctx.args.reproduce;
+    let dir = Path::new(tar_path)
+        .file_stem()
+        .map_or("repro".into(), |stem| stem.to_string_lossy());
+    let mut tar = TarWriter::new();
+    let mut response: Vec<String> = ctx.args.options.iter().map(|arg| quote(arg)).collect();
+
+    let add_file = |tar: &mut TarWriter, path: &str, data: &[u8]| {
+        let path = rewrite_path(path);
+        tar.append(&format!("{}/{}", dir, path), data);
+        path
+    };
+    let read_file = |path: &str| {
+        map_input_file(path).map_err(|error| LinkError::Io {
+            path: path.to_string(),
+            error,
+        })
+    };
+
+    for path in &ctx.args.version_script_files {
+        let path = add_file(&mut tar, path, &read_file(path)?);
+        response.push(quote(&format!("--version-script={}", path)));
+    }
+    for file in files {
+        match file.input {
+            LinkInput::Format(format) => {
+                response.push("-b".to_string());
+                response.push(quote(format));
+            }
+            // Replayed, the link fails the same way.
+            LinkInput::Library(name) if file.contents.is_none() => {
+                response.push(quote(&format!("-l{}", name)))
+            }
+            _ => {
+                let contents = file.contents.as_deref().unwrap();
+                let path = add_file(&mut tar, &file.name, contents);
+                response.push(quote(&path));
+                let (prefix, new_prefix) =
+                    (get_symbol_prefix(&file.name), get_symbol_prefix(&path));
+                if file.is_binary && prefix != new_prefix {
+                    for suffix in ["_start", "_end", "_size"] {
+                        let defsym =
+                            format!("--defsym={}{}={}{}", prefix, suffix, new_prefix, suffix);
+                        response.push(quote(&defsym));
+                    }
+                }
+            }
+        }
+    }
+
+    let response = response.join("\n") + "\n";
+    tar.append(&format!("{}/response.txt", dir), response.as_bytes());
+    let version = format!("rvld {}\n", env!("CARGO_PKG_VERSION"));
+    tar.append(&format!("{}/version.txt", dir), version.as_bytes());
+
+    fs::write(tar_path, tar.finish()).map_err(|error| LinkError::Io {
+        path: tar_path.to_string(),
+        error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::split_response_file;
+    use crate::input::open_input_files;
+
+    // Returns the path and contents of every file in a tar archive.
+    fn read_tar(buf: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut files = vec![];
+        let mut pax_path = None;
+        let mut pos = 0;
+        while buf[pos] != 0 {
+            let hdr = &buf[pos..pos + BLOCK_SIZE];
+            let sum: u32 = hdr[..148]
+                .iter()
+                .chain(&[b' '; 8])
+                .chain(&hdr[156..])
+                .map(|b| *b as u32)
+                .sum();
+            let size_field = std::str::from_utf8(&hdr[124..135]).unwrap();
+            let sum_field = std::str::from_utf8(&hdr[148..154]).unwrap();
+            assert_eq!(u32::from_str_radix(sum_field, 8).unwrap(), sum);
+
+            let size = usize::from_str_radix(size_field, 8).unwrap();
+            let data = buf[pos + BLOCK_SIZE..pos + BLOCK_SIZE + size].to_vec();
+            pos += BLOCK_SIZE + size.next_multiple_of(BLOCK_SIZE);
+
+            if hdr[156] == b'x' {
+                let record = String::from_utf8(data).unwrap();
+                pax_path = Some(
+                    record
+                        .split_once(" path=")
+                        .unwrap()
+                        .1
+                        .trim_end()
+                        .to_string(),
+                );
+                continue;
+            }
+            let name = String::from_utf8(hdr[..100].to_vec()).unwrap();
+            let name = pax_path
+                .take()
+                .unwrap_or(name.trim_end_matches('\0').to_string());
+            files.push((name, data));
+        }
+        files
+    }
+
+    #[test]
+    fn test_quote() {
+        let args = ["-o", "a b", "say \"hi\"", "c:\\dir", ""];
+        let quoted: Vec<String> = args.iter().map(|arg| quote(arg)).collect();
+        assert_eq!(quoted[0], "-o");
+        assert_eq!(split_response_file(&quoted.join(" ")), args);
+    }
+
+    #[test]
+    fn test_write_reproduce() {
+        let dir = env::temp_dir().join(format!("rvld-reproduce-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let obj = dir.join("a.o").to_string_lossy().into_owned();
+        fs::write(&obj, b"object").unwrap();
+        let long_name = format!("{}/{}.o", dir.to_string_lossy(), "x".repeat(120));
+        fs::write(&long_name, b"long").unwrap();
+
+        let mut ctx = Context::new();
+        ctx.args.reproduce = dir.join("repro.tar").to_string_lossy().into_owned();
+        ctx.args.options = vec!["-o".to_string(), "out dir/a.out".to_string()];
+        let inputs = vec![
+            LinkInput::from(obj.as_str()),
+            LinkInput::Format("binary".to_string()),
+            LinkInput::from(long_name.as_str()),
+            LinkInput::Library("missing".to_string()),
+        ];
+        let files = open_input_files(&ctx.args, &inputs).unwrap();
+        write_reproduce(&ctx, &files).unwrap();
+
+        let files = read_tar(&fs::read(&ctx.args.reproduce).unwrap());
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        let obj_path = rewrite_path(&obj);
+        let long_path = rewrite_path(&long_name);
+        assert_eq!(
+            names,
+            [
+                format!("repro/{}", obj_path),
+                format!("repro/{}", long_path),
+                "repro/response.txt".to_string(),
+                "repro/version.txt".to_string(),
+            ]
+        );
+        assert_eq!(files[0].1, b"object");
+        assert_eq!(files[1].1, b"long");
+
+        // The long file is a -b binary input.
+        let response = String::from_utf8(files[2].1.clone()).unwrap();
+        let defsym = |suffix: &str| {
+            let (prefix, new_prefix) =
+                (get_symbol_prefix(&long_name), get_symbol_prefix(&long_path));
+            format!("--defsym={}{}={}{}", prefix, suffix, new_prefix, suffix)
+        };
+        assert_eq!(
+            split_response_file(&response),
+            [
+                "-o",
+                "out dir/a.out",
+                &obj_path,
+                "-b",
+                "binary",
+                &long_path,
+                &defsym("_start"),
+                &defsym("_end"),
+                &defsym("_size"),
+                "-lmissing"
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
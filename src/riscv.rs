@@ -0,0 +1,465 @@
+use std::sync::atomic::Ordering;
+
+use crate::{
+    context::Context,
+    elf::{
+        self, Phdr, RRicsv, Rela, EF_RISCV_FLOAT_ABI, EF_RISCV_RVC, EF_RISCV_RVE, EF_RISCV_TSO,
+        EM_RISCV, IMAGE_BASE, R_RISCV_32, R_RISCV_32_PCREL, R_RISCV_64, R_RISCV_ADD16,
+        R_RISCV_ADD32, R_RISCV_ADD64, R_RISCV_ADD8, R_RISCV_BRANCH, R_RISCV_CALL, R_RISCV_CALL_PLT,
+        R_RISCV_HI20, R_RISCV_JAL, R_RISCV_LO12_I, R_RISCV_LO12_S, R_RISCV_NONE,
+        R_RISCV_PCREL_HI20, R_RISCV_PCREL_LO12_I, R_RISCV_PCREL_LO12_S, R_RISCV_RELAX,
+        R_RISCV_SET16, R_RISCV_SET32, R_RISCV_SET6, R_RISCV_SET8, R_RISCV_SET_ULEB128,
+        R_RISCV_SUB16, R_RISCV_SUB32, R_RISCV_SUB6, R_RISCV_SUB64, R_RISCV_SUB8,
+        R_RISCV_SUB_ULEB128, R_RISCV_TLS_GOT_HI20, R_RISCV_TPREL_LO12_I, R_RISCV_TPREL_LO12_S,
+    },
+    elf_class::ElfClass,
+    error::LinkError,
+    input_section::InputSection,
+    machine_type::{MachineType, MACHINE_TYPE_RISCV32, MACHINE_TYPE_RISCV64},
+    object_file::ObjectFile,
+    symbol::NEEDS_GOT_TP,
+    target::Target,
+    utils::{bit, bits, overwrite_uleb, read, read_uleb, sign_extend, try_read, write},
+};
+
+// RV64 and RV32 only differ in the ELF class.
+pub struct RiscV {
+    pub class: ElfClass,
+}
+
+pub static RISCV64: RiscV = RiscV {
+    class: ElfClass::Elf64,
+};
+
+pub static RISCV32: RiscV = RiscV {
+    class: ElfClass::Elf32,
+};
+
+impl Target for RiscV {
+    fn machine_type(&self) -> MachineType {
+        if self.class.is_32() {
+            MACHINE_TYPE_RISCV32
+        } else {
+            MACHINE_TYPE_RISCV64
+        }
+    }
+
+    fn e_machine(&self) -> u16 {
+        EM_RISCV
+    }
+
+    fn class(&self) -> ElfClass {
+        self.class
+    }
+
+    fn image_base(&self) -> u64 {
+        IMAGE_BASE
+    }
+
+    // TLS variant I with no TCB: tp points to the start of the TLS block.
+    fn get_tp_addr(&self, tls: &Phdr) -> u64 {
+        tls.v_addr
+    }
+
+    // Every input must use the same floating-point ABI, RVE and TSO
+    // settings as the first one, and the output uses compressed
+    // instructions if any input does.
+    fn merge_flags<'a>(&self, objs: &[&ObjectFile<'a>]) -> Result<u32, LinkError> {
+        if objs.is_empty() {
+            return Ok(0);
+        }
+
+        let get_ehdr_flags = |file: &ObjectFile| file.input_file.get_ehdr().flags;
+        let get_name = |file: &ObjectFile<'a>| file.input_file.file.name;
+
+        let mut flags = get_ehdr_flags(objs[0]);
+        for obj in &objs[1..] {
+            let obj_flags = get_ehdr_flags(obj);
+            for (mask, what) in [
+                (EF_RISCV_FLOAT_ABI, "floating-point ABI"),
+                (EF_RISCV_RVE, "EF_RISCV_RVE"),
+                (EF_RISCV_TSO, "EF_RISCV_TSO"),
+            ] {
+                if obj_flags & mask != flags & mask {
+                    return Err(LinkError::Other(format!(
+                        "{}: cannot link object files with different {} from {}",
+                        get_name(obj),
+                        what,
+                        get_name(objs[0])
+                    )));
+                }
+            }
+            flags |= obj_flags & EF_RISCV_RVC;
+        }
+        Ok(flags)
+    }
+
+    fn scan_relocations(&self, ctx: &Context, isec: &InputSection) {
+        let symbols = &ctx.files[isec.object_file].input_file.symbols;
+        for rel in &isec.rels {
+            let sym = &ctx.symbols[symbols[rel.sym as usize]];
+            if !sym.is_defined() {
+                continue;
+            }
+            if rel.ty == elf::R_RISCV_TLS_GOT_HI20 as u32 {
+                sym.flags.fetch_or(NEEDS_GOT_TP, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn apply_reloc_alloc<'a>(
+        &self,
+        ctx: &Context<'a>,
+        isec: &InputSection<'a>,
+        base: &mut [u8],
+    ) -> Result<(), LinkError> {
+        let symbols = &ctx.files[isec.object_file].input_file.symbols;
+        let rels = &isec.rels;
+        // PCREL_HI20 and TLS_GOT_HI20 first store their full value in
+        // place of the instruction, so that the PCREL_LO12 relocations
+        // that point at it can read it. The instruction is restored and
+        // encoded once every relocation has been applied.
+        let mut hi20 = vec![];
+
+        for i in 0..rels.len() {
+            let rel = rels[i];
+            if rel.ty == R_RISCV_NONE || rel.ty == R_RISCV_RELAX {
+                continue;
+            }
+
+            let sym = &ctx.symbols[symbols[rel.sym as usize]];
+            let loc = &mut base[rel.offset as usize..];
+
+            if !sym.is_defined() {
+                continue;
+            }
+
+            let (s, a) = isec.get_rel_target(ctx, i, sym);
+            let p = isec.get_addr(ctx) + rel.offset;
+
+            match rel.ty as RRicsv {
+                R_RISCV_32 => write(&mut loc[..4], s.wrapping_add(a) as u32),
+                R_RISCV_64 => {
+                    if self.class.is_32() {
+                        ctx.error(LinkError::UnsupportedReloc {
+                            section: isec.name().to_string(),
+                            ty: rel.ty,
+                        })?;
+                        continue;
+                    }
+                    write(&mut loc[..8], s.wrapping_add(a))
+                }
+                R_RISCV_BRANCH => write_b_type(loc, s.wrapping_add(a).wrapping_sub(p) as u32),
+                R_RISCV_JAL => write_j_type(loc, s.wrapping_add(a).wrapping_sub(p) as u32),
+                R_RISCV_CALL_PLT | R_RISCV_CALL => {
+                    let val = s.wrapping_add(a).wrapping_sub(p) as u32;
+                    write_u_type(loc, val);
+                    write_i_type(&mut loc[4..], val);
+                }
+                R_RISCV_TLS_GOT_HI20 => {
+                    let val = sym.get_got_tp_addr(ctx).wrapping_add(a).wrapping_sub(p);
+                    write(&mut loc[..4], val as u32);
+                    hi20.push(rel.offset as usize);
+                }
+                R_RISCV_PCREL_HI20 => {
+                    write(&mut loc[..4], s.wrapping_add(a).wrapping_sub(p) as u32);
+                    hi20.push(rel.offset as usize);
+                }
+                R_RISCV_PCREL_LO12_I | R_RISCV_PCREL_LO12_S => {
+                    // The symbol is the label of the AUIPC in this section.
+                    let hi = s.wrapping_sub(isec.get_addr(ctx)) as usize;
+                    let Some(val) = base.get(hi..).and_then(try_read::<u32>) else {
+                        ctx.error(LinkError::Other(format!(
+                            "{}: {} at offset {:#x} does not point to a PCREL_HI20 relocation",
+                            isec.name(),
+                            if rel.ty == R_RISCV_PCREL_LO12_I as u32 {
+                                "R_RISCV_PCREL_LO12_I"
+                            } else {
+                                "R_RISCV_PCREL_LO12_S"
+                            },
+                            rel.offset
+                        )))?;
+                        continue;
+                    };
+                    let loc = &mut base[rel.offset as usize..];
+                    if rel.ty == R_RISCV_PCREL_LO12_I as u32 {
+                        write_i_type(loc, val)
+                    } else {
+                        write_s_type(loc, val)
+                    }
+                }
+                R_RISCV_HI20 => {
+                    // On RV64, LUI sign-extends its result, so an absolute
+                    // address must fit in the lower or upper 2 GiB.
+                    let val = s.wrapping_add(a);
+                    if !self.class.is_32() && val as i64 != val as i32 as i64 {
+                        ctx.error(LinkError::RelocOverflow {
+                            section: isec.name().to_string(),
+                            ty: rel.ty,
+                            offset: rel.offset,
+                            value: val as i64,
+                            lo: i32::MIN as i64,
+                            hi: 1 << 31,
+                        })?;
+                        continue;
+                    }
+                    write_u_type(loc, val as u32)
+                }
+                R_RISCV_LO12_S | R_RISCV_LO12_I => {
+                    let val = s.wrapping_add(a);
+                    if rel.ty == R_RISCV_LO12_I as u32 {
+                        write_i_type(loc, val as u32)
+                    } else {
+                        write_s_type(loc, val as u32)
+                    }
+                    if sign_extend(val, 11) == val {
+                        set_rs1(loc, 0)
+                    }
+                }
+                R_RISCV_TPREL_LO12_I | R_RISCV_TPREL_LO12_S => {
+                    let val = s.wrapping_add(a).wrapping_sub(ctx.tp_addr);
+                    if rel.ty == R_RISCV_TPREL_LO12_I as u32 {
+                        write_i_type(loc, val as u32);
+                    } else {
+                        write_s_type(loc, val as u32);
+                    }
+
+                    if sign_extend(val, 11) == val {
+                        set_rs1(loc, 4);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for offset in hi20 {
+            let loc = &mut base[offset..];
+            let val = read::<u32>(loc);
+            write(&mut loc[..4], read::<u32>(&isec.contents[offset..]));
+            write_u_type(loc, val)
+        }
+        Ok(())
+    }
+
+    // Debug sections are not loaded, so they only need relocations that
+    // compute addresses or address differences. Code lengths and DWARF5
+    // offsets are computed with ADD/SUB and ULEB128 pairs. A reference to
+    // a discarded section, e.g. a function dropped by --gc-sections, gets a
+    // tombstone value so that debuggers can skip it.
+    fn apply_reloc_nonalloc<'a>(
+        &self,
+        ctx: &Context<'a>,
+        isec: &InputSection<'a>,
+        base: &mut [u8],
+    ) -> Result<(), LinkError> {
+        let rels = &isec.rels;
+        let tombstone = isec.get_tombstone();
+
+        for rel in rels {
+            if rel.ty == R_RISCV_NONE || rel.ty == R_RISCV_RELAX {
+                continue;
+            }
+
+            let loc = &mut base[rel.offset as usize..];
+            let val = isec.get_nonalloc_value(ctx, rel);
+
+            let val = match val {
+                Some(val) => val,
+                None => {
+                    match rel.ty as RRicsv {
+                        R_RISCV_32 => write(&mut loc[..4], tombstone as u32),
+                        R_RISCV_64 => write(&mut loc[..8], tombstone),
+                        _ => {}
+                    }
+                    continue;
+                }
+            };
+
+            match rel.ty as RRicsv {
+                R_RISCV_ADD8 => loc[0] = loc[0].wrapping_add(val as u8),
+                R_RISCV_SUB8 => loc[0] = loc[0].wrapping_sub(val as u8),
+                R_RISCV_ADD16 => {
+                    let v = read::<u16>(loc).wrapping_add(val as u16);
+                    write(&mut loc[..2], v)
+                }
+                R_RISCV_SUB16 => {
+                    let v = read::<u16>(loc).wrapping_sub(val as u16);
+                    write(&mut loc[..2], v)
+                }
+                R_RISCV_ADD32 => {
+                    let v = read::<u32>(loc).wrapping_add(val as u32);
+                    write(&mut loc[..4], v)
+                }
+                R_RISCV_SUB32 => {
+                    let v = read::<u32>(loc).wrapping_sub(val as u32);
+                    write(&mut loc[..4], v)
+                }
+                R_RISCV_ADD64 => {
+                    let v = read::<u64>(loc).wrapping_add(val);
+                    write(&mut loc[..8], v)
+                }
+                R_RISCV_SUB64 => {
+                    let v = read::<u64>(loc).wrapping_sub(val);
+                    write(&mut loc[..8], v)
+                }
+                R_RISCV_SUB6 => loc[0] = (loc[0] & 0xc0) | (loc[0].wrapping_sub(val as u8) & 0x3f),
+                R_RISCV_SET6 => loc[0] = (loc[0] & 0xc0) | (val as u8 & 0x3f),
+                R_RISCV_SET8 => loc[0] = val as u8,
+                R_RISCV_SET16 => write(&mut loc[..2], val as u16),
+                R_RISCV_SET32 => write(&mut loc[..4], val as u32),
+                R_RISCV_SET_ULEB128 => overwrite_uleb(loc, val),
+                R_RISCV_SUB_ULEB128 => {
+                    let v = read_uleb(loc).wrapping_sub(val);
+                    overwrite_uleb(loc, v)
+                }
+                R_RISCV_32 => write(&mut loc[..4], val as u32),
+                R_RISCV_64 => write(&mut loc[..8], val),
+                _ => ctx.error(LinkError::UnsupportedReloc {
+                    section: isec.name().to_string(),
+                    ty: rel.ty,
+                })?,
+            }
+        }
+        Ok(())
+    }
+
+    // Besides absolute and PC-relative addresses, the assembler emits
+    // ADD/SUB pairs for code lengths and SET/SUB pairs for
+    // DW_CFA_advance_loc operands in `.eh_frame`.
+    fn apply_eh_reloc(&self, loc: &mut [u8], rel: &Rela, s: u64, p: u64) -> bool {
+        if rel.ty == R_RISCV_NONE {
+            return true;
+        }
+        let val = s.wrapping_add(rel.addend as u64);
+
+        match rel.ty as RRicsv {
+            R_RISCV_32 | R_RISCV_SET32 => write(&mut loc[..4], val as u32),
+            R_RISCV_64 => write(&mut loc[..8], val),
+            R_RISCV_32_PCREL => write(&mut loc[..4], val.wrapping_sub(p) as u32),
+            R_RISCV_SET8 => loc[0] = val as u8,
+            R_RISCV_SET16 => write(&mut loc[..2], val as u16),
+            R_RISCV_SET6 => loc[0] = (loc[0] & 0xc0) | (val as u8 & 0x3f),
+            R_RISCV_SUB6 => loc[0] = (loc[0] & 0xc0) | (loc[0].wrapping_sub(val as u8) & 0x3f),
+            R_RISCV_ADD8 => loc[0] = loc[0].wrapping_add(val as u8),
+            R_RISCV_SUB8 => loc[0] = loc[0].wrapping_sub(val as u8),
+            R_RISCV_ADD16 => {
+                let v = read::<u16>(loc).wrapping_add(val as u16);
+                write(&mut loc[..2], v)
+            }
+            R_RISCV_SUB16 => {
+                let v = read::<u16>(loc).wrapping_sub(val as u16);
+                write(&mut loc[..2], v)
+            }
+            R_RISCV_ADD32 => {
+                let v = read::<u32>(loc).wrapping_add(val as u32);
+                write(&mut loc[..4], v)
+            }
+            R_RISCV_SUB32 => {
+                let v = read::<u32>(loc).wrapping_sub(val as u32);
+                write(&mut loc[..4], v)
+            }
+            R_RISCV_ADD64 => {
+                let v = read::<u64>(loc).wrapping_add(val);
+                write(&mut loc[..8], v)
+            }
+            R_RISCV_SUB64 => {
+                let v = read::<u64>(loc).wrapping_sub(val);
+                write(&mut loc[..8], v)
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+fn i_type(val: u32) -> u32 {
+    val << 20
+}
+
+fn s_type(val: u32) -> u32 {
+    bits(val, 11, 5) << 25 | bits(val, 4, 0) << 7
+}
+
+fn b_type(val: u32) -> u32 {
+    bit(val, 12) << 31 | bits(val, 10, 5) << 25 | bits(val, 4, 1) << 8 | bit(val, 11) << 7
+}
+
+// AUIPC and LUI are paired with an instruction that adds a sign-extended
+// 12-bit value, so the upper 20 bits are rounded.
+fn u_type(val: u32) -> u32 {
+    val.wrapping_add(0x800) & 0xffff_f000
+}
+
+fn j_type(val: u32) -> u32 {
+    bit(val, 20) << 31 | bits(val, 10, 1) << 21 | bit(val, 11) << 20 | bits(val, 19, 12) << 12
+}
+
+// Each of these replaces the immediate of the instruction at `loc` and
+// keeps its opcode and registers. The masks are grouped by field.
+#[allow(clippy::unusual_byte_groupings)]
+fn write_b_type(loc: &mut [u8], val: u32) {
+    let mask = 0b000000_11111_11111_111_00000_1111111;
+    let insn = read::<u32>(loc) & mask | b_type(val);
+    write(&mut loc[..4], insn)
+}
+
+#[allow(clippy::unusual_byte_groupings)]
+fn write_j_type(loc: &mut [u8], val: u32) {
+    let mask = 0b000000_00000_00000_000_11111_1111111;
+    let insn = read::<u32>(loc) & mask | j_type(val);
+    write(&mut loc[..4], insn)
+}
+
+#[allow(clippy::unusual_byte_groupings)]
+fn write_i_type(loc: &mut [u8], val: u32) {
+    let mask = 0b000000_00000_11111_111_11111_1111111;
+    let insn = read::<u32>(loc) & mask | i_type(val);
+    write(&mut loc[..4], insn)
+}
+
+#[allow(clippy::unusual_byte_groupings)]
+fn write_s_type(loc: &mut [u8], val: u32) {
+    let mask = 0b000000_11111_11111_111_00000_1111111;
+    let insn = read::<u32>(loc) & mask | s_type(val);
+    write(&mut loc[..4], insn)
+}
+
+#[allow(clippy::unusual_byte_groupings)]
+fn write_u_type(loc: &mut [u8], val: u32) {
+    let mask = 0b000000_00000_00000_000_11111_1111111;
+    let insn = read::<u32>(loc) & mask | u_type(val);
+    write(&mut loc[..4], insn)
+}
+
+#[allow(clippy::unusual_byte_groupings)]
+fn set_rs1(loc: &mut [u8], rs1: u32) {
+    let mask = 0b111111_11111_00000_111_11111_1111111;
+    let insn = read::<u32>(loc) & mask | rs1 << 15;
+    write(&mut loc[..4], insn)
+}
+
+#[test]
+fn test_apply_eh_reloc() {
+    let rel = |ty: RRicsv, addend: i64| Rela {
+        offset: 0,
+        ty: ty as u32,
+        sym: 0,
+        addend,
+    };
+
+    let mut buf = [0u8; 4];
+    RISCV64.apply_eh_reloc(&mut buf, &rel(R_RISCV_32_PCREL, 0x10), 0x1000, 0x800);
+    assert_eq!(read::<u32>(&buf), 0x810);
+
+    RISCV64.apply_eh_reloc(&mut buf, &rel(R_RISCV_SUB32, 0), 0x10, 0);
+    assert_eq!(read::<u32>(&buf), 0x800);
+
+    // DW_CFA_advance_loc keeps its opcode in the top two bits.
+    let mut buf = [0x40u8];
+    RISCV64.apply_eh_reloc(&mut buf, &rel(R_RISCV_SET6, 0), 0x1008, 0);
+    RISCV64.apply_eh_reloc(&mut buf, &rel(R_RISCV_SUB6, 0), 0x1004, 0);
+    assert_eq!(buf[0], 0x44);
+
+    let tls_got = rel(R_RISCV_TLS_GOT_HI20, 0);
+    assert!(!RISCV64.apply_eh_reloc(&mut buf, &tls_got, 0, 0));
+}
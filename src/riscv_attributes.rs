@@ -0,0 +1,435 @@
+use std::fmt;
+
+use crate::error::LinkError;
+use crate::utils::{encode_uleb, read, read_uleb, warn};
+
+const TAG_FILE: u64 = 1;
+const TAG_RISCV_STACK_ALIGN: u64 = 4;
+const TAG_RISCV_ARCH: u64 = 5;
+const TAG_RISCV_UNALIGNED_ACCESS: u64 = 6;
+const TAG_RISCV_PRIV_SPEC: u64 = 8;
+const TAG_RISCV_PRIV_SPEC_MINOR: u64 = 10;
+const TAG_RISCV_PRIV_SPEC_REVISION: u64 = 12;
+
+// The canonical order of single-letter extensions in an ISA string.
+const EXTENSION_ORDER: &str = "iemafdqlcbkjtpvh";
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extension {
+    pub name: String,
+    pub version: Option<(u64, u64)>,
+}
+
+// An ISA string such as "rv64i2p1_m2p0_a2p1_c2p0_zicsr2p0".
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arch {
+    pub xlen: u32,
+    pub extensions: Vec<Extension>,
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    s.parse::<u64>().ok()
+}
+
+// Splits "zicsr2p0" into ("zicsr", Some((2, 0))). Names of multi-letter
+// extensions may contain digits, e.g. "zvl128b", so only a trailing
+// "<major>p<minor>" is taken as the version.
+fn split_version(token: &str) -> (&str, Option<(u64, u64)>) {
+    let digits = |s: &str| s.len() - s.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+
+    let minor_len = digits(token);
+    if minor_len == 0 || minor_len == token.len() {
+        return (token, None);
+    }
+    let rest = &token[..token.len() - minor_len];
+    if let Some(rest) = rest.strip_suffix('p') {
+        let major_len = digits(rest);
+        if major_len > 0 && major_len < rest.len() {
+            let name = &rest[..rest.len() - major_len];
+            let major = parse_number(&rest[name.len()..]).unwrap();
+            let minor = parse_number(&token[token.len() - minor_len..]).unwrap();
+            return (name, Some((major, minor)));
+        }
+    }
+    (
+        rest,
+        parse_number(&token[rest.len()..]).map(|major| (major, 0)),
+    )
+}
+
+#[allow(dead_code)]
+impl Arch {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.to_ascii_lowercase();
+        let (xlen, rest) = if let Some(rest) = s.strip_prefix("rv32") {
+            (32, rest)
+        } else if let Some(rest) = s.strip_prefix("rv64") {
+            (64, rest)
+        } else {
+            return None;
+        };
+
+        let mut arch = Arch {
+            xlen,
+            extensions: vec![],
+        };
+        for token in rest.split('_').filter(|t| !t.is_empty()) {
+            if token.starts_with(['z', 's', 'x']) {
+                let (name, version) = split_version(token);
+                arch.add(name, version);
+                continue;
+            }
+
+            // A run of single-letter extensions, each optionally followed
+            // by a version, e.g. "i2p1" or "imac".
+            let mut chars = token.char_indices().peekable();
+            while let Some((start, c)) = chars.next() {
+                if !c.is_ascii_alphabetic() {
+                    return None;
+                }
+                let mut end = start + 1;
+                while let Some((i, c)) = chars.peek() {
+                    let is_minor = *c == 'p'
+                        && end > start + 1
+                        && token[i + 1..].starts_with(|c: char| c.is_ascii_digit());
+                    if !c.is_ascii_digit() && !is_minor {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let (_, version) = split_version(&token[start..end]);
+
+                if c == 'g' {
+                    for name in ["i", "m", "a", "f", "d", "zicsr", "zifencei"] {
+                        arch.add(name, None);
+                    }
+                } else {
+                    arch.add(&token[start..start + 1], version);
+                }
+            }
+        }
+        Some(arch)
+    }
+
+    fn add(&mut self, name: &str, version: Option<(u64, u64)>) {
+        match self.extensions.iter_mut().find(|ext| ext.name == name) {
+            Some(ext) => ext.version = ext.version.max(version),
+            None => self.extensions.push(Extension {
+                name: String::from(name),
+                version,
+            }),
+        }
+    }
+
+    // The union of both extension sets. If both have an extension, the
+    // newer version wins.
+    pub fn merge(&mut self, other: &Arch) {
+        for ext in &other.extensions {
+            self.add(&ext.name, ext.version);
+        }
+    }
+
+    // Single-letter extensions come first in canonical order, then
+    // multi-letter ones: Z extensions ordered by the category letter that
+    // follows the Z, then S and X extensions, each group alphabetically.
+    fn sort(&mut self) {
+        let order = |c: char| EXTENSION_ORDER.find(c).unwrap_or(EXTENSION_ORDER.len());
+        self.extensions.sort_by_cached_key(|ext| {
+            let mut chars = ext.name.chars();
+            let first = chars.next().unwrap();
+            let key = if ext.name.len() == 1 {
+                (0, order(first))
+            } else {
+                match first {
+                    'z' => (1, order(chars.next().unwrap())),
+                    's' => (2, 0),
+                    _ => (3, 0),
+                }
+            };
+            (key, ext.name.clone())
+        });
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut arch = self.clone();
+        arch.sort();
+
+        write!(f, "rv{}", arch.xlen)?;
+        for (i, ext) in arch.extensions.iter().enumerate() {
+            if i > 0 {
+                write!(f, "_")?;
+            }
+            write!(f, "{}", ext.name)?;
+            if let Some((major, minor)) = ext.version {
+                write!(f, "{}p{}", major, minor)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The attributes of the "riscv" vendor subsection that the linker
+// understands. Others are dropped from the output.
+#[allow(dead_code)]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RiscvAttributes {
+    pub stack_align: Option<u64>,
+    pub arch: Option<Arch>,
+    pub unaligned_access: Option<u64>,
+    pub priv_spec: Option<(u64, u64, u64)>,
+}
+
+fn corrupted(file_name: &str) -> LinkError {
+    LinkError::bad_elf(file_name, "corrupted .riscv.attributes section")
+}
+
+fn read_uleb_from(data: &mut &[u8]) -> u64 {
+    let val = read_uleb(data);
+    let len = data
+        .iter()
+        .position(|b| b & 0x80 == 0)
+        .map_or(data.len(), |i| i + 1);
+    *data = &data[len..];
+    val
+}
+
+fn read_string_from<'b>(data: &mut &'b [u8], file_name: &str) -> Result<&'b str, LinkError> {
+    let end = data
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| corrupted(file_name))?;
+    let s = std::str::from_utf8(&data[..end]).map_err(|_| corrupted(file_name))?;
+    *data = &data[end + 1..];
+    Ok(s)
+}
+
+// Reads a length-prefixed block whose 32-bit length counts `header` bytes
+// that precede the length field as well as the field itself.
+fn read_block<'b>(
+    data: &mut &'b [u8],
+    header: usize,
+    file_name: &str,
+) -> Result<&'b [u8], LinkError> {
+    if data.len() < 4 {
+        return Err(corrupted(file_name));
+    }
+    let size = read::<u32>(data) as usize;
+    if size < header + 4 || size - header > data.len() {
+        return Err(corrupted(file_name));
+    }
+    let block = &data[4..size - header];
+    *data = &data[size - header..];
+    Ok(block)
+}
+
+#[allow(dead_code)]
+impl RiscvAttributes {
+    // The section is the letter 'A' followed by subsections, each a length,
+    // a vendor name and a list of (tag, length, attributes) records. Only
+    // file-wide attributes are used. Attributes with an even tag have a
+    // ULEB128 value and those with an odd tag a string.
+    pub fn parse(mut data: &[u8], file_name: &str) -> Result<Self, LinkError> {
+        let mut attrs = RiscvAttributes::default();
+        let mut priv_spec = (0, 0, 0);
+        if data.first() != Some(&b'A') {
+            return Err(corrupted(file_name));
+        }
+        data = &data[1..];
+
+        while !data.is_empty() {
+            let mut sub = read_block(&mut data, 0, file_name)?;
+            if read_string_from(&mut sub, file_name)? != "riscv" {
+                continue;
+            }
+
+            while !sub.is_empty() {
+                let before = sub.len();
+                let tag = read_uleb_from(&mut sub);
+                let tag_size = before - sub.len();
+                let mut body = read_block(&mut sub, tag_size, file_name)?;
+                if tag != TAG_FILE {
+                    continue;
+                }
+
+                while !body.is_empty() {
+                    let tag = read_uleb_from(&mut body);
+                    if tag % 2 == 1 {
+                        let val = read_string_from(&mut body, file_name)?;
+                        if tag == TAG_RISCV_ARCH {
+                            attrs.arch = Some(Arch::parse(val).ok_or_else(|| {
+                                LinkError::bad_elf(
+                                    file_name,
+                                    format!("invalid Tag_RISCV_arch: {}", val),
+                                )
+                            })?);
+                        }
+                        continue;
+                    }
+
+                    let val = read_uleb_from(&mut body);
+                    match tag {
+                        TAG_RISCV_STACK_ALIGN => attrs.stack_align = Some(val),
+                        TAG_RISCV_UNALIGNED_ACCESS => attrs.unaligned_access = Some(val),
+                        TAG_RISCV_PRIV_SPEC => priv_spec.0 = val,
+                        TAG_RISCV_PRIV_SPEC_MINOR => priv_spec.1 = val,
+                        TAG_RISCV_PRIV_SPEC_REVISION => priv_spec.2 = val,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        if priv_spec != (0, 0, 0) {
+            attrs.priv_spec = Some(priv_spec);
+        }
+        Ok(attrs)
+    }
+
+    // Merges the attributes of another input file into these. Objects built
+    // for a different base ISA or stack alignment can't be linked together.
+    // The output may use unaligned access if any input does. Inputs built
+    // for different privileged specs are accepted with a warning, and the
+    // output then makes no claim about the spec.
+    pub fn merge(&mut self, other: &RiscvAttributes, file_name: &str) -> Result<(), LinkError> {
+        match (&mut self.arch, &other.arch) {
+            (Some(arch), Some(other_arch)) => {
+                if arch.xlen != other_arch.xlen {
+                    return Err(LinkError::Other(format!(
+                        "{}: cannot link {} object with {} object",
+                        file_name, other_arch, arch
+                    )));
+                }
+                arch.merge(other_arch);
+            }
+            (None, Some(other_arch)) => self.arch = Some(other_arch.clone()),
+            _ => {}
+        }
+
+        match (self.stack_align, other.stack_align) {
+            (Some(x), Some(y)) if x != y => {
+                return Err(LinkError::Other(format!(
+                    "{}: Tag_RISCV_stack_align {} does not match the {} of other inputs",
+                    file_name, y, x
+                )))
+            }
+            (None, Some(y)) => self.stack_align = Some(y),
+            _ => {}
+        }
+
+        self.unaligned_access = self.unaligned_access.max(other.unaligned_access);
+
+        if other.priv_spec.is_some() && self.priv_spec != other.priv_spec {
+            if self.priv_spec.is_some() {
+                warn(&format!(
+                    "{}: Tag_RISCV_priv_spec does not match other inputs",
+                    file_name
+                ));
+            }
+            self.priv_spec = other.priv_spec;
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut attrs = vec![];
+        if let Some(val) = self.stack_align {
+            encode_uleb(&mut attrs, TAG_RISCV_STACK_ALIGN);
+            encode_uleb(&mut attrs, val);
+        }
+        if let Some(arch) = &self.arch {
+            encode_uleb(&mut attrs, TAG_RISCV_ARCH);
+            attrs.extend_from_slice(arch.to_string().as_bytes());
+            attrs.push(0);
+        }
+        if let Some(val) = self.unaligned_access {
+            encode_uleb(&mut attrs, TAG_RISCV_UNALIGNED_ACCESS);
+            encode_uleb(&mut attrs, val);
+        }
+        if let Some((major, minor, revision)) = self.priv_spec {
+            for (tag, val) in [
+                (TAG_RISCV_PRIV_SPEC, major),
+                (TAG_RISCV_PRIV_SPEC_MINOR, minor),
+                (TAG_RISCV_PRIV_SPEC_REVISION, revision),
+            ] {
+                encode_uleb(&mut attrs, tag);
+                encode_uleb(&mut attrs, val);
+            }
+        }
+
+        let mut file = vec![];
+        encode_uleb(&mut file, TAG_FILE);
+        file.extend_from_slice(&((file.len() + 4 + attrs.len()) as u32).to_le_bytes());
+        file.extend_from_slice(&attrs);
+
+        let vendor = b"riscv\0";
+        let mut buf = vec![b'A'];
+        buf.extend_from_slice(&((4 + vendor.len() + file.len()) as u32).to_le_bytes());
+        buf.extend_from_slice(vendor);
+        buf.extend_from_slice(&file);
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_arch() {
+        let mut arch = Arch::parse("rv64i2p1_m2p0_a2p1_c2p0_zicsr2p0").unwrap();
+        arch.merge(&Arch::parse("rv64i2p0_m2p0_f2p2_d2p2_zifencei2p0_zba1p0").unwrap());
+        assert_eq!(
+            arch.to_string(),
+            "rv64i2p1_m2p0_a2p1_f2p2_d2p2_c2p0_zicsr2p0_zifencei2p0_zba1p0"
+        );
+
+        let arch = Arch::parse("rv32imac_zvl128b1p0").unwrap();
+        assert_eq!(arch.to_string(), "rv32i_m_a_c_zvl128b1p0");
+        assert_eq!(Arch::parse("x86_64"), None);
+    }
+
+    #[test]
+    fn test_riscv_attributes() {
+        let attrs = RiscvAttributes {
+            stack_align: Some(16),
+            arch: Arch::parse("rv64i2p1_m2p0_c2p0"),
+            unaligned_access: Some(0),
+            priv_spec: Some((1, 12, 0)),
+        };
+        assert_eq!(RiscvAttributes::parse(&attrs.encode(), "a.o").unwrap(), attrs);
+        assert!(matches!(
+            RiscvAttributes::parse(&attrs.encode()[..8], "a.o"),
+            Err(LinkError::BadElf { .. })
+        ));
+
+        let mut merged = attrs.clone();
+        merged
+            .merge(
+                &RiscvAttributes {
+                    arch: Arch::parse("rv64i2p1_a2p1"),
+                    unaligned_access: Some(1),
+                    ..RiscvAttributes::default()
+                },
+                "b.o",
+            )
+            .unwrap();
+        assert!(merged
+            .clone()
+            .merge(
+                &RiscvAttributes {
+                    arch: Arch::parse("rv32i2p1"),
+                    ..RiscvAttributes::default()
+                },
+                "c.o",
+            )
+            .is_err());
+        assert_eq!(merged.arch.unwrap().to_string(), "rv64i2p1_m2p0_a2p1_c2p0");
+        assert_eq!(merged.unaligned_access, Some(1));
+        assert_eq!(merged.stack_align, Some(16));
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{
+    chunk::Chunk, context::Context, elf::SHT_RISCV_ATTRIBUTES, error::LinkError,
+    riscv_attributes::RiscvAttributes,
+};
+
+// The output `.riscv.attributes` section, the merge of the attributes of
+// every input object. It is described by the PT_RISCV_ATTRIBUTES segment.
+#[allow(dead_code)]
+pub struct RiscvAttributesSection {
+    pub chunk: Chunk,
+    pub contents: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl RiscvAttributesSection {
+    pub fn new() -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".riscv.attributes".to_string();
+        chunk.shdr.shdr_type = SHT_RISCV_ATTRIBUTES;
+        chunk.shdr.addr_align = 1;
+
+        Self {
+            chunk,
+            contents: vec![],
+        }
+    }
+
+    // Leaves the section empty if no input has attributes.
+    pub fn construct(&mut self, ctx: &Context) -> Result<(), LinkError> {
+        let mut merged: Option<RiscvAttributes> = None;
+        for file in &ctx.objs {
+            let file = &ctx.files[*file];
+            let attrs = match &file.riscv_attributes {
+                Some(attrs) => attrs,
+                None => continue,
+            };
+            let file_name = file.input_file.file.name;
+            match &mut merged {
+                Some(merged) => {
+                    if let Err(err) = merged.merge(attrs, file_name) {
+                        ctx.error(err)?;
+                    }
+                }
+                None => merged = Some(attrs.clone()),
+            }
+        }
+
+        if let Some(merged) = merged {
+            self.contents = merged.encode();
+        }
+        self.chunk.shdr.size = self.contents.len() as u64;
+        Ok(())
+    }
+
+    pub fn copy_buf(&self, buf: &mut [u8]) {
+        let offset = self.chunk.shdr.offset as usize;
+        buf[offset..offset + self.contents.len()].copy_from_slice(&self.contents);
+    }
+}
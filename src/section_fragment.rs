@@ -1,25 +1,32 @@
-use crate::merged_section::MergedSection;
+use std::sync::atomic::AtomicBool;
+
+use crate::{arena::MergedSectionId, context::Context};
 
 #[allow(dead_code)]
 pub struct SectionFragment {
-    pub output: *mut MergedSection,
+    pub output: MergedSectionId,
     pub offset: u32,
     pub p2_align: u32,
-    pub is_alive: bool,
+    // Set by the garbage collector, which only has shared access to the
+    // fragments.
+    pub is_alive: AtomicBool,
+    // The order in which the pieces were first seen in the inputs.
+    pub idx: u32,
 }
 
 #[allow(dead_code)]
 impl SectionFragment {
-    pub fn new(m: *mut MergedSection) -> Self {
+    pub fn new(m: MergedSectionId, idx: u32) -> Self {
         SectionFragment {
             output: m,
             offset: std::u32::MAX,
             p2_align: 0,
-            is_alive: false,
+            is_alive: AtomicBool::new(false),
+            idx,
         }
     }
 
-    pub fn get_addr(&self) -> u64{
-        unsafe { self.output.as_ref().unwrap().chunk.as_ref().unwrap().shdr.addr + self.offset as u64 } 
+    pub fn get_addr(&self, ctx: &Context) -> u64 {
+        ctx.merged_sections[self.output].chunk.shdr.addr + self.offset as u64
     }
 }
@@ -0,0 +1,197 @@
+use crate::{
+    arena::DsoId,
+    context::Context,
+    elf::{
+        elf_get_name, Dyn, Sym, Verdaux, Verdef, DT_NULL, DT_SONAME, SHT_DYNAMIC,
+        SHT_DYNSYM, SHT_GNU_VERDEF, SHT_GNU_VERSYM, VERSYM_HIDDEN, VER_NDX_GLOBAL, VER_NDX_LOCAL,
+    },
+    error::LinkError,
+    file::ElfFile,
+    input_file::{new_input_file, InputFile},
+    symbol::Symbol,
+    utils::{read, try_read},
+};
+
+#[allow(dead_code)]
+pub struct SharedFile<'a> {
+    pub id: DsoId,
+    pub input_file: InputFile<'a>,
+    pub soname: String,
+    pub version_strings: Vec<String>,
+    pub versyms: Vec<u16>,
+}
+
+#[allow(dead_code)]
+pub fn new_shared_file(id: DsoId, elf_file: ElfFile) -> Result<SharedFile, LinkError> {
+    let mut input_file = new_input_file(elf_file)?;
+    input_file.is_alive = true;
+    Ok(SharedFile {
+        id,
+        input_file,
+        soname: String::new(),
+        version_strings: vec![],
+        versyms: vec![],
+    })
+}
+
+#[allow(dead_code)]
+impl<'a> SharedFile<'a> {
+    pub fn parse(&mut self, ctx: &mut Context<'a>) -> Result<(), LinkError> {
+        let input_file = &mut self.input_file;
+        let dynsym = input_file.find_section(SHT_DYNSYM);
+        if dynsym.is_none() {
+            return Ok(());
+        }
+        let dynsym = dynsym.unwrap();
+
+        input_file.first_global = Some(dynsym.info as i64);
+        input_file.fillup_elf_syms(dynsym)?;
+        input_file.symbol_strtab = Some(input_file.get_bytes_from_idx(dynsym.link as i64)?);
+
+        if let Some(shdr) = input_file.find_section(SHT_GNU_VERSYM) {
+            let bs = input_file.get_bytes_from_shdr(&shdr)?;
+            for i in 0..bs.len() / 2 {
+                self.versyms.push(read::<u16>(&bs[i * 2..]));
+            }
+        }
+
+        self.soname = self.read_soname()?;
+        self.version_strings = self.read_verdef()?;
+        self.initialize_symbols(ctx)
+    }
+
+    fn read_soname(&self) -> Result<String, LinkError> {
+        let input_file = &self.input_file;
+        if let Some(shdr) = input_file.find_section(SHT_DYNAMIC) {
+            let bs = input_file.get_bytes_from_shdr(&shdr)?;
+            let strtab = input_file.get_bytes_from_idx(shdr.link as i64)?;
+            let dyn_size = input_file.class.dyn_size();
+            for i in 0..bs.len() / dyn_size {
+                let dyn_ent: Dyn = input_file.class.read_dyn(&bs[i * dyn_size..]);
+                if dyn_ent.tag == DT_NULL {
+                    break;
+                }
+                if dyn_ent.tag == DT_SONAME {
+                    let soname = elf_get_name(strtab, dyn_ent.val as u32)
+                        .map_err(|reason| input_file.corrupted(reason))?;
+                    return Ok(String::from(soname));
+                }
+            }
+        }
+
+        let name = input_file.file.name;
+        Ok(String::from(&name[name.rfind('/').map_or(0, |i| i + 1)..]))
+    }
+
+    // Returns version names indexed by their version index. The first two
+    // entries stand for VER_NDX_LOCAL and VER_NDX_GLOBAL and are empty.
+    fn read_verdef(&self) -> Result<Vec<String>, LinkError> {
+        let mut ret = vec![String::new(); VER_NDX_GLOBAL as usize + 1];
+        let input_file = &self.input_file;
+        let shdr = input_file.find_section(SHT_GNU_VERDEF);
+        if shdr.is_none() {
+            return Ok(ret);
+        }
+
+        let bs = input_file.get_bytes_from_shdr(&shdr.unwrap())?;
+        let strtab = input_file.get_bytes_from_idx(shdr.unwrap().link as i64)?;
+        let out_of_range = || input_file.corrupted("version definition is out of range");
+        let mut offset = 0usize;
+        loop {
+            let verdef: Verdef = bs
+                .get(offset..)
+                .and_then(try_read)
+                .ok_or_else(out_of_range)?;
+            let verdaux: Verdaux = bs
+                .get(offset + verdef.aux as usize..)
+                .and_then(try_read)
+                .ok_or_else(out_of_range)?;
+            let idx = verdef.ndx as usize;
+            if ret.len() <= idx {
+                ret.resize(idx + 1, String::new());
+            }
+            let name =
+                elf_get_name(strtab, verdaux.name).map_err(|reason| input_file.corrupted(reason))?;
+            ret[idx] = String::from(name);
+
+            if verdef.next == 0 {
+                break;
+            }
+            offset += verdef.next as usize;
+        }
+        Ok(ret)
+    }
+
+    // Symbols past the end of .gnu.version, if any, are unversioned.
+    pub fn get_versym(&self, idx: usize) -> u16 {
+        self.versyms.get(idx).copied().unwrap_or(VER_NDX_GLOBAL)
+    }
+
+    // A symbol with a non-default version is only visible by its versioned
+    // name, e.g. `memcpy@GLIBC_2.2.5`. A symbol with the default version is
+    // visible both by its plain name and by its versioned name.
+    fn initialize_symbols(&mut self, ctx: &mut Context<'a>) -> Result<(), LinkError> {
+        let first_global = self.input_file.first_global.unwrap() as usize;
+        let str_tab = self.input_file.symbol_strtab.unwrap();
+
+        for i in 0..self.input_file.elf_syms.len() {
+            let esym = self.input_file.elf_syms[i];
+            let versym = self.get_versym(i);
+            let ver = versym & !VERSYM_HIDDEN;
+            if i < first_global || esym.is_undef() || ver == VER_NDX_LOCAL {
+                let sym = ctx.symbols.push(Symbol::new(""));
+                self.input_file.symbols.push(sym);
+                continue;
+            }
+
+            let name = elf_get_name(str_tab, esym.name)
+                .map_err(|reason| self.input_file.corrupted(reason))?;
+            // get_version_name is called with the index later.
+            let ver_name = self.version_strings.get(ver as usize).ok_or_else(|| {
+                self.input_file
+                    .corrupted(format!("invalid version index: {}", ver))
+            })?;
+            if versym & VERSYM_HIDDEN != 0 {
+                let key = format!("{}@{}", name, ver_name);
+                let sym = Symbol::get_symbol_by_name(ctx, &key);
+                self.input_file.symbols.push(sym);
+                continue;
+            }
+
+            let sym = Symbol::get_symbol_by_name(ctx, name);
+            if ver > VER_NDX_GLOBAL {
+                let key = format!("{}@{}", name, ver_name);
+                ctx.symbol_map.entry(key).or_insert(sym);
+            }
+            self.input_file.symbols.push(sym);
+        }
+        Ok(())
+    }
+
+    pub fn resolve_symbols(ctx: &mut Context<'a>, id: DsoId) {
+        let file = &ctx.dsos[id];
+        let input_file = &file.input_file;
+        let first_global = input_file.first_global.unwrap_or(0) as usize;
+
+        for i in first_global..input_file.elf_syms.len() {
+            let esym: Sym = input_file.elf_syms[i];
+            let versym = file.get_versym(i);
+            if esym.is_undef() || versym & !VERSYM_HIDDEN == VER_NDX_LOCAL {
+                continue;
+            }
+
+            let sym = &mut ctx.symbols[input_file.symbols[i]];
+            if sym.is_defined() || sym.shared_file.is_some() {
+                continue;
+            }
+            sym.shared_file = Some(id);
+            sym.value = esym.val;
+            sym.symidx = i as i32;
+            sym.ver_idx = versym & !VERSYM_HIDDEN;
+        }
+    }
+
+    pub fn get_version_name(&self, ver_idx: u16) -> &str {
+        &self.version_strings[ver_idx as usize]
+    }
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+
+use crate::{chunk::Chunk, elf::SHT_STRTAB};
+
+// A non-allocated string table, used for both `.strtab` and `.shstrtab`.
+#[allow(dead_code)]
+pub struct StrtabSection {
+    pub chunk: Chunk,
+    pub contents: Vec<u8>,
+    pub offsets: HashMap<String, u32>,
+}
+
+#[allow(dead_code)]
+impl StrtabSection {
+    pub fn new(name: &str) -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = name.to_string();
+        chunk.shdr.shdr_type = SHT_STRTAB;
+        chunk.shdr.size = 1;
+
+        Self {
+            chunk,
+            contents: vec![0],
+            offsets: HashMap::new(),
+        }
+    }
+
+    pub fn add_string(&mut self, s: &str) -> u32 {
+        if s.is_empty() {
+            return 0;
+        }
+        if let Some(offset) = self.offsets.get(s) {
+            return *offset;
+        }
+        let offset = self.contents.len() as u32;
+        self.contents.extend_from_slice(s.as_bytes());
+        self.contents.push(0);
+        self.offsets.insert(String::from(s), offset);
+        self.chunk.shdr.size = self.contents.len() as u64;
+        offset
+    }
+
+    pub fn copy_buf(&self, buf: &mut [u8]) {
+        let offset = self.chunk.shdr.offset as usize;
+        buf[offset..offset + self.contents.len()].copy_from_slice(&self.contents);
+    }
+}
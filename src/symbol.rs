@@ -1,89 +1,171 @@
+use std::sync::atomic::AtomicU32;
+
+use crate::arena::{DsoId, FileId, FragmentId, SectionId, SymbolId};
+use crate::chunk::ChunkId;
 use crate::context::Context;
-use crate::elf::Sym;
-use crate::section_fragment::SectionFragment;
-use crate::{input_section::InputSection, object_file::ObjectFile};
+use crate::elf::{Sym, SHN_ABS, SHN_UNDEF, STV_DEFAULT, VER_NDX_GLOBAL};
 
 pub const NEEDS_GOT_TP: u32 = 1 << 0;
+pub const NEEDS_GOT: u32 = 1 << 1;
 
 #[allow(dead_code)]
-pub struct Symbol<'a> {
-    pub object_file: Option<*mut ObjectFile<'a>>,
-    pub name: &'a str,
+pub struct Symbol {
+    pub object_file: Option<FileId>,
+    pub shared_file: Option<DsoId>,
+    pub name: String,
     pub value: u64,
     pub symidx: i32,
     pub got_tp_id: i32,
-    
-    pub input_section: Option<*mut InputSection<'a>>,
-    pub section_fragment: Option<*mut SectionFragment>,
-    
-    pub flags: u32,
+    pub got_id: i32,
+
+    pub input_section: Option<SectionId>,
+    pub section_fragment: Option<FragmentId>,
+    pub chunk: Option<ChunkId>,
+
+    pub is_synthetic: bool,
+    pub visibility: u8,
+    pub ver_idx: u16,
+    pub dynsym_idx: i32,
+    // NEEDS_* bits. Relocations are scanned on several threads.
+    pub flags: AtomicU32,
 }
 
 #[allow(dead_code)]
-impl<'a> Symbol<'a> {
-    pub fn new(name: &'static str) -> Symbol<'a> {
+impl Symbol {
+    pub fn new(name: &str) -> Symbol {
         Symbol {
-            name: name,
+            name: name.to_string(),
             value: 0,
             symidx: 0,
             got_tp_id: 0,
+            got_id: 0,
             object_file: None,
+            shared_file: None,
             input_section: None,
             section_fragment: None,
-            flags: 0,
+            chunk: None,
+            is_synthetic: false,
+            visibility: STV_DEFAULT,
+            ver_idx: VER_NDX_GLOBAL,
+            dynsym_idx: -1,
+            flags: AtomicU32::new(0),
         }
     }
 
-    pub fn get_symbol_by_name(ctx: &Context<'a>, name: &str) -> *mut Symbol<'a> {
-        ctx.symbol_map[name]
-    }
-
-    pub fn elf_sym(&self) -> Sym {
-        assert!(
-            self.symidx
-                < unsafe {
-                    (self.object_file.unwrap().as_ref().unwrap())
-                        .input_file
-                        .as_ref()
-                        .unwrap()
-                }
-                .elf_syms
-                .len() as i32
-        );
-        unsafe {
-            (self.object_file.unwrap().as_ref().unwrap())
-                .input_file
-                .as_ref()
-                .unwrap()
+    pub fn get_symbol_by_name(ctx: &mut Context, name: &str) -> SymbolId {
+        if let Some(sym) = ctx.symbol_map.get(name) {
+            return *sym;
         }
-        .elf_syms[self.symidx as usize]
+        let sym = ctx.symbols.push(Symbol::new(name));
+        ctx.symbol_map.insert(String::from(name), sym);
+        sym
+    }
+
+    pub fn is_defined(&self) -> bool {
+        self.object_file.is_some() || self.is_synthetic
     }
 
-    pub fn clear(&self) {
-        // nothing
+    pub fn is_imported(&self) -> bool {
+        self.object_file.is_none() && self.shared_file.is_some()
     }
 
-    pub fn set_input_section(&'a mut self, isec: *mut InputSection<'a>){
+    // The name without a `@VERSION` suffix.
+    pub fn get_base_name(&self) -> &str {
+        match self.name.find('@') {
+            Some(pos) => &self.name[..pos],
+            None => &self.name,
+        }
+    }
+
+    pub fn elf_sym(&self, ctx: &Context) -> Sym {
+        if self.is_imported() {
+            return ctx.dsos[self.shared_file.unwrap()].input_file.elf_syms[self.symidx as usize];
+        }
+        let input_file = &ctx.files[self.object_file.unwrap()].input_file;
+        assert!(self.symidx < input_file.elf_syms.len() as i32);
+        input_file.elf_syms[self.symidx as usize]
+    }
+
+    // Makes the symbol undefined again.
+    pub fn clear(&mut self) {
+        self.object_file = None;
+        self.input_section = None;
+        self.section_fragment = None;
+        self.value = 0;
+        self.symidx = 0;
+    }
+
+    pub fn set_input_section(&mut self, isec: SectionId) {
         self.input_section = Some(isec);
         self.section_fragment = None;
     }
 
-    pub fn set_section_fragment(&'a mut self, frag: *mut SectionFragment){
+    pub fn set_section_fragment(&mut self, frag: FragmentId) {
         self.input_section = None;
         self.section_fragment = Some(frag);
     }
 
-    pub fn get_addr(&self) -> u64 {
-        if self.section_fragment.is_none() {
-            return unsafe { self.section_fragment.unwrap().as_ref().unwrap().get_addr()  + self.value};
+    pub fn set_chunk(&mut self, chunk: ChunkId, value: u64) {
+        self.input_section = None;
+        self.section_fragment = None;
+        self.chunk = Some(chunk);
+        self.is_synthetic = true;
+        self.value = value;
+    }
+
+    pub fn set_absolute(&mut self, value: u64) {
+        self.object_file = None;
+        self.input_section = None;
+        self.section_fragment = None;
+        self.chunk = None;
+        self.is_synthetic = true;
+        self.value = value;
+    }
+
+    pub fn get_addr(&self, ctx: &Context) -> u64 {
+        if let Some(frag) = self.section_fragment {
+            return ctx.fragments[frag].get_addr(ctx) + self.value;
         };
-        if self.input_section.is_none() {
-            return unsafe { self.input_section.unwrap().as_ref().unwrap().get_addr()  + self.value};
+        if let Some(isec) = self.input_section {
+            return ctx.sections[isec].get_addr(ctx) + self.value;
         };
-        return self.value
+        if let Some(chunk) = self.chunk {
+            return ctx.chunk(chunk).shdr.addr + self.value;
+        }
+        return self.value;
+    }
+
+    // Section header index of the output chunk this symbol lives in.
+    pub fn get_output_shndx(&self, ctx: &Context) -> u16 {
+        if !self.is_defined() {
+            return SHN_UNDEF;
+        }
+        match self.get_output_chunk(ctx) {
+            Some(chunk) => ctx.chunk(chunk).shndx as u16,
+            None => SHN_ABS,
+        }
+    }
+
+    // The chunk the symbol is defined in. None for undefined and absolute
+    // symbols.
+    pub fn get_output_chunk(&self, ctx: &Context) -> Option<ChunkId> {
+        if !self.is_defined() {
+            return None;
+        }
+        if let Some(frag) = self.section_fragment {
+            Some(ChunkId::Merged(ctx.fragments[frag].output))
+        } else if let Some(isec) = self.input_section {
+            Some(ChunkId::Output(ctx.sections[isec].output_section.unwrap()))
+        } else {
+            self.chunk
+        }
     }
 
     pub fn get_got_tp_addr(&self, ctx: &Context) -> u64{
-        ctx.got.chunk.shdr.addr + (self.got_tp_id as u64 * 8u64)
+        ctx.got.chunk.shdr.addr + self.got_tp_id as u64 * ctx.got.word_size
+    }
+
+    pub fn get_got_addr(&self, ctx: &Context) -> u64 {
+        ctx.got.chunk.shdr.addr + self.got_id as u64 * ctx.got.word_size
     }
 }
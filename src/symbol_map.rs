@@ -0,0 +1,210 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    ops::Index,
+};
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    arena::{Arena, Id, SymbolId},
+    parallel::{par_for_each_mut, par_map},
+    symbol::Symbol,
+};
+
+// The global symbol table. Names are spread over a fixed number of shards
+// by their hash, and `intern` fills the shards on separate threads. The
+// number of shards doesn't depend on --threads, and new symbols are
+// numbered shard by shard in the order their names first appear in the
+// inputs, so symbol IDs are the same for any thread count.
+const NUM_SHARDS: usize = 64;
+
+pub struct SymbolMap {
+    shards: Vec<HashMap<String, SymbolId>>,
+}
+
+// What `intern` found for one name in the shard it belongs to.
+enum Interned {
+    Existing(SymbolId),
+    // The n-th name added to the shard by this call.
+    New(usize),
+}
+
+#[allow(dead_code)]
+impl SymbolMap {
+    pub fn new() -> Self {
+        SymbolMap {
+            shards: (0..NUM_SHARDS).map(|_| HashMap::new()).collect(),
+        }
+    }
+
+    fn get_shard(name: &str) -> usize {
+        (xxh3_64(name.as_bytes()) % NUM_SHARDS as u64) as usize
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SymbolId> {
+        self.shards[Self::get_shard(name)].get(name)
+    }
+
+    pub fn insert(&mut self, name: String, sym: SymbolId) -> Option<SymbolId> {
+        self.shards[Self::get_shard(&name)].insert(name, sym)
+    }
+
+    pub fn entry(&mut self, name: String) -> Entry<'_, String, SymbolId> {
+        self.shards[Self::get_shard(&name)].entry(name)
+    }
+
+    // The symbols in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &SymbolId> {
+        self.shards.iter().flat_map(|shard| shard.values())
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Looks up the names of several files at once, creating symbols for
+    // the ones not seen before, and returns their symbols file by file.
+    pub fn intern(
+        &mut self,
+        symbols: &mut Arena<SymbolId, Symbol>,
+        names: &[Vec<String>],
+        num_threads: usize,
+    ) -> Vec<Vec<SymbolId>> {
+        let shard_idxs: Vec<Vec<usize>> = par_map(num_threads, names, |names| {
+            names.iter().map(|name| Self::get_shard(name)).collect()
+        });
+
+        // The names of each shard, as (file, index) pairs in input order.
+        let mut buckets: Vec<Vec<(usize, usize)>> = vec![vec![]; NUM_SHARDS];
+        for (file, shard_idxs) in shard_idxs.iter().enumerate() {
+            for (i, shard_idx) in shard_idxs.iter().enumerate() {
+                buckets[*shard_idx].push((file, i));
+            }
+        }
+
+        struct ShardWork<'a, 'b> {
+            idx: usize,
+            shard: &'a mut HashMap<String, SymbolId>,
+            names: Vec<(usize, usize)>,
+            new_names: Vec<&'b str>,
+            found: Vec<(usize, usize, Interned)>,
+        }
+        let mut work: Vec<ShardWork> = self
+            .shards
+            .iter_mut()
+            .zip(buckets)
+            .enumerate()
+            .map(|(idx, (shard, names))| ShardWork {
+                idx,
+                shard,
+                names,
+                new_names: vec![],
+                found: vec![],
+            })
+            .collect();
+
+        par_for_each_mut(num_threads, &mut work, |w| {
+            let mut new_idxs: HashMap<&str, usize> = HashMap::new();
+            for &(file, i) in &w.names {
+                let name = names[file][i].as_str();
+                let interned = match w.shard.get(name) {
+                    Some(sym) => Interned::Existing(*sym),
+                    None => Interned::New(*new_idxs.entry(name).or_insert_with(|| {
+                        w.new_names.push(name);
+                        w.new_names.len() - 1
+                    })),
+                };
+                w.found.push((file, i, interned));
+            }
+        });
+
+        let mut bases = vec![0; NUM_SHARDS];
+        for w in &work {
+            bases[w.idx] = symbols.len();
+            for name in &w.new_names {
+                symbols.push(Symbol::new(name));
+            }
+        }
+
+        par_for_each_mut(num_threads, &mut work, |w| {
+            let base = bases[w.idx];
+            for (i, name) in w.new_names.iter().enumerate() {
+                w.shard
+                    .insert(name.to_string(), SymbolId::from_index(base + i));
+            }
+        });
+
+        let mut ret: Vec<Vec<SymbolId>> = names
+            .iter()
+            .map(|names| vec![SymbolId::from_index(0); names.len()])
+            .collect();
+        for w in &work {
+            for (file, i, interned) in &w.found {
+                ret[*file][*i] = match interned {
+                    Interned::Existing(sym) => *sym,
+                    Interned::New(n) => SymbolId::from_index(bases[w.idx] + n),
+                };
+            }
+        }
+        ret
+    }
+}
+
+impl Default for SymbolMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index<&str> for SymbolMap {
+    type Output = SymbolId;
+
+    fn index(&self, name: &str) -> &SymbolId {
+        self.get(name).expect("symbol not found")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let to_names = |names: &[&str]| names.iter().map(|s| s.to_string()).collect();
+        let names: Vec<Vec<String>> = vec![
+            to_names(&["main", "printf", "foo"]),
+            to_names(&["foo", "bar"]),
+            to_names(&["bar", "printf", "baz"]),
+        ];
+
+        let intern = |num_threads| {
+            let mut map = SymbolMap::new();
+            let mut symbols: Arena<SymbolId, Symbol> = Arena::new();
+            let exit = symbols.push(Symbol::new("exit"));
+            map.insert("exit".to_string(), exit);
+            let mut names = names.clone();
+            names[2].push("exit".to_string());
+
+            let syms = map.intern(&mut symbols, &names, num_threads);
+            assert_eq!(symbols.len(), 6);
+            assert_eq!(map.len(), 6);
+            for (names, syms) in names.iter().zip(&syms) {
+                for (name, sym) in names.iter().zip(syms) {
+                    assert_eq!(&symbols[*sym].name, name);
+                    assert_eq!(map[name.as_str()], *sym);
+                }
+            }
+            assert_eq!(syms[2][3], exit);
+            syms
+        };
+
+        let syms = intern(1);
+        for num_threads in [2, 4, 64] {
+            assert_eq!(intern(num_threads), syms);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{
+    arena::{FileId, SymbolId},
+    chunk::Chunk,
+    context::Context,
+    elf::{
+        elf_get_name, Sym, SHN_ABS, SHN_LORESERVE, SHN_XINDEX, SHT_SYMTAB, STB_GLOBAL, STT_NOTYPE,
+        STT_SECTION,
+    },
+    elf_class::ElfClass,
+    object_file::ObjectFile,
+    strtab_section::StrtabSection,
+    utils::write,
+};
+
+// `.symtab` lists the local symbols of every live object file followed by
+// all global symbols, as ELF requires locals to come first. `sh_info` is
+// the index of the first global.
+#[allow(dead_code)]
+pub struct SymtabSection {
+    pub chunk: Chunk,
+    pub locals: Vec<(FileId, usize)>,
+    pub globals: Vec<SymbolId>,
+    pub name_offsets: Vec<u32>,
+}
+
+#[allow(dead_code)]
+impl SymtabSection {
+    pub fn new(class: ElfClass) -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".symtab".to_string();
+        chunk.shdr.shdr_type = SHT_SYMTAB;
+        chunk.shdr.addr_align = class.word_size() as u64;
+        chunk.shdr.ent_size = class.sym_size() as u64;
+
+        Self {
+            chunk,
+            locals: vec![],
+            globals: vec![],
+            name_offsets: vec![],
+        }
+    }
+
+    fn is_local_live(ctx: &Context, file: &ObjectFile, idx: usize) -> bool {
+        let input_file = &file.input_file;
+        let esym = input_file.elf_syms[idx];
+        if esym.st_type() == STT_SECTION || esym.is_undef() {
+            return false;
+        }
+
+        let sym = &ctx.symbols[input_file.symbols[idx]];
+        if sym.section_fragment.is_some() || esym.is_abs() {
+            return true;
+        }
+        file.get_symbol_section(ctx, idx)
+            .is_some_and(|isec| ctx.sections[isec].is_alive)
+    }
+
+    pub fn construct(&mut self, ctx: &Context, strtab: &mut StrtabSection) {
+        for file in &ctx.objs {
+            let obj = &ctx.files[*file];
+            let input_file = &obj.input_file;
+            let str_tab = input_file.symbol_strtab.unwrap_or(&[]);
+            let first_global = input_file.first_global.unwrap_or(0) as usize;
+
+            for i in 1..first_global {
+                if !Self::is_local_live(ctx, obj, i) {
+                    continue;
+                }
+                // Checked by ObjectFile::initialize_symbols.
+                let name = elf_get_name(str_tab, input_file.elf_syms[i].name).unwrap_or_default();
+                if ctx.args.discard_locals && name.starts_with(".L") {
+                    continue;
+                }
+                self.locals.push((*file, i));
+                self.name_offsets.push(strtab.add_string(name));
+            }
+        }
+
+        for file in &ctx.objs {
+            let input_file = &ctx.files[*file].input_file;
+            let first_global = input_file.first_global.unwrap_or(0) as usize;
+
+            for i in first_global..input_file.elf_syms.len() {
+                let sym = input_file.symbols[i];
+                if ctx.symbols[sym].object_file != Some(*file) {
+                    continue;
+                }
+                self.add_global(ctx, sym, strtab);
+            }
+        }
+
+        // Symbols the linker defines itself, such as __start_ symbols and
+        // --defsym, sorted by name to keep the output stable.
+        let mut synthetic: Vec<SymbolId> = vec![];
+        for sym in ctx.symbol_map.values() {
+            let sym_ref = &ctx.symbols[*sym];
+            if sym_ref.is_synthetic && sym_ref.object_file.is_none() {
+                synthetic.push(*sym);
+            }
+        }
+        synthetic.sort_by_key(|sym| &ctx.symbols[*sym].name);
+        for sym in synthetic {
+            self.add_global(ctx, sym, strtab);
+        }
+
+        let chunk = &mut self.chunk;
+        chunk.shdr.info = self.locals.len() as u32 + 1;
+        chunk.shdr.size = (self.locals.len() + self.globals.len() + 1) as u64 * chunk.shdr.ent_size;
+    }
+
+    fn add_global(&mut self, ctx: &Context, sym: SymbolId, strtab: &mut StrtabSection) {
+        self.globals.push(sym);
+        self.name_offsets
+            .push(strtab.add_string(&ctx.symbols[sym].name));
+    }
+
+    pub fn copy_buf(&self, ctx: &Context, buf: &mut [u8]) {
+        let class = ctx.elf_class();
+        let base = self.chunk.shdr.offset as usize;
+        class.write_sym(&mut buf[base..], Sym::new());
+        if let Some(symtab_shndx) = &ctx.symtab_shndx {
+            let offset = symtab_shndx.chunk.shdr.offset as usize;
+            write::<u32>(&mut buf[offset..offset + 4], 0);
+        }
+
+        for (i, (file, idx)) in self.locals.iter().enumerate() {
+            let file = &ctx.files[*file];
+            let input_file = &file.input_file;
+            let esym = input_file.elf_syms[*idx];
+            let sym = &ctx.symbols[input_file.symbols[*idx]];
+
+            let mut out = esym;
+            out.name = self.name_offsets[i];
+            out.val = file.get_symbol_addr(ctx, *idx);
+            let shndx = if sym.section_fragment.is_some() {
+                sym.get_output_chunk(ctx)
+                    .map(|chunk| ctx.chunk(chunk).shndx)
+            } else if esym.is_abs() {
+                out.shndx = SHN_ABS;
+                None
+            } else {
+                let isec = &ctx.sections[file.get_symbol_section(ctx, *idx).unwrap()];
+                let osec = &ctx.output_sections[isec.output_section.unwrap()];
+                Some(osec.chunk.shndx)
+            };
+            self.write_sym(ctx, buf, i + 1, out, shndx);
+        }
+
+        for (i, sym) in self.globals.iter().enumerate() {
+            let sym = &ctx.symbols[*sym];
+            let mut out = if sym.object_file.is_some() {
+                sym.elf_sym(ctx)
+            } else {
+                let mut esym = Sym::new();
+                esym.info = STB_GLOBAL << 4 | STT_NOTYPE;
+                esym.other = sym.visibility;
+                esym
+            };
+            out.name = self.name_offsets[self.locals.len() + i];
+            out.shndx = sym.get_output_shndx(ctx);
+            out.val = sym.get_addr(ctx);
+            let shndx = sym
+                .get_output_chunk(ctx)
+                .map(|chunk| ctx.chunk(chunk).shndx);
+            self.write_sym(ctx, buf, self.locals.len() + i + 1, out, shndx);
+        }
+    }
+
+    // Writes entry `i`. `shndx` is the index of the section the symbol is
+    // in, if any; st_shndx is SHN_XINDEX for one that does not fit, and the
+    // index goes to .symtab_shndx.
+    fn write_sym(&self, ctx: &Context, buf: &mut [u8], i: usize, mut out: Sym, shndx: Option<i64>) {
+        let mut xindex = 0;
+        if let Some(shndx) = shndx {
+            if shndx >= SHN_LORESERVE as i64 {
+                out.shndx = SHN_XINDEX;
+                xindex = shndx as u32;
+            } else {
+                out.shndx = shndx as u16;
+            }
+        }
+
+        let class = ctx.elf_class();
+        let offset = self.chunk.shdr.offset as usize + i * class.sym_size();
+        class.write_sym(&mut buf[offset..], out);
+        if let Some(symtab_shndx) = &ctx.symtab_shndx {
+            let offset = symtab_shndx.chunk.shdr.offset as usize + i * 4;
+            write::<u32>(&mut buf[offset..offset + 4], xindex);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk::ChunkId,
+        elf::{SHF_ALLOC, SHT_PROGBITS},
+        output_section::OutputSection,
+        symbol::Symbol,
+        symtab_shndx_section::SymtabShndxSection,
+    };
+
+    #[test]
+    fn test_symtab_xindex() {
+        let mut ctx = Context::new();
+        let class = ctx.elf_class();
+        let mut strtab = StrtabSection::new(".strtab");
+        let mut symtab = SymtabSection::new(class);
+        for (name, idx) in [("low", 1), ("high", 70000)] {
+            let osec = ctx.output_sections.push(OutputSection::new(
+                format!(".{}", name),
+                SHT_PROGBITS,
+                SHF_ALLOC,
+            ));
+            ctx.output_sections[osec].chunk.shndx = idx;
+            let sym = Symbol::get_symbol_by_name(&mut ctx, name);
+            ctx.symbols[sym].set_chunk(ChunkId::Output(osec), 0);
+            symtab.add_global(&ctx, sym, &mut strtab);
+        }
+        symtab.chunk.shdr.size = 3 * class.sym_size() as u64;
+        let mut symtab_shndx = SymtabShndxSection::new(&symtab);
+        symtab_shndx.chunk.shdr.offset = symtab.chunk.shdr.size;
+        let size = symtab.chunk.shdr.size + symtab_shndx.chunk.shdr.size;
+        ctx.symtab_shndx = Some(symtab_shndx);
+
+        // Entries not written are left as 0xff.
+        let mut buf = vec![0xff; size as usize];
+        symtab.copy_buf(&ctx, &mut buf);
+        let sym = |i: usize| class.read_sym(&buf[i * class.sym_size()..]);
+        let xindex = |i: usize| {
+            let offset = symtab.chunk.shdr.size as usize + i * 4;
+            u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+        };
+        assert_eq!((sym(1).shndx, xindex(1)), (1, 0));
+        assert_eq!((sym(2).shndx, xindex(2)), (SHN_XINDEX, 70000));
+        assert_eq!(xindex(0), 0);
+    }
+}
@@ -0,0 +1,22 @@
+use crate::{chunk::Chunk, elf::SHT_SYMTAB_SHNDX, symtab_section::SymtabSection};
+
+// `.symtab_shndx` holds the section index of every `.symtab` entry whose
+// st_shndx is SHN_XINDEX, and 0 for the others. It is only created when
+// the output has SHN_LORESERVE sections or more. `.symtab` writes it.
+#[allow(dead_code)]
+pub struct SymtabShndxSection {
+    pub chunk: Chunk,
+}
+
+#[allow(dead_code)]
+impl SymtabShndxSection {
+    pub fn new(symtab: &SymtabSection) -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".symtab_shndx".to_string();
+        chunk.shdr.shdr_type = SHT_SYMTAB_SHNDX;
+        chunk.shdr.addr_align = 4;
+        chunk.shdr.ent_size = 4;
+        chunk.shdr.size = symtab.chunk.shdr.size / symtab.chunk.shdr.ent_size * 4;
+        Self { chunk }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{
+    context::Context,
+    elf::{Phdr, Rela},
+    elf_class::ElfClass,
+    error::LinkError,
+    input_section::InputSection,
+    machine_type::{MachineType, MACHINE_TYPE_RISCV32, MACHINE_TYPE_RISCV64, MACHINE_TYPE_X86_64},
+    object_file::ObjectFile,
+    riscv::{RISCV32, RISCV64},
+    x86_64::X86_64,
+};
+
+// Everything about the output that depends on the architecture. The passes
+// are written against this trait, and `Context::target` picks the
+// implementation for the emulation. Only static executables are produced,
+// so there is no PLT, and the GOT only holds addresses and TP offsets.
+#[allow(dead_code)]
+pub trait Target: Sync {
+    fn machine_type(&self) -> MachineType;
+
+    fn e_machine(&self) -> u16;
+
+    fn class(&self) -> ElfClass;
+
+    fn page_size(&self) -> u64 {
+        4096
+    }
+
+    // The address of the first allocated chunk.
+    fn image_base(&self) -> u64;
+
+    fn got_entry_size(&self) -> u64 {
+        self.class().word_size() as u64
+    }
+
+    // The thread pointer value for the given PT_TLS segment.
+    fn get_tp_addr(&self, tls: &Phdr) -> u64;
+
+    // The e_flags of the output, merged from those of the input objects.
+    fn merge_flags<'a>(&self, objs: &[&ObjectFile<'a>]) -> Result<u32, LinkError>;
+
+    // Sets the NEEDS_* flags of the symbols the relocations of an allocated
+    // section refer to, so that their GOT entries can be created.
+    fn scan_relocations(&self, ctx: &Context, isec: &InputSection);
+
+    // Relocation errors are reported with `Context::error`, so these only
+    // fail once --error-limit is reached.
+    fn apply_reloc_alloc<'a>(
+        &self,
+        ctx: &Context<'a>,
+        isec: &InputSection<'a>,
+        base: &mut [u8],
+    ) -> Result<(), LinkError>;
+
+    fn apply_reloc_nonalloc<'a>(
+        &self,
+        ctx: &Context<'a>,
+        isec: &InputSection<'a>,
+        base: &mut [u8],
+    ) -> Result<(), LinkError>;
+
+    // Applies a relocation of a CIE or FDE copied to `.eh_frame`, where
+    // `s` is the symbol address and `p` the address of `loc`. Returns
+    // false for a type that cannot appear there.
+    fn apply_eh_reloc(&self, loc: &mut [u8], rel: &Rela, s: u64, p: u64) -> bool;
+}
+
+#[allow(dead_code)]
+pub fn get_target(machine_type: MachineType) -> Option<&'static dyn Target> {
+    match machine_type {
+        MACHINE_TYPE_RISCV64 => Some(&RISCV64),
+        MACHINE_TYPE_RISCV32 => Some(&RISCV32),
+        MACHINE_TYPE_X86_64 => Some(&X86_64),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_get_target() {
+    use crate::{
+        elf::{EM_RISCV, EM_X86_64},
+        machine_type::MACHINE_TYPE_NONE,
+    };
+
+    let target = get_target(MACHINE_TYPE_RISCV32).unwrap();
+    assert_eq!(
+        (target.e_machine(), target.class(), target.got_entry_size()),
+        (EM_RISCV, ElfClass::Elf32, 4)
+    );
+    let target = get_target(MACHINE_TYPE_X86_64).unwrap();
+    assert_eq!(
+        (target.e_machine(), target.class(), target.got_entry_size()),
+        (EM_X86_64, ElfClass::Elf64, 8)
+    );
+    assert!(get_target(MACHINE_TYPE_NONE).is_none());
+}
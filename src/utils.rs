@@ -1,10 +1,14 @@
 use std::{mem, process::exit, slice, usize};
 
-pub fn fatal(v: &str) {
-    println!("rvld: fatal: {:?}", v);
+pub fn fatal(v: &str) -> ! {
+    eprintln!("rvld: fatal: {:?}", v);
     exit(1);
 }
 
+pub fn warn(v: &str) {
+    eprintln!("rvld: warning: {}", v);
+}
+
 #[allow(dead_code)]
 fn must_no(err: &str) {
     if err == "nil" {
@@ -19,18 +23,35 @@ pub fn assert(con: bool) {
     }
 }
 
+/// Types that can be read from raw bytes.
+///
+/// # Safety
+///
+/// Any byte pattern must be a valid value of the type, as it is for
+/// integers and #[repr(C)] structs made only of them.
+pub unsafe trait Pod: Copy {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for i64 {}
+
+// Reads a T from the start of `data`, or None if `data` is too short.
 #[allow(dead_code)]
-pub fn read<T: Copy>(data: &[u8]) -> T {
-    let mut v: Vec<u8> = vec![];
-    for i in 0..mem::size_of::<T>() {
-        v.push(data[i])
+pub fn try_read<T: Pod>(data: &[u8]) -> Option<T> {
+    if data.len() < mem::size_of::<T>() {
+        return None;
     }
-    return unsafe { v.align_to::<T>() }.1[0];
+    Some(unsafe { (data.as_ptr() as *const T).read_unaligned() })
 }
 
+// Like try_read, for data whose length is known to be sufficient, such as
+// the output buffer.
 #[allow(dead_code)]
-pub fn read_v2<T: Copy>(data: &[u8]) -> T {
-    return unsafe { *(data.as_ptr() as *const T) };
+pub fn read<T: Pod>(data: &[u8]) -> T {
+    try_read(data).expect("read out of bounds")
 }
 
 #[allow(dead_code)]
@@ -80,21 +101,6 @@ pub fn align_to(val: u64, align: u64) -> u64 {
     return (val + align - 1) & !(align - 1);
 }
 
-#[allow(dead_code)]
-pub fn read_slice<T>(mut data: &mut [u8], sz: usize) -> Vec<T>
-where
-    T: Copy,
-{
-    let nums = data.len() / sz;
-    let mut res: Vec<T> = vec![];
-
-    for _i in 0..nums {
-        res.push(read::<T>(data));
-        data = &mut data[sz..];
-    }
-    res
-}
-
 #[allow(dead_code)]
 fn has_single_bit(n: u64) -> bool {
     n & (n - 1) == 0
@@ -120,13 +126,91 @@ pub fn bits(val: u32, hi: usize, lo: usize) -> u32 {
 
 #[allow(dead_code)]
 pub fn sign_extend(val: u64, size: i32) -> u64 {
-    val << (63 - size) >> (63 - size)
+    ((val << (63 - size)) as i64 >> (63 - size)) as u64
+}
+
+#[allow(dead_code)]
+pub fn is_c_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Parses a decimal or 0x-prefixed hexadecimal number.
+#[allow(dead_code)]
+pub fn parse_number(s: &str) -> Option<u64> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return u64::from_str_radix(hex, 16).ok();
+    }
+    s.parse::<u64>().ok()
+}
+
+#[allow(dead_code)]
+pub fn read_uleb(buf: &[u8]) -> u64 {
+    let mut val = 0u64;
+    let mut shift = 0;
+    for b in buf {
+        val |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    val
+}
+
+#[allow(dead_code)]
+pub fn encode_uleb(buf: &mut Vec<u8>, mut val: u64) {
+    loop {
+        let b = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            buf.push(b);
+            return;
+        }
+        buf.push(b | 0x80);
+    }
+}
+
+// Writes `val` over an existing ULEB128 number, keeping its length. Bits
+// that don't fit are dropped.
+#[allow(dead_code)]
+pub fn overwrite_uleb(buf: &mut [u8], mut val: u64) {
+    let mut i = 0;
+    while buf[i] & 0x80 != 0 {
+        buf[i] = 0x80 | (val & 0x7f) as u8;
+        val >>= 7;
+        i += 1;
+    }
+    buf[i] = (val & 0x7f) as u8;
+}
+
+#[test]
+fn test_is_c_identifier() {
+    assert!(is_c_identifier("registry"));
+    assert!(is_c_identifier("_foo1"));
+    assert!(!is_c_identifier(".text"));
+    assert!(!is_c_identifier("1abc"));
+    assert!(!is_c_identifier(""));
 }
 
 #[test]
 fn test_remove_prefix() {
     let s = "1234567890".to_string();
     let prefix = "123456";
-    let r = remove_prefix(&s, prefix);
-    println!("{:?}", r);
+    assert_eq!(remove_prefix(&s, prefix), ("7890".to_string(), true));
+    assert_eq!(remove_prefix(&s, "0"), (s.clone(), false));
+}
+
+#[test]
+fn test_overwrite_uleb() {
+    // 0x10 padded to three bytes, as emitted by assemblers.
+    let mut buf = [0x90, 0x80, 0x00, 0xff];
+    assert_eq!(read_uleb(&buf), 0x10);
+    overwrite_uleb(&mut buf, 0x1234);
+    assert_eq!(buf, [0xb4, 0xa4, 0x00, 0xff]);
+    assert_eq!(read_uleb(&buf), 0x1234);
 }
@@ -0,0 +1,102 @@
+use crate::{
+    chunk::Chunk,
+    context::Context,
+    dynsym_section::DynstrSection,
+    elf::{
+        elf_hash, Verdaux, Verdef, SHF_ALLOC, SHT_GNU_VERDEF, VERDAUX_SIZE, VERDEF_SIZE,
+        VER_FLG_BASE, VER_NDX_GLOBAL, VER_NDX_LAST_RESERVED,
+    },
+    utils::write,
+};
+
+#[allow(dead_code)]
+pub struct VerdefSection {
+    pub chunk: Chunk,
+    pub contents: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl VerdefSection {
+    pub fn new() -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".gnu.version_d".to_string();
+        chunk.shdr.shdr_type = SHT_GNU_VERDEF;
+        chunk.shdr.flags = SHF_ALLOC;
+        chunk.shdr.addr_align = 8;
+
+        Self {
+            chunk,
+            contents: vec![],
+        }
+    }
+
+    // The first entry names the output file itself and is flagged as the
+    // base version. The others follow the named nodes of the version script
+    // in order; a node that inherits from another one gets a second
+    // auxiliary entry naming its parent.
+    pub fn construct(&mut self, ctx: &Context, dynstr: &mut DynstrSection) {
+        let mut defs: Vec<(u16, String, Option<String>)> =
+            vec![(VER_NDX_GLOBAL, ctx.get_soname(), None)];
+        for (i, name) in ctx.version_definitions.iter().enumerate() {
+            let idx = i as u16 + VER_NDX_LAST_RESERVED + 1;
+            let parent = ctx
+                .args
+                .version_script
+                .nodes
+                .iter()
+                .find(|node| &node.name == name)
+                .and_then(|node| node.parent.clone());
+            defs.push((idx, name.clone(), parent));
+        }
+
+        self.contents = vec![];
+        for (i, (idx, name, parent)) in defs.iter().enumerate() {
+            let mut verdef = Verdef {
+                version: 1,
+                flags: 0,
+                ndx: *idx,
+                cnt: 1,
+                hash: elf_hash(name),
+                aux: VERDEF_SIZE as u32,
+                next: 0,
+            };
+            if i == 0 {
+                verdef.flags = VER_FLG_BASE;
+            }
+            if parent.is_some() {
+                verdef.cnt = 2;
+            }
+            if i + 1 < defs.len() {
+                verdef.next = (VERDEF_SIZE + verdef.cnt as usize * VERDAUX_SIZE) as u32;
+            }
+
+            let mut buf = vec![0u8; VERDEF_SIZE + verdef.cnt as usize * VERDAUX_SIZE];
+            write(&mut buf[..VERDEF_SIZE], verdef);
+
+            let mut verdaux = Verdaux {
+                name: dynstr.add_string(name),
+                next: 0,
+            };
+            if let Some(parent) = parent {
+                verdaux.next = VERDAUX_SIZE as u32;
+                let parent_aux = Verdaux {
+                    name: dynstr.add_string(parent),
+                    next: 0,
+                };
+                let off = VERDEF_SIZE + VERDAUX_SIZE;
+                write(&mut buf[off..off + VERDAUX_SIZE], parent_aux);
+            }
+            write(&mut buf[VERDEF_SIZE..VERDEF_SIZE + VERDAUX_SIZE], verdaux);
+            self.contents.append(&mut buf);
+        }
+
+        let chunk = &mut self.chunk;
+        chunk.shdr.size = self.contents.len() as u64;
+        chunk.shdr.info = defs.len() as u32;
+    }
+
+    pub fn copy_buf(&self, buf: &mut [u8]) {
+        let offset = self.chunk.shdr.offset as usize;
+        buf[offset..offset + self.contents.len()].copy_from_slice(&self.contents);
+    }
+}
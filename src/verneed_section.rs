@@ -0,0 +1,115 @@
+use crate::{
+    arena::DsoId,
+    chunk::Chunk,
+    context::Context,
+    dynsym_section::DynstrSection,
+    elf::{
+        elf_hash, Verneed, Vernaux, SHF_ALLOC, SHT_GNU_VERNEED, VERNAUX_SIZE, VERNEED_SIZE,
+        VER_NDX_GLOBAL, VER_NDX_LAST_RESERVED,
+    },
+    utils::write,
+};
+
+#[allow(dead_code)]
+pub struct VerneedSection {
+    pub chunk: Chunk,
+    pub contents: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl VerneedSection {
+    pub fn new() -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".gnu.version_r".to_string();
+        chunk.shdr.shdr_type = SHT_GNU_VERNEED;
+        chunk.shdr.flags = SHF_ALLOC;
+        chunk.shdr.addr_align = 8;
+
+        Self {
+            chunk,
+            contents: vec![],
+        }
+    }
+
+    // Groups imported symbols by the library and version they come from and
+    // gives each (library, version) pair a fresh version index following
+    // the ones used by .gnu.version_d. The symbols' `ver_idx` is rewritten
+    // to that index so that .gnu.version refers to our own numbering.
+    pub fn construct(&mut self, ctx: &mut Context, dynstr: &mut DynstrSection) {
+        let dynsym = ctx.dynsym.as_ref().unwrap();
+
+        let mut files: Vec<(DsoId, Vec<(u16, u16)>)> = vec![];
+        let mut ver_idx = VER_NDX_LAST_RESERVED + ctx.version_definitions.len() as u16 + 1;
+
+        for sym in &dynsym.symbols {
+            let sym = &mut ctx.symbols[*sym];
+            if !sym.is_imported() || sym.ver_idx <= VER_NDX_GLOBAL {
+                continue;
+            }
+
+            let file = sym.shared_file.unwrap();
+            let pos = match files.iter().position(|(f, _)| *f == file) {
+                Some(pos) => pos,
+                None => {
+                    files.push((file, vec![]));
+                    files.len() - 1
+                }
+            };
+
+            let vers = &mut files[pos].1;
+            match vers.iter().find(|(orig, _)| *orig == sym.ver_idx) {
+                Some((_, new)) => sym.ver_idx = *new,
+                None => {
+                    vers.push((sym.ver_idx, ver_idx));
+                    sym.ver_idx = ver_idx;
+                    ver_idx += 1;
+                }
+            }
+        }
+
+        self.contents = vec![];
+        for (i, (file, vers)) in files.iter().enumerate() {
+            let file = &ctx.dsos[*file];
+            let size = VERNEED_SIZE + vers.len() * VERNAUX_SIZE;
+            let mut buf = vec![0u8; size];
+
+            let mut verneed = Verneed {
+                version: 1,
+                cnt: vers.len() as u16,
+                file: dynstr.add_string(&file.soname),
+                aux: VERNEED_SIZE as u32,
+                next: 0,
+            };
+            if i + 1 < files.len() {
+                verneed.next = size as u32;
+            }
+            write(&mut buf[..VERNEED_SIZE], verneed);
+
+            for (j, (orig, new)) in vers.iter().enumerate() {
+                let name = file.get_version_name(*orig);
+                let mut vernaux = Vernaux {
+                    hash: elf_hash(name),
+                    flags: 0,
+                    other: *new,
+                    name: dynstr.add_string(name),
+                    next: 0,
+                };
+                if j + 1 < vers.len() {
+                    vernaux.next = VERNAUX_SIZE as u32;
+                }
+                let off = VERNEED_SIZE + j * VERNAUX_SIZE;
+                write(&mut buf[off..off + VERNAUX_SIZE], vernaux);
+            }
+            self.contents.append(&mut buf);
+        }
+
+        let chunk = &mut self.chunk;
+        chunk.shdr.size = self.contents.len() as u64;
+        chunk.shdr.info = files.len() as u32;
+    }
+
+    pub fn copy_buf(&self, buf: &mut [u8]) {
+        let offset = self.chunk.shdr.offset as usize;
+        buf[offset..offset + self.contents.len()].copy_from_slice(&self.contents);
+    }
+}
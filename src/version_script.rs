@@ -0,0 +1,317 @@
+use std::fs;
+
+use crate::error::LinkError;
+
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+pub struct VersionNode {
+    pub name: String,
+    pub parent: Option<String>,
+    pub globals: Vec<String>,
+    pub locals: Vec<String>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+pub struct VersionScript {
+    pub nodes: Vec<VersionNode>,
+}
+
+fn tokenize(script: &str) -> Result<Vec<String>, LinkError> {
+    let mut tokens = vec![];
+    let mut s = script;
+
+    loop {
+        s = s.trim_start();
+        if s.is_empty() {
+            break;
+        }
+
+        if s.starts_with('#') {
+            s = &s[s.find('\n').unwrap_or(s.len())..];
+            continue;
+        }
+        if let Some(rest) = s.strip_prefix("/*") {
+            match rest.find("*/") {
+                Some(end) => s = &rest[end + 2..],
+                None => return Err(bad_script("unterminated comment")),
+            }
+            continue;
+        }
+        if let Some(rest) = s.strip_prefix('"') {
+            match rest.find('"') {
+                Some(end) => {
+                    tokens.push(String::from(&rest[..end]));
+                    s = &rest[end + 1..];
+                }
+                None => return Err(bad_script("unterminated string")),
+            }
+            continue;
+        }
+        if s.starts_with(|c: char| "{};:".contains(c)) {
+            tokens.push(String::from(&s[..1]));
+            s = &s[1..];
+            continue;
+        }
+
+        let end = s
+            .find(|c: char| c.is_whitespace() || "{};:\"".contains(c))
+            .unwrap_or(s.len());
+        tokens.push(String::from(&s[..end]));
+        s = &s[end..];
+    }
+    Ok(tokens)
+}
+
+fn bad_script(reason: impl Into<String>) -> LinkError {
+    LinkError::BadVersionScript(reason.into())
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &str {
+        if self.pos < self.tokens.len() {
+            return &self.tokens[self.pos];
+        }
+        ""
+    }
+
+    fn next(&mut self) -> Result<String, LinkError> {
+        if self.pos >= self.tokens.len() {
+            return Err(bad_script("unexpected end of file"));
+        }
+        self.pos += 1;
+        Ok(self.tokens[self.pos - 1].clone())
+    }
+
+    fn skip(&mut self, tok: &str) -> Result<(), LinkError> {
+        let t = self.next()?;
+        if t != tok {
+            return Err(bad_script(format!("expected '{}', but got '{}'", tok, t)));
+        }
+        Ok(())
+    }
+
+    fn parse_patterns(
+        &mut self,
+        node: &mut VersionNode,
+        is_global: &mut bool,
+    ) -> Result<(), LinkError> {
+        while self.peek() != "}" {
+            let tok = self.next()?;
+
+            if (tok == "global" || tok == "local") && self.peek() == ":" {
+                self.skip(":")?;
+                *is_global = tok == "global";
+                continue;
+            }
+
+            // Patterns in an `extern "C++"` block are meant to be matched
+            // against demangled names. We have no demangler, so they are
+            // matched against the raw symbol names instead.
+            if tok == "extern" {
+                self.next()?;
+                self.skip("{")?;
+                self.parse_patterns(node, is_global)?;
+                self.skip("}")?;
+                if self.peek() == ";" {
+                    self.skip(";")?;
+                }
+                continue;
+            }
+
+            if *is_global {
+                node.globals.push(tok);
+            } else {
+                node.locals.push(tok);
+            }
+            self.skip(";")?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(dead_code)]
+pub fn parse_version_script(script: &str) -> Result<VersionScript, LinkError> {
+    let mut p = Parser {
+        tokens: tokenize(script)?,
+        pos: 0,
+    };
+    let mut vs = VersionScript::default();
+
+    while p.pos < p.tokens.len() {
+        let mut node = VersionNode::default();
+        if p.peek() != "{" {
+            node.name = p.next()?;
+        }
+
+        p.skip("{")?;
+        let mut is_global = true;
+        p.parse_patterns(&mut node, &mut is_global)?;
+        p.skip("}")?;
+
+        if p.peek() != ";" {
+            node.parent = Some(p.next()?);
+        }
+        p.skip(";")?;
+        vs.nodes.push(node);
+    }
+
+    for node in &vs.nodes {
+        if let Some(parent) = &node.parent {
+            if vs.find_node(parent).is_none() {
+                return Err(bad_script(format!("undefined version: {}", parent)));
+            }
+        }
+    }
+    Ok(vs)
+}
+
+#[allow(dead_code)]
+pub fn read_version_script(path: &str) -> Result<VersionScript, LinkError> {
+    match fs::read_to_string(path) {
+        Ok(script) => parse_version_script(&script),
+        Err(error) => Err(LinkError::Io {
+            path: path.to_string(),
+            error,
+        }),
+    }
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+#[allow(dead_code)]
+pub fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    if pattern.is_empty() {
+        return s.is_empty();
+    }
+
+    match pattern[0] {
+        b'*' => (0..=s.len()).any(|i| glob_match(&pattern[1..], &s[i..])),
+        b'?' => !s.is_empty() && glob_match(&pattern[1..], &s[1..]),
+        b'[' => {
+            let end = pattern.iter().skip(2).position(|c| *c == b']');
+            if end.is_none() || s.is_empty() {
+                return false;
+            }
+            let end = end.unwrap() + 2;
+            let mut class = &pattern[1..end];
+            let negate = class[0] == b'!' || class[0] == b'^';
+            if negate {
+                class = &class[1..];
+            }
+
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    matched |= class[i] <= s[0] && s[0] <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == s[0];
+                    i += 1;
+                }
+            }
+            matched != negate && glob_match(&pattern[end + 1..], &s[1..])
+        }
+        c => !s.is_empty() && c == s[0] && glob_match(&pattern[1..], &s[1..]),
+    }
+}
+
+#[allow(dead_code)]
+impl VersionScript {
+    pub fn find_node(&self, name: &str) -> Option<usize> {
+        self.nodes
+            .iter()
+            .position(|node| !node.name.is_empty() && node.name == name)
+    }
+
+    // Returns the node a symbol belongs to and whether it is listed as
+    // global in it. An exact name match takes precedence over a wildcard
+    // match, and `local: *` only applies when nothing else matches.
+    pub fn find_symbol(&self, name: &str) -> Option<(usize, bool)> {
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.globals.iter().any(|p| !is_glob(p) && p == name) {
+                return Some((i, true));
+            }
+            if node.locals.iter().any(|p| !is_glob(p) && p == name) {
+                return Some((i, false));
+            }
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node
+                .globals
+                .iter()
+                .any(|p| is_glob(p) && p != "*" && glob_match(p.as_bytes(), name.as_bytes()))
+            {
+                return Some((i, true));
+            }
+            if node
+                .locals
+                .iter()
+                .any(|p| is_glob(p) && p != "*" && glob_match(p.as_bytes(), name.as_bytes()))
+            {
+                return Some((i, false));
+            }
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.globals.iter().any(|p| p == "*") {
+                return Some((i, true));
+            }
+            if node.locals.iter().any(|p| p == "*") {
+                return Some((i, false));
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn test_parse_version_script() {
+    let vs = parse_version_script(
+        "# comment\n\
+         V1 { global: foo; bar*; local: *; };\n\
+         V2 { global: baz; extern \"C++\" { \"ns::f\"; }; } V1;\n",
+    )
+    .unwrap();
+    assert_eq!(vs.nodes.len(), 2);
+    assert_eq!(vs.nodes[0].name, "V1");
+    assert_eq!(vs.nodes[0].globals, vec!["foo", "bar*"]);
+    assert_eq!(vs.nodes[0].locals, vec!["*"]);
+    assert_eq!(vs.nodes[1].parent, Some(String::from("V1")));
+    assert_eq!(vs.nodes[1].globals, vec!["baz", "ns::f"]);
+
+    assert_eq!(vs.find_symbol("foo"), Some((0, true)));
+    assert_eq!(vs.find_symbol("bar1"), Some((0, true)));
+    assert_eq!(vs.find_symbol("baz"), Some((1, true)));
+    assert_eq!(vs.find_symbol("qux"), Some((0, false)));
+
+    for (script, reason) in [
+        ("V1 { foo; ", "unexpected end of file"),
+        ("V1 { foo }; ", "expected ';', but got '}'"),
+        ("/* V1 {};", "unterminated comment"),
+        ("V1 {}; V2 {} V3;", "undefined version: V3"),
+    ] {
+        let Err(LinkError::BadVersionScript(msg)) = parse_version_script(script) else {
+            panic!("{:?} was accepted", script);
+        };
+        assert_eq!(msg, reason);
+    }
+}
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match(b"foo*", b"foobar"));
+    assert!(glob_match(b"f?o", b"fxo"));
+    assert!(glob_match(b"[a-c]x", b"bx"));
+    assert!(!glob_match(b"[!a-c]x", b"bx"));
+    assert!(!glob_match(b"foo", b"foobar"));
+}
@@ -0,0 +1,46 @@
+use crate::{
+    chunk::Chunk,
+    context::Context,
+    elf::{SHF_ALLOC, SHT_GNU_VERSYM, VER_NDX_LOCAL},
+    utils::write,
+};
+
+#[allow(dead_code)]
+pub struct VersymSection {
+    pub chunk: Chunk,
+    pub contents: Vec<u16>,
+}
+
+#[allow(dead_code)]
+impl VersymSection {
+    pub fn new() -> Self {
+        let mut chunk = Chunk::new();
+        chunk.name = ".gnu.version".to_string();
+        chunk.shdr.shdr_type = SHT_GNU_VERSYM;
+        chunk.shdr.flags = SHF_ALLOC;
+        chunk.shdr.addr_align = 2;
+        chunk.shdr.ent_size = 2;
+
+        Self {
+            chunk,
+            contents: vec![],
+        }
+    }
+
+    // One entry per .dynsym entry, including the null symbol.
+    pub fn update_shdr(&mut self, ctx: &Context) {
+        let dynsym = ctx.dynsym.as_ref().unwrap();
+        self.contents = vec![VER_NDX_LOCAL];
+        for sym in &dynsym.symbols {
+            self.contents.push(ctx.symbols[*sym].ver_idx);
+        }
+        self.chunk.shdr.size = self.contents.len() as u64 * 2;
+    }
+
+    pub fn copy_buf(&self, buf: &mut [u8]) {
+        let base = self.chunk.shdr.offset as usize;
+        for i in 0..self.contents.len() {
+            write(&mut buf[base + i * 2..base + i * 2 + 2], self.contents[i]);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::sync::atomic::Ordering;
+
+use crate::{
+    context::Context,
+    elf::{
+        Phdr, Rela, EM_X86_64, RX86_64, R_X86_64_16, R_X86_64_32, R_X86_64_32S, R_X86_64_64,
+        R_X86_64_8, R_X86_64_GOTPCREL, R_X86_64_GOTPCRELX, R_X86_64_GOTTPOFF, R_X86_64_NONE,
+        R_X86_64_PC16, R_X86_64_PC32, R_X86_64_PC64, R_X86_64_PC8, R_X86_64_PLT32,
+        R_X86_64_REX_GOTPCRELX, R_X86_64_TPOFF32, R_X86_64_TPOFF64,
+    },
+    elf_class::ElfClass,
+    error::LinkError,
+    input_section::InputSection,
+    machine_type::{MachineType, MACHINE_TYPE_X86_64},
+    object_file::ObjectFile,
+    symbol::{NEEDS_GOT, NEEDS_GOT_TP},
+    target::Target,
+    utils::{align_to, write},
+};
+
+const IMAGE_BASE: u64 = 0x400000;
+
+// x86-64 static executables. Calls through the PLT resolve directly to
+// the callee, and GOT-relative loads use a GOT entry holding the address.
+pub struct X86_64;
+
+// Reports an error unless `val` fits in [lo, hi). The field is written
+// either way.
+fn check_range(
+    ctx: &Context,
+    isec: &InputSection,
+    rel: &Rela,
+    val: i64,
+    lo: i64,
+    hi: i64,
+) -> Result<(), LinkError> {
+    if val < lo || val >= hi {
+        ctx.error(LinkError::RelocOverflow {
+            section: isec.name().to_string(),
+            ty: rel.ty,
+            offset: rel.offset,
+            value: val,
+            lo,
+            hi,
+        })?;
+    }
+    Ok(())
+}
+
+impl Target for X86_64 {
+    fn machine_type(&self) -> MachineType {
+        MACHINE_TYPE_X86_64
+    }
+
+    fn e_machine(&self) -> u16 {
+        EM_X86_64
+    }
+
+    fn class(&self) -> ElfClass {
+        ElfClass::Elf64
+    }
+
+    fn image_base(&self) -> u64 {
+        IMAGE_BASE
+    }
+
+    // TLS variant II: the TLS block ends at tp, and the thread control
+    // block follows it.
+    fn get_tp_addr(&self, tls: &Phdr) -> u64 {
+        align_to(tls.v_addr + tls.mem_size, tls.align.max(1))
+    }
+
+    // The psABI defines no e_flags.
+    fn merge_flags<'a>(&self, _objs: &[&ObjectFile<'a>]) -> Result<u32, LinkError> {
+        Ok(0)
+    }
+
+    fn scan_relocations(&self, ctx: &Context, isec: &InputSection) {
+        let symbols = &ctx.files[isec.object_file].input_file.symbols;
+        for rel in &isec.rels {
+            let sym = &ctx.symbols[symbols[rel.sym as usize]];
+            if !sym.is_defined() {
+                continue;
+            }
+            let flags = match rel.ty as RX86_64 {
+                R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX => NEEDS_GOT,
+                R_X86_64_GOTTPOFF => NEEDS_GOT_TP,
+                _ => continue,
+            };
+            sym.flags.fetch_or(flags, Ordering::Relaxed);
+        }
+    }
+
+    fn apply_reloc_alloc<'a>(
+        &self,
+        ctx: &Context<'a>,
+        isec: &InputSection<'a>,
+        base: &mut [u8],
+    ) -> Result<(), LinkError> {
+        let symbols = &ctx.files[isec.object_file].input_file.symbols;
+
+        for (i, rel) in isec.rels.iter().enumerate() {
+            if rel.ty == R_X86_64_NONE as u32 {
+                continue;
+            }
+
+            let sym = &ctx.symbols[symbols[rel.sym as usize]];
+            if !sym.is_defined() {
+                continue;
+            }
+
+            let loc = &mut base[rel.offset as usize..];
+            let (s, a) = isec.get_rel_target(ctx, i, sym);
+            let p = isec.get_addr(ctx) + rel.offset;
+            let write32 = |loc: &mut [u8], val: u64, lo: i64, hi: i64| {
+                write(&mut loc[..4], val as u32);
+                check_range(ctx, isec, rel, val as i64, lo, hi)
+            };
+
+            match rel.ty as RX86_64 {
+                R_X86_64_8 => {
+                    loc[0] = s.wrapping_add(a) as u8;
+                    check_range(ctx, isec, rel, s.wrapping_add(a) as i64, 0, 1 << 8)?
+                }
+                R_X86_64_16 => {
+                    write(&mut loc[..2], s.wrapping_add(a) as u16);
+                    check_range(ctx, isec, rel, s.wrapping_add(a) as i64, 0, 1 << 16)?
+                }
+                R_X86_64_32 => write32(loc, s.wrapping_add(a), 0, 1 << 32)?,
+                R_X86_64_32S => write32(loc, s.wrapping_add(a), -(1 << 31), 1 << 31)?,
+                R_X86_64_64 => write(&mut loc[..8], s.wrapping_add(a)),
+                R_X86_64_PC8 => {
+                    let val = s.wrapping_add(a).wrapping_sub(p);
+                    loc[0] = val as u8;
+                    check_range(ctx, isec, rel, val as i64, -(1 << 7), 1 << 7)?
+                }
+                R_X86_64_PC16 => {
+                    let val = s.wrapping_add(a).wrapping_sub(p);
+                    write(&mut loc[..2], val as u16);
+                    check_range(ctx, isec, rel, val as i64, -(1 << 15), 1 << 15)?
+                }
+                R_X86_64_PC32 | R_X86_64_PLT32 => {
+                    write32(loc, s.wrapping_add(a).wrapping_sub(p), -(1 << 31), 1 << 31)?
+                }
+                R_X86_64_PC64 => write(&mut loc[..8], s.wrapping_add(a).wrapping_sub(p)),
+                R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX => {
+                    let g = sym.get_got_addr(ctx);
+                    write32(loc, g.wrapping_add(a).wrapping_sub(p), -(1 << 31), 1 << 31)?
+                }
+                R_X86_64_GOTTPOFF => {
+                    let g = sym.get_got_tp_addr(ctx);
+                    write32(loc, g.wrapping_add(a).wrapping_sub(p), -(1 << 31), 1 << 31)?
+                }
+                R_X86_64_TPOFF32 => {
+                    let val = s.wrapping_add(a).wrapping_sub(ctx.tp_addr);
+                    write32(loc, val, -(1 << 31), 1 << 31)?
+                }
+                R_X86_64_TPOFF64 => {
+                    write(&mut loc[..8], s.wrapping_add(a).wrapping_sub(ctx.tp_addr))
+                }
+                _ => ctx.error(LinkError::UnsupportedReloc {
+                    section: isec.name().to_string(),
+                    ty: rel.ty,
+                })?,
+            }
+        }
+        Ok(())
+    }
+
+    // Debug info refers to code and data with absolute addresses only. A
+    // reference to a discarded section gets a tombstone value.
+    fn apply_reloc_nonalloc<'a>(
+        &self,
+        ctx: &Context<'a>,
+        isec: &InputSection<'a>,
+        base: &mut [u8],
+    ) -> Result<(), LinkError> {
+        let tombstone = isec.get_tombstone();
+
+        for rel in &isec.rels {
+            if rel.ty == R_X86_64_NONE as u32 {
+                continue;
+            }
+
+            let loc = &mut base[rel.offset as usize..];
+            let val = isec.get_nonalloc_value(ctx, rel).unwrap_or(tombstone);
+            match rel.ty as RX86_64 {
+                R_X86_64_32 | R_X86_64_32S => write(&mut loc[..4], val as u32),
+                R_X86_64_64 => write(&mut loc[..8], val),
+                _ => ctx.error(LinkError::UnsupportedReloc {
+                    section: isec.name().to_string(),
+                    ty: rel.ty,
+                })?,
+            }
+        }
+        Ok(())
+    }
+
+    // CIEs and FDEs refer to code with PC-relative addresses, and to
+    // personality routines with absolute ones in non-PIC code.
+    fn apply_eh_reloc(&self, loc: &mut [u8], rel: &Rela, s: u64, p: u64) -> bool {
+        let val = s.wrapping_add(rel.addend as u64);
+        match rel.ty as RX86_64 {
+            R_X86_64_NONE => {}
+            R_X86_64_32 => write(&mut loc[..4], val as u32),
+            R_X86_64_64 => write(&mut loc[..8], val),
+            R_X86_64_PC32 => write(&mut loc[..4], val.wrapping_sub(p) as u32),
+            R_X86_64_PC64 => write(&mut loc[..8], val.wrapping_sub(p)),
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[test]
+fn test_get_tp_addr() {
+    let mut tls = Phdr::new();
+    tls.v_addr = 0x401000;
+    tls.mem_size = 0x14;
+    tls.align = 16;
+    assert_eq!(X86_64.get_tp_addr(&tls), 0x401020);
+}